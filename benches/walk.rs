@@ -0,0 +1,50 @@
+//! Timings of whole runs over a generated tree; compare before and after changes to the
+//! walker or the line counting with `cargo bench`.
+
+use std::path::Path;
+use std::process::Command;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+
+fn sourcelines(args: &[&str], dir: &Path) {
+    let status = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(args)
+        .arg(dir)
+        .stdout(std::process::Stdio::null())
+        .status()
+        .expect("failed to run sourcelines");
+    assert!(status.success());
+}
+
+fn generate_tree(files: &str, size: &str) -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["generate-test-tree", "--files", files, "--size", size])
+        .arg(dir.path())
+        .status()
+        .expect("failed to run sourcelines");
+    assert!(status.success());
+    dir
+}
+
+fn bench_walk(c: &mut Criterion) {
+    let tree = generate_tree("2000", "4K");
+    let mut group = c.benchmark_group("walk");
+    group.sample_size(10);
+    group.bench_function("parallel", |b| b.iter(|| sourcelines(&["-rs"], tree.path())));
+    group.bench_function("sequential", |b| b.iter(|| sourcelines(&["-rs", "-j", "1"], tree.path())));
+    group.bench_function("mmap", |b| b.iter(|| sourcelines(&["-rs", "--mmap"], tree.path())));
+    group.finish();
+}
+
+fn bench_large_files(c: &mut Criterion) {
+    let tree = generate_tree("20", "2M");
+    let mut group = c.benchmark_group("large_files");
+    group.sample_size(10);
+    group.bench_function("buffered", |b| b.iter(|| sourcelines(&["-rs"], tree.path())));
+    group.bench_function("line_cap", |b| b.iter(|| sourcelines(&["-rs", "--line-cap", "1K"], tree.path())));
+    group.finish();
+}
+
+criterion_group!(benches, bench_walk, bench_large_files);
+criterion_main!(benches);
@@ -0,0 +1,152 @@
+//! Node.js bindings: `countPath(path, options)` counts a tree off the main thread
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use napi::bindgen_prelude::AsyncTask;
+use napi::{Env, Error, Result, Task};
+use napi_derive::napi;
+use sourcelines::{DirSummary, Options};
+
+/// Options named after the command-line flags; all are optional
+#[napi(object)]
+#[derive(Default)]
+pub struct CountOptions {
+    pub recursive: Option<bool>,
+    pub follow_symlinks: Option<bool>,
+    pub ignore_files: Option<bool>,
+    pub hidden: Option<bool>,
+    pub exclude: Option<Vec<String>>,
+    pub include: Option<Vec<String>>,
+    pub include_langs: Option<Vec<String>>,
+    pub exclude_langs: Option<Vec<String>>,
+    pub max_depth: Option<u32>,
+    pub include_vendored: Option<bool>,
+    pub by_dir: Option<u32>,
+    pub keep_files: Option<bool>,
+}
+
+impl From<CountOptions> for Options {
+    fn from(options: CountOptions) -> Options {
+        let mut builder = Options::builder();
+        if let Some(yes) = options.recursive {
+            builder = builder.recursive(yes);
+        }
+        if let Some(yes) = options.follow_symlinks {
+            builder = builder.follow_symlinks(yes);
+        }
+        if let Some(yes) = options.ignore_files {
+            builder = builder.ignore_files(yes);
+        }
+        if let Some(yes) = options.include_vendored {
+            builder = builder.include_vendored(yes);
+        }
+        builder
+            .hidden(options.hidden.unwrap_or(false))
+            .exclude(options.exclude.unwrap_or_default())
+            .include(options.include.unwrap_or_default())
+            .include_langs(options.include_langs.unwrap_or_default())
+            .exclude_langs(options.exclude_langs.unwrap_or_default())
+            .max_depth(options.max_depth.map(|depth| depth as usize))
+            .by_dir(options.by_dir.map(|depth| depth as usize))
+            .keep_files(options.keep_files.unwrap_or(false))
+            .build()
+    }
+}
+
+/// Line counts, as in `sourcelines::Stats`
+#[napi(object)]
+pub struct Stats {
+    pub actual_loc: i64,
+    pub raw_loc: i64,
+    pub comments: i64,
+    pub words: i64,
+    pub chars: i64,
+    pub bytes: i64,
+    pub files: i64,
+}
+
+impl From<&sourcelines::Stats> for Stats {
+    fn from(stats: &sourcelines::Stats) -> Stats {
+        Stats {
+            actual_loc: stats.actual_loc as i64,
+            raw_loc: stats.raw_loc as i64,
+            comments: stats.comments as i64,
+            words: stats.words as i64,
+            chars: stats.chars as i64,
+            bytes: stats.bytes as i64,
+            files: stats.files as i64,
+        }
+    }
+}
+
+/// A counted file, with `keepFiles`
+#[napi(object)]
+pub struct FileCount {
+    pub path: String,
+    pub language: String,
+    pub stats: Stats,
+}
+
+/// The counts of a tree
+#[napi(object)]
+pub struct Summary {
+    pub total: Stats,
+    /// Part of `total` that is test code
+    pub tests: Stats,
+    pub languages: HashMap<String, Stats>,
+    /// Binary files that were not counted
+    pub binary_skipped: i64,
+    /// Subdirectories down to `byDir`, by path
+    pub children: HashMap<String, Summary>,
+    pub files: Vec<FileCount>,
+}
+
+impl From<&DirSummary> for Summary {
+    fn from(summary: &DirSummary) -> Summary {
+        Summary {
+            total: (&summary.total).into(),
+            tests: (&summary.tests).into(),
+            languages: summary.langs.iter().map(|(lang, stats)| (lang.clone(), stats.into())).collect(),
+            binary_skipped: summary.binary_skipped as i64,
+            children: summary.children.iter().map(|(name, child)| (name.clone(), child.into())).collect(),
+            files: summary
+                .files
+                .iter()
+                .map(|(path, lang, stats)| FileCount {
+                    path: path.display().to_string(),
+                    language: lang.clone(),
+                    stats: stats.into(),
+                })
+                .collect(),
+        }
+    }
+}
+
+pub struct CountPath {
+    path: PathBuf,
+    options: Options,
+}
+
+impl Task for CountPath {
+    type Output = DirSummary;
+    type JsValue = Summary;
+
+    // Runs on the libuv thread pool
+    fn compute(&mut self) -> Result<DirSummary> {
+        sourcelines::count_path(&self.path, &self.options).map_err(|e| Error::from_reason(e.to_string()))
+    }
+
+    fn resolve(&mut self, _env: Env, summary: DirSummary) -> Result<Summary> {
+        Ok((&summary).into())
+    }
+}
+
+/// Count the source files under `path`; the promise is rejected if `path` cannot be read
+#[napi]
+pub fn count_path(path: String, options: Option<CountOptions>) -> AsyncTask<CountPath> {
+    AsyncTask::new(CountPath {
+        path: path.into(),
+        options: options.unwrap_or_default().into(),
+    })
+}
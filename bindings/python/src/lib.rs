@@ -0,0 +1,94 @@
+//! Python bindings: `pysourcelines.count(path, **options)` returns the counts as dicts
+
+use pyo3::prelude::*;
+
+#[pymodule]
+mod pysourcelines {
+    use std::path::PathBuf;
+
+    use pyo3::exceptions::{PyOSError, PyTypeError};
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+    use sourcelines::{DirSummary, Options, Stats};
+
+    /// Count the source files under `path`. Options are named after the command-line flags:
+    /// recursive, follow_symlinks, ignore_files, hidden, exclude, include, include_langs,
+    /// exclude_langs, max_depth, include_vendored, by_dir and keep_files.
+    /// Returns a dict with "total", "tests" and "languages", and "children" and "files" as
+    /// asked for with by_dir and keep_files.
+    #[pyfunction]
+    #[pyo3(signature = (path, **options))]
+    fn count<'py>(
+        py: Python<'py>,
+        path: PathBuf,
+        options: Option<&Bound<'py, PyDict>>,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let mut builder = Options::builder();
+        for (key, value) in options.into_iter().flatten() {
+            let key: String = key.extract()?;
+            builder = match key.as_str() {
+                "recursive" => builder.recursive(value.extract()?),
+                "follow_symlinks" => builder.follow_symlinks(value.extract()?),
+                "ignore_files" => builder.ignore_files(value.extract()?),
+                "hidden" => builder.hidden(value.extract()?),
+                "exclude" => builder.exclude(value.extract::<Vec<String>>()?),
+                "include" => builder.include(value.extract::<Vec<String>>()?),
+                "include_langs" => builder.include_langs(value.extract::<Vec<String>>()?),
+                "exclude_langs" => builder.exclude_langs(value.extract::<Vec<String>>()?),
+                "max_depth" => builder.max_depth(value.extract::<Option<usize>>()?),
+                "include_vendored" => builder.include_vendored(value.extract()?),
+                "by_dir" => builder.by_dir(value.extract::<Option<usize>>()?),
+                "keep_files" => builder.keep_files(value.extract()?),
+                _ => return Err(PyTypeError::new_err(format!("count() got an unexpected keyword argument '{}'", key))),
+            };
+        }
+        let options = builder.build();
+        // Other Python threads run while the tree is walked
+        let summary = py
+            .detach(|| sourcelines::count_path(&path, &options))
+            .map_err(|e| PyOSError::new_err(e.to_string()))?;
+        summary_dict(py, &summary)
+    }
+
+    fn stats_dict<'py>(py: Python<'py>, stats: &Stats) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("actual_loc", stats.actual_loc)?;
+        dict.set_item("raw_loc", stats.raw_loc)?;
+        dict.set_item("comments", stats.comments)?;
+        dict.set_item("words", stats.words)?;
+        dict.set_item("chars", stats.chars)?;
+        dict.set_item("bytes", stats.bytes)?;
+        dict.set_item("files", stats.files)?;
+        Ok(dict)
+    }
+
+    fn summary_dict<'py>(py: Python<'py>, summary: &DirSummary) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("total", stats_dict(py, &summary.total)?)?;
+        dict.set_item("tests", stats_dict(py, &summary.tests)?)?;
+        let langs = PyDict::new(py);
+        for (lang, stats) in &summary.langs {
+            langs.set_item(lang, stats_dict(py, stats)?)?;
+        }
+        dict.set_item("languages", langs)?;
+        dict.set_item("binary_skipped", summary.binary_skipped)?;
+        if !summary.children.is_empty() {
+            let children = PyDict::new(py);
+            for (name, child) in &summary.children {
+                children.set_item(name, summary_dict(py, child)?)?;
+            }
+            dict.set_item("children", children)?;
+        }
+        if !summary.files.is_empty() {
+            let mut files = Vec::new();
+            for (path, lang, stats) in &summary.files {
+                let file = stats_dict(py, stats)?;
+                file.set_item("path", path)?;
+                file.set_item("language", lang)?;
+                files.push(file);
+            }
+            dict.set_item("files", files)?;
+        }
+        Ok(dict)
+    }
+}
@@ -0,0 +1,149 @@
+//! Tar archives, plain or compressed with gzip, xz or zstd: read entry by entry to be counted
+//! in place, or unpacked into temporary directories
+
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use sourcelines::{
+    DirSummary, Options, Stats, count_reader, detect_language_from_prefix, looks_binary, remap_language,
+};
+
+/// Endings of the names of the archives counted in place of files
+const TARBALL_SUFFIXES: [&str; 5] = [".tar", ".tar.gz", ".tgz", ".tar.xz", ".tar.zst"];
+
+/// Whether PATH is a file named like a tar archive
+pub fn is_tarball(path: &Path) -> bool {
+    let name = path.file_name().map(|name| name.to_string_lossy().to_lowercase()).unwrap_or_default();
+    TARBALL_SUFFIXES.iter().any(|suffix| name.ends_with(suffix)) && path.is_file()
+}
+
+/// Count the regular files of the tar archive read from READER as OPTS asks, with their paths
+/// below PREFIX, and call COUNTED with the path, content and counts of each. Files left out by
+/// their names or sizes are not read. With LIMIT, an archive whose decompressed size exceeds it
+/// is refused. The compression is told by the first bytes of the archive, not its name
+pub fn count(
+    reader: impl BufRead,
+    prefix: &Path,
+    opts: &Options,
+    limit: Option<u64>,
+    mut counted: impl FnMut(&Path, &[u8], &Stats),
+) -> io::Result<DirSummary> {
+    let mut summary = DirSummary::default();
+    let skip = |count: &AtomicUsize| count.fetch_add(1, Ordering::Relaxed);
+    let wanted = |name: &Path, size: u64| {
+        if !opts.path_selected(name, Path::new("")) {
+            skip(&opts.skipped.excluded);
+            return false;
+        }
+        if opts.min_file_size.is_some_and(|min| size < min) || opts.max_file_size.is_some_and(|max| size > max) {
+            skip(&opts.skipped.size);
+            return false;
+        }
+        true
+    };
+    for_each_file(reader, limit, wanted, |name, content| {
+        let path = prefix.join(name);
+        let binary = looks_binary(content);
+        if opts.binary_extension(name) || (binary && !opts.count_binary) {
+            tracing::debug!(path = %path.display(), detail = "binary", "skipped");
+            summary.binary_skipped += 1;
+            return;
+        }
+        let lang = remap_language(detect_language_from_prefix(name, content), &opts.remap);
+        if !opts.lang_selected(&lang) {
+            return;
+        }
+        // Only the size of binary files is counted, with --count-binary
+        let stats = match binary {
+            true => Stats { bytes: content.len(), files: 1, ..Stats::default() },
+            false => count_reader(&lang, content).unwrap_or_default(),
+        };
+        counted(&path, content, &stats);
+        if opts.keep_files {
+            summary.files.push((path, lang.to_string(), stats.clone()));
+        }
+        summary.add_file(&lang, stats, opts.is_test_file(name, Path::new("")));
+    })?;
+    Ok(summary)
+}
+
+// Call FOUND with the path and content of each regular file of the archive, in its order, if
+// WANTED is true for its path and size: the content of the others is not read
+fn for_each_file(
+    reader: impl BufRead,
+    limit: Option<u64>,
+    mut wanted: impl FnMut(&Path, u64) -> bool,
+    mut found: impl FnMut(&Path, &[u8]),
+) -> io::Result<()> {
+    let limit = limit.unwrap_or(u64::MAX);
+    let mut archive = tar::Archive::new(Limited { reader: decompress(reader)?, left: limit });
+    let mut content = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.into_owned();
+        let path = path.strip_prefix("./").unwrap_or(&path).to_path_buf();
+        if !wanted(&path, entry.size()) {
+            continue;
+        }
+        // Told by the header, before its content is read into memory
+        if entry.size() > limit {
+            return Err(too_large());
+        }
+        content.clear();
+        entry.read_to_end(&mut content)?;
+        found(&path, &content);
+    }
+    Ok(())
+}
+
+// A reader failing once more than LEFT bytes have been read from it
+struct Limited<R> {
+    reader: R,
+    left: u64,
+}
+
+impl<R: Read> Read for Limited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.left = self.left.checked_sub(n as u64).ok_or_else(too_large)?;
+        Ok(n)
+    }
+}
+
+fn too_large() -> io::Error {
+    io::Error::other("the archive is too large")
+}
+
+/// An archive unpacked into a temporary directory, removed again when dropped
+pub struct Unpacked {
+    dir: tempfile::TempDir,
+}
+
+impl Unpacked {
+    /// Unpack DATA, a tar archive; its compression is told by its first bytes. Entries are
+    /// kept below the directory, whatever their names. The directory is created anew, with
+    /// a name of its own
+    pub fn new(data: &[u8]) -> io::Result<Self> {
+        let dir = tempfile::Builder::new().prefix("sourcelines-archive-").tempdir()?;
+        tar::Archive::new(decompress(data)?).unpack(dir.path())?;
+        Ok(Unpacked { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+// READER decompressed as its magic number tells, or as it is
+fn decompress<'a>(mut reader: impl BufRead + 'a) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(match reader.fill_buf()? {
+        [0x1f, 0x8b, ..] => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+        [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(reader)),
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => Box::new(zstd::stream::read::Decoder::with_buffer(reader)?),
+        _ => Box::new(reader),
+    })
+}
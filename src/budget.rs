@@ -0,0 +1,90 @@
+//! --budgets: the most actual lines of code the files matching a glob, or a language, may
+//! have, read from a TOML file such as
+//!
+//!   "src/legacy/**" = 20000
+//!   "src/*/generated/**" = 5000
+//!
+//!   [languages]
+//!   javascript = 100000
+//!
+//! Globs are matched against the paths as walked, without a leading ./; * does not cross
+//! directories, ** does.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use globset::{GlobBuilder, GlobMatcher};
+use sourcelines::Stats;
+use toml::{Table, Value};
+
+pub struct Budgets {
+    pub file: PathBuf,
+    paths: Vec<(String, GlobMatcher, usize)>,
+    langs: Vec<(String, usize)>,
+    /// Actual LOC of the files matching each glob so far
+    used: Mutex<Vec<usize>>,
+}
+
+/// A budget and what the run used of it
+pub struct BudgetResult {
+    /// The glob, or the language between < and >
+    pub name: String,
+    pub used: usize,
+    pub limit: usize,
+}
+
+impl Budgets {
+    pub fn load(file: &Path) -> Result<Budgets, String> {
+        let text = std::fs::read_to_string(file).map_err(|e| format!("could not read {}: {}", file.display(), e))?;
+        let table = text.parse::<Table>().map_err(|e| format!("{}: {}", file.display(), e))?;
+        let limit = |key: &str, value: &Value| match value.as_integer() {
+            Some(n) if n >= 0 => Ok(n as usize),
+            _ => Err(format!("{}: the budget of '{}' must be a number of lines", file.display(), key)),
+        };
+        let (mut paths, mut langs) = (Vec::new(), Vec::new());
+        for (key, value) in &table {
+            if key == "languages"
+                && let Some(languages) = value.as_table()
+            {
+                for (lang, value) in languages {
+                    langs.push((lang.to_lowercase(), limit(lang, value)?));
+                }
+                continue;
+            }
+            let glob = GlobBuilder::new(key).literal_separator(true).build();
+            let glob = glob.map_err(|e| format!("{}: invalid glob '{}': {}", file.display(), key, e))?;
+            paths.push((key.clone(), glob.compile_matcher(), limit(key, value)?));
+        }
+        let used = Mutex::new(vec![0; paths.len()]);
+        Ok(Budgets { file: file.to_path_buf(), paths, langs, used })
+    }
+
+    /// Add a file counted to the budgets its path matches
+    pub fn add(&self, path: &Path, stats: &Stats) {
+        let path = path.strip_prefix("./").unwrap_or(path);
+        let mut used = self.used.lock().unwrap();
+        for (i, (_, glob, _)) in self.paths.iter().enumerate() {
+            if glob.is_match(path) {
+                used[i] += stats.actual_loc;
+            }
+        }
+    }
+
+    /// Every budget with what the run used of it, the languages' from LANGS, their totals.
+    /// The sums of the globs start again from 0
+    pub fn results(&self, langs: &BTreeMap<String, Stats>) -> Vec<BudgetResult> {
+        let used = std::mem::replace(&mut *self.used.lock().unwrap(), vec![0; self.paths.len()]);
+        let paths = self.paths.iter().zip(used).map(|((glob, _, limit), used)| BudgetResult {
+            name: glob.clone(),
+            used,
+            limit: *limit,
+        });
+        let langs = self.langs.iter().map(|(lang, limit)| BudgetResult {
+            name: format!("<{}>", lang),
+            used: langs.get(lang).map_or(0, |stats| stats.actual_loc),
+            limit: *limit,
+        });
+        paths.chain(langs).collect()
+    }
+}
@@ -0,0 +1,294 @@
+//! Building `Options` from the same settings as the command-line flags
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+
+use regex::Regex;
+
+use crate::{
+    CountCache, DEFAULT_EXCLUDES, DEFAULT_TEST_PATTERNS, FileCallback, MetricFactory, Options, SubmoduleMode,
+    Visitor, build_globset,
+};
+
+/// Options built step by step, one method per command-line flag; patterns are compiled by
+/// `build`. Every metric is always counted, so there is nothing to select about them here.
+#[derive(Clone)]
+pub struct OptionsBuilder {
+    options: Options,
+    default_excludes: bool,
+    hidden: bool,
+    case_insensitive: bool,
+    excludes: Vec<String>,
+    includes: Vec<String>,
+    test_patterns: Vec<String>,
+}
+
+impl Default for OptionsBuilder {
+    fn default() -> Self {
+        OptionsBuilder {
+            options: Options::default(),
+            default_excludes: true,
+            hidden: false,
+            case_insensitive: false,
+            excludes: Vec::new(),
+            includes: Vec::new(),
+            test_patterns: Vec::new(),
+        }
+    }
+}
+
+impl Options {
+    /// A builder starting from the defaults of `Options::default()`
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder::default()
+    }
+}
+
+impl OptionsBuilder {
+    /// Walk subdirectories (-r)
+    pub fn recursive(mut self, yes: bool) -> Self {
+        self.options.recursive = yes;
+        self
+    }
+
+    /// Follow symlinks (-L)
+    pub fn follow_symlinks(mut self, yes: bool) -> Self {
+        self.options.follow_symlinks = yes;
+        self
+    }
+
+    /// Honor ignore files (-i, -I)
+    pub fn ignore_files(mut self, yes: bool) -> Self {
+        self.options.ignore_files = yes;
+        self
+    }
+
+    /// Leave out entries matching these globs (--exclude)
+    pub fn exclude<S: Into<String>>(mut self, patterns: impl IntoIterator<Item = S>) -> Self {
+        self.excludes.extend(patterns.into_iter().map(Into::into).filter(|p: &String| !p.is_empty()));
+        self
+    }
+
+    /// Count entries matching these globs even if an exclude pattern matches them (--include)
+    pub fn include<S: Into<String>>(mut self, patterns: impl IntoIterator<Item = S>) -> Self {
+        self.includes.extend(patterns.into_iter().map(Into::into).filter(|p: &String| !p.is_empty()));
+        self
+    }
+
+    /// Leave out entries whose path matches one of these regexes (--exclude-regex)
+    pub fn exclude_regex(mut self, regexes: impl IntoIterator<Item = Regex>) -> Self {
+        self.options.exclude_regex.extend(regexes);
+        self
+    }
+
+    /// Count entries whose path matches one of these regexes (--include-regex)
+    pub fn include_regex(mut self, regexes: impl IntoIterator<Item = Regex>) -> Self {
+        self.options.include_regex.extend(regexes);
+        self
+    }
+
+    /// Start from DEFAULT_EXCLUDES (on by default, --no-default-excludes)
+    pub fn default_excludes(mut self, yes: bool) -> Self {
+        self.default_excludes = yes;
+        self
+    }
+
+    /// Count dot-prefixed entries among the default excludes, such as .git (--hidden)
+    pub fn hidden(mut self, yes: bool) -> Self {
+        self.hidden = yes;
+        self
+    }
+
+    /// Leave out every dot-prefixed entry (--no-hidden)
+    pub fn skip_hidden(mut self, yes: bool) -> Self {
+        self.options.skip_hidden = yes;
+        self
+    }
+
+    /// Match globs case-insensitively (--iglob)
+    pub fn case_insensitive(mut self, yes: bool) -> Self {
+        self.case_insensitive = yes;
+        self
+    }
+
+    /// Report languages under other names, as FROM=TO pairs (--remap)
+    pub fn remap<S: Into<String>>(mut self, pairs: impl IntoIterator<Item = (S, S)>) -> Self {
+        self.options.remap.extend(pairs.into_iter().map(|(from, to)| (from.into(), to.into())));
+        self
+    }
+
+    /// Only count these languages (--include-lang)
+    pub fn include_langs<S: Into<String>>(mut self, langs: impl IntoIterator<Item = S>) -> Self {
+        self.options.include_langs.extend(langs.into_iter().map(Into::into));
+        self
+    }
+
+    /// Do not count these languages (--exclude-lang)
+    pub fn exclude_langs<S: Into<String>>(mut self, langs: impl IntoIterator<Item = S>) -> Self {
+        self.options.exclude_langs.extend(langs.into_iter().map(Into::into));
+        self
+    }
+
+    /// Descend at most this many directories (--max-depth)
+    pub fn max_depth(mut self, depth: impl Into<Option<usize>>) -> Self {
+        self.options.max_depth = depth.into();
+        self
+    }
+
+    /// Stay on the file system of each argument (--one-file-system)
+    pub fn one_file_system(mut self, yes: bool) -> Self {
+        self.options.one_file_system = yes;
+        self
+    }
+
+    /// Count vendored code under its language instead of "vendored" (--include-vendored)
+    pub fn include_vendored(mut self, yes: bool) -> Self {
+        self.options.include_vendored = yes;
+        self
+    }
+
+    /// What to do with submodules and nested repositories (--submodules)
+    pub fn submodules(mut self, mode: SubmoduleMode) -> Self {
+        self.options.submodules = mode;
+        self
+    }
+
+    /// Count files matching these globs as test code, besides DEFAULT_TEST_PATTERNS (--test-pattern)
+    pub fn test_patterns<S: Into<String>>(mut self, patterns: impl IntoIterator<Item = S>) -> Self {
+        self.test_patterns.extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Count the size of binary files (--count-binary)
+    pub fn count_binary(mut self, yes: bool) -> Self {
+        self.options.count_binary = yes;
+        self
+    }
+
+    /// Treat files with these extensions as binary (--binary-extensions)
+    pub fn binary_extensions<S: Into<String>>(mut self, exts: impl IntoIterator<Item = S>) -> Self {
+        self.options.binary_exts.extend(exts.into_iter().map(Into::into));
+        self
+    }
+
+    /// Memory-map every file (--mmap)
+    pub fn mmap(mut self, yes: bool) -> Self {
+        self.options.mmap = yes;
+        self
+    }
+
+    /// Count lines longer than this many bytes piece by piece (--line-cap)
+    pub fn line_cap(mut self, bytes: usize) -> Self {
+        self.options.line_cap = bytes;
+        self
+    }
+
+    /// Skip files smaller than this many bytes (--min-file-size)
+    pub fn min_file_size(mut self, bytes: impl Into<Option<u64>>) -> Self {
+        self.options.min_file_size = bytes.into();
+        self
+    }
+
+    /// Skip files larger than this many bytes (--max-file-size)
+    pub fn max_file_size(mut self, bytes: impl Into<Option<u64>>) -> Self {
+        self.options.max_file_size = bytes.into();
+        self
+    }
+
+    /// Take the counts of unchanged files from `cache`, and store those of the others (--cache)
+    pub fn cache(mut self, cache: Arc<Mutex<CountCache>>) -> Self {
+        self.options.cache = Some(cache);
+        self
+    }
+
+    /// Call `progress` with each file before it is counted
+    pub fn progress(mut self, progress: FileCallback) -> Self {
+        self.options.progress = Some(progress);
+        self
+    }
+
+    /// Call the methods of `visitor` as the walk goes
+    pub fn visitor(mut self, visitor: Arc<dyn Visitor>) -> Self {
+        self.options.visitor = Some(visitor);
+        self
+    }
+
+    /// Stop walking once `cancel` is set to true
+    pub fn cancel(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.options.cancel = Some(cancel);
+        self
+    }
+
+    /// Run a metric made by `new` over every line of the files counted, summed under `name`
+    pub fn metric(mut self, name: impl Into<String>, new: MetricFactory) -> Self {
+        self.options.metrics.push((name.into(), new));
+        self
+    }
+
+    /// Only walk these paths and their parent directories (--git, --changed-in)
+    pub fn only(mut self, paths: HashSet<PathBuf>) -> Self {
+        self.options.only = Some(Arc::new(paths));
+        self
+    }
+
+    /// Count hardlinks and copies of a file once (--dedupe)
+    pub fn dedupe(mut self, yes: bool) -> Self {
+        self.options.seen = yes.then(Arc::default);
+        self
+    }
+
+    /// Keep the summaries of subdirectories down to this depth (--by-dir)
+    pub fn by_dir(mut self, depth: impl Into<Option<usize>>) -> Self {
+        self.options.by_dir = depth.into();
+        self
+    }
+
+    /// Keep the counts of every file in the summaries
+    pub fn keep_files(mut self, yes: bool) -> Self {
+        self.options.keep_files = yes;
+        self
+    }
+
+    /// Only find the files that would be counted (--list-only)
+    pub fn list_only(mut self, yes: bool) -> Self {
+        self.options.list_only = yes;
+        self
+    }
+
+    /// Report the comment syntax inferred for languages without a built-in one, as tracing
+    /// events (--explain)
+    pub fn explain(mut self, yes: bool) -> Self {
+        self.options.explain = yes;
+        self
+    }
+
+    /// The exclude globs in effect, in the order they were given (--list-excludes)
+    pub fn exclude_patterns(&self) -> Vec<String> {
+        let defaults = DEFAULT_EXCLUDES
+            .iter()
+            .filter(|_| self.default_excludes)
+            .filter(|s| !(self.hidden && s.starts_with('.')))
+            .map(|s| s.to_string());
+        // An include pattern cancels the same exclude pattern
+        defaults.chain(self.excludes.iter().cloned()).filter(|e| !self.includes.contains(e)).collect()
+    }
+
+    /// The include globs in effect
+    pub fn include_patterns(&self) -> &[String] {
+        &self.includes
+    }
+
+    /// Compile the patterns into the `Options` to walk with
+    pub fn build(self) -> Options {
+        let tests: Vec<String> =
+            DEFAULT_TEST_PATTERNS.iter().map(|s| s.to_string()).chain(self.test_patterns.iter().cloned()).collect();
+        Options {
+            exclude_set: build_globset(&self.exclude_patterns(), self.case_insensitive),
+            include_set: (!self.includes.is_empty()).then(|| build_globset(&self.includes, self.case_insensitive)),
+            test_set: build_globset(&tests, self.case_insensitive),
+            ..self.options
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Counts of unchanged files kept between runs
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{ContentHash, Stats};
+
+/// What --cache remembers about a file
+#[derive(Debug, Clone)]
+pub(crate) struct CacheEntry {
+    pub(crate) size: u64,
+    /// Modification time in nanoseconds since the epoch
+    pub(crate) mtime: u128,
+    /// Content hash, used when only the modification time changed
+    pub(crate) hash: Option<u64>,
+    pub(crate) lang: String,
+    pub(crate) binary: bool,
+    pub(crate) stats: Stats,
+}
+
+const CACHE_HEADER: &str = concat!("# sourcelines cache v2 ", env!("CARGO_PKG_VERSION"));
+
+/// Per-file counts kept between runs with --cache, keyed by absolute path
+#[derive(Debug, Default)]
+pub struct CountCache {
+    entries: std::collections::HashMap<PathBuf, CacheEntry>,
+}
+
+impl CountCache {
+    /// Load the cache at `path`; a missing cache, or one written by another version, is empty
+    pub fn load(path: &str) -> CountCache {
+        let mut cache = CountCache::default();
+        let Ok(text) = fs::read_to_string(path) else {
+            return cache;
+        };
+        let mut lines = text.lines();
+        if lines.next() != Some(CACHE_HEADER) {
+            return cache;
+        }
+        for line in lines {
+            // size, mtime, hash, lang, binary, 6 counts, path
+            let fields: Vec<&str> = line.splitn(12, '\t').collect();
+            let [size, mtime, hash, lang, binary, actual, raw, comments, words, chars, bytes, name] = fields[..] else {
+                continue;
+            };
+            let num = |s: &str| s.parse::<usize>().ok();
+            let counts = [num(actual), num(raw), num(comments), num(words), num(chars), num(bytes)];
+            let (Ok(size), Ok(mtime), [Some(actual_loc), Some(raw_loc), Some(comments), Some(words), Some(chars), Some(bytes)]) =
+                (size.parse(), mtime.parse(), counts)
+            else {
+                continue;
+            };
+            let entry = CacheEntry {
+                size,
+                mtime,
+                hash: u64::from_str_radix(hash, 16).ok(),
+                lang: lang.to_string(),
+                binary: binary == "1",
+                stats: Stats { actual_loc, raw_loc, comments, words, chars, bytes, files: 0 },
+            };
+            cache.entries.insert(PathBuf::from(name), entry);
+        }
+        cache
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut out = format!("{}\n", CACHE_HEADER);
+        for (name, e) in &self.entries {
+            let Some(name) = name.to_str().filter(|n| !n.contains('\n')) else {
+                continue;
+            };
+            let hash = e.hash.map_or("-".to_string(), |h| format!("{:016x}", h));
+            let s = &e.stats;
+            out += &format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                e.size, e.mtime, hash, e.lang, e.binary as u8,
+                s.actual_loc, s.raw_loc, s.comments, s.words, s.chars, s.bytes, name
+            );
+        }
+        // Replace the old cache in one step so an interrupted run cannot corrupt it
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, out)?;
+        fs::rename(&tmp, path)
+    }
+
+    /// The entry for `path` if the file is unchanged since it was counted
+    pub(crate) fn lookup(&mut self, path: &Path) -> Option<&CacheEntry> {
+        let (key, size, mtime) = file_signature(path)?;
+        let entry = self.entries.get_mut(&key)?;
+        if entry.size != size {
+            return None;
+        }
+        if entry.mtime != mtime {
+            // Touched, e.g. by a checkout, but possibly with the same content
+            if entry.hash.is_none() || entry.hash != ContentHash::of_file(path).ok() {
+                return None;
+            }
+            entry.mtime = mtime;
+        }
+        Some(entry)
+    }
+
+    pub(crate) fn store(&mut self, path: &Path, lang: String, binary: bool, stats: &Stats, hash: Option<u64>) {
+        let Some((key, size, mtime)) = file_signature(path) else {
+            return;
+        };
+        let stats = stats.clone();
+        self.entries.insert(key, CacheEntry { size, mtime, hash, lang, binary, stats });
+    }
+}
+
+// Cache key, size and modification time of PATH
+fn file_signature(path: &Path) -> Option<(PathBuf, u64, u128)> {
+    let meta = fs::metadata(path).ok()?;
+    let mtime = meta.modified().ok()?.duration_since(std::time::UNIX_EPOCH).ok()?.as_nanos();
+    Some((std::path::absolute(path).ok()?, meta.len(), mtime))
+}
@@ -0,0 +1,203 @@
+//! The text of comments, for tools that look into them: TODO scanners, license checkers and
+//! the like
+
+use std::io::BufRead;
+
+use crate::count::LineClassifier;
+use crate::{CommentSyntax, LineKind};
+
+/// A comment found by `extract_comments`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommentSpan {
+    /// First line of the comment, from 1
+    pub start_line: usize,
+    /// Last line of the comment
+    pub end_line: usize,
+    /// The comment without its markers, its lines trimmed and joined with newlines
+    pub text: String,
+}
+
+/// The comments of what `reader` yields as a file of language `lang`, in order. They are
+/// found by the classifier the counts come from: every line counted as a comment is in a
+/// span, and so are comments that trail code. A block comment makes one span, as do line
+/// comments on consecutive lines and, in literate sources, consecutive lines of prose.
+/// Stops at the first read error.
+pub fn extract_comments(lang: &str, mut reader: impl BufRead) -> Vec<CommentSpan> {
+    let Ok(mut classifier) = LineClassifier::for_reader(lang, &mut reader) else {
+        return Vec::new();
+    };
+    let mut extractor = Extractor::default();
+    let mut line = Vec::new();
+    let mut n = 0;
+    while matches!(reader.read_until(b'\n', &mut line), Ok(1..)) {
+        n += 1;
+        let text = String::from_utf8_lossy(&line);
+        let kind = classifier.classify(&text);
+        extractor.line(&classifier, n, text.trim_end_matches(['\n', '\r']), kind);
+        line.clear();
+    }
+    extractor.finish()
+}
+
+#[derive(Default)]
+struct Extractor {
+    spans: Vec<CommentSpan>,
+    // A block comment not closed yet, or line comments the next line may continue
+    open: Option<(CommentSpan, Open)>,
+}
+
+enum Open {
+    Block,
+    Lines,
+}
+
+impl Extractor {
+    fn line(&mut self, classifier: &LineClassifier, n: usize, line: &str, kind: LineKind) {
+        let syntax = &classifier.syntax;
+        if let Some((span, Open::Block)) = &mut self.open {
+            // Every line is a comment up to the one that closes the block
+            let trimmed = line.trim();
+            match marker(&syntax.block_end).and_then(|end| trimmed.find(end)) {
+                Some(i) => {
+                    add_line(span, n, &trimmed[..i]);
+                    self.close();
+                }
+                None => add_line(span, n, trimmed),
+            }
+            return;
+        }
+        match kind {
+            LineKind::Comment if classifier.literate.is_some() => self.add_to_lines(n, line.trim()),
+            LineKind::Comment => {
+                let mut trimmed = line.trim();
+                if classifier.jsx && trimmed.starts_with("{/*") {
+                    trimmed = &trimmed[1..];
+                }
+                if let Some(start) = marker(&syntax.block_start)
+                    && let Some(body) = trimmed.strip_prefix(start)
+                {
+                    self.close();
+                    match marker(&syntax.block_end).and_then(|end| body.find(end)) {
+                        Some(i) => self.spans.push(span(n, &body[..i])),
+                        None => self.open = Some((span(n, body), Open::Block)),
+                    }
+                } else if let Some(start) = marker(&syntax.line)
+                    && let Some(body) = trimmed.strip_prefix(start)
+                {
+                    // Doubled markers, as in /// or ##, are part of the marker
+                    let body = body.trim_start_matches(start.chars().last().unwrap_or(' '));
+                    self.add_to_lines(n, body);
+                } else {
+                    self.close();
+                    self.spans.push(span(n, trimmed));
+                }
+            }
+            LineKind::Mixed => {
+                self.close();
+                for body in trailing_comments(line, syntax) {
+                    self.spans.push(span(n, body));
+                }
+            }
+            LineKind::Code | LineKind::Blank => self.close(),
+        }
+    }
+
+    fn add_to_lines(&mut self, n: usize, text: &str) {
+        match &mut self.open {
+            Some((span, Open::Lines)) => add_line(span, n, text),
+            _ => {
+                self.close();
+                self.open = Some((span(n, text), Open::Lines));
+            }
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some((mut span, _)) = self.open.take() {
+            // The lines a block's markers stand on alone
+            span.text = span.text.trim_matches('\n').to_string();
+            self.spans.push(span);
+        }
+    }
+
+    fn finish(mut self) -> Vec<CommentSpan> {
+        self.close();
+        self.spans
+    }
+}
+
+fn marker(marker: &Option<String>) -> Option<&str> {
+    marker.as_deref().filter(|m| !m.is_empty())
+}
+
+fn span(n: usize, text: &str) -> CommentSpan {
+    CommentSpan { start_line: n, end_line: n, text: clean(text).to_string() }
+}
+
+fn add_line(span: &mut CommentSpan, n: usize, text: &str) {
+    span.end_line = n;
+    span.text.push('\n');
+    span.text.push_str(clean(text));
+}
+
+// TEXT trimmed, without the * that lines of a block comment often start with
+fn clean(text: &str) -> &str {
+    let text = text.trim();
+    text.strip_prefix('*').map_or(text, str::trim_start)
+}
+
+// The comments after the code of LINE, skipping markers within string literals; a block
+// comment that is not closed on the line is taken to its end
+fn trailing_comments<'a>(line: &'a str, syntax: &CommentSyntax) -> Vec<&'a str> {
+    let bytes = line.as_bytes();
+    let mut comments = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        if matches!(bytes[i], b'"' | b'\'' | b'`')
+            && let Some(end) = closing_quote(rest)
+        {
+            i += end + 1;
+            continue;
+        }
+        if let Some(start) = marker(&syntax.line)
+            && rest.starts_with(start.as_bytes())
+        {
+            let body = &line[i + start.len()..];
+            comments.push(body.trim_start_matches(start.chars().last().unwrap_or(' ')));
+            break;
+        }
+        if let Some(start) = marker(&syntax.block_start)
+            && rest.starts_with(start.as_bytes())
+        {
+            let body = &line[i + start.len()..];
+            match marker(&syntax.block_end).and_then(|end| Some((body.find(end)?, end.len()))) {
+                Some((end, len)) => {
+                    comments.push(&body[..end]);
+                    i += start.len() + end + len;
+                    continue;
+                }
+                None => {
+                    comments.push(body);
+                    break;
+                }
+            }
+        }
+        i += 1;
+    }
+    comments
+}
+
+// Where the string literal that TEXT starts with ends, if it ends on the line
+fn closing_quote(text: &[u8]) -> Option<usize> {
+    let mut i = 1;
+    while i < text.len() {
+        match text[i] {
+            b'\\' => i += 2,
+            b if b == text[0] => return Some(i),
+            _ => i += 1,
+        }
+    }
+    None
+}
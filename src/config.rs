@@ -0,0 +1,255 @@
+//! The configuration file, .sourcelines.toml: options given as if on the command line, before
+//! its arguments. Keys are long option names, e.g.
+//!
+//!   exclude = ["*.min.js", "fixtures"]
+//!   remap = ["jsp=java"]
+//!
+//!   [profile.ci]
+//!   summary-only = true
+//!   fail-if = ["total.actual_loc > 500000"]
+//!
+//! Top-level keys always apply; the keys of a [profile.NAME] table apply after them with
+//! --profile NAME. SOURCELINES_* environment variables give options the same way, after the
+//! file.
+
+use std::path::{Path, PathBuf};
+
+use toml::{Table, Value};
+
+/// Name of the configuration file, looked up in the current directory and its parents
+pub const CONFIG_FILE: &str = ".sourcelines.toml";
+
+/// Prefix of the environment variables that give options
+pub const ENV_PREFIX: &str = "SOURCELINES_";
+
+pub struct Config {
+    pub path: PathBuf,
+    table: Table,
+}
+
+impl Config {
+    /// Read FILE, or the nearest .sourcelines.toml if FILE is None. Having no configuration
+    /// file is not an error, but a FILE that cannot be read is
+    pub fn find(file: Option<&Path>) -> Result<Option<Config>, String> {
+        let path = match file {
+            Some(file) => file.to_path_buf(),
+            None => {
+                let cwd = std::env::current_dir().map_err(|e| e.to_string())?;
+                match cwd.ancestors().map(|dir| dir.join(CONFIG_FILE)).find(|path| path.is_file()) {
+                    Some(path) => path,
+                    None => return Ok(None),
+                }
+            }
+        };
+        let text = std::fs::read_to_string(&path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        let table = text.parse::<Table>().map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Some(Config { path, table }))
+    }
+
+    /// The command-line options the file gives, with those of PROFILE after the top-level
+    /// ones. Keys are checked against the long options of COMMAND
+    pub fn args(&self, profile: Option<&str>, command: &clap::Command) -> Result<Vec<String>, String> {
+        let mut args = Vec::new();
+        self.add_args(&self.table, command, &mut args)?;
+        if let Some(name) = profile {
+            let profile = self
+                .table
+                .get("profile")
+                .and_then(Value::as_table)
+                .and_then(|profiles| profiles.get(name))
+                .and_then(Value::as_table)
+                .ok_or_else(|| format!("no profile '{}' in {}", name, self.path.display()))?;
+            self.add_args(profile, command, &mut args)?;
+        }
+        Ok(args)
+    }
+
+    /// What is wrong with the file: keys that are not options or have values of the wrong type,
+    /// wildcards that are not valid globs (they match only names written the same), remaps of
+    /// a language to two others or to a language that is itself remapped, and options that
+    /// cannot be used together. Each profile is checked after the top-level keys, and PROFILE,
+    /// the one chosen, must be one of them
+    pub fn check(&self, profile: Option<&str>, command: &clap::Command) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(name) = profile
+            && self.table.get("profile").and_then(Value::as_table).is_none_or(|profiles| !profiles.contains_key(name))
+        {
+            problems.push(format!("no profile '{}' in {}", name, self.path.display()));
+        }
+        let mut profiles = vec![None];
+        for (name, profile) in self.table.get("profile").and_then(Value::as_table).into_iter().flatten() {
+            match profile.is_table() {
+                true => profiles.push(Some(name.as_str())),
+                false => problems.push(format!("{}: [profile.{}] is not a table", self.path.display(), name)),
+            }
+        }
+        // Problems of the top-level keys are only reported for them
+        let mut top_level = Vec::new();
+        for profile in profiles {
+            let mut found = Vec::new();
+            let origin = match profile {
+                Some(name) => format!("{} [profile.{}]", self.path.display(), name),
+                None => self.path.display().to_string(),
+            };
+            let args = self.args(profile, command);
+            if let Err(e) = &args {
+                found.push(e.replace(&format!(" in {}", self.path.display()), ""));
+            }
+            let args = args.unwrap_or_default();
+            let values = |long: &str| -> Vec<&str> {
+                let prefix = format!("--{}=", long);
+                args.iter().filter_map(|arg| arg.strip_prefix(&prefix)).collect()
+            };
+            for long in ["exclude", "include", "test-pattern"] {
+                for glob in values(long).into_iter().filter(|glob| globset::Glob::new(glob).is_err()) {
+                    found.push(format!("'{}' is not a valid wildcard, it only matches itself", glob));
+                }
+            }
+            let remaps: Vec<(&str, &str)> = values("remap")
+                .into_iter()
+                .filter_map(|remap| remap.split_once('='))
+                .map(|(from, to)| (from.trim(), to.trim()))
+                .collect();
+            for (i, (from, to)) in remaps.iter().enumerate() {
+                if let Some((_, other)) = remaps[..i].iter().find(|(other, _)| other == from)
+                    && other != to
+                {
+                    found.push(format!("{} is remapped to both {} and {}", from, other, to));
+                }
+                if let Some((_, next)) = remaps.iter().find(|(other, _)| other == to && other != from) {
+                    found.push(format!("{} is remapped to {}, which is remapped to {}", from, to, next));
+                }
+            }
+            let program = std::iter::once("sourcelines".to_string());
+            if let Err(e) = command.clone().try_get_matches_from(program.chain(args)) {
+                let e = e.to_string();
+                let message = e.lines().next().unwrap_or_default();
+                found.push(message.strip_prefix("error: ").unwrap_or(message).to_string());
+            }
+            match profile {
+                None => top_level = found.clone(),
+                Some(_) => found.retain(|problem| !top_level.contains(problem)),
+            }
+            problems.extend(found.into_iter().map(|problem| format!("{}: {}", origin, problem)));
+        }
+        problems
+    }
+
+    fn add_args(&self, table: &Table, command: &clap::Command, args: &mut Vec<String>) -> Result<(), String> {
+        let origin = self.path.display().to_string();
+        for (key, value) in table {
+            if key == "profile" && value.is_table() {
+                continue;
+            }
+            // The options that choose the file cannot be given in it
+            let chooses_file = |arg: &&clap::Arg| matches!(arg.get_id().as_str(), "config" | "no_config" | "profile");
+            let arg = find_arg(command, key).filter(|arg| !chooses_file(arg));
+            let arg = arg.ok_or_else(|| format!("unknown option '{}' in {}", key, origin))?;
+            args.extend(option_args(arg, key, value, &origin)?);
+        }
+        Ok(())
+    }
+}
+
+/// The command-line options given by SOURCELINES_* environment variables, which come after
+/// those of the configuration file and before the command line. SOURCELINES_EXCLUDE gives
+/// --exclude, and SOURCELINES_JOBS --threads. Flags are set by 1, true, yes or on; options
+/// that can be given several times take comma-separated values
+pub fn env_args(command: &clap::Command) -> Result<Vec<String>, String> {
+    let mut vars: Vec<(String, String)> = std::env::vars().filter(|(name, _)| name.starts_with(ENV_PREFIX)).collect();
+    vars.sort();
+    let mut args = Vec::new();
+    for (name, value) in vars {
+        let key = name[ENV_PREFIX.len()..].to_lowercase();
+        let (key, value) = match key.as_str() {
+            "jobs" => ("threads".to_string(), value),
+            _ => (key, value),
+        };
+        let arg = find_arg(command, &key).ok_or_else(|| format!("unknown option '{}' in {}", key, name))?;
+        let value = if !arg.get_action().takes_values() {
+            match value.to_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => Value::Boolean(true),
+                "" | "0" | "false" | "no" | "off" => Value::Boolean(false),
+                _ => Value::String(value),
+            }
+        } else if matches!(arg.get_action(), clap::ArgAction::Append) {
+            Value::Array(value.split(',').filter(|v| !v.is_empty()).map(|v| Value::String(v.to_string())).collect())
+        } else {
+            Value::String(value)
+        };
+        args.extend(option_args(arg, &key, &value, &name)?);
+    }
+    Ok(args)
+}
+
+/// The options MATCHES were given, by the configuration file, the environment or the command
+/// line, in the form of the configuration file
+pub fn effective(command: &clap::Command, matches: &clap::ArgMatches) -> Table {
+    let mut table = Table::new();
+    for arg in command.get_arguments() {
+        let (Some(long), id) = (arg.get_long(), arg.get_id().as_str()) else { continue };
+        if arg.is_hide_set() || matches!(id, "config" | "no_config" | "profile")
+            || matches.value_source(id) != Some(clap::parser::ValueSource::CommandLine)
+        {
+            continue;
+        }
+        let values: Vec<Value> = matches
+            .get_raw(id)
+            .into_iter()
+            .flatten()
+            .map(|value| {
+                let value = value.to_string_lossy();
+                value.parse().map_or_else(|_| Value::String(value.into_owned()), Value::Integer)
+            })
+            .collect();
+        let value = if !arg.get_action().takes_values() {
+            Value::Boolean(true)
+        } else if matches!(arg.get_action(), clap::ArgAction::Append) {
+            Value::Array(values)
+        } else {
+            match values.into_iter().next() {
+                Some(value) => value,
+                // Given alone, such as --by-dir
+                None => Value::Boolean(true),
+            }
+        };
+        table.insert(long.to_string(), value);
+    }
+    table
+}
+
+// The option of COMMAND with the long name KEY, with _ for -
+fn find_arg<'a>(command: &'a clap::Command, key: &str) -> Option<&'a clap::Arg> {
+    let long = key.replace('_', "-");
+    command.get_arguments().find(|arg| arg.get_long() == Some(&long))
+}
+
+// The command-line options VALUE gives to ARG, from the key KEY of ORIGIN
+fn option_args(arg: &clap::Arg, key: &str, value: &Value, origin: &str) -> Result<Vec<String>, String> {
+    let long = arg.get_long().unwrap_or(key);
+    let takes_value = arg.get_action().takes_values();
+    // Options such as --by-dir[=DEPTH] can also be given alone
+    let value_optional = arg.get_num_args().is_some_and(|n| n.min_values() == 0);
+    let values = match value {
+        Value::Array(values) => values.iter().collect(),
+        value => vec![value],
+    };
+    let mut args = Vec::new();
+    for value in values {
+        let value = match value {
+            Value::Boolean(true) if !takes_value || value_optional => {
+                args.push(format!("--{}", long));
+                continue;
+            }
+            Value::Boolean(false) if !takes_value => continue,
+            Value::String(s) if takes_value => s.clone(),
+            Value::Integer(_) | Value::Float(_) | Value::Boolean(_) if takes_value => value.to_string(),
+            _ => {
+                let expected = if takes_value { "a string or a number" } else { "true or false" };
+                return Err(format!("'{}' in {} must be {}", key, origin, expected));
+            }
+        };
+        args.push(format!("--{}={}", long, value));
+    }
+    Ok(args)
+}
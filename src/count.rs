@@ -0,0 +1,678 @@
+//! Counting the lines, words, characters and bytes of one file
+
+use std::borrow::Cow;
+#[cfg(feature = "fs")]
+use std::fs::File;
+use std::io::{self, BufRead};
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+#[cfg(feature = "fs")]
+use crate::cache::CacheEntry;
+use crate::{
+    CommentSyntax, LineInfo, LiterateStyle, Metric, builtin_comment_syntax, infer_comment_syntax, literate_style,
+};
+#[cfg(feature = "fs")]
+use crate::{Language, detect_language_from_prefix};
+
+#[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Stats {
+    pub actual_loc: usize,
+    pub raw_loc: usize,
+    /// Lines that are neither code nor blank
+    pub comments: usize,
+    pub words: usize,
+    pub chars: usize,
+    pub bytes: usize,
+    /// Number of files counted
+    pub files: usize,
+}
+
+/// Statistics per language
+pub type LangStats = std::collections::HashMap<String, Stats>;
+
+pub fn add_stats(a: Stats, b: Stats) -> Stats {
+    Stats {
+        actual_loc: a.actual_loc + b.actual_loc,
+        raw_loc: a.raw_loc + b.raw_loc,
+        comments: a.comments + b.comments,
+        words: a.words + b.words,
+        chars: a.chars + b.chars,
+        bytes: a.bytes + b.bytes,
+        files: a.files + b.files,
+    }
+}
+
+/// The counts of A less those of B, each at least 0
+pub fn sub_stats(a: Stats, b: Stats) -> Stats {
+    Stats {
+        actual_loc: a.actual_loc.saturating_sub(b.actual_loc),
+        raw_loc: a.raw_loc.saturating_sub(b.raw_loc),
+        comments: a.comments.saturating_sub(b.comments),
+        words: a.words.saturating_sub(b.words),
+        chars: a.chars.saturating_sub(b.chars),
+        bytes: a.bytes.saturating_sub(b.bytes),
+        files: a.files.saturating_sub(b.files),
+    }
+}
+
+/// 64-bit FNV-1a hash of file content, stable across builds for --cache
+pub(crate) struct ContentHash(pub(crate) u64);
+
+impl Default for ContentHash {
+    fn default() -> Self {
+        ContentHash(0xcbf29ce484222325)
+    }
+}
+
+impl ContentHash {
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    #[cfg(feature = "fs")]
+    pub(crate) fn of_file(path: &Path) -> io::Result<u64> {
+        let mut reader = io::BufReader::with_capacity(PREFIX_SIZE, File::open(path)?);
+        let mut hash = ContentHash::default();
+        loop {
+            let chunk = reader.fill_buf()?;
+            if chunk.is_empty() {
+                return Ok(hash.0);
+            }
+            hash.update(chunk);
+            let n = chunk.len();
+            reader.consume(n);
+        }
+    }
+}
+
+/// Bytes read ahead for language, binary and comment syntax detection
+#[cfg(feature = "fs")]
+const PREFIX_SIZE: usize = 64 * 1024;
+
+/// A file opened for counting, with its beginning buffered for detection
+#[cfg(feature = "fs")]
+pub struct SourceFile {
+    /// `None` when the counts come from the --cache
+    reader: Option<io::BufReader<File>>,
+    /// Language detected from the shebang or the extension
+    pub lang: Language,
+    /// Whether the first 8KB contain null bytes
+    binary: bool,
+    /// Counts known without reading the file
+    pub(crate) cached: Option<Stats>,
+}
+
+#[cfg(feature = "fs")]
+impl SourceFile {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut reader = io::BufReader::with_capacity(PREFIX_SIZE, File::open(path)?);
+        reader.fill_buf()?;
+        let prefix = reader.buffer();
+        let lang = detect_language_from_prefix(path, prefix);
+        let binary = looks_binary(prefix);
+        Ok(SourceFile {
+            reader: Some(reader),
+            lang,
+            binary,
+            cached: None,
+        })
+    }
+
+    pub(crate) fn from_cache(entry: &CacheEntry) -> Self {
+        SourceFile {
+            reader: None,
+            lang: Language::named(&entry.lang),
+            binary: entry.binary,
+            cached: Some(entry.stats.clone()),
+        }
+    }
+
+    /// The buffered beginning of the file
+    pub fn prefix(&self) -> &[u8] {
+        self.reader.as_ref().map_or(&[], |reader| reader.buffer())
+    }
+
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+
+    /// Count the whole file, feeding its content to `hash` if given, and setting `invalid`
+    /// to the number of the first line that is not valid UTF-8; large files (or all with
+    /// `use_mmap`) are memory-mapped, and lines longer than `line_cap` bytes are counted
+    /// piece by piece
+    pub(crate) fn count(
+        self,
+        use_mmap: bool,
+        line_cap: usize,
+        hash: Option<&mut ContentHash>,
+        metrics: &mut [Box<dyn Metric>],
+        invalid: &mut Option<usize>,
+    ) -> io::Result<Stats> {
+        if let Some(stats) = self.cached {
+            return Ok(stats);
+        }
+        let mut stats = Stats::default();
+        let syntax = builtin_comment_syntax(&self.lang)
+            .unwrap_or_else(|| infer_comment_syntax(self.prefix()).syntax);
+        let mut classifier = LineClassifier::new(&self.lang, syntax);
+        let Some(mut reader) = self.reader else {
+            return Ok(stats);
+        };
+        let cap = line_cap.max(1);
+        let size = reader.get_ref().metadata().map_or(0, |m| m.len());
+        if size > 0 && (use_mmap || size >= MMAP_THRESHOLD) {
+            // SAFETY: the mapping is only read, and only while the file is open; a file
+            // truncated by another process meanwhile is the caller's problem, as with wc
+            if let Ok(map) = unsafe { memmap2::Mmap::map(reader.get_ref()) } {
+                if let Some(hash) = hash {
+                    hash.update(&map);
+                }
+                for line in split_lines(&map) {
+                    let valid = if line.len() <= cap {
+                        count_line_with(&mut stats, &mut classifier, line, metrics)
+                    } else {
+                        let mut pieces = line.chunks(cap);
+                        let head = pieces.next().unwrap_or_default().to_vec();
+                        let mut long = LongLine::new(head, &classifier);
+                        for piece in pieces {
+                            long.feed(piece, &classifier);
+                        }
+                        long.finish(&mut stats, &mut classifier, metrics)
+                    };
+                    if !valid {
+                        invalid.get_or_insert(stats.raw_loc);
+                    }
+                }
+                return Ok(stats);
+            }
+        }
+        count_lines(&mut reader, &mut classifier, cap, hash, metrics, invalid)
+    }
+}
+
+// Count the lines READER yields up to its end, cutting lines longer than CAP into pieces;
+// INVALID is set to the number of the first line that is not valid UTF-8
+fn count_lines(
+    reader: &mut impl BufRead,
+    classifier: &mut LineClassifier,
+    cap: usize,
+    mut hash: Option<&mut ContentHash>,
+    metrics: &mut [Box<dyn Metric>],
+    invalid: &mut Option<usize>,
+) -> io::Result<Stats> {
+    let mut stats = Stats::default();
+    let mut buf = Vec::new();
+    let mut long: Option<LongLine> = None;
+    loop {
+        let chunk = reader.fill_buf()?;
+        if chunk.is_empty() {
+            break;
+        }
+        let (piece, line_end) = match memchr::memchr(b'\n', chunk) {
+            Some(i) => (&chunk[..=i], true),
+            None => (chunk, false),
+        };
+        if let Some(ref mut hash) = hash {
+            hash.update(piece);
+        }
+        match long {
+            Some(ref mut line) => line.feed(piece, classifier),
+            None if buf.len() + piece.len() <= cap => buf.extend_from_slice(piece),
+            None => {
+                let split = cap - buf.len();
+                buf.extend_from_slice(&piece[..split]);
+                let mut line = LongLine::new(std::mem::take(&mut buf), classifier);
+                line.feed(&piece[split..], classifier);
+                long = Some(line);
+            }
+        }
+        let n = piece.len();
+        reader.consume(n);
+        if line_end {
+            let valid = match long.take() {
+                Some(line) => line.finish(&mut stats, classifier, metrics),
+                None => count_line_with(&mut stats, classifier, &buf, metrics),
+            };
+            if !valid {
+                invalid.get_or_insert(stats.raw_loc);
+            }
+            buf.clear();
+        }
+    }
+    // The last line has no newline
+    let valid = match long {
+        Some(line) => line.finish(&mut stats, classifier, metrics),
+        None if !buf.is_empty() => count_line_with(&mut stats, classifier, &buf, metrics),
+        None => true,
+    };
+    if !valid {
+        invalid.get_or_insert(stats.raw_loc);
+    }
+    Ok(stats)
+}
+
+/// Files at least this large are memory-mapped instead of read
+#[cfg(feature = "fs")]
+const MMAP_THRESHOLD: u64 = 4 * 1024 * 1024;
+
+/// Lines longer than this are counted piece by piece unless --line-cap says otherwise
+pub const DEFAULT_LINE_CAP: usize = 1024 * 1024;
+
+/// Bytes a long line is classified by at either end, whitespace runs squeezed
+const LONG_LINE_ENDS: usize = 64;
+
+/// A line longer than the line cap, such as a minified bundle, counted piece by piece so
+/// that memory stays bounded. It is classified from a summary: its ends with whitespace
+/// runs squeezed, and the comment markers found in between.
+struct LongLine {
+    /// The first line-cap bytes, shown to metrics
+    head: Vec<u8>,
+    /// Incomplete UTF-8 sequence at the end of the last piece
+    partial: Vec<u8>,
+    /// Whether the pieces so far are valid UTF-8
+    valid: bool,
+    in_word: bool,
+    bytes: usize,
+    chars: usize,
+    words: usize,
+    /// Beginning of the squeezed line
+    start: Vec<u8>,
+    /// The squeezed line after START, only its last bytes once it is cut
+    end: Vec<u8>,
+    /// Whether bytes were cut from END
+    cut: bool,
+    /// Whether the last byte squeezed was whitespace
+    in_space: bool,
+    /// Comment markers found in the bytes cut from END
+    markers: Vec<String>,
+}
+
+impl LongLine {
+    fn new(head: Vec<u8>, classifier: &LineClassifier) -> Self {
+        let mut line = LongLine {
+            head: Vec::new(),
+            partial: Vec::new(),
+            valid: true,
+            in_word: false,
+            bytes: 0,
+            chars: 0,
+            words: 0,
+            start: Vec::new(),
+            end: Vec::new(),
+            cut: false,
+            in_space: false,
+            markers: Vec::new(),
+        };
+        line.feed(&head, classifier);
+        line.head = head;
+        line
+    }
+
+    fn feed(&mut self, piece: &[u8], classifier: &LineClassifier) {
+        self.add_text(piece);
+        for &b in piece {
+            let space = b.is_ascii_whitespace();
+            if space && self.in_space {
+                continue;
+            }
+            self.in_space = space;
+            if self.start.len() < LONG_LINE_ENDS {
+                self.start.push(b);
+            } else {
+                self.end.push(b);
+            }
+        }
+        if self.end.len() > 2 * LONG_LINE_ENDS {
+            self.cut_end(&classifier.syntax);
+        }
+    }
+
+    // Keep the last bytes of END, noting the comment markers in those cut, or cut in two
+    fn cut_end(&mut self, syntax: &CommentSyntax) {
+        let markers = [&syntax.line, &syntax.block_start, &syntax.block_end];
+        let markers = markers.into_iter().flatten().filter(|m| !m.is_empty());
+        // A marker may begin at the end of START
+        let longest = markers.clone().map(|m| m.len()).max().unwrap_or(0);
+        let overlap = if self.cut { 0 } else { longest.saturating_sub(1).min(self.start.len()) };
+        let window = [&self.start[self.start.len() - overlap..], &self.end[..]].concat();
+        for marker in markers {
+            if !self.markers.contains(marker) && window.windows(marker.len()).any(|w| w == marker.as_bytes()) {
+                self.markers.push(marker.clone());
+            }
+        }
+        self.end.drain(..self.end.len() - LONG_LINE_ENDS);
+        self.cut = true;
+    }
+
+    // Count bytes, characters and words; a character split between pieces is held back
+    fn add_text(&mut self, piece: &[u8]) {
+        self.bytes += piece.len();
+        self.chars += bytecount::num_chars(piece);
+        let mut text = std::mem::take(&mut self.partial);
+        text.extend_from_slice(piece);
+        let complete = text.len() - incomplete_utf8_tail(&text);
+        let words = String::from_utf8_lossy(&text[..complete]);
+        self.valid &= matches!(words, Cow::Borrowed(_));
+        self.add_words(&words);
+        self.partial = text[complete..].to_vec();
+    }
+
+    fn add_words(&mut self, text: &str) {
+        for c in text.chars() {
+            let space = c.is_whitespace();
+            if !space && !self.in_word {
+                self.words += 1;
+            }
+            self.in_word = !space;
+        }
+    }
+
+    // Count the line as a whole; false if it is not valid UTF-8
+    fn finish(mut self, stats: &mut Stats, classifier: &mut LineClassifier, metrics: &mut [Box<dyn Metric>]) -> bool {
+        let partial = std::mem::take(&mut self.partial);
+        self.add_words(&String::from_utf8_lossy(&partial));
+        stats.raw_loc += 1;
+        stats.bytes += self.bytes;
+        stats.chars += self.chars;
+        stats.words += self.words;
+        // Classified like the whole line would be: whitespace runs do not matter, nor where
+        // in the middle a comment marker occurs
+        let mut summary = std::mem::take(&mut self.start);
+        if self.cut {
+            for marker in &self.markers {
+                summary.push(b' ');
+                summary.extend_from_slice(marker.as_bytes());
+            }
+            summary.push(b' ');
+        }
+        summary.extend_from_slice(&self.end);
+        let summary = String::from_utf8_lossy(&summary);
+        tally(stats, classifier, &summary, &String::from_utf8_lossy(&self.head), metrics);
+        self.valid && partial.is_empty()
+    }
+}
+
+// Length of an incomplete UTF-8 sequence at the end of BUF
+fn incomplete_utf8_tail(buf: &[u8]) -> usize {
+    for i in 1..=buf.len().min(3) {
+        let b = buf[buf.len() - i];
+        if b & 0xC0 == 0x80 {
+            continue;
+        }
+        let len = match b {
+            0xF0.. => 4,
+            0xE0.. => 3,
+            0xC0.. => 2,
+            _ => 1,
+        };
+        return if len > i { i } else { 0 };
+    }
+    0
+}
+
+pub fn count_line(stats: &mut Stats, classifier: &mut LineClassifier, buf: &[u8]) {
+    count_line_with(stats, classifier, buf, &mut []);
+}
+
+// Count the line in BUF; false if it is not valid UTF-8
+fn count_line_with(
+    stats: &mut Stats,
+    classifier: &mut LineClassifier,
+    buf: &[u8],
+    metrics: &mut [Box<dyn Metric>],
+) -> bool {
+    // Borrowed unless the line is not valid UTF-8
+    let line = String::from_utf8_lossy(buf);
+    stats.raw_loc += 1;
+    stats.bytes += buf.len();
+    // Every character starts with a byte that is not a continuation byte
+    stats.chars += bytecount::num_chars(buf);
+    stats.words += if buf.is_ascii() {
+        count_ascii_words(buf)
+    } else {
+        line.split_whitespace().count()
+    };
+    tally(stats, classifier, &line, &line, metrics);
+    matches!(line, Cow::Borrowed(_))
+}
+
+// Count LINE, already counted in raw_loc, as code or comment, and show it to METRICS as SHOWN
+fn tally(stats: &mut Stats, classifier: &mut LineClassifier, line: &str, shown: &str, metrics: &mut [Box<dyn Metric>]) {
+    let code = if metrics.is_empty() {
+        classifier.is_code(line)
+    } else {
+        // Telling mixed lines apart costs a little, only metrics need it
+        let kind = classifier.classify(line);
+        let info = LineInfo { number: stats.raw_loc, text: shown, kind };
+        for metric in metrics.iter_mut() {
+            metric.observe_line(&info);
+        }
+        matches!(kind, LineKind::Code | LineKind::Mixed)
+    };
+    if code {
+        stats.actual_loc += 1;
+    } else if !line.trim().is_empty() {
+        stats.comments += 1;
+    }
+}
+
+// Count words as the non-whitespace bytes that follow whitespace or start the line; written
+// without branches so that it compiles to vector instructions
+fn count_ascii_words(buf: &[u8]) -> usize {
+    let space = |b: u8| b == b' ' || (b'\t'..=b'\r').contains(&b);
+    let first = buf.first().is_some_and(|&b| !space(b)) as usize;
+    first
+        + buf
+            .iter()
+            .zip(&buf[buf.len().min(1)..])
+            .map(|(&prev, &b)| (space(prev) & !space(b)) as usize)
+            .sum::<usize>()
+}
+
+// Lines of BUF including their newlines, found with memchr
+pub fn split_lines(buf: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut rest = buf;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let end = memchr::memchr(b'\n', rest).map_or(rest.len(), |i| i + 1);
+        let (line, tail) = rest.split_at(end);
+        rest = tail;
+        Some(line)
+    })
+}
+
+// Whether CONTENT has a null byte in its first 8KB
+pub fn looks_binary(content: &[u8]) -> bool {
+    memchr::memchr(0, &content[..content.len().min(8192)]).is_some()
+}
+
+/// Count what `reader` yields as a file of language `lang`, without a file system: an
+/// in-memory buffer, a git blob or a network stream. Languages without a built-in comment
+/// syntax have it inferred from the beginning, as much as `reader` buffers at once.
+pub fn count_reader(lang: &str, mut reader: impl BufRead) -> io::Result<Stats> {
+    let mut classifier = LineClassifier::for_reader(lang, &mut reader)?;
+    let stats = count_lines(&mut reader, &mut classifier, DEFAULT_LINE_CAP, None, &mut [], &mut None)?;
+    Ok(Stats { files: 1, ..stats })
+}
+
+/// Count `text` as a file of language `lang`
+pub fn count_str(lang: &str, text: &str) -> Stats {
+    // Reading a slice cannot fail
+    count_reader(lang, text.as_bytes()).unwrap_or_default()
+}
+
+/// Each line of what `reader` yields, numbered from 1, with what it is counted as in a
+/// file of language `lang`; stops at the first read error
+pub fn classify_lines(lang: &str, mut reader: impl BufRead) -> impl Iterator<Item = (usize, LineKind)> {
+    let mut classifier = LineClassifier::for_reader(lang, &mut reader).ok();
+    let mut line = Vec::new();
+    let mut n = 0;
+    std::iter::from_fn(move || {
+        let classifier = classifier.as_mut()?;
+        line.clear();
+        if reader.read_until(b'\n', &mut line).ok()? == 0 {
+            return None;
+        }
+        n += 1;
+        Some((n, classifier.classify(&String::from_utf8_lossy(&line))))
+    })
+}
+
+#[cfg(feature = "fs")]
+pub fn process_file(path: &Path) -> Stats {
+    SourceFile::open(path)
+        .and_then(|file| file.count(false, DEFAULT_LINE_CAP, None, &mut [], &mut None))
+        .unwrap_or_default()
+}
+
+/// What a line is counted as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LineKind {
+    Code,
+    /// A comment, or prose in a literate source
+    Comment,
+    Blank,
+    /// Code that also holds a comment, such as a trailing `// note`; counted as code
+    Mixed,
+}
+
+/// Tells actual code lines from blank, comment and prose lines, one line at a time
+pub struct LineClassifier {
+    pub(crate) syntax: CommentSyntax,
+    pub(crate) literate: Option<LiterateStyle>,
+    pub(crate) jsx: bool,
+    in_block_comment: bool,
+    in_code: bool,
+}
+
+impl LineClassifier {
+    pub fn new(lang: &str, syntax: CommentSyntax) -> Self {
+        LineClassifier {
+            syntax,
+            literate: literate_style(lang),
+            jsx: lang == "javascript" || lang == "typescript",
+            in_block_comment: false,
+            in_code: false,
+        }
+    }
+
+    // A classifier for what READER yields, inferring the comment syntax from its beginning
+    // when LANG has no built-in one
+    pub(crate) fn for_reader(lang: &str, reader: &mut impl BufRead) -> io::Result<Self> {
+        let syntax = match builtin_comment_syntax(lang) {
+            Some(syntax) => syntax,
+            None => infer_comment_syntax(reader.fill_buf()?).syntax,
+        };
+        Ok(LineClassifier::new(lang, syntax))
+    }
+
+    /// Like `is_code`, telling comments from blank lines, and code with a comment from
+    /// code without one. A comment marker within a string literal makes a line Mixed too.
+    pub fn classify(&mut self, line: &str) -> LineKind {
+        if !self.is_code(line) {
+            return if line.trim().is_empty() { LineKind::Blank } else { LineKind::Comment };
+        }
+        let markers = [self.syntax.line.as_deref(), self.syntax.block_start.as_deref()];
+        if self.literate.is_none() && markers.into_iter().flatten().any(|m| !m.is_empty() && line.contains(m)) {
+            LineKind::Mixed
+        } else {
+            LineKind::Code
+        }
+    }
+
+    pub fn is_code(&mut self, line: &str) -> bool {
+        let mut trimmed = line.trim();
+        // JSX comments are written as {/* ... */}
+        if self.jsx && trimmed.starts_with("{/*") {
+            trimmed = &trimmed[1..];
+        }
+        let is_empty = trimmed.is_empty();
+        let is_comment = match self.literate {
+            Some(style) => !is_literate_code(line, style, &mut self.in_code),
+            None => is_pure_comment(trimmed, &self.syntax, &mut self.in_block_comment),
+        };
+        !is_empty && !is_comment
+    }
+}
+
+fn is_pure_comment(line: &str, syntax: &CommentSyntax, in_block_comment: &mut bool) -> bool {
+    if *in_block_comment {
+        if let Some(ref end) = syntax.block_end
+            && line.contains(end)
+        {
+            *in_block_comment = false;
+        }
+        return true;
+    }
+    if let Some(ref start) = syntax.block_start
+        && line.starts_with(start)
+    {
+        // A block closed on the same line does not swallow the following lines
+        *in_block_comment = !syntax
+            .block_end
+            .as_ref()
+            .is_some_and(|end| line[start.len()..].contains(end.as_str()));
+        return true;
+    }
+    if let Some(ref line_comment) = syntax.line
+        && line.starts_with(line_comment)
+    {
+        return true;
+    }
+    false
+}
+
+// Literate sources are prose by default; only embedded code counts as actual LOC
+fn is_literate_code(line: &str, style: LiterateStyle, in_code: &mut bool) -> bool {
+    let trimmed = line.trim();
+    match style {
+        LiterateStyle::Haskell => {
+            if *in_code {
+                if trimmed.starts_with("\\end{code}") {
+                    *in_code = false;
+                    return false;
+                }
+                return true;
+            }
+            if trimmed.starts_with("\\begin{code}") {
+                *in_code = true;
+                return false;
+            }
+            // Bird tracks must start in the first column
+            line.starts_with('>') && !line[1..].trim().is_empty()
+        }
+        LiterateStyle::Org => {
+            let upper = trimmed.to_ascii_uppercase();
+            if *in_code {
+                if upper.starts_with("#+END_SRC") {
+                    *in_code = false;
+                    return false;
+                }
+                return true;
+            }
+            if upper.starts_with("#+BEGIN_SRC") {
+                *in_code = true;
+            }
+            false
+        }
+        LiterateStyle::Noweb => {
+            if line.starts_with("<<") && trimmed.ends_with(">>=") {
+                *in_code = true;
+                return false;
+            }
+            let line = line.trim_end();
+            if line == "@" || line.starts_with("@ ") {
+                *in_code = false;
+                return false;
+            }
+            *in_code
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! sourcelines daemon: counting trees again and again, and appending each snapshot to a sink
+//! for trend dashboards. Sinks are
+//!
+//!   sqlite:FILE   rows of the table snapshots, written by the sqlite3 program:
+//!                 (time, path, language, files, actual_loc, raw_loc, comments, words, chars, bytes)
+//!   jsonl:FILE    one line per path and snapshot:
+//!                 {"time":1700000000,"path":"src","total":{...},"languages":{"rust":{...}}}
+//!
+//! Times are in seconds since the Unix epoch. Files unchanged since the last snapshot are not
+//! read again.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde_json::{Value, json};
+use sourcelines::{CountCache, DirSummary, Options, Stats, count_path};
+
+use crate::serve::stats_json;
+
+/// Where snapshots are appended
+#[derive(Clone, Debug)]
+pub enum Sink {
+    Sqlite(PathBuf),
+    Jsonl(PathBuf),
+}
+
+impl Sink {
+    pub fn parse(s: &str) -> Result<Sink, String> {
+        match s.split_once(':') {
+            Some(("sqlite", file)) if !file.is_empty() => Ok(Sink::Sqlite(PathBuf::from(file))),
+            Some(("jsonl", file)) if !file.is_empty() => Ok(Sink::Jsonl(PathBuf::from(file))),
+            _ => Err(format!("invalid sink '{}', expected sqlite:FILE or jsonl:FILE", s)),
+        }
+    }
+
+    /// Make the sink ready to be appended to, so that a sink that cannot be written fails at
+    /// once rather than at the first snapshot
+    fn open(&self) -> io::Result<()> {
+        match self {
+            Sink::Sqlite(file) => sqlite(
+                file,
+                "CREATE TABLE IF NOT EXISTS snapshots (time INTEGER NOT NULL, path TEXT NOT NULL, \
+                 language TEXT NOT NULL, files INTEGER NOT NULL, actual_loc INTEGER NOT NULL, \
+                 raw_loc INTEGER NOT NULL, comments INTEGER NOT NULL, words INTEGER NOT NULL, \
+                 chars INTEGER NOT NULL, bytes INTEGER NOT NULL);\n",
+            ),
+            Sink::Jsonl(file) => OpenOptions::new().create(true).append(true).open(file).map(drop),
+        }
+    }
+
+    /// Append the snapshot taken at TIME of each of its paths
+    fn append(&self, time: u64, snapshot: &[(&Path, DirSummary)]) -> io::Result<()> {
+        match self {
+            Sink::Sqlite(file) => {
+                let mut sql = String::from("BEGIN;\n");
+                for (path, summary) in snapshot {
+                    let mut langs: Vec<(&String, &Stats)> = summary.langs.iter().collect();
+                    langs.sort_by_key(|(lang, _)| *lang);
+                    for (lang, s) in langs {
+                        sql.push_str(&format!(
+                            "INSERT INTO snapshots VALUES ({}, {}, {}, {}, {}, {}, {}, {}, {}, {});\n",
+                            time,
+                            sql_string(&path.to_string_lossy()),
+                            sql_string(lang),
+                            s.files,
+                            s.actual_loc,
+                            s.raw_loc,
+                            s.comments,
+                            s.words,
+                            s.chars,
+                            s.bytes
+                        ));
+                    }
+                }
+                sql.push_str("COMMIT;\n");
+                sqlite(file, &sql)
+            }
+            Sink::Jsonl(file) => {
+                let mut lines = String::new();
+                for (path, summary) in snapshot {
+                    let mut langs: Vec<(&String, &Stats)> = summary.langs.iter().collect();
+                    langs.sort_by_key(|(lang, _)| *lang);
+                    let languages: serde_json::Map<String, Value> =
+                        langs.into_iter().map(|(lang, stats)| (lang.clone(), stats_json(stats))).collect();
+                    let line = json!({
+                        "time": time,
+                        "path": path.to_string_lossy(),
+                        "total": stats_json(&summary.total),
+                        "languages": languages,
+                    });
+                    lines.push_str(&format!("{}\n", line));
+                }
+                OpenOptions::new().create(true).append(true).open(file)?.write_all(lines.as_bytes())
+            }
+        }
+    }
+}
+
+/// Take a snapshot of PATHS every EVERY and append it to SINK, SNAPSHOTS times or until the
+/// process is stopped. With CACHE, the counts of the files are written to its file after each
+/// snapshot. Paths that cannot be counted are left out of the snapshot
+pub fn run(
+    every: Duration,
+    snapshots: Option<usize>,
+    paths: &[PathBuf],
+    sink: &Sink,
+    opts: &Options,
+    cache: Option<(&str, &Mutex<CountCache>)>,
+) -> io::Result<()> {
+    sink.open()?;
+    // Snapshots are taken at multiples of EVERY from the start, however long counting takes
+    let mut next = Instant::now();
+    let mut taken = 0;
+    while snapshots.is_none_or(|n| taken < n) {
+        let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut snapshot = Vec::new();
+        for path in paths {
+            match count_path(path, opts) {
+                Ok(summary) => snapshot.push((path.as_path(), summary)),
+                Err(e) => eprintln!("sourcelines: could not count {}: {}", path.display(), e),
+            }
+        }
+        if let Err(e) = sink.append(time, &snapshot) {
+            eprintln!("sourcelines: could not append the snapshot: {}", e);
+        }
+        if let Some((file, cache)) = cache
+            && let Err(e) = cache.lock().unwrap().save(file)
+        {
+            eprintln!("sourcelines: could not write cache {}: {}", file, e);
+        }
+        taken += 1;
+        if snapshots.is_none_or(|n| taken < n) {
+            // An interval too long to be added to the clock is never over
+            let Some(later) = next.checked_add(every) else {
+                loop {
+                    std::thread::park();
+                }
+            };
+            next = later;
+            std::thread::sleep(next.saturating_duration_since(Instant::now()));
+        }
+    }
+    Ok(())
+}
+
+/// Parse an interval such as 500ms, 90s, 15m, 1h or 1d; a plain number is in seconds
+pub fn parse_every(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (digits, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => (&s[..i], &s[i..]),
+        None => (s, "s"),
+    };
+    let invalid = || format!("invalid interval '{}'", s);
+    let n: u64 = digits.parse().map_err(|_| invalid())?;
+    let every = match unit {
+        "ms" => Duration::from_millis(n),
+        "s" => Duration::from_secs(n),
+        "m" => Duration::from_secs(n.checked_mul(60).ok_or_else(invalid)?),
+        "h" => Duration::from_secs(n.checked_mul(60 * 60).ok_or_else(invalid)?),
+        "d" => Duration::from_secs(n.checked_mul(24 * 60 * 60).ok_or_else(invalid)?),
+        _ => return Err(invalid()),
+    };
+    if every.is_zero() {
+        return Err(format!("invalid interval '{}', it must not be 0", s));
+    }
+    Ok(every)
+}
+
+// Run the sqlite3 program on FILE with SQL on its stdin
+fn sqlite(file: &Path, sql: &str) -> io::Result<()> {
+    let mut child = Command::new("sqlite3")
+        .arg("-bail")
+        .arg("--")
+        .arg(file)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("could not run sqlite3: {}", e)))?;
+    // With -bail, sqlite3 stops reading at the first failed statement: its message is then
+    // the one to report, not the broken pipe
+    let written = child.stdin.take().expect("piped stdin").write_all(sql.as_bytes());
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        let message = message.trim();
+        return Err(io::Error::other(message.strip_prefix("Error: ").unwrap_or(message).to_string()));
+    }
+    written
+}
+
+fn sql_string(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
@@ -0,0 +1,49 @@
+//! Why a file or directory could not be counted
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The file or directory could not be read
+    Io { path: PathBuf, source: io::Error },
+    /// The file is not valid UTF-8; `line` is the first line that is not
+    Decode { path: PathBuf, line: usize },
+    /// The file looks binary, or has one of the binary extensions, and was not counted
+    Binary { path: PathBuf },
+    /// The walk of `path` was stopped with `Options::cancel`
+    Cancelled { path: PathBuf },
+}
+
+impl Error {
+    /// The file or directory the error is about
+    pub fn path(&self) -> &PathBuf {
+        match self {
+            Error::Io { path, .. }
+            | Error::Decode { path, .. }
+            | Error::Binary { path }
+            | Error::Cancelled { path } => path,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Decode { path, line } => write!(f, "{}: invalid UTF-8 on line {}", path.display(), line),
+            Error::Binary { path } => write!(f, "{}: binary file", path.display()),
+            Error::Cancelled { path } => write!(f, "{}: cancelled", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Ignore files in gitignore syntax
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use globset::Glob;
+
+#[derive(Debug, Clone)]
+struct IgnorePattern {
+    pattern: String,
+    is_negation: bool, // Patterns starting with ! negate previous ignores
+    is_dir_only: bool, // Patterns ending with / match only directories
+}
+
+/// Per-directory ignore files, in gitignore syntax
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".sourcelinesignore"];
+
+#[derive(Debug)]
+pub struct DirObject {
+    path: PathBuf,
+    ignore_patterns: Vec<IgnorePattern>,
+    parent: Option<Arc<DirObject>>,
+}
+
+impl DirObject {
+    pub(crate) fn new(path: PathBuf, parent: Option<Arc<DirObject>>) -> Self {
+        DirObject {
+            path: normalize_path(&path),
+            ignore_patterns: Vec::new(),
+            parent,
+        }
+    }
+
+    fn load_ignore_file(&mut self, ignore_file_name: &str) {
+        let ignore_path = self.path.join(ignore_file_name);
+        if let Ok(content) = fs::read_to_string(&ignore_path) {
+            for line in content.lines() {
+                let line = line.trim();
+                // Skip empty lines and comments
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                
+                let is_negation = line.starts_with('!');
+                let pattern_str = if is_negation { &line[1..] } else { line };
+                let pattern_str = pattern_str.trim();
+                if pattern_str.is_empty() {
+                    continue;
+                }
+                
+                let is_dir_only = pattern_str.ends_with('/');
+                let pattern = if is_dir_only {
+                    pattern_str[..pattern_str.len() - 1].to_string()
+                } else {
+                    pattern_str.to_string()
+                };
+                
+                self.ignore_patterns.push(IgnorePattern {
+                    pattern,
+                    is_negation,
+                    is_dir_only,
+                });
+            }
+        }
+    }
+
+    /// Load every per-directory ignore file; later files take precedence
+    pub(crate) fn load_ignore_files(&mut self) {
+        for name in IGNORE_FILES {
+            self.load_ignore_file(name);
+        }
+    }
+
+    pub fn include_test(&self, file_path: &Path, is_dir: bool) -> bool {
+        // Get relative path from this directory
+        let file_path = normalize_path(file_path);
+        let rel_path = match file_path.strip_prefix(&self.path) {
+            Ok(p) => p,
+            Err(_) => return true, // If we can't get relative path, include it
+        };
+        
+        // Convert to string for pattern matching
+        let path_str = rel_path.to_string_lossy();
+        let path_str = path_str.replace('\\', "/"); // Normalize path separators
+        
+        // Check patterns in order (last match wins)
+        let mut included = true;
+        let mut matched = false;
+        for pattern in &self.ignore_patterns {
+            if pattern.is_dir_only && !is_dir {
+                continue;
+            }
+            
+            if matches_pattern(&pattern.pattern, &path_str, is_dir) {
+                matched = true;
+                included = pattern.is_negation;
+            }
+        }
+        
+        // If not matched in this directory, check parent
+        if !matched
+            && let Some(ref parent) = self.parent
+        {
+            return parent.include_test(&file_path, is_dir);
+        }
+        
+        included
+    }
+}
+
+/// Build the ignore rules that apply above `scan_root`: `.git/info/exclude` and
+/// the per-directory ignore files from the enclosing repository root down to the
+/// parent of `scan_root`. The scan root's own files are loaded by the walk itself.
+pub fn ignore_chain(scan_root: &Path) -> Arc<DirObject> {
+    let root = normalize_path(scan_root);
+    let Some(repo) = root.ancestors().find(|a| a.join(".git").exists()) else {
+        return Arc::new(DirObject::new(root, None));
+    };
+    let mut repo_obj = DirObject::new(repo.to_path_buf(), None);
+    repo_obj.load_ignore_file(".git/info/exclude");
+    let mut chain = Arc::new(repo_obj);
+    let mut dirs: Vec<&Path> = root.ancestors().skip(1).take_while(|a| a.starts_with(repo)).collect();
+    dirs.reverse();
+    for dir in dirs {
+        let mut dir_obj = DirObject::new(dir.to_path_buf(), Some(chain));
+        dir_obj.load_ignore_files();
+        chain = Arc::new(dir_obj);
+    }
+    chain
+}
+
+// Make a path absolute and resolve `.` and `..` lexically, so paths given on
+// the command line and paths found while walking compare equal
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let abs = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut out = PathBuf::new();
+    for comp in abs.components() {
+        match comp {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                out.pop();
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn matches_pattern(pattern: &str, path: &str, _is_dir: bool) -> bool {
+    // Handle simple patterns
+    if pattern == "*" {
+        return true;
+    }
+    
+    // Convert gitignore pattern to glob pattern
+    let mut glob_pattern = pattern.to_string();
+    
+    // If pattern doesn't start with /, it can match anywhere
+    if !glob_pattern.starts_with('/') {
+        glob_pattern = format!("**/{}", glob_pattern);
+    } else {
+        glob_pattern = glob_pattern[1..].to_string();
+    }
+    
+    // Handle ** patterns
+    glob_pattern = glob_pattern.replace("**/", "**");
+    glob_pattern = glob_pattern.replace("/**", "**");
+    
+    // Try to match using glob
+    if let Ok(glob) = Glob::new(&glob_pattern)
+        && glob.compile_matcher().is_match(path)
+    {
+        return true;
+    }
+    
+    // Fallback to simple string matching for common cases
+    if pattern.contains('*') {
+        // Simple wildcard matching
+        let parts: Vec<&str> = pattern.split('*').collect();
+        if parts.len() == 2 {
+            return path.starts_with(parts[0]) && path.ends_with(parts[1]);
+        }
+    } else if pattern == path || path.ends_with(&format!("/{}", pattern)) {
+        return true;
+    }
+    
+    false
+}
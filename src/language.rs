@@ -0,0 +1,154 @@
+//! Language identifiers, as detected for files and as given on the command line
+
+use std::borrow::{Borrow, Cow};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::OnceLock;
+
+/// Known languages: name, display name and other names they go by
+const KNOWN_LANGUAGES: &[(&str, &str, &[&str])] = &[
+    ("awk", "AWK", &["gawk"]),
+    ("batch", "Batch", &["bat", "cmd"]),
+    ("c", "C", &[]),
+    ("config", "Config", &["conf", "ini"]),
+    ("cpp", "C++", &["c++", "cxx"]),
+    ("css", "CSS", &["scss"]),
+    ("go", "Go", &["golang"]),
+    ("haskell", "Haskell", &["hs"]),
+    ("html", "HTML", &["htm"]),
+    ("java", "Java", &[]),
+    ("javascript", "JavaScript", &["js", "node"]),
+    ("jsp", "JSP", &[]),
+    ("kotlin", "Kotlin", &["kt"]),
+    ("literate-haskell", "Literate Haskell", &["lhs"]),
+    ("lua", "Lua", &[]),
+    ("noweb", "noweb", &["nw"]),
+    ("org", "Org", &["org-mode"]),
+    ("perl", "Perl", &["pl"]),
+    ("php", "PHP", &[]),
+    ("python", "Python", &["py", "python3"]),
+    ("ruby", "Ruby", &["rb"]),
+    ("rust", "Rust", &["rs"]),
+    ("scala", "Scala", &[]),
+    ("shell", "Shell", &["sh", "bash", "zsh"]),
+    ("sql", "SQL", &[]),
+    ("tcl", "Tcl", &[]),
+    ("tex", "TeX", &["latex"]),
+    ("text", "Plain text", &["txt"]),
+    ("typescript", "TypeScript", &["ts"]),
+    ("unknown", "Unknown", &[]),
+    ("vala", "Vala", &[]),
+    ("vb", "Visual Basic", &["vba", "visual-basic"]),
+    ("xml", "XML", &[]),
+    ("yaml", "YAML", &["yml"]),
+];
+
+type KnownLanguage = (&'static str, &'static str, &'static [&'static str]);
+
+// Known languages by name and by alias, built on first use; names win over aliases
+fn registry() -> &'static HashMap<&'static str, &'static KnownLanguage> {
+    static REGISTRY: OnceLock<HashMap<&'static str, &'static KnownLanguage>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let aliases = KNOWN_LANGUAGES.iter().flat_map(|known| known.2.iter().map(move |alias| (*alias, known)));
+        aliases.chain(KNOWN_LANGUAGES.iter().map(|known| (known.0, known))).collect()
+    })
+}
+
+/// The language a file is counted under: one of the known languages, which costs no
+/// allocation, or any other name, such as the extension of a file of an unknown kind.
+/// It dereferences to its name, so it can be used wherever a `&str` is expected.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct Language(Cow<'static, str>);
+
+impl Language {
+    /// The language called `name` or one of its aliases, e.g. "py" for python
+    pub fn from_name(name: &str) -> Language {
+        match registry().get(name) {
+            Some((id, _, _)) => Language(Cow::Borrowed(id)),
+            None => Language(Cow::Owned(name.to_string())),
+        }
+    }
+
+    // A language of the detection tables
+    pub(crate) fn from_static(name: &'static str) -> Language {
+        Language(Cow::Borrowed(name))
+    }
+
+    // The language called exactly NAME, without looking at aliases
+    pub(crate) fn named(name: &str) -> Language {
+        match registry().get(name).filter(|(id, _, _)| *id == name) {
+            Some((id, _, _)) => Language(Cow::Borrowed(id)),
+            None => Language(Cow::Owned(name.to_string())),
+        }
+    }
+
+    /// The name counts are reported under, e.g. "cpp"
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+
+    /// The name for people, e.g. "C++"; the name itself for languages that are not known
+    pub fn display_name(&self) -> &str {
+        self.known().map_or(self.name(), |(_, display, _)| display)
+    }
+
+    /// Other names `from_name` accepts for the language
+    pub fn aliases(&self) -> &'static [&'static str] {
+        self.known().map_or(&[], |(_, _, aliases)| aliases)
+    }
+
+    /// Whether the language is one sourcelines knows, rather than an unknown extension
+    pub fn is_known(&self) -> bool {
+        self.known().is_some()
+    }
+
+    fn known(&self) -> Option<&'static KnownLanguage> {
+        registry().get(self.name()).copied().filter(|(id, _, _)| *id == self.name())
+    }
+}
+
+impl Deref for Language {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Language {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Language {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&self.0)
+    }
+}
+
+impl PartialEq<str> for Language {
+    fn eq(&self, other: &str) -> bool {
+        *self.0 == *other
+    }
+}
+
+impl PartialEq<&str> for Language {
+    fn eq(&self, other: &&str) -> bool {
+        *self.0 == **other
+    }
+}
+
+impl From<Language> for String {
+    fn from(lang: Language) -> String {
+        lang.0.into_owned()
+    }
+}
@@ -1,73 +1,179 @@
+use std::collections::HashMap;
+#[cfg(feature = "fs")]
 use std::fs::File;
-use std::io::{self, BufRead};
+#[cfg(feature = "fs")]
+use std::io;
+use std::io::BufRead;
 use std::path::Path;
+use std::sync::OnceLock;
 
+#[cfg(feature = "fs")]
+mod builder;
+#[cfg(feature = "fs")]
+mod cache;
+mod comments;
+mod count;
+mod error;
+#[cfg(feature = "fs")]
+mod ignore;
+mod language;
+mod metric;
+#[cfg(feature = "fs")]
+mod visit;
+#[cfg(feature = "fs")]
+pub mod walk;
+
+#[cfg(feature = "fs")]
+pub use builder::OptionsBuilder;
+#[cfg(feature = "fs")]
+pub use cache::CountCache;
+#[cfg(feature = "fs")]
+pub(crate) use count::ContentHash;
+pub use comments::{CommentSpan, extract_comments};
+pub use error::Error;
+pub use count::{
+    DEFAULT_LINE_CAP, LangStats, LineClassifier, LineKind, Stats, add_stats, classify_lines, count_line, count_reader,
+    count_str, looks_binary, split_lines, sub_stats,
+};
+#[cfg(feature = "fs")]
+pub use count::{SourceFile, process_file};
+#[cfg(feature = "fs")]
+pub use ignore::{DirObject, ignore_chain, normalize_path};
+pub use language::Language;
+pub use metric::{LineInfo, Metric, MetricFactory};
+#[cfg(feature = "fs")]
+pub use visit::Visitor;
+#[cfg(feature = "fs")]
+pub use walk::{
+    Candidate, DEFAULT_EXCLUDES, DEFAULT_TEST_PATTERNS, DirSummary, EXPLAIN_TARGET, FileCallback, FileKey, FileReport,
+    FileStats, Options, SUBMODULE_BUCKET, SeenFiles, SkipCounts, SkipReason, SubmoduleMode, VENDORED_BUCKET, Walker,
+    build_globset, count_file, count_iter, count_path, glob_escape, inference_table, remap_language,
+};
+
+// Options, and the tables and caches they hold, can be shared by the threads of a server
+#[cfg(feature = "fs")]
+const _: () = {
+    const fn shared<T: Send + Sync>() {}
+    shared::<Options>();
+    shared::<OptionsBuilder>();
+    shared::<Walker>();
+    shared::<Language>();
+    shared::<DirSummary>();
+    shared::<CountCache>();
+    shared::<Error>();
+};
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CommentSyntax {
     pub line: Option<String>,
     pub block_start: Option<String>,
     pub block_end: Option<String>,
 }
 
-pub fn detect_language(path: &Path) -> String {
-    // Try shebang first
+#[cfg(feature = "fs")]
+pub fn detect_language(path: &Path) -> Language {
+    let mut first_line = Vec::new();
     if let Ok(file) = File::open(path) {
-        let mut reader = io::BufReader::new(file);
-        let mut first_line = String::new();
-        if reader.read_line(&mut first_line).is_ok() && first_line.starts_with("#!") {
-            if first_line.contains("python") { return "python".to_string(); }
-            if first_line.contains("perl") { return "perl".to_string(); }
-            if first_line.contains("ruby") { return "ruby".to_string(); }
-            if first_line.contains("bash") { return "shell".to_string(); }
-            if first_line.contains("sh") { return "shell".to_string(); }
-            if first_line.contains("zsh") { return "shell".to_string(); }
-            if first_line.contains("node") { return "javascript".to_string(); }
-            if first_line.contains("php") { return "php".to_string(); }
-            if first_line.contains("lua") { return "lua".to_string(); }
-            if first_line.contains("awk") { return "awk".to_string(); }
-            if first_line.contains("tcl") { return "tcl".to_string(); }
+        let _ = io::BufReader::new(file).read_until(b'\n', &mut first_line);
+    }
+    detect_language_from_prefix(path, &first_line)
+}
+
+/// Words looked for in a `#!` line, in this order, and the language each one selects.
+pub const SHEBANG_LANGUAGES: &[(&str, &str)] = &[
+    ("python", "python"),
+    ("perl", "perl"),
+    ("ruby", "ruby"),
+    ("bash", "shell"),
+    ("sh", "shell"),
+    ("zsh", "shell"),
+    ("node", "javascript"),
+    ("php", "php"),
+    ("lua", "lua"),
+    ("awk", "awk"),
+    ("tcl", "tcl"),
+];
+
+/// Languages recognized by file extension; other extensions are their own language.
+pub const EXTENSION_LANGUAGES: &[(&str, &[&str])] = &[
+    ("rust", &["rs"]),
+    ("c", &["c", "h"]),
+    ("cpp", &["cpp", "cxx", "cc", "hpp", "hxx"]),
+    ("python", &["py", "python"]),
+    ("javascript", &["js", "jsx", "mjs", "cjs"]),
+    ("typescript", &["ts", "tsx", "mts", "cts"]),
+    ("java", &["java"]),
+    ("shell", &["sh", "bash", "zsh", "env"]),
+    ("css", &["css", "scss"]),
+    ("html", &["html", "htm"]),
+    ("xml", &["xml", "xsl", "xslt", "xsd", "dtd", "xq"]),
+    ("php", &["php"]),
+    ("perl", &["pl", "pm"]),
+    ("go", &["go"]),
+    ("scala", &["scala"]),
+    ("kotlin", &["kt", "kts"]),
+    ("sql", &["sql"]),
+    ("batch", &["bat"]),
+    ("vb", &["bas", "cls", "ctl", "frm"]),
+    ("jsp", &["jsp"]),
+    ("vala", &["vala"]),
+    ("tex", &["sty"]),
+    ("tcl", &["tcl"]),
+    ("text", &["txt"]),
+    ("yaml", &["yaml", "yml"]),
+    ("config", &["conf", "ini"]),
+    ("haskell", &["hs"]),
+    ("literate-haskell", &["lhs"]),
+    ("org", &["org"]),
+    ("noweb", &["nw", "noweb"]),
+];
+
+// EXTENSION_LANGUAGES by extension, built on first use
+fn extension_languages() -> &'static HashMap<&'static str, &'static str> {
+    static BY_EXTENSION: OnceLock<HashMap<&'static str, &'static str>> = OnceLock::new();
+    BY_EXTENSION.get_or_init(|| {
+        let mut map = HashMap::new();
+        for (lang, exts) in EXTENSION_LANGUAGES {
+            for ext in *exts {
+                // The first language listed for an extension wins, as with a search in order
+                map.entry(*ext).or_insert(*lang);
+            }
         }
+        map
+    })
+}
+
+/// Same as [`detect_language`], taking the shebang from `prefix`, the beginning of the file.
+pub fn detect_language_from_prefix(path: &Path, prefix: &[u8]) -> Language {
+    // Try shebang first
+    let first_line = prefix.split(|&b| b == b'\n').next().unwrap_or_default();
+    let first_line = String::from_utf8_lossy(first_line);
+    if first_line.starts_with("#!")
+        && let Some((_, lang)) = SHEBANG_LANGUAGES.iter().find(|(word, _)| first_line.contains(word))
+    {
+        return Language::from_static(lang);
     }
     // Fallback to extension
     if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-        match ext {
-            "rs" => "rust",
-            "c" | "h" => "c",
-            "cpp" | "cxx" | "cc" | "hpp" | "hxx" => "cpp",
-            "py" | "python" => "python",
-            "js" => "javascript",
-            "ts" => "typescript",
-            "java" => "java",
-            "sh" | "bash" | "zsh" | "env" => "shell",
-            "css" | "scss" => "css",
-            "html" | "htm" => "html",
-            "xml" | "xsl" | "xslt" | "xsd" | "dtd" | "xq" => "xml",
-            "php" => "php",
-            "pl" | "pm" => "perl",
-            "go" => "go",
-            "scala" => "scala",
-            "kt" | "kts" => "kotlin",
-            "sql" => "sql",
-            "bat" => "batch",
-            "bas" | "cls" | "ctl" | "frm" => "vb",
-            "jsp" => "jsp",
-            "vala" => "vala",
-            "sty" => "tex",
-            "tcl" => "tcl",
-            "txt" => "text",
-            "yaml" | "yml" => "yaml",
-            "conf" | "ini" => "config",
-            _ => ext,
-        }.to_string()
+        match extension_languages().get(ext) {
+            Some(lang) => Language::from_static(lang),
+            None => Language::named(ext),
+        }
     } else {
-        "unknown".to_string()
+        Language::from_static("unknown")
     }
 }
 
+#[cfg(feature = "fs")]
 pub fn detect_comment_syntax(lang: &str, path: &Path) -> CommentSyntax {
+    builtin_comment_syntax(lang).unwrap_or_else(|| infer_comment_syntax_from_content(path).syntax)
+}
+
+/// Comment syntax for languages with a built-in mapping, `None` if it has to be inferred.
+pub fn builtin_comment_syntax(lang: &str) -> Option<CommentSyntax> {
     // Use language name for mapping
-    match lang {
+    let syntax = match lang {
         "rust" => CommentSyntax {
             line: Some("//".into()),
             block_start: Some("/*".into()),
@@ -193,16 +299,103 @@ pub fn detect_comment_syntax(lang: &str, path: &Path) -> CommentSyntax {
             block_start: None,
             block_end: None,
         },
-        "text" => CommentSyntax {
+        "haskell" => CommentSyntax {
+            line: Some("--".into()),
+            block_start: Some("{-".into()),
+            block_end: Some("-}".into()),
+        },
+        "text" | "literate-haskell" | "org" | "noweb" => CommentSyntax {
             line: None,
             block_start: None,
             block_end: None,
         },
-        _ => infer_comment_syntax_from_content(path),
+        _ => return None,
+    };
+    Some(syntax)
+}
+
+/// How a literate source marks its embedded code; everything else is prose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LiterateStyle {
+    /// Literate Haskell: `>` bird tracks or `\begin{code}` ... `\end{code}`
+    Haskell,
+    /// Org mode: `#+BEGIN_SRC` ... `#+END_SRC`
+    Org,
+    /// noweb: `<<chunk>>=` opens a code chunk, `@` returns to documentation
+    Noweb,
+}
+
+pub fn literate_style(lang: &str) -> Option<LiterateStyle> {
+    match lang {
+        "literate-haskell" => Some(LiterateStyle::Haskell),
+        "org" => Some(LiterateStyle::Org),
+        "noweb" => Some(LiterateStyle::Noweb),
+        _ => None,
+    }
+}
+
+/// Evidence gathered for one candidate syntax during content inference.
+#[derive(Debug, Clone)]
+pub struct CandidateScore {
+    pub syntax: CommentSyntax,
+    /// Lines this candidate classifies as comments
+    pub comment_lines: usize,
+    /// Of those, lines that look like code (e.g. `#include`, trailing `;` or `{`)
+    pub code_like_lines: usize,
+    pub score: i64,
+}
+
+/// Result of inferring comment syntax from file content.
+#[derive(Debug, Clone)]
+pub struct SyntaxInference {
+    pub syntax: CommentSyntax,
+    /// 0.0 (ambiguous or no evidence) to 1.0 (no competing candidate)
+    pub confidence: f64,
+    /// Index of the winning candidate in `candidates`, if any had evidence
+    pub winner: Option<usize>,
+    /// Index of the strongest candidate that classifies the file differently
+    pub runner_up: Option<usize>,
+    pub candidates: Vec<CandidateScore>,
+    /// Non-blank lines examined
+    pub lines: usize,
+}
+
+impl CommentSyntax {
+    /// Short human-readable form, e.g. `// /* */` or `(none)`
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(ref line) = self.line {
+            parts.push(line.clone());
+        }
+        if let (Some(start), Some(end)) = (&self.block_start, &self.block_end) {
+            parts.push(format!("{} {}", start, end));
+        }
+        if parts.is_empty() {
+            "(none)".to_string()
+        } else {
+            parts.join(" ")
+        }
+    }
+}
+
+fn looks_like_code(line: &str) -> bool {
+    const DIRECTIVES: [&str; 6] = ["#include", "#define", "#if", "#endif", "#pragma", "#import"];
+    if DIRECTIVES.iter().any(|d| line.starts_with(d)) {
+        return true;
+    }
+    line.ends_with(';') || line.ends_with('{') || line.ends_with('}')
+}
+
+#[cfg(feature = "fs")]
+pub fn infer_comment_syntax_from_content(path: &Path) -> SyntaxInference {
+    match File::open(path) {
+        Ok(file) => infer_comment_syntax(io::BufReader::new(file)),
+        Err(_) => infer_comment_syntax(io::empty()),
     }
 }
 
-pub fn infer_comment_syntax_from_content(path: &Path) -> CommentSyntax {
+/// Same as [`infer_comment_syntax_from_content`], reading the content from `reader`.
+pub fn infer_comment_syntax(reader: impl BufRead) -> SyntaxInference {
     // List of candidate comment syntaxes to check
     let candidates = vec![
         CommentSyntax {
@@ -251,68 +444,127 @@ pub fn infer_comment_syntax_from_content(path: &Path) -> CommentSyntax {
             block_end: None,
         },
     ];
-    let mut counts = vec![0; candidates.len()];
-    if let Ok(file) = File::open(path) {
-        let reader = io::BufReader::new(file);
-        let mut in_block = vec![false; candidates.len()];
-        for line in reader.lines().flatten() {
-            let l = line.trim();
-            for (i, cand) in candidates.iter().enumerate() {
-                let mut is_comment = false;
-                if in_block[i] {
-                    if let Some(ref end) = cand.block_end {
-                        if l.contains(end) {
-                            in_block[i] = false;
-                        }
-                    }
-                    is_comment = true;
-                } else if let Some(ref start) = cand.block_start {
-                    if l.starts_with(start) {
-                        in_block[i] = true;
-                        is_comment = true;
-                    }
-                } else if let Some(ref line_comment) = cand.line {
-                    if l.starts_with(line_comment) {
-                        is_comment = true;
-                    }
+    let mut comments = vec![0; candidates.len()];
+    let mut code_like = vec![0; candidates.len()];
+    // Per-candidate classification of each non-blank line, used to tell
+    // genuinely competing candidates from ones that agree on this file
+    let mut marks: Vec<Vec<bool>> = vec![Vec::new(); candidates.len()];
+    let mut lines = 0;
+    let mut in_block = vec![false; candidates.len()];
+    for line in reader.lines().map_while(Result::ok) {
+        let l = line.trim();
+        if l.is_empty() {
+            continue;
+        }
+        lines += 1;
+        let code = looks_like_code(l);
+        for (i, cand) in candidates.iter().enumerate() {
+            let mut is_comment = false;
+            if in_block[i] {
+                if let Some(ref end) = cand.block_end
+                    && l.contains(end)
+                {
+                    in_block[i] = false;
                 }
-                if is_comment {
-                    counts[i] += 1;
+                is_comment = true;
+            } else if let Some(ref start) = cand.block_start
+                && l.starts_with(start)
+            {
+                in_block[i] = !cand.block_end.as_ref().is_some_and(|end| l[start.len()..].contains(end));
+                is_comment = true;
+            } else if let Some(ref line_comment) = cand.line
+                && l.starts_with(line_comment)
+            {
+                is_comment = true;
+            }
+            if is_comment {
+                comments[i] += 1;
+                if code {
+                    code_like[i] += 1;
                 }
             }
+            marks[i].push(is_comment);
+        }
+    }
+    let scores: Vec<CandidateScore> = candidates
+        .into_iter()
+        .enumerate()
+        .map(|(i, syntax)| CandidateScore {
+            syntax,
+            comment_lines: comments[i],
+            code_like_lines: code_like[i],
+            // A comment that looks like code is evidence against the candidate
+            score: comments[i] as i64 - 2 * code_like[i] as i64,
+        })
+        .collect();
+
+    // Pick the candidate with the best score; earlier candidates win ties
+    let mut winner: Option<usize> = None;
+    for (i, cand) in scores.iter().enumerate() {
+        if cand.score > 0 && winner.is_none_or(|w| cand.score > scores[w].score) {
+            winner = Some(i);
         }
     }
-    // Pick the candidate with the most matches
-    if let Some((idx, _)) = counts.iter().enumerate().max_by_key(|&(_, c)| c) {
-        if counts[idx] > 0 {
-            return candidates[idx].clone();
+    let Some(w) = winner else {
+        return SyntaxInference {
+            syntax: CommentSyntax {
+                line: None,
+                block_start: None,
+                block_end: None,
+            },
+            confidence: 0.0,
+            winner: None,
+            runner_up: None,
+            candidates: scores,
+            lines,
+        };
+    };
+    let mut runner_up: Option<usize> = None;
+    for (i, cand) in scores.iter().enumerate() {
+        if i != w && cand.score > 0 && marks[i] != marks[w] && runner_up.is_none_or(|r| cand.score > scores[r].score) {
+            runner_up = Some(i);
         }
     }
-    CommentSyntax {
-        line: None,
-        block_start: None,
-        block_end: None,
+    let best = scores[w].score as f64;
+    let second = runner_up.map_or(0.0, |r| scores[r].score as f64);
+    SyntaxInference {
+        syntax: scores[w].syntax.clone(),
+        confidence: (best - second) / best,
+        winner: Some(w),
+        runner_up,
+        candidates: scores,
+        lines,
     }
 }
 
-
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::path::Path;
 
+    #[test]
+    fn test_sub_stats() {
+        let a = Stats { actual_loc: 5, raw_loc: 8, files: 1, ..Stats::default() };
+        let b = Stats { actual_loc: 2, raw_loc: 9, files: 2, ..Stats::default() };
+        let d = sub_stats(a, b);
+        assert_eq!((d.actual_loc, d.raw_loc, d.files), (3, 0, 0));
+    }
+
+    #[cfg(feature = "fs")]
     #[test]
     fn test_detect_language_py() {
         let path = Path::new("foo.py");
         assert_eq!(detect_language(path), "python");
     }
 
+    #[cfg(feature = "fs")]
     #[test]
     fn test_detect_language_c() {
         let path = Path::new("foo.c");
         assert_eq!(detect_language(path), "c");
     }
 
+    #[cfg(feature = "fs")]
     #[test]
     fn test_detect_language_shebang() {
         use std::fs::File;
@@ -322,4 +574,377 @@ mod tests {
         writeln!(file, "#!/usr/bin/env python").unwrap();
         assert_eq!(detect_language(tmp.path()), "python");
     }
+
+    #[test]
+    fn test_detect_language_from_prefix() {
+        let path = Path::new("tool");
+        assert_eq!(detect_language_from_prefix(path, b"#!/usr/bin/perl -w\nprint 1;\n"), "perl");
+        assert_eq!(detect_language_from_prefix(path, b""), "unknown");
+        assert_eq!(detect_language_from_prefix(Path::new("a.rs"), b"fn main() {}\n"), "rust");
+    }
+
+    #[test]
+    fn test_extract_comments() {
+        let text = "/**\n * Adds one\n */\nfn inc(x: u8) -> u8 { // wraps\n    x + b\"//\"[0] /* no */\n}\n\
+            /// a\n/// b\n";
+        let spans: Vec<_> = extract_comments("rust", text.as_bytes())
+            .into_iter()
+            .map(|span| (span.start_line, span.end_line, span.text))
+            .collect();
+        let expected = [(1, 3, "Adds one"), (4, 4, "wraps"), (5, 5, "no"), (7, 8, "a\nb")];
+        assert_eq!(spans, expected.map(|(start, end, text)| (start, end, text.to_string())));
+        // Prose is the comment of literate sources
+        let spans = extract_comments("literate-haskell", "Doubles.\n> double x = 2 * x\n".as_bytes());
+        assert_eq!((spans.len(), spans[0].text.as_str()), (1, "Doubles."));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_infer_comment_syntax_confidence() {
+        use std::io::Write;
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        writeln!(tmp, "#include <stdio.h>").unwrap();
+        writeln!(tmp, "// a comment").unwrap();
+        writeln!(tmp, "int main() {{").unwrap();
+        writeln!(tmp, "    return 0; // done").unwrap();
+        writeln!(tmp, "}}").unwrap();
+        let inference = infer_comment_syntax_from_content(tmp.path());
+        assert_eq!(inference.syntax.line.as_deref(), Some("//"));
+        assert_eq!(inference.confidence, 1.0);
+        assert!(inference.runner_up.is_none());
+    }
+
+    #[test]
+    fn test_language_tables() {
+        for (lang, exts) in EXTENSION_LANGUAGES {
+            for ext in *exts {
+                assert_eq!(detect_language_from_prefix(Path::new(&format!("a.{}", ext)), b""), *lang);
+            }
+        }
+        for (word, lang) in SHEBANG_LANGUAGES {
+            let shebang = format!("#!/usr/bin/env {}\n", word);
+            assert_eq!(detect_language_from_prefix(Path::new("tool"), shebang.as_bytes()), *lang);
+        }
+        let names = EXTENSION_LANGUAGES.iter().map(|(lang, _)| lang);
+        for name in names.chain(SHEBANG_LANGUAGES.iter().map(|(_, lang)| lang)) {
+            assert!(Language::from_name(name).is_known(), "{} has no display name", name);
+        }
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_language() {
+        let cpp = Language::from_name("c++");
+        assert_eq!((cpp.name(), cpp.display_name()), ("cpp", "C++"));
+        assert_eq!(detect_language(Path::new("main.cc")), cpp);
+        // Unknown extensions are languages of their own
+        let other = detect_language(Path::new("deck.slides"));
+        assert_eq!((other.name(), other.display_name(), other.is_known()), ("slides", "slides", false));
+        assert!(Language::from_name("py").aliases().contains(&"python3"));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_detect_language_modern_js() {
+        assert_eq!(detect_language(Path::new("App.jsx")), "javascript");
+        assert_eq!(detect_language(Path::new("index.mjs")), "javascript");
+        assert_eq!(detect_language(Path::new("App.tsx")), "typescript");
+        assert_eq!(detect_language(Path::new("config.cts")), "typescript");
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_count_path() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.rs"), "// entry\nfn main() {}\n\n").unwrap();
+        std::fs::create_dir(dir.path().join("tests")).unwrap();
+        std::fs::write(dir.path().join("tests/run.py"), "# check\nassert True\n").unwrap();
+        std::fs::create_dir(dir.path().join("target")).unwrap();
+        std::fs::write(dir.path().join("target/out.rs"), "fn built() {}\n").unwrap();
+
+        let summary = count_path(dir.path(), &Options::default()).unwrap();
+        assert_eq!(summary.total.actual_loc, 2);
+        assert_eq!(summary.total.raw_loc, 5);
+        assert_eq!(summary.total.comments, 2);
+        assert_eq!(summary.total.files, 2);
+        assert_eq!(summary.langs["rust"].actual_loc, 1);
+        assert_eq!(summary.tests.files, 1);
+        let file = count_file(&dir.path().join("target/out.rs"), &Options::default()).unwrap();
+        assert_eq!((file.lang.name(), file.stats.actual_loc), ("rust", 1));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_count_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let opts = Options::default();
+        std::fs::write(dir.path().join("blob.bin"), b"\x7fELF\0\0\0").unwrap();
+        std::fs::write(dir.path().join("latin1.txt"), b"caf\xe9\n").unwrap();
+        std::fs::write(dir.path().join("empty.txt"), b"").unwrap();
+        let missing = dir.path().join("missing.rs");
+
+        assert!(matches!(count_file(&dir.path().join("blob.bin"), &opts), Err(Error::Binary { .. })));
+        assert!(matches!(count_file(&dir.path().join("latin1.txt"), &opts), Err(Error::Decode { line: 1, .. })));
+        assert!(matches!(count_file(&missing, &opts), Err(Error::Io { .. })));
+        assert!(matches!(count_path(&missing, &opts), Err(Error::Io { .. })));
+        assert_eq!(count_file(&dir.path().join("empty.txt"), &opts).unwrap().stats.files, 1);
+        // Walks count what they can
+        let summary = count_path(dir.path(), &opts).unwrap();
+        assert_eq!((summary.total.files, summary.binary_skipped), (2, 1));
+        // Whether the counts of a file are cached or not
+        let cache = CountCache::load(dir.path().join("cache").to_str().unwrap());
+        let opts = Options::builder().cache(std::sync::Arc::new(std::sync::Mutex::new(cache))).build();
+        count_path(dir.path(), &opts).unwrap();
+        assert!(matches!(count_file(&dir.path().join("latin1.txt"), &opts), Err(Error::Decode { line: 1, .. })));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_options_builder() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+        std::fs::create_dir(dir.path().join("target")).unwrap();
+        std::fs::write(dir.path().join("target/out.rs"), "fn built() {}\n").unwrap();
+        std::fs::write(dir.path().join("run.py"), "print(1)\n").unwrap();
+
+        let builder = Options::builder().hidden(true).exclude(["*.py"]).include(["target"]);
+        let excludes = builder.exclude_patterns();
+        assert!(!excludes.iter().any(|e| e == ".git" || e == "target"));
+        assert!(excludes.iter().any(|e| e == "*.py"));
+        let summary = count_path(dir.path(), &builder.build()).unwrap();
+        assert_eq!(summary.total.files, 2);
+        assert!(!summary.langs.contains_key("python"));
+        let opts = Options::builder().exclude_langs(["rust"]).build();
+        assert_eq!(count_path(dir.path(), &opts).unwrap().total.files, 1);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_count_iter() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+        std::fs::create_dir(dir.path().join("tests")).unwrap();
+        std::fs::write(dir.path().join("tests/run.py"), "assert True\n").unwrap();
+        std::fs::write(dir.path().join("blob.bin"), b"\x7fELF\0\0\0").unwrap();
+
+        let mut files: Vec<FileReport> = Vec::new();
+        let mut errors = Vec::new();
+        for result in count_iter(dir.path(), &Options::default()) {
+            match result {
+                Ok(file) => files.push(file),
+                Err(e) => errors.push(e),
+            }
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let found: Vec<_> = files.iter().map(|f| (f.lang.name(), f.stats.actual_loc, f.is_test)).collect();
+        assert_eq!(found, [("rust", 1, false), ("python", 1, true)]);
+        assert!(matches!(errors.as_slice(), [Error::Binary { .. }]));
+        let missing: Vec<_> = count_iter(&dir.path().join("missing"), &Options::default()).collect();
+        assert!(matches!(missing.as_slice(), [Err(Error::Io { .. })]));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_visitor() {
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Default)]
+        struct Log(Mutex<Vec<String>>);
+        impl Visitor for Log {
+            fn enter_dir(&self, path: &Path) -> bool {
+                !path.ends_with("skipme")
+            }
+            fn file_counted(&self, file: &FileReport) {
+                self.0.lock().unwrap().push(format!("counted {}", file.lang));
+            }
+            fn file_skipped(&self, _path: &Path, reason: SkipReason) {
+                self.0.lock().unwrap().push(format!("skipped {:?}", reason));
+            }
+            fn error(&self, error: Error) {
+                self.0.lock().unwrap().push(format!("error {}", error.path().file_name().unwrap().display()));
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+        std::fs::write(dir.path().join("notes.log"), "log\n").unwrap();
+        std::fs::write(dir.path().join("blob.bin"), b"\x7fELF\0\0\0").unwrap();
+        std::fs::create_dir(dir.path().join("skipme")).unwrap();
+        std::fs::write(dir.path().join("skipme/a.py"), "print(1)\n").unwrap();
+
+        let log = Arc::new(Log::default());
+        let opts = Options::builder().visitor(log.clone()).build();
+        let summary = count_path(dir.path(), &opts).unwrap();
+        assert_eq!(summary.total.files, 1);
+        let mut events = log.0.lock().unwrap().clone();
+        events.sort();
+        assert_eq!(events, ["counted rust", "error blob.bin", "skipped Excluded"]);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_cancel() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        struct CancelAfterFirst(Arc<AtomicBool>);
+        impl Visitor for CancelAfterFirst {
+            fn file_counted(&self, _file: &FileReport) {
+                self.0.store(true, Ordering::Relaxed);
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..50 {
+            std::fs::write(dir.path().join(format!("f{}.rs", i)), "fn f() {}\n").unwrap();
+        }
+        let cancel = Arc::new(AtomicBool::new(false));
+        let visitor = Arc::new(CancelAfterFirst(cancel.clone()));
+        let opts = Options::builder().cancel(cancel.clone()).visitor(visitor).build();
+        assert!(matches!(count_path(dir.path(), &opts), Err(Error::Cancelled { .. })));
+        let counted = count_iter(dir.path(), &opts).filter(|r| r.is_ok()).count();
+        assert!(counted < 50);
+        cancel.store(false, Ordering::Relaxed);
+        let opts = Options { visitor: None, ..opts };
+        assert_eq!(count_path(dir.path(), &opts).unwrap().total.files, 50);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_count_iter_dropped() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::{Arc, Condvar, Mutex};
+        use std::time::{Duration, Instant};
+
+        // Holds every file counted until the gate is open
+        struct Gated {
+            counted: AtomicUsize,
+            open: Mutex<bool>,
+            opened: Condvar,
+        }
+        impl Visitor for Gated {
+            fn file_counted(&self, _file: &FileReport) {
+                self.counted.fetch_add(1, Ordering::Relaxed);
+                let open = self.open.lock().unwrap();
+                drop(self.opened.wait_while(open, |open| !*open).unwrap());
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..200 {
+            std::fs::write(dir.path().join(format!("f{}.rs", i)), "fn f() {}\n").unwrap();
+        }
+        let gated = Arc::new(Gated { counted: AtomicUsize::new(0), open: Mutex::new(false), opened: Condvar::new() });
+        let opts = Options::builder().visitor(gated.clone()).build();
+        drop(count_iter(dir.path(), &opts));
+        drop(opts);
+        // Wait for a file to be counted before letting the walk see that the iterator is gone
+        let start = Instant::now();
+        while gated.counted.load(Ordering::Relaxed) == 0 {
+            assert!(start.elapsed() < Duration::from_secs(30));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        *gated.open.lock().unwrap() = true;
+        gated.opened.notify_all();
+        // The walk is over once it has let go of the visitor
+        while Arc::strong_count(&gated) > 1 {
+            assert!(start.elapsed() < Duration::from_secs(30));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let counted = gated.counted.load(Ordering::Relaxed);
+        assert!(counted < 200, "{counted} files counted");
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(gated.counted.load(Ordering::Relaxed), counted);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_count_str() {
+        let stats = count_str("rust", "// entry\nfn main() {\n\n    run(); // go\n}");
+        assert_eq!((stats.raw_loc, stats.actual_loc, stats.comments, stats.files), (5, 3, 1, 1));
+        // Without a built-in syntax, it is inferred from the text
+        let stats = count_reader("unknown", &b"# note\nrun 1\n# more\nstop\n"[..]).unwrap();
+        assert_eq!((stats.actual_loc, stats.comments), (2, 2));
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.py"), "# c\nx = 1\n").unwrap();
+        let file = count_file(&dir.path().join("a.py"), &Options::default()).unwrap();
+        assert_eq!(count_str("python", "# c\nx = 1\n").actual_loc, file.stats.actual_loc);
+    }
+
+    #[test]
+    fn test_classify_lines() {
+        let text = "/* a\n   b */\nint x; // x\n\nint y;\n";
+        let kinds: Vec<_> = classify_lines("c", text.as_bytes()).collect();
+        use LineKind::*;
+        assert_eq!(kinds, [(1, Comment), (2, Comment), (3, Mixed), (4, Blank), (5, Code)]);
+        // The same lines as counted
+        let stats = count_str("c", text);
+        let code = kinds.iter().filter(|(_, k)| matches!(k, Code | Mixed)).count();
+        assert_eq!((stats.actual_loc, stats.comments), (code, 2));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_metrics() {
+        use std::sync::Arc;
+
+        // Lines with a TODO, and the trailing comments among them
+        #[derive(Default)]
+        struct Todos(usize, usize);
+        impl Metric for Todos {
+            fn observe_line(&mut self, line: &LineInfo) {
+                if line.text.contains("TODO") {
+                    self.0 += 1;
+                    self.1 += (line.kind == LineKind::Mixed) as usize;
+                }
+            }
+            fn finish(&self) -> f64 {
+                (self.0 + 10 * self.1) as f64
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.rs"), "// TODO: docs\nfn a() {} // TODO\n").unwrap();
+        std::fs::write(dir.path().join("b.py"), "x = 1  # TODO\n").unwrap();
+        let opts = Options::builder().metric("todo", Arc::new(|| Box::new(Todos::default()))).build();
+        let summary = count_path(dir.path(), &opts).unwrap();
+        assert_eq!(summary.metrics["todo"], 23.0);
+        assert_eq!(summary.total.actual_loc, 2);
+        let files: Vec<_> = count_iter(dir.path(), &opts).map(|f| f.unwrap().metrics["todo"]).collect();
+        assert_eq!(files.iter().sum::<f64>(), 23.0);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_walker() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+        std::fs::write(dir.path().join("setup.py"), "x = 1\n").unwrap();
+        std::fs::create_dir(dir.path().join("target")).unwrap();
+        std::fs::write(dir.path().join("target/out.rs"), "fn built() {}\n").unwrap();
+
+        let walker = walk::Walker::new(Options::default());
+        let mut found: Vec<String> = walker.files(dir.path()).map(|f| f.unwrap().lang.to_string()).collect();
+        found.sort();
+        assert_eq!(found, ["python", "rust"]);
+        let walker = walker.filter(|file| file.lang == "rust");
+        let found: Vec<Candidate> = walker.files(dir.path()).map(Result::unwrap).collect();
+        assert_eq!(found.len(), 1);
+        assert!(found[0].path.ends_with("main.rs"));
+        assert_eq!(format!("[{:<6}]", found[0].lang), "[rust  ]");
+    }
+
+    #[cfg(all(feature = "fs", feature = "serde"))]
+    #[test]
+    fn test_serde_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("lib.rs"), "/* doc */\npub fn f() {}\n").unwrap();
+        let summary = count_path(dir.path(), &Options::default()).unwrap();
+        let json = serde_json::to_string(&summary).unwrap();
+        let back: DirSummary = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.langs["rust"].actual_loc, 1);
+        assert_eq!(back.total.comments, 1);
+        let syntax: CommentSyntax = serde_json::from_str(r##"{"line":"#","block_start":null,"block_end":null}"##).unwrap();
+        assert_eq!(syntax.describe(), "#");
+    }
 }
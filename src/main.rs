@@ -1,688 +1,3706 @@
+use std::collections::BTreeMap;
 use std::fs::{self, File};
-use std::io::{self, BufRead, Read};
+use std::io::{self, BufRead, IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 
-use clap::{ArgGroup, Parser};
-use globset::{Glob, GlobSet, GlobSetBuilder};
-use sourcelines::{CommentSyntax, detect_comment_syntax, detect_language};
+use clap::{ArgGroup, CommandFactory, Parser, Subcommand, ValueEnum};
+use regex::Regex;
+use sha2::Digest;
+use unicode_width::UnicodeWidthStr;
+use sourcelines::{
+    CountCache, DirSummary, EXPLAIN_TARGET, EXTENSION_LANGUAGES, FileCallback, FileReport, Language,
+    LineClassifier, LineInfo, Metric, Options, SHEBANG_LANGUAGES, SUBMODULE_BUCKET, SourceFile, Stats,
+    SubmoduleMode, VENDORED_BUCKET, Visitor, Walker, builtin_comment_syntax, count_line, count_path, count_reader,
+    detect_language, detect_language_from_prefix, ignore_chain, inference_table, infer_comment_syntax,
+    literate_style, looks_binary, normalize_path, process_file, remap_language, split_lines, sub_stats,
+};
 
-#[derive(Default, Debug, Clone)]
-struct Stats {
-    actual_loc: usize,
-    raw_loc: usize,
-    words: usize,
-    chars: usize,
-    bytes: usize,
-}
+mod archive;
+mod budget;
+mod config;
+mod daemon;
+mod plugin;
+mod report;
+mod serve;
+#[cfg(feature = "tui")]
+mod tui;
 
-#[derive(Debug, Clone)]
-struct IgnorePattern {
-    pattern: String,
-    is_negation: bool, // Patterns starting with ! negate previous ignores
-    is_dir_only: bool, // Patterns ending with / match only directories
+use report::{RunReport, Skipped};
+
+fn parse_remap(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((from, to)) if !from.trim().is_empty() && !to.trim().is_empty() => {
+            Ok((from.trim().to_string(), to.trim().to_string()))
+        }
+        _ => Err(format!("invalid remap '{}', expected FROM=TO", s)),
+    }
 }
 
-#[derive(Debug)]
-struct DirObject {
-    path: PathBuf,
-    ignore_patterns: Vec<IgnorePattern>,
-    parent: Option<Rc<DirObject>>,
+fn parse_icon(s: &str) -> Result<(String, String), String> {
+    parse_remap(s).map_err(|_| format!("invalid icon '{}', expected LANG=GLYPH", s))
 }
 
-impl DirObject {
-    fn new(path: PathBuf, parent: Option<Rc<DirObject>>) -> Self {
-        DirObject {
-            path,
-            ignore_patterns: Vec::new(),
-            parent,
-        }
+// Read paths from FILE (or stdin for "-"), one per line or NUL-separated. Names are kept as
+// they are on unix, whatever their encoding
+fn read_file_list(list: &str, null_data: bool) -> io::Result<Vec<PathBuf>> {
+    let mut data = Vec::new();
+    if list == "-" {
+        io::stdin().lock().read_to_end(&mut data)?;
+    } else {
+        File::open(list)?.read_to_end(&mut data)?;
     }
+    let sep = if null_data { b'\0' } else { b'\n' };
+    Ok(data
+        .split(|&b| b == sep)
+        .map(|name| name.strip_suffix(b"\r").unwrap_or(name))
+        .filter(|name| !name.is_empty())
+        .map(path_from_bytes)
+        .collect())
+}
 
-    fn load_ignore_file(&mut self, ignore_file_name: &str) {
-        let ignore_path = self.path.join(ignore_file_name);
-        if let Ok(content) = fs::read_to_string(&ignore_path) {
-            for line in content.lines() {
-                let line = line.trim();
-                // Skip empty lines and comments
-                if line.is_empty() || line.starts_with('#') {
-                    continue;
-                }
-                
-                let is_negation = line.starts_with('!');
-                let pattern_str = if is_negation { &line[1..] } else { line };
-                let pattern_str = pattern_str.trim();
-                if pattern_str.is_empty() {
-                    continue;
-                }
-                
-                let is_dir_only = pattern_str.ends_with('/');
-                let pattern = if is_dir_only {
-                    pattern_str[..pattern_str.len() - 1].to_string()
-                } else {
-                    pattern_str.to_string()
-                };
-                
-                self.ignore_patterns.push(IgnorePattern {
-                    pattern,
-                    is_negation,
-                    is_dir_only,
-                });
-            }
-        }
-    }
+#[cfg(unix)]
+fn path_from_bytes(name: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(name))
+}
 
-    fn include_test(&self, file_path: &Path, is_dir: bool) -> bool {
-        // Get relative path from this directory
-        let rel_path = match file_path.strip_prefix(&self.path) {
-            Ok(p) => p,
-            Err(_) => return true, // If we can't get relative path, include it
-        };
-        
-        // Convert to string for pattern matching
-        let path_str = rel_path.to_string_lossy();
-        let path_str = path_str.replace('\\', "/"); // Normalize path separators
-        
-        // Check patterns in order (last match wins)
-        let mut included = true;
-        let mut matched = false;
-        for pattern in &self.ignore_patterns {
-            if pattern.is_dir_only && !is_dir {
-                continue;
-            }
-            
-            if matches_pattern(&pattern.pattern, &path_str, is_dir) {
-                matched = true;
-                if pattern.is_negation {
-                    included = true;
-                } else {
-                    included = false;
-                }
-            }
+#[cfg(not(unix))]
+fn path_from_bytes(name: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(name).as_ref())
+}
+
+// Paths tracked by git under DIR; entries skipped by a sparse checkout are left out
+fn git_tracked_files(dir: &Path) -> io::Result<std::collections::HashSet<PathBuf>> {
+    let output = git_output(dir, &["ls-files", "-z", "-t"])?;
+    let names = output.split(|&b| b == b'\0').filter_map(|entry| {
+        // Each entry is "<tag> <path>"; S marks skip-worktree entries
+        match entry.split_first() {
+            Some((b'S', _)) | None => None,
+            Some((_, rest)) => rest.strip_prefix(b" "),
         }
-        
-        // If not matched in this directory, check parent
-        if !matched {
-            if let Some(ref parent) = self.parent {
-                return parent.include_test(file_path, is_dir);
+    });
+    Ok(git_path_set(dir, names))
+}
+
+// Paths under DIR touched by the commits in RANGE (REV1..REV2)
+fn git_changed_files(dir: &Path, range: &str) -> io::Result<std::collections::HashSet<PathBuf>> {
+    let output = git_output(dir, &["diff", "--name-only", "-z", "--relative", range])?;
+    Ok(git_path_set(dir, output.split(|&b| b == b'\0').filter(|name| !name.is_empty())))
+}
+
+// NAMES relative to DIR joined onto DIR, plus every directory leading to them
+fn git_path_set<'a>(
+    dir: &Path,
+    names: impl Iterator<Item = &'a [u8]>,
+) -> std::collections::HashSet<PathBuf> {
+    let mut set = std::collections::HashSet::new();
+    for name in names {
+        let mut p = dir.join(String::from_utf8_lossy(name).as_ref());
+        while p.as_path() != dir && set.insert(p.clone()) {
+            if !p.pop() {
+                break;
             }
         }
-        
-        included
     }
+    set
 }
 
-fn matches_pattern(pattern: &str, path: &str, _is_dir: bool) -> bool {
-    // Handle simple patterns
-    if pattern == "*" {
-        return true;
+// Run git in DIR and return its stdout; pathspecs are taken literally
+fn git_output(dir: &Path, args: &[&str]) -> io::Result<Vec<u8>> {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .env("GIT_LITERAL_PATHSPECS", "1")
+        .output()?;
+    if !output.status.success() {
+        let msg = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(io::Error::other(msg));
     }
-    
-    // Convert gitignore pattern to glob pattern
-    let mut glob_pattern = pattern.to_string();
-    
-    // If pattern doesn't start with /, it can match anywhere
-    if !glob_pattern.starts_with('/') {
-        glob_pattern = format!("**/{}", glob_pattern);
-    } else {
-        glob_pattern = glob_pattern[1..].to_string();
+    Ok(output.stdout)
+}
+
+const BASELINE_HEADER: &str = "# sourcelines baseline v1";
+
+// Save per-language totals (and the overall total as "*") for --compare-baseline
+fn write_baseline(path: &str, report: &RunReport) -> io::Result<()> {
+    let mut out = format!("{}\n# language\tactual_loc\traw_loc\twords\tchars\tbytes\n", BASELINE_HEADER);
+    let star = "*".to_string();
+    for (lang, stats) in std::iter::once((&star, &report.total)).chain(&report.langs()) {
+        out += &format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\n",
+            lang, stats.actual_loc, stats.raw_loc, stats.words, stats.chars, stats.bytes
+        );
     }
-    
-    // Handle ** patterns
-    glob_pattern = glob_pattern.replace("**/", "**");
-    glob_pattern = glob_pattern.replace("/**", "**");
-    
-    // Try to match using glob
-    if let Ok(glob) = Glob::new(&glob_pattern) {
-        if glob.compile_matcher().is_match(path) {
-            return true;
-        }
+    fs::write(path, out)
+}
+
+fn read_baseline(path: &str) -> io::Result<std::collections::HashMap<String, Stats>> {
+    let text = fs::read_to_string(path)?;
+    if !text.starts_with(BASELINE_HEADER) {
+        return Err(io::Error::other("not a sourcelines baseline"));
     }
-    
-    // Fallback to simple string matching for common cases
-    if pattern.contains('*') {
-        // Simple wildcard matching
-        let parts: Vec<&str> = pattern.split('*').collect();
-        if parts.len() == 2 {
-            return path.starts_with(parts[0]) && path.ends_with(parts[1]);
-        }
-    } else if pattern == path || path.ends_with(&format!("/{}", pattern)) {
-        return true;
+    let mut totals = std::collections::HashMap::new();
+    for line in text.lines().filter(|l| !l.starts_with('#') && !l.trim().is_empty()) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let nums: Vec<usize> = fields[1..].iter().filter_map(|n| n.parse().ok()).collect();
+        let [actual_loc, raw_loc, words, chars, bytes] = nums[..] else {
+            return Err(io::Error::other(format!("malformed line: {}", line)));
+        };
+        let stats = Stats { actual_loc, raw_loc, words, chars, bytes, ..Stats::default() };
+        totals.insert(fields[0].to_string(), stats);
     }
-    
-    false
+    Ok(totals)
 }
 
-#[derive(Parser, Debug)]
-#[command(
-    name = "sourcelines",
-    version,
-    about = "Count source code statistics: actual lines of code, raw lines, words, chars, bytes.",
-    long_about = None,
-    after_help = "For more details, see the man page or sourcelines.1.md."
-)]
-#[command(group(ArgGroup::new("columns").multiple(true)))]
-struct Cli {
-    /// Recursively process directories
-    #[arg(short = 'r', long = "recursive")]
-    recursive: bool,
-
-    /// Output summary line at the end
-    #[arg(short = 's', long = "sum")]
-    sum: bool,
+/// Exit status when a --fail-if gate trips; it takes precedence over UNREADABLE_EXIT_CODE
+const GATE_FAILED_EXIT_CODE: i32 = 3;
 
-    /// Verbose output: with -s, print all file stats; for directories, print per-language summary
-    #[arg(short = 'v', long = "verbose")]
-    verbose: bool,
+const STAT_FIELDS: [&str; 5] = ["actual_loc", "raw_loc", "words", "chars", "bytes"];
 
-    /// Output with ANSI coloring
-    #[arg(short = 'C', long = "color")]
-    color: bool,
+/// Fields of the line gates
+const LINE_FIELDS: [&str; 2] = ["chars", "bytes"];
 
-    /// Exclude files/directories matching these wildcard patterns (can be used multiple times)
-    #[arg(long = "exclude", value_name = "WILDCARD", num_args = 0.., default_value = "")]
-    exclude: Vec<String>,
+/// What a --fail-if condition is about
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum GateSubject {
+    Total,
+    Lang(String),
+    /// Each file counted
+    File,
+    /// Each line of the files counted, without its newline
+    Line,
+}
 
-    /// Include files/directories matching these wildcard patterns (can be used multiple times)
-    #[arg(long = "include", value_name = "WILDCARD", num_args = 0.., default_value = "")]
-    include: Vec<String>,
+/// A --fail-if condition: `total.FIELD [delta] OP N`, `lang.LANG.FIELD [delta] OP N`,
+/// `file.FIELD OP N` or `line.FIELD OP N`
+#[derive(Debug, Clone)]
+struct Gate {
+    expr: String,
+    subject: GateSubject,
+    field: String,
+    /// Compare the change since the baseline instead of the current value
+    delta: bool,
+    op: String,
+    limit: i64,
+}
 
-    /// Show actual klocs (actual lines/1000)
-    #[arg(short = 'k', long = "actual-klocs", group = "columns")]
-    actual_klocs: bool,
-    /// Show actual loc
-    #[arg(short = 'l', long = "actual-loc", group = "columns")]
-    actual_loc: bool,
-    /// Show raw klocs (raw lines/1000)
-    #[arg(short = 'K', long = "raw-klocs", group = "columns")]
-    raw_klocs: bool,
-    /// Show raw loc
-    #[arg(short = 'R', long = "raw-locs", group = "columns")]
-    raw_loc: bool,
-    /// Follow symlinks when recursively processing directories
-    #[arg(short = 'L', long = "follow-symlinks")]
-    follow_symlinks: bool,
-    /// Parse ignore list files (like .gitignore) [default: enabled]
-    #[arg(short = 'i', long = "ignorelist", default_value_t = true)]
-    ignorelist: bool,
-    /// Do not parse ignore list files
-    #[arg(short = 'I', long = "no-ignorelist")]
-    no_ignorelist: bool,
-    /// Output summary in text report format
-    #[arg(long = "text")]
-    text: bool,
-    /// Output summary in HTML report format
-    #[arg(long = "html")]
-    html: bool,
-    /// Output summary in LaTeX report format
-    #[arg(long = "latex")]
-    latex: bool,
-    /// Output summary in PDF report format (requires LaTeX)
-    #[arg(long = "pdf")]
-    pdf: bool,
-    /// Output summary in Markdown report format
-    #[arg(long = "markdown")]
-    markdown: bool,
-    /// Show word count
-    #[arg(short = 'w', long = "words", group = "columns")]
-    words: bool,
-    /// Show char count
-    #[arg(short = 'c', long = "chars", group = "columns")]
-    chars: bool,
-    /// Show byte count
-    #[arg(short = 'b', long = "bytes", group = "columns")]
-    bytes: bool,
+impl Gate {
+    fn value(&self, report: &RunReport, baseline: Option<&std::collections::HashMap<String, Stats>>) -> i64 {
+        // File and line gates are checked as the files are counted, see FileGates and LineGates
+        let (current, key) = match &self.subject {
+            GateSubject::Lang(lang) => (report.langs().get(lang).cloned(), lang.as_str()),
+            _ => (Some(report.total.clone()), "*"),
+        };
+        let value = current.map_or(0, |stats| stat_field(&stats, &self.field) as i64);
+        if !self.delta {
+            return value;
+        }
+        let before = baseline.and_then(|b| b.get(key)).map_or(0, |stats| stat_field(stats, &self.field));
+        value - before as i64
+    }
 
-    /// Files or directories to process
-    #[arg(required = false)]
-    files: Vec<String>,
+    fn trips(&self, value: i64) -> bool {
+        match self.op.as_str() {
+            ">" => value > self.limit,
+            ">=" => value >= self.limit,
+            "<" => value < self.limit,
+            "<=" => value <= self.limit,
+            "==" => value == self.limit,
+            _ => value != self.limit,
+        }
+    }
 }
 
-fn main() {
-    let mut cli = Cli::parse();
-    // If no files provided, default to -rv .
-    // If --text is used, also enable recursive and sum by default
-    if cli.files.is_empty() {
-        cli.files = vec![".".to_string()];
-        cli.recursive = true;
-        cli.verbose = true;
-    }
-    if cli.text || cli.html || cli.latex || cli.pdf || cli.markdown {
-        cli.recursive = true;
-        cli.sum = true;
+fn stat_field(stats: &Stats, field: &str) -> usize {
+    match field {
+        "actual_loc" => stats.actual_loc,
+        "raw_loc" => stats.raw_loc,
+        "words" => stats.words,
+        "chars" => stats.chars,
+        _ => stats.bytes,
     }
-    let show_actual_klocs = cli.actual_klocs;
-    let show_actual_loc = cli.actual_loc;
-    let show_raw_klocs = cli.raw_klocs;
-    let show_raw_loc = cli.raw_loc;
-    let mut show_words = cli.words;
-    let mut show_chars = cli.chars;
-    let mut show_bytes = cli.bytes;
-    let recursive = cli.recursive;
-    let show_sum = cli.sum;
-    let verbose = cli.verbose;
-    let color = cli.color;
-    let follow_symlinks = cli.follow_symlinks;
-    let use_ignorelist = cli.ignorelist && !cli.no_ignorelist;
-    let text_mode = cli.text;
-    let html_mode = cli.html;
-    let latex_mode = cli.latex;
-    let pdf_mode = cli.pdf;
-    let markdown_mode = cli.markdown;
-    let files = &cli.files;
+}
 
-    // Default exclude patterns
-    let default_excludes = vec![
-        "*~",
-        "~*",
-        "*$",
-        "$*",
-        ".git",
-        ".svn",
-        "*.bak",
-        "*.lock",
-        "*.log",
-        "*.tmp",
-        "_build",
-        "build",
-        "builddir",
-        "node_modules",
-        "target",
-    ];
-    // Build exclude set
-    let mut exclude_patterns = default_excludes
-        .iter()
-        .map(|s| s.to_string())
-        .collect::<Vec<_>>();
-    exclude_patterns.extend(cli.exclude.iter().cloned());
-    // Remove from exclude if present in include
-    let include_patterns = cli.include.clone();
-    for inc in &include_patterns {
-        exclude_patterns.retain(|e| e != inc);
-    }
-    let exclude_set = build_globset(&exclude_patterns);
-    let include_set = if !include_patterns.is_empty() {
-        Some(build_globset(&include_patterns))
+fn parse_gate(s: &str) -> Result<Gate, String> {
+    let tokens: Vec<&str> = s.split_whitespace().collect();
+    let (subject, delta, op, limit) = match tokens[..] {
+        [subject, op, limit] => (subject, false, op, limit),
+        [subject, "delta", op, limit] => (subject, true, op, limit),
+        _ => return Err(format!("invalid gate '{}', expected e.g. 'total.actual_loc > 500000'", s)),
+    };
+    let (subject, field) = if let Some(field) = subject.strip_prefix("total.") {
+        (GateSubject::Total, field)
+    } else if let Some((lang, field)) = subject.strip_prefix("lang.").and_then(|rest| rest.rsplit_once('.')) {
+        (GateSubject::Lang(lang.to_string()), field)
+    } else if let Some(field) = subject.strip_prefix("file.") {
+        (GateSubject::File, field)
+    } else if let Some(field) = subject.strip_prefix("line.") {
+        (GateSubject::Line, field)
     } else {
-        None
+        return Err(format!(
+            "invalid gate subject '{}', expected total.FIELD, lang.LANG.FIELD, file.FIELD or line.FIELD",
+            subject
+        ));
     };
+    let fields: &[&str] = if subject == GateSubject::Line { &LINE_FIELDS } else { &STAT_FIELDS };
+    if !fields.contains(&field) {
+        return Err(format!("unknown field '{}', expected one of {}", field, fields.join(", ")));
+    }
+    if delta && matches!(subject, GateSubject::File | GateSubject::Line) {
+        return Err(format!("invalid gate '{}', only totals can be compared with a baseline", s));
+    }
+    if ![">", ">=", "<", "<=", "==", "!="].contains(&op) {
+        return Err(format!("unknown operator '{}'", op));
+    }
+    let limit = limit.parse::<i64>().map_err(|_| format!("invalid number '{}'", limit))?;
+    Ok(Gate {
+        expr: s.to_string(),
+        subject,
+        field: field.to_string(),
+        delta,
+        op: op.to_string(),
+        limit,
+    })
+}
 
-    // By default, show loc, raw loc, words, chars, bytes (not klocs)
-    let show_actual_klocs = show_actual_klocs;
-    let mut show_actual_loc = show_actual_loc;
-    let show_raw_klocs = show_raw_klocs;
-    let mut show_raw_loc = show_raw_loc;
-    let show_default = !(show_actual_klocs
-        || show_actual_loc
-        || show_raw_klocs
-        || show_raw_loc
-        || show_words
-        || show_chars
-        || show_bytes);
-
-    if show_default {
-        show_actual_loc = true;
-        show_raw_loc = true;
-        show_words = true;
-        show_chars = true;
-        show_bytes = true;
-    } else {
-        if show_actual_klocs && show_actual_loc {
-            show_actual_loc = false;
-        }
-        if show_raw_klocs && show_raw_loc {
-            show_raw_loc = false;
+/// A --fail-if gate that failed, for the total or a language, or for a file or one of its
+/// lines, or a --budgets budget that was exceeded. Ordered by file and line
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Violation {
+    path: Option<PathBuf>,
+    line: Option<usize>,
+    /// What failed, e.g. "gate failed: file.actual_loc > 2000"
+    message: String,
+    value: i64,
+}
+
+impl Violation {
+    // Print the violation on stderr, or with --output github as a GitHub Actions annotation on
+    // stdout, which shows it on the file and line in pull requests
+    fn report(&self, github: bool) {
+        // Annotations need paths relative to the repository
+        let path = self.path.as_ref().map(|path| path.strip_prefix("./").unwrap_or(path).display().to_string());
+        if github {
+            let mut properties = Vec::new();
+            if let Some(path) = &path {
+                properties.push(format!("file={}", github_escape(path, true)));
+            }
+            if let Some(line) = self.line {
+                properties.push(format!("line={}", line));
+            }
+            properties.push("title=sourcelines".to_string());
+            let message = format!("{} (value {})", self.message, self.value);
+            println!("::warning {}::{}", properties.join(","), github_escape(&message, false));
+            return;
         }
+        let place = match (path, self.line) {
+            (Some(path), Some(line)) => format!(" at {}:{}", path, line),
+            (Some(path), None) => format!(" in {}", path),
+            _ => String::new(),
+        };
+        eprintln!("sourcelines: {}{} (value {})", self.message, place, self.value);
     }
+}
 
-    let mut sum = Stats::default();
-    let mut per_lang_sum: std::collections::HashMap<String, Stats> =
-        std::collections::HashMap::new();
-    let mut file_stats: Vec<(Stats, String, String, bool)> = Vec::new(); // (stats, lang, arg, is_dir)
-    for arg in files {
-        let path = Path::new(arg);
-        if path.is_dir() {
-            let dir_obj = if use_ignorelist {
-                let mut dir_obj = DirObject::new(path.to_path_buf(), None);
-                dir_obj.load_ignore_file(".gitignore");
-                Some(Rc::new(dir_obj))
-            } else {
-                None
-            };
-            let (dir_stats, lang_map) =
-                process_dir_lang_filtered(path, recursive, follow_symlinks, &exclude_set, include_set.as_ref(), dir_obj.as_ref());
-            sum = add_stats(sum, dir_stats.clone());
-            // Save per-language sums for verbose mode
-            for (lang, stats) in lang_map.iter() {
-                let entry = per_lang_sum.entry(lang.clone()).or_default();
-                *entry = add_stats(entry.clone(), stats.clone());
+// The total and language GATES that REPORT fails, and the BUDGETS it exceeds, whose use is
+// printed at debug level
+fn run_violations(
+    report: &RunReport,
+    gates: &[Gate],
+    baseline: Option<&std::collections::HashMap<String, Stats>>,
+    budgets: Option<&budget::Budgets>,
+    log_level: LogLevel,
+) -> Vec<Violation> {
+    let mut failed = Vec::new();
+    for gate in gates.iter().filter(|gate| matches!(gate.subject, GateSubject::Total | GateSubject::Lang(_))) {
+        let value = gate.value(report, baseline);
+        if gate.trips(value) {
+            let message = format!("gate failed: {}", gate.expr);
+            failed.push(Violation { path: None, line: None, message, value });
+        }
+    }
+    if let Some(budgets) = budgets {
+        for budget in budgets.results(&report.langs()) {
+            if log_level >= LogLevel::Debug {
+                eprintln!("sourcelines: budget {}: {} of {} actual LOC", budget.name, budget.used, budget.limit);
+            }
+            if budget.used > budget.limit {
+                let file = budgets.file.display();
+                let message = format!("over budget: {} = {} in {}", budget.name, budget.limit, file);
+                failed.push(Violation { path: None, line: None, message, value: budget.used as i64 });
             }
-            file_stats.push((dir_stats, "*".to_string(), arg.clone(), true));
-        } else {
-            let stats = process_file(path);
-            sum = add_stats(sum, stats.clone());
-            let lang = detect_language(path);
-            file_stats.push((stats, lang, arg.clone(), false));
         }
     }
+    failed
+}
 
-    if (verbose || !show_sum) && !text_mode && !html_mode && !latex_mode && !pdf_mode && !markdown_mode {
-        // Print all file stats
-        for (stats, lang, arg, is_dir) in &file_stats {
-            print_stats(
-                stats,
-                lang,
-                Some(arg.as_str()),
-                show_actual_klocs,
-                show_actual_loc,
-                show_raw_klocs,
-                show_raw_loc,
-                show_words,
-                show_chars,
-                show_bytes,
-                false,
-                color,
-            );
-            if *is_dir && verbose {
-                // For directories, print per-language sum
-                let path = Path::new(arg);
-                let dir_obj = if use_ignorelist {
-                    let mut dir_obj = DirObject::new(path.to_path_buf(), None);
-                    dir_obj.load_ignore_file(".gitignore");
-                    Some(Rc::new(dir_obj))
-                } else {
-                    None
-                };
-                let (_, lang_map) =
-                    process_dir_lang_filtered(path, recursive, follow_symlinks, &exclude_set, include_set.as_ref(), dir_obj.as_ref());
-
-                // Sort grouped (per-language) results by the first visible column in descending order
-                let first_col_value = |s: &Stats| -> usize {
-                    if show_actual_klocs {
-                        s.actual_loc
-                    } else if show_actual_loc {
-                        s.actual_loc
-                    } else if show_raw_klocs {
-                        s.raw_loc
-                    } else if show_raw_loc {
-                        s.raw_loc
-                    } else if show_words {
-                        s.words
-                    } else if show_chars {
-                        s.chars
-                    } else {
-                        s.bytes
-                    }
-                };
+// Escape S for a GitHub Actions workflow command, as the value of a property or as the message
+fn github_escape(s: &str, property: bool) -> String {
+    let s = s.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A");
+    if property { s.replace(':', "%3A").replace(',', "%2C") } else { s }
+}
 
-                let mut items: Vec<(&String, &Stats)> = lang_map.iter().collect();
-                // Filter out languages with zero counts
-                items.retain(|(_, stats)| {
-                    stats.actual_loc > 0
-                        || stats.raw_loc > 0
-                        || stats.words > 0
-                        || stats.chars > 0
-                        || stats.bytes > 0
-                });
-                items.sort_by(|(la, sa), (lb, sb)| {
-                    let ka = first_col_value(sa);
-                    let kb = first_col_value(sb);
-                    kb.cmp(&ka).then_with(|| la.cmp(lb))
-                });
+/// Checks the file gates of --fail-if on each file counted, and adds it to the --budgets
+struct FileGates {
+    gates: Vec<Gate>,
+    budgets: Option<Arc<budget::Budgets>>,
+    violations: Arc<Mutex<Vec<Violation>>>,
+}
 
-                for (lang, stats) in items.into_iter() {
-                    print_stats(
-                        stats,
-                        lang,
-                        None,
-                        show_actual_klocs,
-                        show_actual_loc,
-                        show_raw_klocs,
-                        show_raw_loc,
-                        show_words,
-                        show_chars,
-                        show_bytes,
-                        false,
-                        color,
-                    );
-                }
+impl Visitor for FileGates {
+    fn file_counted(&self, file: &FileReport) {
+        if let Some(budgets) = &self.budgets {
+            budgets.add(&file.path, &file.stats);
+        }
+        for gate in &self.gates {
+            let value = stat_field(&file.stats, &gate.field) as i64;
+            if gate.trips(value) {
+                let path = Some(file.path.clone());
+                let violation = Violation { path, line: None, message: format!("gate failed: {}", gate.expr), value };
+                self.violations.lock().unwrap().push(violation);
             }
         }
     }
+}
 
-    // Print output according to -s and -v, or report format modes
-    if pdf_mode {
-        print_pdf_report(&sum, &per_lang_sum, show_default, show_actual_klocs, show_actual_loc, show_raw_klocs, show_raw_loc, show_words, show_chars, show_bytes);
-    } else if latex_mode {
-        print_latex_report(&sum, &per_lang_sum, show_default, show_actual_klocs, show_actual_loc, show_raw_klocs, show_raw_loc, show_words, show_chars, show_bytes);
-    } else if html_mode {
-        print_html_report(&sum, &per_lang_sum, show_default, show_actual_klocs, show_actual_loc, show_raw_klocs, show_raw_loc, show_words, show_chars, show_bytes);
-    } else if markdown_mode {
-        print_markdown_report(&sum, &per_lang_sum, show_default, show_actual_klocs, show_actual_loc, show_raw_klocs, show_raw_loc, show_words, show_chars, show_bytes);
-    } else if text_mode {
-        print_text_report(&sum, &per_lang_sum, show_default, show_actual_klocs, show_actual_loc, show_raw_klocs, show_raw_loc, show_words, show_chars, show_bytes);
-    } else if show_sum {
-        // Always print global sum at end
-        print_stats(
-            &sum,
-            "*",
-            Some("(sum)"),
-            show_actual_klocs || (show_default && !show_actual_loc),
-            show_actual_loc || (show_default && !show_actual_klocs),
-            show_raw_klocs || (show_default && !show_raw_loc),
-            show_raw_loc || (show_default && !show_raw_klocs),
-            show_words || show_default,
-            show_chars || show_default,
-            show_bytes || show_default,
-            true,
-            color,
-        );
+/// Checks the line gates of --fail-if on each line of a file, as a metric
+struct LineGates {
+    gates: Arc<Vec<Gate>>,
+    violations: Arc<Mutex<Vec<Violation>>>,
+    path: PathBuf,
+}
+
+impl Metric for LineGates {
+    fn begin_file(&mut self, path: &Path, _lang: &str) {
+        self.path = path.to_path_buf();
     }
 
-    // Like process_dir, but returns (total_stats, per_language_map), with filtering
-    fn process_dir_lang_filtered(
-        path: &Path,
-        recursive: bool,
-        follow_symlinks: bool,
-        exclude_set: &GlobSet,
-        include_set: Option<&GlobSet>,
-        parent_dir_obj: Option<&Rc<DirObject>>,
-    ) -> (Stats, std::collections::HashMap<String, Stats>) {
-        let mut total = Stats::default();
-        let mut lang_map: std::collections::HashMap<String, Stats> =
-            std::collections::HashMap::new();
-        
-        // Create DirObject for this directory if ignorelist is enabled
-        let dir_obj = if let Some(parent) = parent_dir_obj {
-            // Check if ignorelist is enabled (parent exists means it's enabled)
-            let mut dir_obj = DirObject::new(path.to_path_buf(), Some(parent.clone()));
-            dir_obj.load_ignore_file(".gitignore");
-            Some(Rc::new(dir_obj))
-        } else {
-            None
-        };
-        
-        let entries = match fs::read_dir(path) {
-            Ok(e) => e,
-            Err(_) => return (total, lang_map),
-        };
-        for entry in entries.flatten() {
-            let p = entry.path();
-            let fname = p.file_name().and_then(|s| s.to_str()).unwrap_or("");
-            let is_excluded =
-                exclude_set.is_match(fname) && include_set.map_or(true, |inc| !inc.is_match(fname));
-            if is_excluded {
-                continue;
-            }
-            
-            // Check ignore list if enabled
-            if let Some(ref dir_obj) = dir_obj {
-                let is_dir_entry = p.is_dir();
-                if !dir_obj.include_test(&p, is_dir_entry) {
-                    continue;
-                }
-            }
-            
-            // Check if it's a symlink
-            let is_symlink = fs::symlink_metadata(&p)
-                .map(|m| m.file_type().is_symlink())
-                .unwrap_or(false);
-            
-            // Skip symlinks if follow_symlinks is false
-            if is_symlink && !follow_symlinks {
-                continue;
+    fn observe_line(&mut self, line: &LineInfo) {
+        let text = line.text.trim_end_matches(['\n', '\r']);
+        for gate in self.gates.iter() {
+            let value = if gate.field == "bytes" { text.len() } else { text.chars().count() } as i64;
+            if gate.trips(value) {
+                let (path, line) = (Some(self.path.clone()), Some(line.number));
+                let violation = Violation { path, line, message: format!("gate failed: {}", gate.expr), value };
+                self.violations.lock().unwrap().push(violation);
             }
-            
-            if recursive && p.is_dir() {
-                let (dir_stats, dir_lang_map) =
-                    process_dir_lang_filtered(&p, true, follow_symlinks, exclude_set, include_set, dir_obj.as_ref());
-                total = add_stats(total, dir_stats.clone());
-                for (lang, stats) in dir_lang_map {
-                    let entry = lang_map.entry(lang).or_default();
-                    *entry = add_stats(entry.clone(), stats);
-                }
-            } else if p.is_file() {
-                let stats = process_file(&p);
-                let lang = detect_language(&p);
-                let entry = lang_map.entry(lang).or_default();
-                *entry = add_stats(entry.clone(), stats.clone());
-                total = add_stats(total, stats);
+        }
+    }
+
+    // Its value is not shown, the violations are
+    fn finish(&self) -> f64 {
+        0.0
+    }
+}
+
+// Expand the 90d/12w/6m/1y shorthands into dates git understands
+fn parse_since(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    let unit = match s.char_indices().last() {
+        Some((i, unit @ ('d' | 'w' | 'm' | 'y'))) if i > 0 && s[..i].bytes().all(|b| b.is_ascii_digit()) => {
+            match unit {
+                'd' => "days",
+                'w' => "weeks",
+                'm' => "months",
+                _ => "years",
             }
         }
-        (total, lang_map)
+        _ => return Ok(s.to_string()),
+    };
+    Ok(format!("{} {} ago", &s[..s.len() - 1], unit))
+}
+
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (digits, multiplier) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 1024),
+        Some((i, 'm' | 'M')) => (&s[..i], 1024 * 1024),
+        Some((i, 'g' | 'G')) => (&s[..i], 1024 * 1024 * 1024),
+        _ => (s, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size '{}'", s))
+}
+
+// Parse the --line-cap SIZE, which cannot be 0
+fn parse_line_cap(s: &str) -> Result<u64, String> {
+    match parse_size(s)? {
+        0 => Err("the line cap must be at least 1 byte".to_string()),
+        cap => Ok(cap),
     }
+}
+
+/// Which diagnostics are printed on stderr, with --log-level; results always go to stdout
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum LogLevel {
+    /// Only errors and failed --fail-if gates (-q/--quiet)
+    Error,
+    /// Also notes about files left out of the counts
+    Warn,
+    /// Also progress and other notes (default)
+    Info,
+    /// Also every file or directory skipped, with the reason (default with -v)
+    Debug,
+}
+
+/// Column that --sort orders rows by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortColumn {
+    ActualLoc,
+    RawLoc,
+    Comments,
+    Words,
+    Chars,
+    Bytes,
+    Files,
+    Name,
+}
+
+/// Order of file, language and directory rows; ties are broken by name
+#[derive(Debug, Clone, Copy)]
+struct SortOrder {
+    column: SortColumn,
+    descending: bool,
+}
 
-    fn build_globset(patterns: &[String]) -> GlobSet {
-        let mut builder = GlobSetBuilder::new();
-        for pat in patterns {
-            // Accept both literal and glob patterns
-            let g = Glob::new(pat).unwrap_or_else(|_| Glob::new(&glob_escape(pat)).unwrap());
-            builder.add(g);
+impl SortColumn {
+    /// The count in this column; names have none
+    fn value(self, s: &Stats) -> usize {
+        match self {
+            SortColumn::ActualLoc => s.actual_loc,
+            SortColumn::RawLoc => s.raw_loc,
+            SortColumn::Comments => s.comments,
+            SortColumn::Words => s.words,
+            SortColumn::Chars => s.chars,
+            SortColumn::Bytes => s.bytes,
+            SortColumn::Files => s.files,
+            SortColumn::Name => 0,
         }
-        builder.build().unwrap()
     }
 
-    fn glob_escape(s: &str) -> String {
-        // Escape all special glob characters
-        let mut out = String::new();
-        for c in s.chars() {
-            match c {
-                '*' | '?' | '[' | ']' | '{' | '}' | '!' | '(' | ')' | '|' | '^' | '$' | '+'
-                | '.' | '#' => {
-                    out.push('[');
-                    out.push(c);
-                    out.push(']');
-                }
-                _ => out.push(c),
-            }
+    /// Share of `total` in this column that `stats` has, in percent
+    fn percent(self, stats: &Stats, total: &Stats) -> f64 {
+        match self.value(total) {
+            0 => 0.0,
+            t => self.value(stats) as f64 * 100.0 / t as f64,
         }
-        out
     }
 }
 
+impl SortOrder {
+    fn compare(&self, (name_a, a): (&str, &Stats), (name_b, b): (&str, &Stats)) -> std::cmp::Ordering {
+        let ord = match self.column {
+            SortColumn::Name => name_a.cmp(name_b),
+            _ => self.column.value(a).cmp(&self.column.value(b)),
+        };
+        let ord = if self.descending { ord.reverse() } else { ord };
+        ord.then_with(|| name_a.cmp(name_b))
+    }
+}
+
+// Parse "COLUMN[:asc|desc]"; counts sort largest first and names alphabetically by default
+fn parse_sort(s: &str) -> Result<SortOrder, String> {
+    let (column, direction) = match s.split_once(':') {
+        Some((column, direction)) => (column, Some(direction)),
+        None => (s, None),
+    };
+    let column = match column {
+        "loc" | "actual-loc" => SortColumn::ActualLoc,
+        "raw-loc" => SortColumn::RawLoc,
+        "comments" => SortColumn::Comments,
+        "words" => SortColumn::Words,
+        "chars" => SortColumn::Chars,
+        "bytes" => SortColumn::Bytes,
+        "files" => SortColumn::Files,
+        "name" => SortColumn::Name,
+        _ => {
+            return Err(format!(
+                "unknown column '{}' (expected loc, raw-loc, comments, words, chars, bytes, files or name)",
+                column
+            ));
+        }
+    };
+    let descending = match direction {
+        None => column != SortColumn::Name,
+        Some("asc") => false,
+        Some("desc") => true,
+        Some(other) => return Err(format!("unknown direction '{}' (expected asc or desc)", other)),
+    };
+    Ok(SortOrder { column, descending })
+}
+
+/// A number column of the output, as selected by --columns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Column {
+    ActualKlocs,
+    ActualLoc,
+    RawKlocs,
+    RawLoc,
+    Comments,
+    Words,
+    Chars,
+    Bytes,
+    Files,
+}
+
+impl Column {
+    /// The count this column shows, for --sort, --percent and --chart
+    fn sort_column(self) -> SortColumn {
+        match self {
+            Column::ActualKlocs | Column::ActualLoc => SortColumn::ActualLoc,
+            Column::RawKlocs | Column::RawLoc => SortColumn::RawLoc,
+            Column::Comments => SortColumn::Comments,
+            Column::Words => SortColumn::Words,
+            Column::Chars => SortColumn::Chars,
+            Column::Bytes => SortColumn::Bytes,
+            Column::Files => SortColumn::Files,
+        }
+    }
+
+    fn format(self, s: &Stats, numbers: NumberFormat) -> String {
+        match self {
+            Column::ActualKlocs => numbers.fixed(s.actual_loc as f64 / 1000.0, 3),
+            Column::RawKlocs => numbers.fixed(s.raw_loc as f64 / 1000.0, 3),
+            _ => numbers.integer(self.sort_column().value(s)),
+        }
+    }
+
+    /// Heading of the column in report tables
+    fn title(self) -> &'static str {
+        match self {
+            Column::ActualKlocs => "Actual KLOC",
+            Column::ActualLoc => "Actual LOC",
+            Column::RawKlocs => "Raw KLOC",
+            Column::RawLoc => "Raw LOC",
+            Column::Comments => "Comments",
+            Column::Words => "Words",
+            Column::Chars => "Chars",
+            Column::Bytes => "Bytes",
+            Column::Files => "Files",
+        }
+    }
+
+    /// Name of the total in report summaries
+    fn label(self) -> &'static str {
+        match self {
+            Column::ActualKlocs => "Actual Lines of Code (KLOC)",
+            Column::ActualLoc => "Actual Lines of Code",
+            Column::RawKlocs => "Raw Lines of Code (KLOC)",
+            Column::RawLoc => "Raw Lines of Code",
+            Column::Comments => "Comment Lines",
+            Column::Words => "Words",
+            Column::Chars => "Characters",
+            Column::Bytes => "Bytes",
+            Column::Files => "Files",
+        }
+    }
+
+    fn style(self) -> Style {
+        match self {
+            Column::ActualKlocs | Column::ActualLoc => Style::Cyan,
+            Column::RawKlocs | Column::RawLoc => Style::Green,
+            Column::Words => Style::Yellow,
+            Column::Chars => Style::Magenta,
+            Column::Bytes => Style::Blue,
+            Column::Comments | Column::Files => Style::Faint,
+        }
+    }
+}
+
+// Parse one name of the --columns list; "_" and "-" are interchangeable
+fn parse_column(s: &str) -> Result<Column, String> {
+    Ok(match s.trim().replace('_', "-").as_str() {
+        "actual-klocs" | "klocs" => Column::ActualKlocs,
+        "actual-loc" | "loc" => Column::ActualLoc,
+        "raw-klocs" => Column::RawKlocs,
+        "raw-loc" => Column::RawLoc,
+        "comments" => Column::Comments,
+        "words" => Column::Words,
+        "chars" => Column::Chars,
+        "bytes" => Column::Bytes,
+        "files" => Column::Files,
+        _ => {
+            return Err(format!(
+                "unknown column '{}' (expected actual_klocs, actual_loc, raw_klocs, raw_loc, comments, words, chars, bytes or files)",
+                s
+            ));
+        }
+    })
+}
+
+/// Digit grouping and decimal separator of the numbers in rows and reports, from --locale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct NumberFormat {
+    group: Option<char>,
+    decimal: char,
+}
+
+impl NumberFormat {
+    /// Plain digits and a decimal point, as machine-readable output always has
+    const C: NumberFormat = NumberFormat { group: None, decimal: '.' };
+
+    fn integer(self, n: usize) -> String {
+        self.grouped(&n.to_string())
+    }
+
+    fn fixed(self, x: f64, precision: usize) -> String {
+        let s = format!("{:.*}", precision, x);
+        match s.split_once('.') {
+            Some((int, frac)) => format!("{}{}{}", self.grouped(int), self.decimal, frac),
+            None => self.grouped(&s),
+        }
+    }
+
+    /// DIGITS with the group separator between each group of three
+    fn grouped(self, digits: &str) -> String {
+        let Some(sep) = self.group else {
+            return digits.to_string();
+        };
+        let (sign, digits) = digits.split_at(digits.len() - digits.trim_start_matches('-').len());
+        let mut out = sign.to_string();
+        for (i, c) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i) % 3 == 0 {
+                out.push(sep);
+            }
+            out.push(c);
+        }
+        out
+    }
+}
+
+// Parse a --locale name such as "de_DE.UTF-8"; "auto" takes it from LC_ALL, LC_NUMERIC or LANG
+fn parse_locale(s: &str) -> Result<NumberFormat, String> {
+    if s == "auto" {
+        let name = ["LC_ALL", "LC_NUMERIC", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty());
+        return Ok(name.and_then(|name| parse_locale(&name).ok()).unwrap_or(NumberFormat::C));
+    }
+    let name = s.split(['.', '@']).next().unwrap_or(s);
+    let (lang, territory) = name.split_once(['_', '-']).unwrap_or((name, ""));
+    let format = |group, decimal| NumberFormat { group: Some(group), decimal };
+    Ok(match (lang, territory) {
+        ("C" | "POSIX", _) => NumberFormat::C,
+        ("de" | "it", "CH") => format('\'', '.'),
+        ("pt", "PT") => format('\u{a0}', ','),
+        ("en" | "ja" | "zh" | "ko" | "he" | "th" | "ga" | "ms" | "hi", _) => format(',', '.'),
+        ("de" | "nl" | "it" | "es" | "pt" | "id" | "da" | "tr" | "el" | "ro" | "hr" | "sl" | "sr", _) => {
+            format('.', ',')
+        }
+        (
+            "fr" | "ru" | "pl" | "cs" | "sk" | "sv" | "fi" | "nb" | "nn" | "no" | "uk" | "hu" | "bg" | "et"
+            | "lt" | "lv" | "be" | "kk",
+            _,
+        ) => format('\u{a0}', ','),
+        _ => return Err(format!("unknown locale '{}' (expected e.g. C, en_US, de_DE, fr_FR or auto)", s)),
+    })
+}
+
+/// Note on stderr what was skipped and why, unless `level` is below warn; returns the
+/// number of files and directories that could not be read
+fn report_skipped(skipped: &Skipped, level: LogLevel) -> usize {
+    let plural = |n: usize| if n == 1 { "" } else { "s" };
+    let Skipped { excluded, size, symlinks, unreadable, binary } = *skipped;
+    if level < LogLevel::Warn {
+        return unreadable;
+    }
+    if binary > 0 {
+        eprintln!(
+            "sourcelines: skipped {} binary file{} (use --count-binary to include their sizes)",
+            binary,
+            plural(binary)
+        );
+    }
+    let mut parts = Vec::new();
+    if excluded > 0 {
+        parts.push(format!("{} excluded entr{}", excluded, if excluded == 1 { "y" } else { "ies" }));
+    }
+    if size > 0 {
+        parts.push(format!("{} file{} outside the size limits", size, plural(size)));
+    }
+    if symlinks > 0 {
+        parts.push(format!("{} symlink{}", symlinks, plural(symlinks)));
+    }
+    if !parts.is_empty() {
+        eprintln!("sourcelines: skipped {} (use --verbose to list them)", parts.join(", "));
+    }
+    if unreadable > 0 {
+        eprintln!(
+            "sourcelines: could not read {} file{} or director{} (use --verbose to list them)",
+            unreadable,
+            plural(unreadable),
+            if unreadable == 1 { "y" } else { "ies" }
+        );
+    }
+    unreadable
+}
+
+/// Runs shorter than this show no progress
+const PROGRESS_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+/// Minimum time between redraws of the status line
+const PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Status line on stderr with the files counted so far, the current directory and,
+/// when the number of files is known, the estimated time left
+struct Progress {
+    start: std::time::Instant,
+    /// When the status line was last drawn
+    drawn: Mutex<Option<std::time::Instant>>,
+    files: std::sync::atomic::AtomicUsize,
+    total: Option<usize>,
+}
+
+impl Progress {
+    fn new(total: Option<usize>) -> Self {
+        Progress {
+            start: std::time::Instant::now(),
+            drawn: Mutex::new(None),
+            files: std::sync::atomic::AtomicUsize::new(0),
+            total,
+        }
+    }
+
+    /// A callback for Options::progress that updates this status line
+    fn callback(self: &Arc<Self>) -> FileCallback {
+        let progress = self.clone();
+        Arc::new(move |path| progress.file_counted(path))
+    }
+
+    fn file_counted(&self, path: &Path) {
+        let files = self.files.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+        let now = std::time::Instant::now();
+        let elapsed = now - self.start;
+        if elapsed < PROGRESS_DELAY {
+            return;
+        }
+        // Another thread is drawing
+        let Ok(mut drawn) = self.drawn.try_lock() else {
+            return;
+        };
+        if drawn.is_some_and(|t| now - t < PROGRESS_INTERVAL) {
+            return;
+        }
+        *drawn = Some(now);
+        let mut line = format!("{} files", files);
+        if let Some(total) = self.total.filter(|&t| t >= files) {
+            let left = elapsed.mul_f64((total - files) as f64 / files as f64);
+            line += &format!(" of {}, {}s left", total, left.as_secs());
+        }
+        let dir = path.parent().unwrap_or(path).display().to_string();
+        line += &format!(" in {}", dir);
+        // Keep the line on one 80-column row, dropping the start of the directory
+        let chars: Vec<char> = line.chars().collect();
+        if chars.len() > 79 {
+            line = format!("...{}", chars[chars.len() - 76..].iter().collect::<String>());
+        }
+        eprint!("\r\x1b[K{}", line);
+    }
+
+    /// Erase the status line, if one was drawn
+    fn finish(&self) {
+        if self.drawn.lock().unwrap().is_some() {
+            eprint!("\r\x1b[K");
+        }
+    }
+}
+
+/// How long --watch waits for a burst of changes (such as a checkout) to end
+const WATCH_SETTLE: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// Change notifications for the arguments of --watch
+struct Watch {
+    _watcher: notify::RecommendedWatcher,
+    events: std::sync::mpsc::Receiver<notify::Result<notify::Event>>,
+    /// Canonical argument paths, and whether they are directories
+    roots: Vec<(PathBuf, bool)>,
+    /// The --cache file and its temporary copy, written by each run itself
+    cache: Vec<PathBuf>,
+}
+
+impl Watch {
+    fn new(paths: &[PathBuf], recursive: bool, cache: Option<&str>) -> notify::Result<Self> {
+        use notify::{RecursiveMode, Watcher};
+        let (tx, events) = std::sync::mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        let mut roots = Vec::new();
+        for path in paths {
+            let root = fs::canonicalize(path)?;
+            if root.is_dir() {
+                let mode = if recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+                watcher.watch(&root, mode)?;
+                roots.push((root, true));
+            } else {
+                // Editors often replace a file instead of writing to it, so watch its directory
+                watcher.watch(root.parent().unwrap_or(&root), RecursiveMode::NonRecursive)?;
+                roots.push((root, false));
+            }
+        }
+        let cache = cache
+            .and_then(|file| {
+                let path = Path::new(file);
+                let dir = path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+                Some(fs::canonicalize(dir).ok()?.join(path.file_name()?))
+            })
+            .map(|file| vec![file.with_added_extension("tmp"), file])
+            .unwrap_or_default();
+        Ok(Watch {
+            _watcher: watcher,
+            events,
+            roots,
+            cache,
+        })
+    }
+
+    /// Whether `event` touches a file or directory that would be counted
+    fn relevant(&self, event: notify::Result<notify::Event>, opts: &Options) -> notify::Result<bool> {
+        let event = event?;
+        if event.kind.is_access() {
+            return Ok(false);
+        }
+        Ok(event.paths.iter().any(|path| {
+            !self.cache.contains(path)
+                && self.roots.iter().any(|(root, is_dir)| match is_dir {
+                    true => path.starts_with(root) && opts.path_selected(path, root),
+                    false => path == root,
+                })
+        }))
+    }
+
+    /// Block until something relevant changes and the changes have settled
+    fn wait(&self, opts: &Options) -> notify::Result<()> {
+        let stopped = || notify::Error::generic("the watcher stopped");
+        while !self.relevant(self.events.recv().map_err(|_| stopped())?, opts)? {}
+        while let Ok(event) = self.events.recv_timeout(WATCH_SETTLE) {
+            self.relevant(event, opts)?;
+        }
+        Ok(())
+    }
+
+    /// Whether something relevant changed since the last call, without blocking
+    #[cfg(feature = "tui")]
+    fn poll(&self, opts: &Options) -> notify::Result<bool> {
+        let mut changed = false;
+        while let Ok(event) = self.events.try_recv() {
+            changed |= self.relevant(event, opts)?;
+        }
+        Ok(changed)
+    }
+}
+
+/// Exit status when some file or directory could not be read and the counts are partial
+/// (2 is used for usage and other errors, which stop the run)
+const UNREADABLE_EXIT_CODE: i32 = 1;
+
+/// When to color the output of -C/--color
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ColorMode {
+    /// Color if stdout is a terminal, unless NO_COLOR is set or CLICOLOR_FORCE forces it
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    fn enabled(self) -> bool {
+        let set = |name: &str| std::env::var_os(name).is_some_and(|v| !v.is_empty() && v != "0");
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto if set("CLICOLOR_FORCE") => true,
+            ColorMode::Auto => std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && io::stdout().is_terminal(),
+        }
+    }
+}
+
+/// Glyphs of --icons
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum IconSet {
+    /// Emoji, which most terminals can show
+    Emoji,
+    /// Nerd Font symbols, for terminals using a patched font
+    Nerd,
+}
+
+/// (language, emoji, Nerd Font glyph) of --icons
+const LANGUAGE_ICONS: &[(&str, &str, &str)] = &[
+    ("rust", "🦀", "\u{e7a8}"),
+    ("c", "🔧", "\u{e61e}"),
+    ("cpp", "🔩", "\u{e61d}"),
+    ("python", "🐍", "\u{e606}"),
+    ("shell", "🐚", "\u{f489}"),
+    ("perl", "🐪", "\u{e769}"),
+    ("javascript", "🟨", "\u{e74e}"),
+    ("typescript", "🟦", "\u{e628}"),
+    ("java", "☕", "\u{e738}"),
+    ("css", "🎨", "\u{e749}"),
+    ("html", "🌐", "\u{e736}"),
+    ("xml", "📰", "\u{f05c0}"),
+    ("php", "🐘", "\u{e73d}"),
+    ("go", "🐹", "\u{e626}"),
+    ("scala", "🔺", "\u{e737}"),
+    ("kotlin", "🟪", "\u{e634}"),
+    ("sql", "💾", "\u{e706}"),
+    ("haskell", "🎓", "\u{e777}"),
+    ("literate-haskell", "🎓", "\u{e777}"),
+    ("tex", "📐", "\u{e69b}"),
+    ("md", "📝", "\u{e73e}"),
+    ("text", "📄", "\u{f15c}"),
+    ("yaml", "📋", "\u{e6a8}"),
+    ("config", "🔨", "\u{e615}"),
+];
+
+/// Glyph in front of the rows of each language, with --icons
+struct Icons {
+    glyphs: std::collections::HashMap<String, String>,
+    /// For languages without a glyph of their own
+    default: String,
+    /// Terminal columns taken by the widest glyph
+    width: usize,
+}
+
+impl Icons {
+    /// The glyphs of SET, replaced or extended by the --icon LANG=GLYPH pairs
+    fn new(set: IconSet, overrides: &[(String, String)]) -> Self {
+        let pick = |(emoji, nerd): (&str, &str)| match set {
+            IconSet::Emoji => emoji.to_string(),
+            IconSet::Nerd => nerd.to_string(),
+        };
+        let mut glyphs: std::collections::HashMap<String, String> = LANGUAGE_ICONS
+            .iter()
+            .map(|(lang, emoji, nerd)| (lang.to_string(), pick((emoji, nerd))))
+            .collect();
+        glyphs.extend(overrides.iter().cloned());
+        let default = pick(("📄", "\u{f15b}"));
+        let width = glyphs.values().chain([&default]).map(|g| g.width()).max().unwrap_or(0);
+        Icons { glyphs, default, width }
+    }
+
+    /// The glyph of LANG padded to the width of the widest one; blank for sums and directories
+    fn cell(&self, lang: Option<&str>) -> String {
+        let glyph = match lang {
+            Some(lang) => self.glyphs.get(lang).unwrap_or(&self.default),
+            None => "",
+        };
+        format!("{}{}", glyph, " ".repeat(self.width.saturating_sub(glyph.width())))
+    }
+}
+
+/// ANSI styles of the rows printed by print_stats
+#[derive(Clone, Copy, Debug)]
+enum Style {
+    Cyan,
+    Green,
+    Yellow,
+    Magenta,
+    Blue,
+    Faint,
+}
+
+impl Style {
+    /// TEXT wrapped in this style and a reset, or unchanged when COLOR is off
+    fn paint(self, text: &str, color: bool) -> String {
+        if !color {
+            return text.to_string();
+        }
+        let code = match self {
+            Style::Cyan => "36",
+            Style::Green => "32",
+            Style::Yellow => "33",
+            Style::Magenta => "35",
+            Style::Blue => "34",
+            Style::Faint => "2",
+        };
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Report actual LOC added, removed and changed per language between two git revisions
+    Diff {
+        /// Old revision
+        rev1: String,
+        /// New revision
+        rev2: String,
+    },
+    /// Report actual LOC differences per file and per language between two directory trees
+    Compare {
+        /// Old tree
+        dir_a: PathBuf,
+        /// New tree
+        dir_b: PathBuf,
+    },
+    /// List the known languages with their extensions, shebang words and comment syntax
+    Languages,
+    /// Print a summary for use in a git hook
+    Hook {
+        /// Hook to produce output for
+        #[arg(value_enum)]
+        hook: HookKind,
+    },
+    /// Print a time series of per-language actual LOC over the git history
+    History {
+        /// Only consider commits more recent than DATE (anything git log --since accepts)
+        #[arg(long = "since", value_name = "DATE")]
+        since: Option<String>,
+        /// Length of each step of the series
+        #[arg(long = "interval", value_enum, default_value_t = Interval::Week)]
+        interval: Interval,
+        /// Output format
+        #[arg(long = "format", value_enum, default_value_t = SeriesFormat::Csv)]
+        format: SeriesFormat,
+        /// Revision whose history is walked
+        #[arg(default_value = "HEAD")]
+        rev: String,
+    },
+    /// Print a completion script for SHELL on stdout
+    Completions {
+        /// Shell to complete for
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Print the man page, in roff, on stdout
+    Man,
+    /// Check the configuration file, and print the options in effect
+    Config {
+        #[arg(value_enum)]
+        action: ConfigAction,
+    },
+    /// Answer POST /count requests over HTTP with JSON reports of a path or an uploaded archive
+    Serve {
+        /// Address and port to listen on
+        #[arg(long = "listen", value_name = "ADDR", default_value = "127.0.0.1:7878")]
+        listen: String,
+        /// Directory whose paths may be counted, repeatable; paths outside of them are refused.
+        /// Defaults to the current directory
+        #[arg(long = "root", value_name = "DIR")]
+        roots: Vec<PathBuf>,
+    },
+    /// Download a package from crates.io and count it, e.g. serde@1.0.200
+    Crate {
+        /// Name of the crate, and the version (by default, the latest stable one)
+        #[arg(value_name = "NAME[@VERSION]")]
+        spec: String,
+    },
+    /// Count trees periodically, and append each snapshot to a sink for trend dashboards
+    Daemon {
+        /// Interval between snapshots, such as 90s, 15m, 1h or 1d
+        #[arg(long = "every", value_name = "INTERVAL", value_parser = daemon::parse_every, default_value = "1h")]
+        every: std::time::Duration,
+        /// Trees to count, each recursively
+        #[arg(long = "paths", value_name = "PATH", num_args = 1.., value_delimiter = ',', required = true)]
+        paths: Vec<PathBuf>,
+        /// Where to append snapshots: sqlite:FILE or jsonl:FILE
+        #[arg(long = "sink", value_name = "SINK", value_parser = daemon::Sink::parse)]
+        sink: daemon::Sink,
+        /// Stop after N snapshots (by default, run until stopped)
+        #[arg(long = "snapshots", value_name = "N")]
+        snapshots: Option<usize>,
+    },
+    /// Browse the directory tree with the counts of every directory (needs the tui feature)
+    Tui {
+        /// Directory to browse
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+    },
+    /// Write a reproducible tree of synthetic source files, for benchmarks
+    #[command(hide = true)]
+    GenerateTestTree {
+        /// Directory to create the files in
+        dir: PathBuf,
+        /// Number of files
+        #[arg(long = "files", value_name = "N", default_value_t = 1000)]
+        files: usize,
+        /// Average file size (K, M and G suffixes are accepted)
+        #[arg(long = "size", value_name = "SIZE", value_parser = parse_size, default_value = "4K")]
+        size: u64,
+        /// Extensions of the files, used in turn
+        #[arg(long = "langs", value_name = "EXT,...", value_delimiter = ',', default_value = "c,rs,py,js")]
+        langs: Vec<String>,
+        /// Seed of the generator; the same seed gives the same tree
+        #[arg(long = "seed", default_value_t = 1)]
+        seed: u64,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum HookKind {
+    /// Actual LOC staged for the next commit, compared with HEAD
+    PreCommit,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Html,
+    Latex,
+    Pdf,
+    Markdown,
+    /// The usual output, and a warning annotation for each failed --fail-if gate
+    Github,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ConfigAction {
+    /// Report unknown keys, invalid wildcards and conflicting remaps in every profile, and
+    /// print the options of the file, its --profile, the environment and the command line
+    Check,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Interval {
+    Day,
+    Week,
+    Month,
+}
+
+impl Interval {
+    /// strftime format naming the interval a commit date falls into
+    fn date_format(self) -> &'static str {
+        match self {
+            Interval::Day => "%Y-%m-%d",
+            Interval::Week => "%G-W%V",
+            Interval::Month => "%Y-%m",
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum SeriesFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "sourcelines",
+    version,
+    about = "Count source code statistics: actual lines of code, raw lines, words, chars, bytes.",
+    long_about = None,
+    after_help = "Exit status: 0 on success, 1 if some files could not be read, 2 on usage and other \
+                  errors, 3 if a --fail-if gate failed or a budget was exceeded.\n\nFor more details, see README.md."
+)]
+#[command(group(ArgGroup::new("columns").multiple(true)), disable_help_subcommand = true, args_override_self = true)]
+struct Cli {
+    /// Recursively process directories
+    #[arg(short = 'r', long = "recursive")]
+    recursive: bool,
+
+    /// Output summary line at the end
+    #[arg(short = 's', long = "sum")]
+    sum: bool,
+
+    /// With several arguments, also output a summary line for each of them before the total
+    #[arg(long = "per-arg-sum")]
+    per_arg_sum: bool,
+
+    /// Output only the language totals and the total, without keeping any per-file results
+    #[arg(long = "summary-only", conflicts_with_all = ["by_dir", "per_arg_sum", "list_only"])]
+    summary_only: bool,
+
+    /// Verbose output: with -s, print all file stats; for directories, print per-language summary
+    #[arg(short = 'v', long = "verbose")]
+    verbose: bool,
+
+    /// Print only errors on stderr, same as --log-level error
+    #[arg(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Diagnostics to print on stderr: error, warn, info (default) or debug (default with -v)
+    #[arg(long = "log-level", value_enum, value_name = "LEVEL")]
+    log_level: Option<LogLevel>,
+
+    /// Trace the walk on stderr: each directory and file with the time spent on it, and why
+    /// entries were skipped. RUST_LOG selects the events instead, e.g. RUST_LOG=sourcelines=warn
+    #[arg(long = "debug")]
+    debug: bool,
+
+    /// Color the output: auto (default), always or never; -C alone means always
+    #[arg(
+        short = 'C',
+        long = "color",
+        value_enum,
+        value_name = "WHEN",
+        num_args = 0..=1,
+        require_equals = true,
+        default_value_t = ColorMode::Auto,
+        default_missing_value = "always"
+    )]
+    color: ColorMode,
+
+    /// Prefix rows with a glyph for their language: emoji (default) or nerd (Nerd Font symbols)
+    #[arg(
+        long = "icons",
+        value_enum,
+        value_name = "SET",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "emoji"
+    )]
+    icons: Option<IconSet>,
+
+    /// With --icons, show GLYPH for language LANG, e.g. --icon rust=R (can be used multiple times)
+    #[arg(long = "icon", value_name = "LANG=GLYPH", value_parser = parse_icon, requires = "icons")]
+    icon: Vec<(String, String)>,
+
+    /// Exclude files/directories matching these wildcard patterns (can be used multiple times)
+    #[arg(long = "exclude", value_name = "WILDCARD", num_args = 0.., default_value = "")]
+    exclude: Vec<String>,
+
+    /// Include files/directories matching these wildcard patterns (can be used multiple times)
+    #[arg(long = "include", value_name = "WILDCARD", num_args = 0.., default_value = "")]
+    include: Vec<String>,
+
+    /// Exclude paths matching this regular expression (can be used multiple times)
+    #[arg(long = "exclude-regex", value_name = "REGEX")]
+    exclude_regex: Vec<Regex>,
+
+    /// Re-include paths matching this regular expression (can be used multiple times)
+    #[arg(long = "include-regex", value_name = "REGEX")]
+    include_regex: Vec<Regex>,
+
+    /// Remap a detected language to another one, e.g. jsp=java (can be used multiple times)
+    #[arg(long = "remap", value_name = "FROM=TO", value_parser = parse_remap)]
+    remap: Vec<(String, String)>,
+
+    /// Do not descend into directories on other file systems
+    #[arg(short = 'x', long = "one-file-system")]
+    one_file_system: bool,
+
+    /// Report files under vendor/, third_party/, deps/, ... under their own language instead of "vendored"
+    #[arg(long = "include-vendored")]
+    include_vendored: bool,
+
+    /// How to count git submodules and nested repositories
+    #[arg(long = "submodules", value_enum, value_name = "MODE", default_value_t = SubmoduleMode::Separate)]
+    submodules: SubmoduleMode,
+
+    /// Print test-code and production-code totals as two extra rows
+    #[arg(long = "split-tests")]
+    split_tests: bool,
+
+    /// Treat files matching this wildcard as test code, in addition to the defaults (can be used multiple times)
+    #[arg(long = "test-pattern", value_name = "WILDCARD")]
+    test_pattern: Vec<String>,
+
+    /// Include all hidden (dot-prefixed) files and directories, even default-excluded ones like .git
+    #[arg(long = "hidden", overrides_with = "no_hidden")]
+    hidden: bool,
+
+    /// Skip all hidden (dot-prefixed) files and directories
+    #[arg(long = "no-hidden", overrides_with = "hidden")]
+    no_hidden: bool,
+
+    /// Descend at most N directory levels below each argument (0 = files directly in it)
+    #[arg(long = "max-depth", value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Skip files larger than SIZE bytes (K, M and G suffixes are accepted)
+    #[arg(long = "max-file-size", value_name = "SIZE", value_parser = parse_size)]
+    max_file_size: Option<u64>,
+
+    /// Skip files smaller than SIZE bytes (K, M and G suffixes are accepted)
+    #[arg(long = "min-file-size", value_name = "SIZE", value_parser = parse_size)]
+    min_file_size: Option<u64>,
+
+    /// Skip files already counted in this run (hardlinks or identical content)
+    #[arg(long = "dedupe")]
+    dedupe: bool,
+
+    /// Count binary files too, contributing their size in bytes only
+    #[arg(long = "count-binary")]
+    count_binary: bool,
+
+    /// Always treat files with these extensions as binary and skip them (comma-separated)
+    #[arg(long = "binary-extensions", value_name = "EXT", value_delimiter = ',')]
+    binary_extensions: Vec<String>,
+
+    /// Match --exclude, --include and --test-pattern globs case-insensitively
+    #[arg(long = "iglob", visible_alias = "glob-case-insensitive")]
+    iglob: bool,
+
+    /// Count lines longer than SIZE bytes piece by piece, keeping only their first SIZE
+    /// bytes in memory (K, M and G suffixes are accepted)
+    #[arg(long = "line-cap", value_name = "SIZE", value_parser = parse_line_cap, default_value = "1M")]
+    line_cap: u64,
+
+    /// Walk directories and count files with N threads (default: one per CPU)
+    #[arg(short = 'j', long = "threads", value_name = "N", default_value_t = 0, hide_default_value = true)]
+    threads: usize,
+
+    /// Print only one row per language over all arguments, and the total
+    #[arg(long = "by-lang")]
+    by_lang: bool,
+
+    /// Also show each row's share of the total in the first visible column
+    #[arg(long = "percent", conflicts_with = "stream")]
+    percent: bool,
+
+    /// Count and print again whenever a file under the arguments changes
+    #[arg(long = "watch")]
+    watch: bool,
+
+    /// Make number columns at least N characters wide; they grow to fit the totals
+    #[arg(long = "width", value_name = "N", default_value_t = 8)]
+    width: usize,
+
+    /// Append a bar to each language row, proportional to the largest language in the
+    /// first visible column
+    #[arg(long = "chart")]
+    chart: bool,
+
+    /// Order file, language and directory rows by COLUMN (loc, raw-loc, words, chars, bytes
+    /// or name), optionally followed by :asc or :desc
+    #[arg(long = "sort", value_name = "COLUMN[:asc|desc]", value_parser = parse_sort)]
+    sort: Option<SortOrder>,
+
+    /// Also print a row for each subdirectory down to DEPTH levels (default 1) below
+    /// directory arguments, indented by level
+    #[arg(long = "by-dir", value_name = "DEPTH", num_args = 0..=1, require_equals = true, default_missing_value = "1")]
+    by_dir: Option<usize>,
+
+    /// Accepted and ignored, for compatibility: unreadable files and directories now always
+    /// give exit status 1 (--strict used to give 4)
+    #[arg(long = "strict")]
+    _strict: bool,
+
+    /// Print each argument's row as soon as it is counted and keep only the totals, so
+    /// that memory stays bounded with millions of arguments
+    #[arg(long = "stream")]
+    stream: bool,
+
+    /// Do not show progress on stderr during long runs
+    #[arg(long = "no-progress")]
+    no_progress: bool,
+
+    /// Keep per-file counts in FILE and only count files changed since the last run
+    #[arg(long = "cache", value_name = "FILE")]
+    cache: Option<String>,
+
+    /// Memory-map every file instead of only files of 4 MiB and more
+    #[arg(long = "mmap")]
+    mmap: bool,
+
+    /// Do not apply the built-in exclude list (.git, target, build, node_modules, ...)
+    #[arg(long = "no-default-excludes")]
+    no_default_excludes: bool,
+
+    /// Print the effective exclude patterns (include patterns prefixed with !) and exit
+    #[arg(long = "list-excludes")]
+    list_excludes: bool,
+
+    /// Print the files that would be counted, with their language, without counting them
+    #[arg(long = "list-only")]
+    list_only: bool,
+
+    /// Count only files of these languages (comma-separated, can be used multiple times)
+    #[arg(long = "include-lang", value_name = "LANG", value_delimiter = ',')]
+    include_lang: Vec<String>,
+
+    /// Skip files of these languages (comma-separated, can be used multiple times)
+    #[arg(long = "exclude-lang", value_name = "LANG", value_delimiter = ',')]
+    exclude_lang: Vec<String>,
+
+    /// Explain how comment syntax was inferred for files of unknown languages (on stderr)
+    #[arg(long = "explain")]
+    explain: bool,
+
+    /// Run CMD (a program and its arguments) as a metric plugin, which is sent each file
+    /// counted as JSON lines and answers with values shown as extra columns (can be used
+    /// multiple times)
+    #[arg(long = "metric-plugin", value_name = "CMD")]
+    metric_plugin: Vec<String>,
+
+    /// Warn when more than PERCENT of the lines of a directory argument are of languages without built-in comment syntax
+    #[arg(long = "warn-unknown", value_name = "PERCENT", default_value_t = 50, value_parser = clap::value_parser!(u8).range(0..=100))]
+    warn_unknown: u8,
+
+    /// Show actual klocs (actual lines/1000)
+    #[arg(short = 'k', long = "actual-klocs", group = "columns")]
+    actual_klocs: bool,
+    /// Show actual loc
+    #[arg(short = 'l', long = "actual-loc", group = "columns")]
+    actual_loc: bool,
+    /// Show raw klocs (raw lines/1000)
+    #[arg(short = 'K', long = "raw-klocs", group = "columns")]
+    raw_klocs: bool,
+    /// Show raw loc
+    #[arg(short = 'R', long = "raw-locs", group = "columns")]
+    raw_loc: bool,
+    /// Follow symlinks when recursively processing directories
+    #[arg(short = 'L', long = "follow-symlinks")]
+    follow_symlinks: bool,
+    /// Parse ignore list files (.gitignore, .sourcelinesignore, .git/info/exclude) [default: enabled]
+    #[arg(short = 'i', long = "ignorelist", visible_alias = "respect-gitignore", default_value_t = true)]
+    ignorelist: bool,
+    /// Do not parse ignore list files
+    #[arg(short = 'I', long = "no-ignorelist", visible_alias = "no-respect-gitignore")]
+    no_ignorelist: bool,
+    /// Output summary in text report format
+    #[arg(long = "text")]
+    text: bool,
+    /// Output summary in HTML report format
+    #[arg(long = "html")]
+    html: bool,
+    /// Output summary in LaTeX report format
+    #[arg(long = "latex")]
+    latex: bool,
+    /// Output summary in PDF report format (requires LaTeX)
+    #[arg(long = "pdf")]
+    pdf: bool,
+    /// Output summary in Markdown report format
+    #[arg(long = "markdown")]
+    markdown: bool,
+    /// Output format: text, html, latex, pdf or markdown, the same as their flags, or github
+    /// to print failed --fail-if gates as GitHub Actions annotations
+    #[arg(long = "output", value_enum, value_name = "FORMAT")]
+    output: Option<OutputFormat>,
+    /// Show word count
+    #[arg(short = 'w', long = "words", group = "columns")]
+    words: bool,
+    /// Show char count
+    #[arg(short = 'c', long = "chars", group = "columns")]
+    chars: bool,
+    /// Show byte count
+    #[arg(short = 'b', long = "bytes", group = "columns")]
+    bytes: bool,
+    /// Show these columns in this order, e.g. actual_loc,comments,files,bytes (in all output formats)
+    #[arg(long = "columns", value_name = "LIST", value_delimiter = ',', value_parser = parse_column, conflicts_with = "columns")]
+    column_list: Vec<Column>,
+    /// Group digits and write decimals as LOCALE does (e.g. de_DE, or auto for $LANG) [default: C]
+    #[arg(long = "locale", value_name = "LOCALE", value_parser = parse_locale)]
+    locale: Option<NumberFormat>,
+
+    /// Read additional files or directories to process from FILE, one per line ("-" for stdin)
+    #[arg(long = "files-from", value_name = "FILE")]
+    files_from: Option<String>,
+
+    /// With --files-from, names are separated by NUL characters instead of newlines
+    #[arg(short = '0', long = "null", requires = "files_from")]
+    null_data: bool,
+
+    /// Language of the content read from stdin for the argument "-" [default: detected]
+    #[arg(long = "stdin-lang", value_name = "LANG")]
+    stdin_lang: Option<String>,
+
+    /// Name of the content read from stdin for the argument "-", to detect its language and
+    /// match it against the filters
+    #[arg(long = "stdin-name", value_name = "NAME")]
+    stdin_name: Option<PathBuf>,
+
+    /// Count only the files tracked by git (as listed by git ls-files) in directory arguments
+    #[arg(long = "git")]
+    git: bool,
+
+    /// Count the content staged in the git index of the files staged under the arguments,
+    /// instead of the files on disk, and print it on one line, for pre-commit hooks
+    #[arg(long = "staged", conflicts_with_all = ["git", "changed_in", "watch", "files_from"])]
+    staged: bool,
+
+    /// Count only the files in directory arguments that were touched by the commits in REV1..REV2
+    #[arg(long = "changed-in", value_name = "REV1..REV2")]
+    changed_in: Option<String>,
+
+    /// Branch or tag to check out when counting a remote repository URL
+    #[arg(long = "ref", value_name = "REF")]
+    git_ref: Option<String>,
+
+    /// Save the per-language totals of this run to FILE
+    #[arg(long = "save-baseline", value_name = "FILE")]
+    save_baseline: Option<String>,
+
+    /// Print per-language and total actual LOC changes since the run saved in FILE
+    #[arg(long = "compare-baseline", value_name = "FILE")]
+    compare_baseline: Option<String>,
+
+    /// Exit with status 3 if EXPR holds, e.g. 'total.actual_loc > 500000' or
+    /// 'lang.javascript.actual_loc delta > 1000' (can be used multiple times)
+    #[arg(long = "fail-if", value_name = "EXPR", value_parser = parse_gate)]
+    fail_if: Vec<Gate>,
+
+    /// Exit with status 3 if the files matching a glob, or a language, have more actual LOC
+    /// than FILE allows them, e.g. "src/legacy/**" = 20000
+    #[arg(long = "budgets", value_name = "FILE")]
+    budgets: Option<PathBuf>,
+
+    /// Also report per-file and per-language line churn from the git history of directory arguments
+    #[arg(long = "churn")]
+    churn: bool,
+
+    /// With --churn, only consider commits more recent than DATE (e.g. 90d, 12w, 6m, 1y or 2024-01-01)
+    #[arg(long = "since", value_name = "DATE", requires = "churn", value_parser = parse_since)]
+    since: Option<String>,
+
+    /// Read options from FILE instead of the nearest .sourcelines.toml
+    #[arg(long = "config", value_name = "FILE", conflicts_with = "no_config")]
+    config: Option<PathBuf>,
+
+    /// Do not read a configuration file
+    #[arg(long = "no-config")]
+    no_config: bool,
+
+    /// Also apply the options of the [profile.NAME] table of the configuration file
+    #[arg(long = "profile", value_name = "NAME", conflicts_with = "no_config")]
+    profile: Option<String>,
+
+    /// Files or directories to process
+    #[arg(required = false)]
+    files: Vec<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+// Print the notes among the tracing events of the library on stderr as LEVEL asks, and with
+// --debug or RUST_LOG, all of its events
+fn init_tracing(debug: bool, level: LogLevel) {
+    use tracing_subscriber::fmt::format::FmtSpan;
+    use tracing_subscriber::prelude::*;
+    use tracing_subscriber::{EnvFilter, filter};
+
+    let max = if level >= LogLevel::Debug { tracing::Level::DEBUG } else { tracing::Level::WARN };
+    let notes = Notes { level }
+        .with_filter(filter::filter_fn(move |meta| meta.target() == EXPLAIN_TARGET || *meta.level() <= max));
+    let filter = match std::env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(directives) => Some(EnvFilter::new(directives)),
+        Err(_) if debug => Some(EnvFilter::new("sourcelines=debug")),
+        Err(_) => None,
+    };
+    let events = filter.map(|filter| {
+        tracing_subscriber::fmt::layer()
+            .with_writer(io::stderr)
+            .with_ansi(io::stderr().is_terminal())
+            // Closing spans show the time spent in each directory and file
+            .with_span_events(if debug { FmtSpan::CLOSE } else { FmtSpan::NONE })
+            .with_filter(filter)
+    });
+    tracing_subscriber::registry().with(notes).with(events).init();
+}
+
+// Prints the files left out with --log-level debug, the files without actual lines of code
+// with warn and up, and what --explain tells whatever the level
+struct Notes {
+    level: LogLevel,
+}
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for Notes {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: tracing_subscriber::layer::Context<'_, S>) {
+        let mut fields = EventFields::default();
+        event.record(&mut fields);
+        let field = |name: &str| fields.0.get(name).map_or("", String::as_str);
+        match field("message") {
+            "skipped" | "unreadable" if self.level >= LogLevel::Debug => {
+                eprintln!("skipped {} ({})", field("path"), field("detail"))
+            }
+            "no actual lines of code" if self.level >= LogLevel::Warn => eprintln!(
+                "sourcelines: warning: {} has {} bytes but no actual lines of code (see --explain)",
+                field("path"),
+                field("bytes")
+            ),
+            "syntax inferred" if event.metadata().target() == EXPLAIN_TARGET => {
+                eprintln!(
+                    "{}: language '{}' has no built-in comment syntax, inferred from {} non-blank lines",
+                    field("path"),
+                    field("lang"),
+                    field("lines")
+                );
+                eprintln!("{}", field("table"));
+            }
+            _ => {}
+        }
+    }
+}
+
+// The fields of a tracing event, formatted
+#[derive(Default)]
+struct EventFields(std::collections::HashMap<&'static str, String>);
+
+impl tracing::field::Visit for EventFields {
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        self.0.insert(field.name(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        self.0.insert(field.name(), format!("{:?}", value));
+    }
+}
+
+// The command line, after the options of the configuration file and of the --profile
+// chosen, then of the SOURCELINES_* variables, so that those given on the command line win.
+// Also returns the arguments parsed, which for config check do not include the file
+fn parse_cli() -> (Cli, Vec<std::ffi::OsString>) {
+    let exit = |e: String| -> ! {
+        eprintln!("Error: {}", e);
+        std::process::exit(2);
+    };
+    let mut args: Vec<std::ffi::OsString> = std::env::args_os().collect();
+    let env = config::env_args(&Cli::command()).unwrap_or_else(|e| exit(e));
+    args.splice(1..1, env.into_iter().map(Into::into));
+    let cli = match Cli::try_parse_from(&args) {
+        Ok(cli) if !cli.no_config => cli,
+        // Errors, --help and --version
+        _ => return (Cli::parse_from(&args), args),
+    };
+    // config check reports what is wrong with the file instead
+    if matches!(cli.command, Some(Command::Config { action: ConfigAction::Check })) {
+        return (cli, args);
+    }
+    let Some(config) = config::Config::find(cli.config.as_deref()).unwrap_or_else(|e| exit(e)) else {
+        if let Some(profile) = &cli.profile {
+            exit(format!("--profile {}: no {} found", profile, config::CONFIG_FILE));
+        }
+        return (cli, args);
+    };
+    let options = config.args(cli.profile.as_deref(), &Cli::command()).unwrap_or_else(|e| exit(e));
+    args.splice(1..1, options.into_iter().map(Into::into));
+    (Cli::parse_from(&args), args)
+}
+
+// config check: print the problems of the configuration file on stderr and the options in
+// effect on stdout, and exit with status 1 if there are problems. ARGS are those of the
+// environment and the command line
+fn check_config(cli: &Cli, args: &[std::ffi::OsString]) {
+    let command = Cli::command();
+    let (mut problems, mut usable) = (Vec::new(), true);
+    let mut args = args.to_vec();
+    match config::Config::find(cli.config.as_deref()) {
+        _ if cli.no_config => {}
+        Ok(Some(config)) => {
+            problems = config.check(cli.profile.as_deref(), &command);
+            match config.args(cli.profile.as_deref(), &command) {
+                Ok(options) => drop(args.splice(1..1, options.into_iter().map(Into::into))),
+                Err(_) => usable = false,
+            }
+        }
+        Ok(None) => eprintln!("sourcelines: no {} found", config::CONFIG_FILE),
+        Err(e) => (problems, usable) = (vec![e], false),
+    }
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+    // Options that cannot be used together were reported for the file's profiles
+    if usable && let Ok(matches) = command.clone().try_get_matches_from(&args) {
+        print!("{}", config::effective(&command, &matches));
+    }
+    if !problems.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+fn main() {
+    let (mut cli, args) = parse_cli();
+    if let Some(Command::Completions { shell }) = &cli.command {
+        clap_complete::generate(*shell, &mut Cli::command(), "sourcelines", &mut io::stdout());
+        return;
+    }
+    if let Some(Command::Man) = &cli.command {
+        if let Err(e) = clap_mangen::Man::new(Cli::command()).render(&mut io::stdout()) {
+            eprintln!("Error: could not write the man page: {}", e);
+            exit(2);
+        }
+        return;
+    }
+    if let Some(Command::Config { action: ConfigAction::Check }) = &cli.command {
+        check_config(&cli, &args);
+        return;
+    }
+    if let Some(Command::Languages) = &cli.command {
+        let remap: std::collections::HashMap<String, String> = cli.remap.iter().cloned().collect();
+        print_languages(&remap);
+        return;
+    }
+    if let Some(Command::GenerateTestTree { dir, files, size, langs, seed }) = &cli.command {
+        if let Err(e) = generate_test_tree(dir, *files, *size, langs, *seed) {
+            eprintln!("Error: could not generate test tree in {}: {}", dir.display(), e);
+            exit(2);
+        }
+        return;
+    }
+    // --explain reports files one after another, in walk order
+    let threads = if cli.explain { 1 } else { cli.threads };
+    if let Err(e) = rayon::ThreadPoolBuilder::new().num_threads(threads).build_global() {
+        eprintln!("Error: could not start {} threads: {}", threads, e);
+        exit(2);
+    }
+    // Listed paths that are not valid UTF-8, by the names they are shown with
+    let mut listed: std::collections::HashMap<String, PathBuf> = std::collections::HashMap::new();
+    if let Some(ref list) = cli.files_from {
+        match read_file_list(list, cli.null_data) {
+            Ok(paths) => {
+                for path in paths {
+                    let name = path.to_string_lossy().into_owned();
+                    if path.to_str().is_none() {
+                        listed.insert(name.clone(), path);
+                    }
+                    cli.files.push(name);
+                }
+            }
+            Err(e) => {
+                eprintln!("Error: could not read file list {}: {}", list, e);
+                exit(2);
+            }
+        }
+    }
+    if cli.files_from.as_deref() == Some("-") && cli.files.iter().any(|f| f == "-") {
+        eprintln!("Error: stdin cannot be read both for --files-from - and for the argument -");
+        exit(2);
+    }
+    let log_level = match cli.log_level {
+        _ if cli.quiet => LogLevel::Error,
+        Some(level) => level,
+        None if cli.verbose => LogLevel::Debug,
+        None => LogLevel::Info,
+    };
+    init_tracing(cli.debug, log_level);
+    // If no files provided, default to -rv .
+    // If --text is used, also enable recursive and sum by default
+    let counting_crate = matches!(cli.command, Some(Command::Crate { .. }));
+    if cli.files.is_empty() && cli.files_from.is_none() && !counting_crate {
+        cli.files = vec![".".to_string()];
+        cli.recursive = true;
+        cli.verbose = true;
+    }
+    match cli.output {
+        Some(OutputFormat::Text) => cli.text = true,
+        Some(OutputFormat::Html) => cli.html = true,
+        Some(OutputFormat::Latex) => cli.latex = true,
+        Some(OutputFormat::Pdf) => cli.pdf = true,
+        Some(OutputFormat::Markdown) => cli.markdown = true,
+        Some(OutputFormat::Github) | None => {}
+    }
+    if cli.text || cli.html || cli.latex || cli.pdf || cli.markdown {
+        cli.recursive = true;
+        cli.sum = true;
+    }
+    if cli.compare_baseline.is_none()
+        && let Some(gate) = cli.fail_if.iter().find(|gate| gate.delta)
+    {
+        eprintln!("Error: --fail-if '{}' compares with a baseline, use --compare-baseline", gate.expr);
+        exit(2);
+    }
+    // Remote repositories are shallow-cloned, crates downloaded and unpacked, and both always
+    // walked recursively
+    let mut fetched: std::collections::HashMap<String, Fetched> = std::collections::HashMap::new();
+    if let Some(Command::Crate { spec }) = &cli.command {
+        match fetch_crate(spec) {
+            Ok((label, unpacked)) => {
+                cli.files.push(label.clone());
+                FETCHED_DIRS.lock().unwrap().push(unpacked.dir().to_path_buf());
+                fetched.insert(label, Fetched::Crate(unpacked));
+            }
+            Err(e) => {
+                eprintln!("Error: could not download crate {}: {}", spec, e);
+                exit(2);
+            }
+        }
+        cli.recursive = true;
+        cli.verbose = true;
+    }
+    for arg in &cli.files {
+        if listed.contains_key(arg) || !is_remote_repository(arg) || fetched.contains_key(arg) {
+            continue;
+        }
+        match RemoteClone::new(arg, cli.git_ref.as_deref()) {
+            Ok(clone) => {
+                FETCHED_DIRS.lock().unwrap().push(clone.dir().to_path_buf());
+                fetched.insert(arg.clone(), Fetched::Clone(clone));
+            }
+            Err(e) => {
+                eprintln!("Error: could not clone {}: {}", arg, e);
+                exit(2);
+            }
+        }
+        cli.recursive = true;
+    }
+    let arg_path = |arg: &str| -> PathBuf {
+        match (fetched.get(arg), listed.get(arg)) {
+            (Some(fetched), _) => fetched.dir().to_path_buf(),
+            (None, Some(path)) => path.clone(),
+            (None, None) => PathBuf::from(arg),
+        }
+    };
+    // --summary-only prints what --by-lang -s does, and keeps no rows to print
+    if cli.summary_only {
+        cli.by_lang = true;
+        cli.sum = true;
+    }
+    let recursive = cli.recursive;
+    let show_sum = cli.sum;
+    let verbose = cli.verbose;
+    let color = cli.color.enabled();
+    let follow_symlinks = cli.follow_symlinks;
+    // Git already decides what is tracked, ignore files have no say in --git mode
+    let use_ignorelist = cli.ignorelist && !cli.no_ignorelist && !cli.git;
+    let text_mode = cli.text;
+    let html_mode = cli.html;
+    let latex_mode = cli.latex;
+    let pdf_mode = cli.pdf;
+    let markdown_mode = cli.markdown;
+    let files = &cli.files;
+
+    let mut builder = Options::builder()
+        .recursive(recursive)
+        .follow_symlinks(follow_symlinks)
+        .ignore_files(use_ignorelist)
+        .default_excludes(!cli.no_default_excludes)
+        // --hidden counts dot-prefixed entries such as .git as well
+        .hidden(cli.hidden)
+        .exclude(cli.exclude.iter().cloned())
+        .include(cli.include.iter().cloned())
+        .case_insensitive(cli.iglob)
+        .exclude_regex(cli.exclude_regex.iter().cloned())
+        .include_regex(cli.include_regex.iter().cloned())
+        .remap(cli.remap.iter().cloned())
+        .include_langs(cli.include_lang.iter().cloned())
+        .exclude_langs(cli.exclude_lang.iter().cloned())
+        .max_depth(cli.max_depth)
+        .one_file_system(cli.one_file_system)
+        .include_vendored(cli.include_vendored)
+        .submodules(cli.submodules)
+        .test_patterns(cli.test_pattern.iter().cloned())
+        .count_binary(cli.count_binary)
+        .binary_extensions(cli.binary_extensions.iter().cloned())
+        .mmap(cli.mmap)
+        .line_cap(cli.line_cap.try_into().unwrap_or(usize::MAX))
+        .skip_hidden(cli.no_hidden)
+        .min_file_size(cli.min_file_size)
+        .max_file_size(cli.max_file_size)
+        .dedupe(cli.dedupe)
+        .by_dir(cli.by_dir)
+        .explain(cli.explain);
+    let exclude_patterns = builder.exclude_patterns();
+    if cli.list_excludes {
+        for pat in &exclude_patterns {
+            println!("{}", pat);
+        }
+        for pat in builder.include_patterns() {
+            println!("!{}", pat);
+        }
+        return;
+    }
+    let count_cache = Arc::new(Mutex::new(cli.cache.as_deref().map(CountCache::load).unwrap_or_default()));
+    // With --git and --changed-in, only paths listed by git in directory arguments are walked
+    let restrict = cli.git || cli.changed_in.is_some();
+    let mut only = std::collections::HashSet::new();
+    for arg in files.iter().filter(|f| restrict && arg_path(f).is_dir()) {
+        let dir = &arg_path(arg);
+        let listed = match (cli.git, &cli.changed_in) {
+            (true, Some(range)) => git_tracked_files(dir).and_then(|tracked| {
+                let changed = git_changed_files(dir, range)?;
+                Ok(tracked.intersection(&changed).cloned().collect())
+            }),
+            (true, None) => git_tracked_files(dir),
+            (false, Some(range)) => git_changed_files(dir, range),
+            (false, None) => unreachable!(),
+        };
+        match listed {
+            Ok(paths) => only.extend(paths),
+            Err(e) => {
+                eprintln!("Error: could not list git files in {}: {}", arg, e);
+                exit(2);
+            }
+        }
+    }
+    // The number of files is known in advance for file arguments and git file lists
+    let file_args = files.iter().filter(|f| !arg_path(f).is_dir()).count();
+    let expected_files = if restrict {
+        let dirs: std::collections::HashSet<&Path> =
+            only.iter().filter_map(|p| p.parent()).filter(|d| only.contains(*d)).collect();
+        Some(only.len() - dirs.len() + file_args)
+    } else {
+        (file_args == files.len()).then_some(file_args)
+    };
+    let progress = Arc::new(Progress::new(expected_files));
+    // Streamed rows show the progress themselves
+    let show_progress = !cli.no_progress
+        && log_level >= LogLevel::Info
+        && !cli.stream && !cli.watch && io::stdout().is_terminal() && io::stderr().is_terminal();
+    // The server and the daemon keep the counts of unchanged files between runs
+    let long_running = matches!(cli.command, Some(Command::Serve { .. } | Command::Daemon { .. }));
+    if cli.cache.is_some() || cli.watch || long_running {
+        builder = builder.cache(count_cache.clone());
+    }
+    if show_progress {
+        builder = builder.progress(progress.callback());
+    }
+    if restrict {
+        builder = builder.only(only);
+    }
+    // Names of the --metric-plugin columns, in the order they are printed
+    let mut metric_names: Vec<String> = Vec::new();
+    for command in &cli.metric_plugin {
+        let plugin = plugin::Plugin::start(command).unwrap_or_else(|e| {
+            eprintln!("Error: could not start metric plugin {}: {}", command, e);
+            exit(2);
+        });
+        let plugin = Arc::new(plugin);
+        for name in &plugin.columns {
+            if !metric_names.contains(name) {
+                metric_names.push(name.clone());
+            }
+        }
+        builder = builder.metric(command.clone(), plugin.factory());
+    }
+    // File and line gates fail as the files are counted
+    let violations: Arc<Mutex<Vec<Violation>>> = Arc::default();
+    let gates = |subject: GateSubject| cli.fail_if.iter().filter(|gate| gate.subject == subject).cloned().collect();
+    let file_gates: Vec<Gate> = gates(GateSubject::File);
+    let budgets = cli.budgets.as_ref().map(|file| {
+        Arc::new(budget::Budgets::load(file).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            exit(2);
+        }))
+    });
+    if !file_gates.is_empty() || budgets.is_some() {
+        let budgets = budgets.clone();
+        builder = builder.visitor(Arc::new(FileGates { gates: file_gates, budgets, violations: violations.clone() }));
+    }
+    let line_gates: Arc<Vec<Gate>> = Arc::new(gates(GateSubject::Line));
+    if !line_gates.is_empty() {
+        let violations = violations.clone();
+        let factory: sourcelines::MetricFactory = Arc::new(move || {
+            let (gates, violations) = (line_gates.clone(), violations.clone());
+            Box::new(LineGates { gates, violations, path: PathBuf::new() })
+        });
+        builder = builder.metric("--fail-if", factory);
+    }
+    let walk_opts = builder.build();
+    let skipped = walk_opts.skipped.clone();
+
+    #[cfg(not(feature = "tui"))]
+    if let Some(Command::Tui { .. }) = &cli.command {
+        eprintln!("Error: sourcelines was built without the tui feature");
+        exit(2);
+    }
+    #[cfg(feature = "tui")]
+    if let Some(Command::Tui { dir }) = &cli.command {
+        if !io::stdout().is_terminal() {
+            eprintln!("Error: the tui subcommand needs a terminal");
+            exit(2);
+        }
+        // Keep every directory of the tree, however deep
+        let tree_opts = Options {
+            recursive: true,
+            by_dir: Some(usize::MAX),
+            progress: None,
+            ..walk_opts
+        };
+        let scan = |progress: Option<FileCallback>| {
+            tree_opts.forget_seen();
+            count_path(dir, &Options { progress, ..tree_opts.clone() }).unwrap_or_default()
+        };
+        let progress = Arc::new(Progress::new(None));
+        let root = scan(show_progress.then(|| progress.callback()));
+        progress.finish();
+        let watch = cli.watch.then(|| {
+            Watch::new(std::slice::from_ref(dir), true, cli.cache.as_deref()).unwrap_or_else(|e| {
+                eprintln!("Error: could not watch {}: {}", dir.display(), e);
+                exit(2);
+            })
+        });
+        let changed = || watch.as_ref().map_or(Ok(false), |w| w.poll(&tree_opts).map_err(io::Error::other));
+        let changed: Option<&dyn Fn() -> io::Result<bool>> = watch.is_some().then_some(&changed);
+        if let Err(e) = tui::run(dir, root, &|| scan(None), changed) {
+            eprintln!("Error: could not run the tui: {}", e);
+            exit(2);
+        }
+        if let Some(ref file) = cli.cache
+            && let Err(e) = count_cache.lock().unwrap().save(file)
+        {
+            eprintln!("Error: could not write cache {}: {}", file, e);
+            exit(2);
+        }
+        return;
+    }
+    // --explain with a single file argument describes how that file is counted in detail
+    if cli.explain
+        && cli.command.is_none()
+        && let [arg] = &files[..]
+        && arg_path(arg).is_file()
+    {
+        if let Err(e) = explain_file(&arg_path(arg), &walk_opts, &exclude_patterns, use_ignorelist) {
+            eprintln!("Error: could not read {}: {}", arg, e);
+            exit(2);
+        }
+        return;
+    }
+    if let Some(Command::Diff { rev1, rev2 }) = &cli.command {
+        if let Err(e) = run_diff(rev1, rev2, &walk_opts) {
+            eprintln!("Error: could not diff {} and {}: {}", rev1, rev2, e);
+            exit(2);
+        }
+        return;
+    }
+    if let Some(Command::Compare { dir_a, dir_b }) = &cli.command {
+        let scan = |dir: &PathBuf| {
+            if !dir.is_dir() {
+                eprintln!("Error: {} is not a directory", dir.display());
+                exit(2);
+            }
+            let tree_opts = Options {
+                recursive: true,
+                keep_files: true,
+                ..walk_opts.clone()
+            };
+            // --dedupe only drops copies within a tree
+            tree_opts.forget_seen();
+            count_path(dir, &tree_opts).unwrap_or_default()
+        };
+        let (old, new) = (scan(dir_a), scan(dir_b));
+        progress.finish();
+        print_tree_comparison(dir_a, &old, dir_b, &new);
+        return;
+    }
+    if cli.staged && cli.command.is_none() {
+        let report = match count_staged(files, &walk_opts, &cli.fail_if, budgets.as_deref()) {
+            Ok(report) => report,
+            Err(e) => {
+                eprintln!("Error: could not count the staged files: {}", e);
+                exit(2);
+            }
+        };
+        let (report, mut failed) = report;
+        let langs = report.langs();
+        let langs: Vec<String> = langs.iter().map(|(lang, stats)| format!("{} {}", lang, stats.actual_loc)).collect();
+        let (count, loc) = (report.total.files, report.total.actual_loc);
+        let files = if count == 1 { "file" } else { "files" };
+        if langs.is_empty() {
+            println!("sourcelines: staged {} {}, {} actual LOC", count, files, loc);
+        } else {
+            println!("sourcelines: staged {} {}, {} actual LOC ({})", count, files, loc, langs.join(", "));
+        }
+        let mut run_failed = run_violations(&report, &cli.fail_if, None, budgets.as_deref(), log_level);
+        run_failed.append(&mut failed);
+        for violation in &run_failed {
+            violation.report(cli.output == Some(OutputFormat::Github));
+        }
+        if !run_failed.is_empty() {
+            exit(GATE_FAILED_EXIT_CODE);
+        }
+        return;
+    }
+    if let Some(Command::Hook { hook: HookKind::PreCommit }) = &cli.command {
+        if let Err(e) = run_pre_commit_hook(&walk_opts) {
+            eprintln!("Error: could not compare the staged changes: {}", e);
+            exit(2);
+        }
+        return;
+    }
+    if let Some(Command::Serve { listen, roots }) = &cli.command {
+        let opts = Options { recursive: true, ..walk_opts.clone() };
+        let cache = cli.cache.as_deref().map(|file| (file, &*count_cache));
+        let roots = if roots.is_empty() { vec![PathBuf::from(".")] } else { roots.clone() };
+        let roots: Vec<PathBuf> = roots
+            .iter()
+            .map(|root| {
+                root.canonicalize().unwrap_or_else(|e| {
+                    eprintln!("Error: invalid root {}: {}", root.display(), e);
+                    exit(2);
+                })
+            })
+            .collect();
+        if let Err(e) = serve::serve(listen, &roots, &opts, cache) {
+            eprintln!("Error: could not listen on {}: {}", listen, e);
+            exit(2);
+        }
+        return;
+    }
+    if let Some(Command::Daemon { every, paths, sink, snapshots }) = &cli.command {
+        let opts = Options { recursive: true, ..walk_opts.clone() };
+        let cache = cli.cache.as_deref().map(|file| (file, &*count_cache));
+        if let Err(e) = daemon::run(*every, *snapshots, paths, sink, &opts, cache) {
+            eprintln!("Error: could not take snapshots: {}", e);
+            exit(2);
+        }
+        return;
+    }
+    if let Some(Command::History { since, interval, format, rev }) = &cli.command {
+        if let Err(e) = run_history(rev, since.as_deref(), *interval, *format, &walk_opts) {
+            eprintln!("Error: could not walk the history of {}: {}", rev, e);
+            exit(2);
+        }
+        return;
+    }
+
+    // --list-only walks and filters the arguments as counting would, but only lists the files
+    if cli.list_only {
+        let mut listed: Vec<(PathBuf, Language)> = Vec::new();
+        let mut binary_skipped = 0;
+        let walker = Walker::new(walk_opts.clone());
+        for arg in files {
+            let mut arg_files = Vec::new();
+            for found in walker.files(&arg_path(arg)) {
+                match found {
+                    Ok(file) => arg_files.push((file.path, file.lang)),
+                    Err(sourcelines::Error::Binary { .. }) => binary_skipped += 1,
+                    // Counted in `skipped`
+                    Err(_) => {}
+                }
+            }
+            arg_files.sort();
+            listed.extend(arg_files);
+        }
+        progress.finish();
+        for (path, lang) in &listed {
+            println!("{:<15} {}", lang, path.display());
+        }
+        if log_level >= LogLevel::Info {
+            eprintln!(
+                "sourcelines: {} file{} would be counted",
+                listed.len(),
+                if listed.len() == 1 { "" } else { "s" }
+            );
+        }
+        if report_skipped(&Skipped::take(&skipped, binary_skipped), log_level) > 0 {
+            exit(UNREADABLE_EXIT_CODE);
+        }
+        return;
+    }
+
+    // --columns picks the columns and their order; the single column flags show theirs in
+    // a fixed order, klocs replacing loc. By default, show loc, raw loc, words, chars, bytes
+    let columns: Vec<Column> = if !cli.column_list.is_empty() {
+        cli.column_list.clone()
+    } else {
+        let flags = [
+            (cli.actual_klocs, Column::ActualKlocs),
+            (cli.actual_loc && !cli.actual_klocs, Column::ActualLoc),
+            (cli.raw_klocs, Column::RawKlocs),
+            (cli.raw_loc && !cli.raw_klocs, Column::RawLoc),
+            (cli.words, Column::Words),
+            (cli.chars, Column::Chars),
+            (cli.bytes, Column::Bytes),
+        ];
+        let chosen: Vec<Column> = flags.into_iter().filter(|(shown, _)| *shown).map(|(_, c)| c).collect();
+        if chosen.is_empty() {
+            vec![Column::ActualLoc, Column::RawLoc, Column::Words, Column::Chars, Column::Bytes]
+        } else {
+            chosen
+        }
+    };
+    let numbers = cli.locale.unwrap_or(NumberFormat::C);
+    let icons = cli.icons.map(|set| Icons::new(set, &cli.icon));
+    // Cells of the --metric-plugin columns; rows the plugins' values are not summed for,
+    // such as languages, leave them blank
+    let metric_cells = |metrics: Option<&BTreeMap<String, f64>>| -> Vec<String> {
+        let cell = |value: f64| {
+            if value.fract() == 0.0 { numbers.grouped(&format!("{}", value)) } else { numbers.fixed(value, 2) }
+        };
+        let value = |name: &String| metrics.map(|m| cell(m.get(name).copied().unwrap_or(0.0)));
+        metric_names.iter().map(|name| value(name).unwrap_or_default()).collect()
+    };
+    let no_metrics = metric_cells(None);
+
+    // With --watch, everything is counted and printed again after each change, reusing
+    // the counts of unchanged files from the cache
+    let watch = cli.watch.then(|| {
+        let paths: Vec<PathBuf> = files.iter().map(|f| arg_path(f)).collect();
+        Watch::new(&paths, recursive, cli.cache.as_deref()).unwrap_or_else(|e| {
+            eprintln!("Error: could not watch the arguments: {}", e);
+            exit(2);
+        })
+    });
+    loop {
+        walk_opts.forget_seen();
+        let started = std::time::Instant::now();
+        let show_rows = (verbose || !show_sum)
+            && !cli.by_lang
+            && !text_mode && !html_mode && !latex_mode && !pdf_mode && !markdown_mode;
+        // The first visible column, which --percent gives the share of
+        let primary = columns[0].sort_column();
+        let percent = cli.percent.then_some(primary);
+        // Grouped (per-language or per-directory) results are sorted by the first visible
+        // column in descending order, unless --sort says otherwise
+        let group_order = cli.sort.unwrap_or(SortOrder {
+            column: primary,
+            descending: true,
+        });
+        // With --chart, language rows get a bar scaled to the largest language in the
+        // first visible column
+        let chart = |stats: &Stats, largest: usize| {
+            (cli.chart && largest > 0).then(|| primary.value(stats) as f64 / largest as f64)
+        };
+        // Number columns are as wide as the widest value of TOTAL shown in them, and at least
+        // --width; while streaming, TOTAL is only known at the end
+        let column_width = |total: Option<&Stats>| {
+            let Some(total) = total else { return cli.width };
+            columns
+                .iter()
+                .fold(cli.width, |width, column| width.max(column.format(total, numbers).chars().count()))
+        };
+        // Print the row of a file or directory argument, and for directories in verbose mode
+        // the rows of its languages; shares are of TOTAL, unknown while streaming
+        let print_entry = |stats: &Stats,
+                           lang: &str,
+                           arg: &str,
+                           metrics: &BTreeMap<String, f64>,
+                           dir: Option<&DirSummary>,
+                           total: Option<&Stats>| {
+            let share = |stats: &Stats| percent.zip(total).map(|(column, total)| column.percent(stats, total));
+            let width = column_width(total);
+            print_stats(
+                stats,
+                lang,
+                Some(arg),
+                &columns,
+                &metric_cells(Some(metrics)),
+                numbers,
+                icons.as_ref(),
+                false,
+                color,
+                share(stats),
+                None,
+                width,
+            );
+            if let Some(dir) = dir
+                && verbose
+            {
+                // For directories, print per-language sums from the walk above
+                let mut items: Vec<(&String, &Stats)> = dir.langs.iter().collect();
+                // Filter out languages with zero counts
+                items.retain(|(_, stats)| {
+                    stats.actual_loc > 0
+                        || stats.raw_loc > 0
+                        || stats.words > 0
+                        || stats.chars > 0
+                        || stats.bytes > 0
+                });
+                items.sort_by(|(la, sa), (lb, sb)| group_order.compare((la, sa), (lb, sb)));
+                let largest = items.iter().map(|(_, stats)| primary.value(stats)).max().unwrap_or(0);
+
+                for (lang, stats) in items.into_iter() {
+                    print_stats(
+                        stats,
+                        lang,
+                        None,
+                        &columns,
+                        &no_metrics,
+                        numbers,
+                        icons.as_ref(),
+                        false,
+                        color,
+                        share(stats),
+                        chart(stats, largest),
+                        width,
+                    );
+                }
+            }
+            if let Some(dir) = dir {
+                let row = |dir: &DirSummary, label: &str| {
+                    print_stats(
+                        &dir.total,
+                        "*",
+                        Some(label),
+                        &columns,
+                        &metric_cells(Some(&dir.metrics)),
+                        numbers,
+                        icons.as_ref(),
+                        false,
+                        color,
+                        share(&dir.total),
+                        None,
+                        width,
+                    );
+                };
+                print_dir_tree(&dir.children, 1, group_order, &row);
+            }
+        };
+        // With --stream, rows are printed as soon as they are counted instead of from the
+        // report at the end
+        let mut report = RunReport::default();
+        let dir_opts = Options {
+            keep_files: html_mode,
+            ..walk_opts.clone()
+        };
+        // Arguments that cannot be read are counted in `skipped` and reported after the run,
+        // like the files and directories below them
+        for arg in files {
+            if arg == "-" {
+                let name = cli.stdin_name.as_deref();
+                let counted = match count_stdin(&walk_opts, name, cli.stdin_lang.as_deref()) {
+                    Ok(counted) => counted,
+                    Err(e) => {
+                        eprintln!("Error: could not read stdin: {}", e);
+                        exit(2);
+                    }
+                };
+                let Some((lang, stats, content)) = counted else {
+                    continue;
+                };
+                let path = name.unwrap_or(Path::new("-"));
+                violations.lock().unwrap().extend(content_violations(path, &content, &stats, &cli.fail_if));
+                if let Some(budgets) = &budgets {
+                    budgets.add(path, &stats);
+                }
+                let label = path.to_string_lossy();
+                if cli.stream && show_rows {
+                    print_entry(&stats, &lang, &label, &BTreeMap::new(), None, None);
+                }
+                let is_test = walk_opts.is_test_file(path, Path::new(""));
+                report.add_file(&label, path, lang, stats, BTreeMap::new(), is_test);
+                continue;
+            }
+            let path = &arg_path(arg);
+            if archive::is_tarball(path) {
+                let mut failed = Vec::new();
+                let counted = count_tarball(path, arg, &dir_opts, &cli.fail_if, budgets.as_deref(), &mut failed);
+                let summary = match counted {
+                    Ok(summary) => summary,
+                    Err(e) => {
+                        if log_level >= LogLevel::Debug {
+                            eprintln!("skipped {} ({})", arg, e);
+                        }
+                        skipped.unreadable.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        continue;
+                    }
+                };
+                violations.lock().unwrap().extend(failed);
+                if cli.stream && show_rows {
+                    print_entry(&summary.total, "*", arg, &summary.metrics, Some(&summary), None);
+                }
+                report.add_dir(arg, path, summary);
+                continue;
+            }
+            if path.is_dir() {
+                let summary = count_path(path, &dir_opts).unwrap_or_default();
+                if log_level >= LogLevel::Warn {
+                    warn_unknown_languages(arg, &summary, cli.warn_unknown);
+                }
+                if cli.stream && show_rows {
+                    print_entry(&summary.total, "*", arg, &summary.metrics, Some(&summary), None);
+                }
+                report.add_dir(arg, path, summary);
+            } else {
+                let summary = count_path(path, &walk_opts).unwrap_or_default();
+                report.skipped.binary += summary.binary_skipped;
+                let is_test = summary.tests.files > 0;
+                // Nothing is counted for files left out by a filter, binary or unreadable
+                let Some((lang, stats)) = summary.langs.into_iter().next() else {
+                    continue;
+                };
+                if cli.stream && show_rows {
+                    print_entry(&stats, &lang, arg, &summary.metrics, None, None);
+                }
+                report.add_file(arg, path, lang, stats, summary.metrics, is_test);
+            }
+        }
+        report.elapsed = started.elapsed();
+        let binary = report.skipped.binary;
+        report.skipped = Skipped::take(&skipped, binary);
+
+        progress.finish();
+
+        let sum = &report.total;
+        if show_rows && !cli.stream && !cli.summary_only {
+            let mut entries: Vec<&report::Entry> = report.entries.iter().collect();
+            if let Some(order) = cli.sort {
+                entries.sort_by(|a, b| order.compare((&a.arg, &a.stats), (&b.arg, &b.stats)));
+            }
+            for entry in entries {
+                print_entry(&entry.stats, &entry.lang, &entry.arg, &entry.metrics, entry.dir.as_ref(), Some(sum));
+            }
+        }
+        let width = column_width((!cli.stream).then_some(sum));
+        let lang_totals = report.langs();
+        if cli.by_lang && !text_mode && !html_mode && !latex_mode && !pdf_mode && !markdown_mode {
+            let mut items: Vec<(&String, &Stats)> =
+                lang_totals.iter().filter(|(_, s)| s.raw_loc > 0 || s.bytes > 0).collect();
+            items.sort_by(|(la, sa), (lb, sb)| group_order.compare((la, sa), (lb, sb)));
+            let largest = items.iter().map(|(_, stats)| primary.value(stats)).max().unwrap_or(0);
+            for (lang, stats) in items {
+                print_stats(
+                    stats,
+                    lang,
+                    Some(""),
+                    &columns,
+                    &no_metrics,
+                    numbers,
+                    icons.as_ref(),
+                    false,
+                    color,
+                    percent.map(|column| column.percent(stats, sum)),
+                    chart(stats, largest),
+                    width,
+                );
+            }
+        }
+
+        // Print output according to -s and -v, or report format modes
+        let report_order = cli.sort.unwrap_or(SortOrder {
+            column: SortColumn::ActualLoc,
+            descending: true,
+        });
+        if pdf_mode {
+            print_pdf_report(&report, &columns, numbers, report_order, percent);
+        } else if latex_mode {
+            print_latex_report(&report, &columns, numbers, report_order, percent);
+        } else if html_mode {
+            print_html_report(&report, &columns, numbers, report_order, percent);
+        } else if markdown_mode {
+            print_markdown_report(&report, &columns, numbers, report_order, percent);
+        } else if text_mode {
+            print_text_report(&report, &columns, numbers, report_order, percent);
+        } else if show_sum || cli.by_lang || cli.per_arg_sum {
+            let sum_row = |stats: &Stats, metrics: &BTreeMap<String, f64>, label: &str| {
+                print_stats(
+                    stats,
+                    "*",
+                    Some(label),
+                    &columns,
+                    &metric_cells(Some(metrics)),
+                    numbers,
+                    icons.as_ref(),
+                    true,
+                    color,
+                    percent.map(|column| column.percent(stats, sum)),
+                    None,
+                    width,
+                );
+            };
+            // With --per-arg-sum and several arguments, each one's totals come first
+            if cli.per_arg_sum && report.entries.len() > 1 {
+                let mut entries: Vec<&report::Entry> = report.entries.iter().collect();
+                if let Some(order) = cli.sort {
+                    entries.sort_by(|a, b| order.compare((&a.arg, &a.stats), (&b.arg, &b.stats)));
+                }
+                for entry in entries {
+                    sum_row(&entry.stats, &entry.metrics, &format!("{} (sum)", entry.arg));
+                }
+            }
+            // Always print global sum at end
+            sum_row(sum, &report.metrics, "(sum)");
+        }
+        if cli.split_tests && !text_mode && !html_mode && !latex_mode && !pdf_mode && !markdown_mode {
+            let production = sub_stats(sum.clone(), report.tests.clone());
+            for (stats, kind) in [(&report.tests, "test"), (&production, "production")] {
+                print_stats(
+                    stats,
+                    kind,
+                    Some("(sum)"),
+                    &columns,
+                    &no_metrics,
+                    numbers,
+                    icons.as_ref(),
+                    false,
+                    color,
+                    percent.map(|column| column.percent(stats, sum)),
+                    None,
+                    width,
+                );
+            }
+        }
+
+        if cli.churn && !text_mode && !html_mode && !latex_mode && !pdf_mode && !markdown_mode {
+            for arg in files {
+                let dir = arg_path(arg);
+                if !dir.is_dir() {
+                    continue;
+                }
+                if let Err(e) = print_churn(&dir, cli.since.as_deref(), &walk_opts) {
+                    eprintln!("Error: could not read the git history of {}: {}", arg, e);
+                    exit(2);
+                }
+            }
+        }
+
+        let baseline = cli.compare_baseline.as_ref().map(|file| match read_baseline(file) {
+            Ok(baseline) => baseline,
+            Err(e) => {
+                eprintln!("Error: could not read baseline {}: {}", file, e);
+                exit(2);
+            }
+        });
+        if let (Some(baseline), Some(file)) = (&baseline, &cli.compare_baseline) {
+            print_baseline_comparison(baseline, &report, file);
+        }
+        if let Some(ref file) = cli.save_baseline
+            && let Err(e) = write_baseline(file, &report)
+        {
+            eprintln!("Error: could not write baseline {}: {}", file, e);
+            exit(2);
+        }
+
+        if let Some(ref file) = cli.cache
+            && let Err(e) = count_cache.lock().unwrap().save(file)
+        {
+            eprintln!("Error: could not write cache {}: {}", file, e);
+            exit(2);
+        }
+
+        let unreadable = report_skipped(&report.skipped, log_level);
+        if log_level >= LogLevel::Debug {
+            let files = report.total.files;
+            let seconds = report.elapsed.as_secs_f64();
+            eprintln!("sourcelines: counted {} file{} in {:.2}s", files, if files == 1 { "" } else { "s" }, seconds);
+        }
+
+        let mut failed = run_violations(&report, &cli.fail_if, baseline.as_ref(), budgets.as_deref(), log_level);
+        // Files are counted in parallel
+        let mut files_failed = std::mem::take(&mut *violations.lock().unwrap());
+        files_failed.sort();
+        failed.extend(files_failed);
+        for violation in &failed {
+            violation.report(cli.output == Some(OutputFormat::Github));
+        }
+        let tripped = !failed.is_empty();
+        if tripped && watch.is_none() {
+            exit(GATE_FAILED_EXIT_CODE);
+        }
+        if unreadable > 0 && watch.is_none() {
+            exit(UNREADABLE_EXIT_CODE);
+        }
+
+        let Some(watch) = &watch else {
+            break;
+        };
+        if let Err(e) = watch.wait(&walk_opts) {
+            eprintln!("Error: could not watch the arguments: {}", e);
+            exit(2);
+        }
+        if io::stdout().is_terminal() {
+            // Clear the screen for the next counts
+            print!("\x1b[2J\x1b[H");
+        } else {
+            println!();
+        }
+    }
+
+}
+
+/// Actual LOC added, removed and changed between two revisions
+#[derive(Default, Debug, Clone, Copy)]
+struct LocDelta {
+    added: usize,
+    removed: usize,
+    changed: usize,
+}
+
+impl LocDelta {
+    fn add(&mut self, other: LocDelta) {
+        self.added += other.added;
+        self.removed += other.removed;
+        self.changed += other.changed;
+    }
+
+    /// "+ADDED -REMOVED ~CHANGED", leaving out zero counts
+    fn compact(&self) -> String {
+        let parts: Vec<String> = [('+', self.added), ('-', self.removed), ('~', self.changed)]
+            .iter()
+            .filter(|(_, n)| *n > 0)
+            .map(|(sign, n)| format!("{}{}", sign, n))
+            .collect();
+        if parts.is_empty() { "+0".to_string() } else { parts.join(" ") }
+    }
+}
+
+// Print per-language actual LOC deltas between REV1 and REV2 of the repository
+// in the current directory
+fn run_diff(rev1: &str, rev2: &str, opts: &Options) -> io::Result<()> {
+    let per_lang = diff_by_language(Path::new("."), rev1, Some(rev2), opts)?;
+    let mut total = LocDelta::default();
+    let row = |delta: &LocDelta, label: &str| {
+        println!(
+            "{:>8} {:>8} {:>8} {}",
+            format!("+{}", delta.added),
+            format!("-{}", delta.removed),
+            format!("~{}", delta.changed),
+            label
+        );
+    };
+    for (lang, delta) in &per_lang {
+        row(delta, &format!("<{}>", lang));
+        total.add(*delta);
+    }
+    row(&total, &format!("<*> {}..{}", rev1, rev2));
+    Ok(())
+}
+
+// Print a one-line summary of the actual LOC staged for the next commit
+fn run_pre_commit_hook(opts: &Options) -> io::Result<()> {
+    let repo = Path::new(".");
+    // Before the first commit everything is compared with the empty tree
+    let head = match git_output(repo, &["rev-parse", "--verify", "--quiet", "HEAD"]) {
+        Ok(_) => "HEAD",
+        Err(_) => EMPTY_TREE,
+    };
+    let per_lang = diff_by_language(repo, head, None, opts)?;
+    let mut total = LocDelta::default();
+    let mut langs = Vec::new();
+    for (lang, delta) in &per_lang {
+        total.add(*delta);
+        langs.push(format!("{} {}", lang, delta.compact()));
+    }
+    if langs.is_empty() {
+        println!("sourcelines: {} actual LOC", total.compact());
+    } else {
+        println!("sourcelines: {} actual LOC ({})", total.compact(), langs.join(", "));
+    }
+    Ok(())
+}
+
+// --staged: count the content in the git index of the files under ARGS that differ from
+// HEAD, as if they were file arguments, and check the file and line GATES on them. Their
+// budgets are added to BUDGETS
+fn count_staged(
+    args: &[String],
+    opts: &Options,
+    gates: &[Gate],
+    budgets: Option<&budget::Budgets>,
+) -> io::Result<(RunReport, Vec<Violation>)> {
+    let repo = Path::new(".");
+    let head = match git_output(repo, &["rev-parse", "--verify", "--quiet", "HEAD"]) {
+        Ok(_) => "HEAD",
+        Err(_) => EMPTY_TREE,
+    };
+    // Paths relative to the current directory, deleted files left out
+    let mut diff = vec!["diff", "--cached", "--name-only", "-z", "--relative", "--no-renames", "--diff-filter=d", head];
+    diff.push("--");
+    diff.extend(args.iter().map(String::as_str));
+    let names = git_output(repo, &diff)?;
+    let mut blobs = BlobReader::new(repo)?;
+    let (mut report, mut failed) = (RunReport::default(), Vec::new());
+    for name in names.split(|&b| b == b'\0').filter(|name| !name.is_empty()) {
+        let name = String::from_utf8_lossy(name).into_owned();
+        let path = Path::new(&name);
+        if !opts.path_selected(path, Path::new("")) {
+            continue;
+        }
+        // Submodules are staged as commits, not blobs
+        let Ok(content) = blobs.read(&format!(":./{}", name)) else {
+            continue;
+        };
+        let lang = remap_language(detect_language_from_prefix(path, &content), &opts.remap);
+        if looks_binary(&content) || !opts.lang_selected(&lang) {
+            continue;
+        }
+        let stats = count_reader(&lang, content.as_slice()).unwrap_or_default();
+        failed.extend(content_violations(path, &content, &stats, gates));
+        if let Some(budgets) = budgets {
+            budgets.add(path, &stats);
+        }
+        let is_test = opts.is_test_file(path, Path::new(""));
+        report.add_file(&name, path, lang.to_string(), stats, BTreeMap::new(), is_test);
+    }
+    failed.sort();
+    Ok((report, failed))
+}
+
+/// The file and line gates of GATES that CONTENT, counted as STATS, fails as the file PATH
+fn content_violations(path: &Path, content: &[u8], stats: &Stats, gates: &[Gate]) -> Vec<Violation> {
+    let mut failed = Vec::new();
+    for gate in gates {
+        let path = Some(path.to_path_buf());
+        let message = format!("gate failed: {}", gate.expr);
+        if gate.subject == GateSubject::File {
+            let value = stat_field(stats, &gate.field) as i64;
+            if gate.trips(value) {
+                failed.push(Violation { path, line: None, message, value });
+            }
+        } else if gate.subject == GateSubject::Line {
+            for (n, line) in String::from_utf8_lossy(content).lines().enumerate() {
+                let value = if gate.field == "bytes" { line.len() } else { line.chars().count() } as i64;
+                if gate.trips(value) {
+                    let (path, message) = (path.clone(), message.clone());
+                    failed.push(Violation { path, line: Some(n + 1), message, value });
+                }
+            }
+        }
+    }
+    failed
+}
+
+/// Read and count the content piped to sourcelines for the argument -, as the file NAME if
+/// given: its language is LANG, or detected from NAME and the content. None if it is binary
+/// or a filter leaves it out; otherwise its language, counts and content
+fn count_stdin(
+    opts: &Options,
+    name: Option<&Path>,
+    lang: Option<&str>,
+) -> io::Result<Option<(String, Stats, Vec<u8>)>> {
+    let mut content = Vec::new();
+    io::stdin().lock().read_to_end(&mut content)?;
+    if let Some(name) = name
+        && !opts.path_selected(name, Path::new(""))
+    {
+        return Ok(None);
+    }
+    let lang = match lang {
+        Some(lang) => lang.to_lowercase(),
+        None => detect_language_from_prefix(name.unwrap_or(Path::new("-")), &content).to_string(),
+    };
+    let lang = opts.remap.get(&lang).cloned().unwrap_or(lang);
+    if (looks_binary(&content) && !opts.count_binary) || !opts.lang_selected(&lang) {
+        return Ok(None);
+    }
+    let stats = count_reader(&lang, content.as_slice())?;
+    Ok(Some((lang, stats, content)))
+}
+
+/// Count the files of the tar archive FILE as they are read from it, as if it were a directory
+/// named ARG: the file and line gates of GATES they fail are added to FAILED, and their counts
+/// to BUDGETS. Ignore files in the archive are not read
+fn count_tarball(
+    file: &Path,
+    arg: &str,
+    opts: &Options,
+    gates: &[Gate],
+    budgets: Option<&budget::Budgets>,
+    failed: &mut Vec<Violation>,
+) -> io::Result<DirSummary> {
+    let reader = io::BufReader::new(File::open(file)?);
+    archive::count(reader, Path::new(arg), opts, None, |path, content, stats| {
+        failed.extend(content_violations(path, content, stats, gates));
+        if let Some(budgets) = budgets {
+            budgets.add(path, stats);
+        }
+    })
+}
+
+/// Object name of the empty tree, for diffs against a repository without commits
+const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+// Actual LOC deltas per language between REV1 and REV2, or the index if REV2 is `None`
+fn diff_by_language(
+    repo: &Path,
+    rev1: &str,
+    rev2: Option<&str>,
+    opts: &Options,
+) -> io::Result<std::collections::BTreeMap<Language, LocDelta>> {
+    let revs = match rev2 {
+        Some(rev2) => vec![rev1, rev2],
+        None => vec!["--cached", rev1],
+    };
+    // The changed files with their blobs, then the patches of the same files in the same order
+    let diff = ["diff", "--raw", "--patch", "-z", "-U0", "--no-abbrev", "-M", "--no-color", "--no-ext-diff"];
+    let output = git_output(repo, &[&diff[..], &revs[..]].concat())?;
+    let mut rest = &output[..];
+    let mut field = || {
+        let end = rest.iter().position(|&b| b == b'\0')?;
+        let field = &rest[..end];
+        rest = &rest[end + 1..];
+        Some(field)
+    };
+    let mut changes = Vec::new();
+    // The list of files ends with an empty field
+    while let Some(header) = field().filter(|header| !header.is_empty()) {
+        // ":<old mode> <new mode> <old id> <new id> <status>", then the path; renames and
+        // copies are followed by the old and the new path
+        let header = String::from_utf8_lossy(header).into_owned();
+        let old = field().unwrap_or_default();
+        let new = match header.split(' ').nth(4) {
+            Some(status) if status.starts_with(['R', 'C']) => field().unwrap_or_default(),
+            _ => old,
+        };
+        changes.push((header, old, new));
+    }
+    let mut patches = hunks_by_file(rest).into_iter();
+    let mut blobs = BlobReader::new(repo)?;
+    let mut per_lang: std::collections::BTreeMap<Language, LocDelta> = std::collections::BTreeMap::new();
+    for (header, old, new) in changes {
+        let fields: Vec<&str> = header.trim_start_matches(':').split(' ').collect();
+        let [old_mode, new_mode, old_id, new_id, status] = fields[..] else {
+            continue;
+        };
+        // Unmerged files have no patch
+        if status == "U" {
+            continue;
+        }
+        let hunks = patches.next().unwrap_or_default();
+        // Submodules are recorded as commits, not blobs
+        if old_mode == "160000" || new_mode == "160000" {
+            continue;
+        }
+        // The id of the side a file is missing from is all zeros
+        let mut blob = |id: &str| if id.bytes().all(|b| b == b'0') { Ok(Vec::new()) } else { blobs.read(id) };
+        let (old_blob, new_blob) = (blob(old_id)?, blob(new_id)?);
+        let (old, new) = (path_from_bytes(old), path_from_bytes(new));
+        let (path, content) = if status == "D" { (&old, &old_blob) } else { (&new, &new_blob) };
+        let lang = remap_language(detect_language_from_prefix(path, content), &opts.remap);
+        if !opts.lang_selected(&lang) || !opts.path_selected(path, Path::new("")) {
+            continue;
+        }
+        if let Some(delta) = file_delta((&old, &old_blob), (&new, &new_blob), &hunks) {
+            per_lang.entry(lang).or_default().add(delta);
+        }
+    }
+    Ok(per_lang)
+}
+
+// The old and new line ranges of a hunk, each its first line and number of lines
+type Hunk = ((usize, usize), (usize, usize));
+
+// The hunks of each file of PATCH, a git diff -U0
+fn hunks_by_file(patch: &[u8]) -> Vec<Vec<Hunk>> {
+    let mut files: Vec<Vec<_>> = Vec::new();
+    for line in patch.split(|&b| b == b'\n') {
+        if line.starts_with(b"diff --git ") {
+            files.push(Vec::new());
+        } else if line.starts_with(b"@@ ")
+            && let Some(file) = files.last_mut()
+            && let Some(hunk) = parse_hunk_header(&String::from_utf8_lossy(line))
+        {
+            file.push(hunk);
+        }
+    }
+    files
+}
+
+// Actual LOC delta of a file from OLD to NEW, each its path and content, given the line
+// ranges of the HUNKS of their diff; `None` if either side is binary
+fn file_delta(old: (&Path, &[u8]), new: (&Path, &[u8]), hunks: &[Hunk]) -> Option<LocDelta> {
+    if looks_binary(old.1) || looks_binary(new.1) {
+        return None;
+    }
+    let old_code = code_lines(old.0, old.1);
+    let new_code = code_lines(new.0, new.1);
+    let mut delta = LocDelta::default();
+    for &(removed, added) in hunks {
+        // Within a hunk, a removed code line paired with an added one is a change
+        let count = |code: &[bool], (start, len): (usize, usize)| {
+            (start..start + len).filter(|&n| n >= 1 && code.get(n - 1) == Some(&true)).count()
+        };
+        let removed = count(&old_code, removed);
+        let added = count(&new_code, added);
+        let changed = removed.min(added);
+        delta.changed += changed;
+        delta.removed += removed - changed;
+        delta.added += added - changed;
+    }
+    Some(delta)
+}
+
+// Which lines of CONTENT are actual code, for a file named PATH
+fn code_lines(path: &Path, content: &[u8]) -> Vec<bool> {
+    let lang = detect_language_from_prefix(path, content);
+    let syntax = builtin_comment_syntax(&lang).unwrap_or_else(|| infer_comment_syntax(content).syntax);
+    let mut classifier = LineClassifier::new(&lang, syntax);
+    String::from_utf8_lossy(content)
+        .split_inclusive('\n')
+        .map(|line| classifier.is_code(line))
+        .collect()
+}
+
+// Parse "@@ -START[,LEN] +START[,LEN] @@" into the old and new line ranges
+fn parse_hunk_header(line: &str) -> Option<Hunk> {
+    let rest = line.strip_prefix("@@ -")?;
+    let (ranges, _) = rest.split_once(" @@")?;
+    let (old, new) = ranges.split_once(" +")?;
+    let range = |r: &str| -> Option<(usize, usize)> {
+        match r.split_once(',') {
+            Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+            None => Some((r.parse().ok()?, 1)),
+        }
+    };
+    Some((range(old)?, range(new)?))
+}
+
+/// A shallow clone of a remote repository, removed again when dropped
+struct RemoteClone {
+    dir: tempfile::TempDir,
+}
+
+impl RemoteClone {
+    fn new(url: &str, git_ref: Option<&str>) -> io::Result<Self> {
+        // Created with a name of its own, so only a directory made here is ever removed
+        let clone = RemoteClone { dir: tempfile::Builder::new().prefix("sourcelines-").tempdir()? };
+        let mut git = std::process::Command::new("git");
+        git.args(["clone", "--quiet", "--depth", "1"]);
+        if let Some(git_ref) = git_ref {
+            git.args(["--branch", git_ref]);
+        }
+        let status = git.arg("--").arg(url).arg(clone.dir()).status()?;
+        if !status.success() {
+            return Err(io::Error::other("git clone failed"));
+        }
+        Ok(clone)
+    }
+
+    fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+/// Directories fetched for the arguments, removed by `exit` as std::process::exit skips the
+/// destructors that otherwise remove them
+static FETCHED_DIRS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Exit with CODE, once the directories fetched for the arguments are removed
+fn exit(code: i32) -> ! {
+    for dir in std::mem::take(&mut *FETCHED_DIRS.lock().unwrap_or_else(|e| e.into_inner())) {
+        let _ = fs::remove_dir_all(dir);
+    }
+    std::process::exit(code)
+}
+
+/// An argument fetched into a temporary directory, removed again when dropped
+enum Fetched {
+    Clone(RemoteClone),
+    Crate(archive::Unpacked),
+}
+
+impl Fetched {
+    fn dir(&self) -> &Path {
+        match self {
+            Fetched::Clone(clone) => clone.dir(),
+            Fetched::Crate(unpacked) => unpacked.dir(),
+        }
+    }
+}
+
+/// Download the crate SPEC, NAME or NAME@VERSION (by default the latest stable version), from
+/// crates.io, or take it from Cargo's download cache for crates.io, and unpack it. Downloads
+/// are checked against the SHA-256 checksum of the crates.io index; cached crates were checked
+/// by Cargo when it downloaded them. Returns it with its name and version as NAME@VERSION
+fn fetch_crate(spec: &str) -> io::Result<(String, archive::Unpacked)> {
+    let (name, version) = match spec.split_once('@') {
+        Some((name, version)) => (name, Some(version.to_string())),
+        None => (spec, None),
+    };
+    let valid = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || "-_.+".contains(c));
+    if !valid(name) || version.as_deref().is_some_and(|version| !valid(version)) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "expected NAME or NAME@VERSION"));
+    }
+    let version = match version {
+        Some(version) => version,
+        None => {
+            let info = download(&format!("https://crates.io/api/v1/crates/{}", name))?;
+            let info: serde_json::Value = serde_json::from_slice(&info).map_err(io::Error::other)?;
+            let latest = ["max_stable_version", "max_version"].iter().find_map(|key| info["crate"][key].as_str());
+            latest.ok_or_else(|| io::Error::other("crates.io gave no version"))?.to_string()
+        }
+    };
+    let file = format!("{}-{}.crate", name, version);
+    let cargo_home = std::env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cargo")));
+    // One directory per registry, named after the host of its index: only those of crates.io,
+    // through its sparse or its git index, are looked in
+    let cached = cargo_home
+        .and_then(|home| fs::read_dir(home.join("registry").join("cache")).ok())
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|registry| {
+            let registry = registry.file_name().to_string_lossy().into_owned();
+            registry.starts_with("index.crates.io-") || registry == "github.com-1ecc6299db9ec823"
+        })
+        .map(|registry| registry.path().join(&file))
+        .find(|path| path.is_file());
+    let data = match cached {
+        Some(path) => fs::read(path)?,
+        None => {
+            let cksum = crate_checksum(name, &version)?;
+            let data = download(&format!("https://static.crates.io/crates/{}/{}", name, file))?;
+            let digest: String = sha2::Sha256::digest(&data).iter().map(|b| format!("{:02x}", b)).collect();
+            if digest != cksum {
+                return Err(io::Error::other(format!("{} does not match its checksum on crates.io", file)));
+            }
+            data
+        }
+    };
+    Ok((format!("{}@{}", name, version), archive::Unpacked::new(&data)?))
+}
+
+// The SHA-256 checksum of the crate NAME at VERSION, in hex, from the sparse index of crates.io
+fn crate_checksum(name: &str, version: &str) -> io::Result<String> {
+    let lower = name.to_lowercase();
+    let dir = match lower.len() {
+        1 => "1".to_string(),
+        2 => "2".to_string(),
+        3 => format!("3/{}", &lower[..1]),
+        _ => format!("{}/{}", &lower[..2], &lower[2..4]),
+    };
+    let index = download(&format!("https://index.crates.io/{}/{}", dir, lower))?;
+    // One JSON object per version and line
+    let cksum = index
+        .split(|&b| b == b'\n')
+        .filter_map(|line| serde_json::from_slice::<serde_json::Value>(line).ok())
+        .find(|entry| entry["vers"] == version)
+        .and_then(|entry| entry["cksum"].as_str().map(str::to_string));
+    cksum.ok_or_else(|| io::Error::other(format!("crates.io has no version {} of {}", version, name)))
+}
+
+// The body at URL, downloaded by curl
+fn download(url: &str) -> io::Result<Vec<u8>> {
+    let user_agent = format!("sourcelines/{}", env!("CARGO_PKG_VERSION"));
+    let output = std::process::Command::new("curl")
+        .args(["--fail", "--silent", "--show-error", "--location", "--user-agent", &user_agent, url])
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("could not run curl: {}", e)))?;
+    if !output.status.success() {
+        return Err(io::Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(output.stdout)
+}
+
+// URLs (and scp-like user@host:path addresses) that do not name a local path; nothing
+// starting with "-" is one, so that git never takes it for an option
+fn is_remote_repository(arg: &str) -> bool {
+    if arg.starts_with('-') || Path::new(arg).exists() {
+        return false;
+    }
+    ["https://", "http://", "git://", "ssh://", "file://"].iter().any(|scheme| arg.starts_with(scheme))
+        || arg
+            .split_once(':')
+            .is_some_and(|(host, _)| host.contains('@') && !host.contains('/'))
+}
+
+/// A long-running `git cat-file --batch` process for reading many blobs
+struct BlobReader {
+    child: std::process::Child,
+    stdin: Option<std::process::ChildStdin>,
+    stdout: io::BufReader<std::process::ChildStdout>,
+}
+
+impl BlobReader {
+    fn new(repo: &Path) -> io::Result<Self> {
+        let mut child = std::process::Command::new("git")
+            .arg("-C")
+            .arg(repo)
+            .args(["cat-file", "--batch"])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take();
+        let stdout = io::BufReader::new(child.stdout.take().expect("piped stdout"));
+        Ok(BlobReader { child, stdin, stdout })
+    }
+
+    fn read(&mut self, id: &str) -> io::Result<Vec<u8>> {
+        let stdin = self.stdin.as_mut().expect("open stdin");
+        writeln!(stdin, "{}", id)?;
+        stdin.flush()?;
+        // The object follows a "<id> <type> <size>" header line
+        let mut header = String::new();
+        self.stdout.read_line(&mut header)?;
+        let size = header
+            .split_whitespace()
+            .nth(2)
+            .and_then(|n| n.parse::<usize>().ok())
+            .ok_or_else(|| io::Error::other(format!("cannot read object {}", id)))?;
+        let mut data = vec![0; size + 1];
+        self.stdout.read_exact(&mut data)?;
+        data.pop();
+        Ok(data)
+    }
+}
+
+impl Drop for BlobReader {
+    fn drop(&mut self) {
+        // Closing stdin ends the batch
+        self.stdin.take();
+        let _ = self.child.wait();
+    }
+}
+
+/// xorshift64* generator, so that generated test trees do not depend on the platform
+struct TreeRng(u64);
+
+impl TreeRng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n.max(1)
+    }
+}
+
+// Write FILES synthetic source files of about SIZE bytes each under DIR, 100 to a
+// directory, with a mix of code, comment and blank lines
+fn generate_test_tree(dir: &Path, files: usize, size: u64, langs: &[String], seed: u64) -> io::Result<()> {
+    let mut rng = TreeRng(seed.max(1));
+    for i in 0..files {
+        let ext = langs.get(i % langs.len().max(1)).map_or("c", |s| s.as_str());
+        let (comment, code) = match ext {
+            "py" | "sh" | "rb" => ("#", "x{} = y{} + {}"),
+            "rs" => ("//", "let x{} = y{} + {};"),
+            "js" | "ts" => ("//", "const x{} = y{} + {};"),
+            _ => ("//", "int x{} = y{} + {};"),
+        };
+        let sub = dir.join(format!("d{}", i / 1000)).join(format!("d{}", i / 100 % 10));
+        fs::create_dir_all(&sub)?;
+        let target = size / 2 + rng.below(size + 1);
+        let mut content = String::new();
+        while (content.len() as u64) < target {
+            let indent = "    ".repeat(rng.below(3) as usize);
+            match rng.below(20) {
+                0..=11 => {
+                    let line = code
+                        .replacen("{}", &rng.below(100).to_string(), 1)
+                        .replacen("{}", &rng.below(100).to_string(), 1)
+                        .replacen("{}", &rng.below(1000).to_string(), 1);
+                    content += &format!("{}{}\n", indent, line);
+                }
+                12..=15 => content += &format!("{}{} note {}\n", indent, comment, rng.below(1000)),
+                _ => content.push('\n'),
+            }
+        }
+        fs::write(sub.join(format!("f{}.{}", i, ext)), content)?;
+    }
+    Ok(())
+}
+
+// Print per-language actual LOC of the last commit of each interval in the
+// history of REV, oldest first
+fn run_history(
+    rev: &str,
+    since: Option<&str>,
+    interval: Interval,
+    format: SeriesFormat,
+    opts: &Options,
+) -> io::Result<()> {
+    let repo = Path::new(".");
+    let date_format = format!("--date=format:{}", interval.date_format());
+    let since_arg = since.map(|date| format!("--since={}", date));
+    let mut args = vec!["log", "--first-parent", "--reverse", "--format=%H %cd %cs", &date_format];
+    args.extend(since_arg.as_deref());
+    args.push(rev);
+    let log = String::from_utf8_lossy(&git_output(repo, &args)?).into_owned();
+    // (interval, date, commit) of the last commit in each interval
+    let mut snapshots: Vec<(&str, &str, &str)> = Vec::new();
+    for line in log.lines() {
+        let mut fields = line.split(' ');
+        let (Some(commit), Some(period), Some(date)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        match snapshots.last_mut() {
+            Some(last) if last.0 == period => *last = (period, date, commit),
+            _ => snapshots.push((period, date, commit)),
+        }
+    }
+
+    let mut blobs = BlobReader::new(repo)?;
+    // Actual LOC by (blob, language); unchanged files are only counted once
+    let mut cache: std::collections::HashMap<(String, Language), Option<usize>> =
+        std::collections::HashMap::new();
+    if let SeriesFormat::Csv = format {
+        println!("date,commit,language,actual_loc");
+    } else {
+        println!("[");
+    }
+    for (i, (_, date, commit)) in snapshots.iter().enumerate() {
+        let tree = git_output(repo, &["ls-tree", "-r", "-z", commit])?;
+        let mut per_lang: std::collections::BTreeMap<Language, usize> = std::collections::BTreeMap::new();
+        for entry in tree.split(|&b| b == b'\0') {
+            let entry = String::from_utf8_lossy(entry);
+            // "<mode> <type> <id>\t<path>"
+            let Some((meta, name)) = entry.split_once('\t') else {
+                continue;
+            };
+            let mut meta = meta.split(' ');
+            let (Some(mode), Some("blob"), Some(id)) = (meta.next(), meta.next(), meta.next()) else {
+                continue;
+            };
+            // Symlinks are stored as blobs holding the link target
+            if mode == "120000" {
+                continue;
+            }
+            let path = Path::new(name);
+            let detected = detect_language(path);
+            let lang = remap_language(detected.clone(), &opts.remap);
+            if !opts.lang_selected(&lang) || !opts.path_selected(path, Path::new("")) {
+                continue;
+            }
+            let key = (id.to_string(), detected);
+            let loc = match cache.get(&key) {
+                Some(loc) => *loc,
+                None => {
+                    let content = blobs.read(id)?;
+                    let lang = detect_language_from_prefix(path, &content);
+                    let loc = (!looks_binary(&content))
+                        .then(|| count_reader(&lang, content.as_slice()).unwrap_or_default().actual_loc);
+                    cache.insert(key, loc);
+                    loc
+                }
+            };
+            if let Some(loc) = loc {
+                *per_lang.entry(lang).or_default() += loc;
+            }
+        }
+        match format {
+            SeriesFormat::Csv => {
+                for (lang, loc) in &per_lang {
+                    println!("{},{},{},{}", date, commit, csv_field(lang), loc);
+                }
+            }
+            SeriesFormat::Json => {
+                let langs: Vec<String> = per_lang
+                    .iter()
+                    .map(|(lang, loc)| format!("{}: {}", json_string(lang), loc))
+                    .collect();
+                println!(
+                    "  {{\"date\": {}, \"commit\": {}, \"languages\": {{{}}}, \"total\": {}}}{}",
+                    json_string(date),
+                    json_string(commit),
+                    langs.join(", "),
+                    per_lang.values().sum::<usize>(),
+                    if i + 1 < snapshots.len() { "," } else { "" }
+                );
+            }
+        }
+    }
+    if let SeriesFormat::Json = format {
+        println!("]");
+    }
+    Ok(())
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            // Also escaped so that the string can be embedded in an HTML script element
+            '<' | '>' | '&' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Lines added and deleted by the commits touching a file
+#[derive(Default, Debug, Clone, Copy)]
+struct Churn {
+    commits: usize,
+    added: usize,
+    deleted: usize,
+    actual_loc: usize,
+}
+
+impl Churn {
+    fn add(&mut self, other: Churn) {
+        self.commits += other.commits;
+        self.added += other.added;
+        self.deleted += other.deleted;
+        self.actual_loc += other.actual_loc;
+    }
+
+    fn lines(&self) -> usize {
+        self.added + self.deleted
+    }
+}
+
+// Print commits, added and deleted lines and current actual LOC for the files
+// in DIR changed since SINCE, busiest first, then the same per language
+fn print_churn(dir: &Path, since: Option<&str>, opts: &Options) -> io::Result<()> {
+    let since_arg = since.map(|date| format!("--since={}", date));
+    let mut args = vec!["-c", "core.quotePath=false", "log", "--no-renames", "--numstat", "--format=", "--relative"];
+    args.extend(since_arg.as_deref());
+    let log = String::from_utf8_lossy(&git_output(dir, &args)?).into_owned();
+    let mut per_file: std::collections::HashMap<&str, Churn> = std::collections::HashMap::new();
+    for line in log.lines() {
+        // "<added>\t<deleted>\t<path>", with "-" counts for binary files
+        let mut fields = line.splitn(3, '\t');
+        let (Some(added), Some(deleted), Some(name)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        let (Ok(added), Ok(deleted)) = (added.parse::<usize>(), deleted.parse::<usize>()) else {
+            continue;
+        };
+        per_file.entry(name).or_default().add(Churn { commits: 1, added, deleted, actual_loc: 0 });
+    }
+    let mut rows: Vec<(Churn, Language, &str)> = Vec::new();
+    for (name, mut churn) in per_file {
+        let path = dir.join(name);
+        // Files deleted since are no hotspots
+        if !path.is_file() {
+            continue;
+        }
+        let lang = remap_language(detect_language(&path), &opts.remap);
+        if !opts.lang_selected(&lang) || !opts.path_selected(&path, dir) {
+            continue;
+        }
+        churn.actual_loc = process_file(&path).actual_loc;
+        rows.push((churn, lang, name));
+    }
+    rows.sort_by(|a, b| b.0.lines().cmp(&a.0.lines()).then_with(|| a.2.cmp(b.2)));
+    let row = |churn: &Churn, label: &str| {
+        println!(
+            "{:>8} {:>8} {:>8} {:>8} {}",
+            churn.commits,
+            format!("+{}", churn.added),
+            format!("-{}", churn.deleted),
+            churn.actual_loc,
+            label
+        );
+    };
+    let mut per_lang: std::collections::HashMap<&str, Churn> = std::collections::HashMap::new();
+    for (churn, lang, name) in &rows {
+        row(churn, &format!("<{}> {}", lang, dir.join(name).display()));
+        per_lang.entry(lang.name()).or_default().add(*churn);
+    }
+    let mut langs: Vec<(&str, Churn)> = per_lang.into_iter().collect();
+    langs.sort_by(|a, b| b.1.lines().cmp(&a.1.lines()).then_with(|| a.0.cmp(b.0)));
+    for (lang, churn) in &langs {
+        row(churn, &format!("<{}> (churn)", lang));
+    }
+    Ok(())
+}
+
+// Print how each known language is recognized and which comment syntax it is counted with
+fn print_languages(remap: &std::collections::HashMap<String, String>) {
+    let mut langs: Vec<&str> = EXTENSION_LANGUAGES
+        .iter()
+        .map(|(lang, _)| *lang)
+        .chain(SHEBANG_LANGUAGES.iter().map(|(_, lang)| *lang))
+        .collect();
+    langs.sort();
+    langs.dedup();
+    println!("{:<18}{:<30}{:<18}comments", "language", "extensions", "shebang");
+    for lang in langs {
+        let list = |items: Vec<String>| if items.is_empty() { "-".to_string() } else { items.join(" ") };
+        let exts = EXTENSION_LANGUAGES
+            .iter()
+            .filter(|(l, _)| *l == lang)
+            .flat_map(|(_, exts)| exts.iter().map(|ext| format!(".{}", ext)))
+            .collect();
+        let words = SHEBANG_LANGUAGES
+            .iter()
+            .filter(|(_, l)| *l == lang)
+            .map(|(word, _)| word.to_string())
+            .collect();
+        let mut comments = match builtin_comment_syntax(lang) {
+            Some(_) if literate_style(lang).is_some() => "(literate, only code chunks count)".to_string(),
+            Some(syntax) => syntax.describe(),
+            None => "(inferred from content)".to_string(),
+        };
+        if let Some(to) = remap.get(lang) {
+            comments += &format!(", counted as {}", to);
+        }
+        println!("{:<18}{:<30}{:<18}{}", lang, list(exts), list(words), comments);
+    }
+}
+
+// Print baseline and current actual LOC with the change, per language and in total
+fn print_baseline_comparison(baseline: &std::collections::HashMap<String, Stats>, report: &RunReport, file: &str) {
+    let totals = report.langs();
+    let mut langs: Vec<&String> = baseline.keys().chain(totals.keys()).filter(|l| *l != "*").collect();
+    langs.sort();
+    langs.dedup();
+    for lang in langs {
+        let before = baseline.get(lang).map_or(0, |s| s.actual_loc);
+        let after = totals.get(lang).map_or(0, |s| s.actual_loc);
+        print_delta_row(before, after, &format!("<{}>", lang));
+    }
+    let before = baseline.get("*").map_or(0, |s| s.actual_loc);
+    print_delta_row(before, report.total.actual_loc, &format!("<*> (since {})", file));
+}
+
+// Print the actual LOC of the files that differ between two trees, then per language
+// and in total, like a baseline comparison
+fn print_tree_comparison(dir_a: &Path, old: &DirSummary, dir_b: &Path, new: &DirSummary) {
+    // Files are matched by their path relative to the tree
+    let by_name = |dir: &Path, summary: &DirSummary| -> std::collections::BTreeMap<PathBuf, (String, usize)> {
+        summary
+            .files
+            .iter()
+            .map(|(path, lang, stats)| {
+                let name = path.strip_prefix(dir).unwrap_or(path).to_path_buf();
+                (name, (lang.clone(), stats.actual_loc))
+            })
+            .collect()
+    };
+    let (old_files, new_files) = (by_name(dir_a, old), by_name(dir_b, new));
+    let mut names: Vec<&PathBuf> = old_files.keys().chain(new_files.keys()).collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        let (before, after) = (old_files.get(name), new_files.get(name));
+        let before_loc = before.map_or(0, |(_, loc)| *loc);
+        let after_loc = after.map_or(0, |(_, loc)| *loc);
+        // Added and removed files are listed even when they have no actual LOC
+        if before.is_some() && after.is_some() && before_loc == after_loc {
+            continue;
+        }
+        let lang = after.or(before).map_or("", |(lang, _)| lang.as_str());
+        print_delta_row(before_loc, after_loc, &format!("<{}> {}", lang, name.display()));
+    }
+    let mut langs: Vec<&String> = old.langs.keys().chain(new.langs.keys()).collect();
+    langs.sort();
+    langs.dedup();
+    for lang in langs {
+        let before = old.langs.get(lang).map_or(0, |s| s.actual_loc);
+        let after = new.langs.get(lang).map_or(0, |s| s.actual_loc);
+        print_delta_row(before, after, &format!("<{}>", lang));
+    }
+    print_delta_row(
+        old.total.actual_loc,
+        new.total.actual_loc,
+        &format!("<*> {}..{}", dir_a.display(), dir_b.display()),
+    );
+}
+
+// Print a row with an old and a new actual LOC and the change between them
+fn print_delta_row(before: usize, after: usize, label: &str) {
+    println!("{:>8} {:>8} {:>8} {}", before, after, format!("{:+}", after as i64 - before as i64), label);
+}
+
+/// Width of the --chart bars in terminal cells
+const CHART_WIDTH: usize = 20;
+
+// A bar of Unicode blocks filling FRACTION of WIDTH cells, in eighths of a cell
+fn chart_bar(fraction: f64, width: usize) -> String {
+    const PARTIAL: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+    let eighths = (fraction.clamp(0.0, 1.0) * (width * 8) as f64).round() as usize;
+    let bar = "█".repeat(eighths / 8);
+    match PARTIAL[eighths % 8] {
+        ' ' => bar,
+        partial => bar + &partial.to_string(),
+    }
+}
+
+// Print the subdirectory rows of --by-dir in ORDER, each indented by its depth
+// below the argument and followed by its own subdirectories
+fn print_dir_tree(
+    children: &[(String, DirSummary)],
+    depth: usize,
+    order: SortOrder,
+    row: &dyn Fn(&DirSummary, &str),
+) {
+    let mut sorted: Vec<&(String, DirSummary)> =
+        children.iter().filter(|(_, d)| d.total.raw_loc > 0 || d.total.bytes > 0).collect();
+    sorted.sort_by(|(na, a), (nb, b)| order.compare((na, &a.total), (nb, &b.total)));
+    for (name, child) in sorted {
+        row(child, &format!("{}{}", "  ".repeat(depth), name));
+        print_dir_tree(&child.children, depth + 1, order, row);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn print_stats(
     stats: &Stats,
     lang: &str,
     filename: Option<&str>,
-    show_actual_klocs: bool,
-    show_actual_loc: bool,
-    show_raw_klocs: bool,
-    show_raw_loc: bool,
-    show_words: bool,
-    show_chars: bool,
-    show_bytes: bool,
+    columns: &[Column],
+    metrics: &[String],
+    numbers: NumberFormat,
+    icons: Option<&Icons>,
     is_sum: bool,
     color: bool,
+    share: Option<f64>,
+    chart: Option<f64>,
+    width: usize,
 ) {
     let mut out = String::new();
     let fname = filename.unwrap_or("");
+    // Language rows (without a file name) are faint as a whole instead
+    let paint = color && filename.is_some();
 
-    let cyan = "\x1b[36m";
-    let green = "\x1b[32m";
-    let yellow = "\x1b[33m";
-    let magenta = "\x1b[35m";
-    let blue = "\x1b[34m";
-    // let lightgray = "\x1b[35m";
-    let lightgray = "\x1b[2:38m";
-    let reset = "\x1b[0m";
+    if let Some(icons) = icons {
+        out += &icons.cell((!is_sum && lang != "*").then_some(lang));
+        out += " ";
+    }
+    for column in columns {
+        out += &column.style().paint(&format!("{:>width$}", column.format(stats, numbers)), paint);
+        out += " ";
+    }
+    for cell in metrics {
+        out += &format!("{:>width$} ", cell);
+    }
+    if let Some(share) = share {
+        out += &format!("{:>7}% ", numbers.fixed(share, 1));
+    }
+    if let Some(fraction) = chart {
+        out += &format!("{:<width$} ", chart_bar(fraction, CHART_WIDTH), width = CHART_WIDTH);
+    }
+    if is_sum {
+        out += &Style::Cyan.paint(&format!("<*> {}", fname), paint);
+    } else {
+        out += &Style::Green.paint(&format!("<{}>", lang), paint);
+        out += &format!(" {}", fname);
+    }
 
-    if color && filename.is_some() {
-        if show_actual_klocs {
-            out += &format!("{}{:>8.3}{} ", cyan, stats.actual_loc as f64 / 1000.0, reset);
-        }
-        if show_actual_loc {
-            out += &format!("{}{:>8}{} ", cyan, stats.actual_loc, reset);
-        }
-        if show_raw_klocs {
-            out += &format!("{}{:>8.3}{} ", green, stats.raw_loc as f64 / 1000.0, reset);
-        }
-        if show_raw_loc {
-            out += &format!("{}{:>8}{} ", green, stats.raw_loc, reset);
-        }
-        if show_words {
-            out += &format!("{}{:>8}{} ", yellow, stats.words, reset);
-        }
-        if show_chars {
-            out += &format!("{}{:>8}{} ", magenta, stats.chars, reset);
-        }
-        if show_bytes {
-            out += &format!("{}{:>8}{} ", blue, stats.bytes, reset);
-        }
-        if is_sum {
-            out += &format!("{}<*> {}{}", cyan, fname, reset);
-        } else {
-            out += &format!("{}<{}>{} {}", green, lang, reset, fname);
-        }
+    let out = out.trim_end();
+    if filename.is_none() {
+        println!("{}", Style::Faint.paint(out, color));
     } else {
-        if show_actual_klocs {
-            out += &format!("{:>8.3} ", stats.actual_loc as f64 / 1000.0);
-        }
-        if show_actual_loc {
-            out += &format!("{:>8} ", stats.actual_loc);
-        }
-        if show_raw_klocs {
-            out += &format!("{:>8.3} ", stats.raw_loc as f64 / 1000.0);
-        }
-        if show_raw_loc {
-            out += &format!("{:>8} ", stats.raw_loc);
-        }
-        if show_words {
-            out += &format!("{:>8} ", stats.words);
-        }
-        if show_chars {
-            out += &format!("{:>8} ", stats.chars);
-        }
-        if show_bytes {
-            out += &format!("{:>8} ", stats.bytes);
+        println!("{}", out);
+    }
+}
+
+/// Directory in the treemap of the HTML report, with the files counted in it
+#[derive(Default)]
+struct TreemapDir {
+    dirs: std::collections::BTreeMap<String, TreemapDir>,
+    /// (name, language, actual LOC)
+    files: Vec<(String, String, usize)>,
+}
+
+impl TreemapDir {
+    /// The files counted in a run, under their arguments
+    fn from_report(report: &RunReport) -> Self {
+        let mut treemap = TreemapDir::default();
+        for entry in &report.entries {
+            let Some(dir) = &entry.dir else {
+                treemap.insert(std::slice::from_ref(&entry.arg), &entry.lang, entry.stats.actual_loc);
+                continue;
+            };
+            for (p, lang, stats) in &dir.files {
+                let below = p.strip_prefix(&entry.path).unwrap_or(p).components();
+                let names = below.map(|c| c.as_os_str().to_string_lossy().into_owned());
+                let path: Vec<String> = std::iter::once(entry.arg.clone()).chain(names).collect();
+                treemap.insert(&path, lang, stats.actual_loc);
+            }
         }
-        if is_sum {
-            out += &format!("<*> {}", fname);
-        } else {
-            out += &format!("<{}> {}", lang, fname);
+        treemap
+    }
+
+    /// Add the file at PATH, given as the names of its directories and its own name
+    fn insert(&mut self, path: &[String], lang: &str, actual_loc: usize) {
+        match path {
+            [] => {}
+            [name] => self.files.push((name.clone(), lang.to_string(), actual_loc)),
+            [dir, rest @ ..] => self.dirs.entry(dir.clone()).or_default().insert(rest, lang, actual_loc),
         }
     }
 
-    if filename.is_none() {
-        print!("{}", lightgray);
+    fn actual_loc(&self) -> usize {
+        self.dirs.values().map(TreemapDir::actual_loc).sum::<usize>()
+            + self.files.iter().map(|(_, _, loc)| loc).sum::<usize>()
     }
-    println!("{}", out.trim_end());
-    if filename.is_none() {
-        print!("{}", reset);
+
+    /// This directory as {"n": name, "c": [children]}, with files as {"n": name, "l": language,
+    /// "v": actual LOC}; files without actual LOC take no area and are left out
+    fn to_json(&self, name: &str) -> String {
+        let dirs = self.dirs.iter().filter(|(_, dir)| dir.actual_loc() > 0).map(|(name, dir)| dir.to_json(name));
+        let files = self.files.iter().filter(|(_, _, loc)| *loc > 0).map(|(name, lang, loc)| {
+            format!("{{\"n\":{},\"l\":{},\"v\":{}}}", json_string(name), json_string(lang), loc)
+        });
+        format!("{{\"n\":{},\"c\":[{}]}}", json_string(name), dirs.chain(files).collect::<Vec<_>>().join(","))
+    }
+}
+
+/// Draws `tree` into #treemap as a squarified treemap, with a zoomable path and a legend
+const TREEMAP_SCRIPT: &str = r#"function total(node) {
+  if (node.v !== undefined) return node.v;
+  if (node.t === undefined) node.t = node.c.reduce((sum, child) => sum + total(child), 0);
+  return node.t;
+}
+function color(lang) {
+  let hue = 0;
+  for (const ch of lang) hue = (hue * 31 + ch.charCodeAt(0)) % 360;
+  return `hsl(${hue}, 55%, 65%)`;
+}
+// Aspect ratio of the worst rectangle when ROW is laid along a side of length SIDE
+function worst(row, side, scale) {
+  const areas = row.map(node => total(node) * scale);
+  const sum = areas.reduce((a, b) => a + b, 0);
+  return Math.max(side * side * Math.max(...areas) / (sum * sum), sum * sum / (side * side * Math.min(...areas)));
+}
+function squarify(nodes, x, y, w, h) {
+  const rects = [];
+  const sum = nodes.reduce((s, node) => s + total(node), 0);
+  if (sum <= 0 || w <= 0 || h <= 0) return rects;
+  const scale = w * h / sum;
+  let rest = nodes.slice().sort((a, b) => total(b) - total(a));
+  while (rest.length) {
+    const side = Math.min(w, h);
+    let count = 1;
+    while (count < rest.length && worst(rest.slice(0, count + 1), side, scale) <= worst(rest.slice(0, count), side, scale)) count++;
+    const row = rest.slice(0, count);
+    rest = rest.slice(count);
+    const thick = row.reduce((s, node) => s + total(node), 0) * scale / side;
+    let offset = 0;
+    for (const node of row) {
+      const len = total(node) * scale / thick;
+      rects.push(w >= h ? [node, x, y + offset, thick, len] : [node, x + offset, y, len, thick]);
+      offset += len;
+    }
+    if (w >= h) { x += thick; w -= thick; } else { y += thick; h -= thick; }
+  }
+  return rects;
+}
+function path(node) {
+  return node.p ? path(node.p).concat([node]) : [node];
+}
+function draw(container, node, x, y, w, h) {
+  for (const [child, cx, cy, cw, ch] of squarify(node.c, x, y, w, h)) {
+    if (cw < 2 || ch < 2) continue;
+    const el = document.createElement("div");
+    Object.assign(el.style, { left: cx + "px", top: cy + "px", width: cw + "px", height: ch + "px" });
+    const name = path(child).map(n => n.n).filter(n => n).join("/");
+    if (child.c) {
+      el.className = "dir";
+      el.title = `${name}: ${total(child)} actual LOC`;
+      el.onclick = event => { event.stopPropagation(); show(child); };
+      if (cw > 30 && ch > 16) el.textContent = child.n;
+      container.appendChild(el);
+      const header = ch > 30 ? 14 : 0;
+      draw(container, child, cx + 2, cy + header + 2, cw - 4, ch - header - 4);
+    } else {
+      el.className = "file";
+      el.style.backgroundColor = color(child.l);
+      el.title = `${name}: ${child.v} actual LOC (${child.l})`;
+      if (cw > 40 && ch > 14) el.textContent = child.n;
+      container.appendChild(el);
     }
+  }
+}
+function show(node) {
+  const container = document.getElementById("treemap");
+  container.replaceChildren();
+  draw(container, node, 0, 0, container.clientWidth, container.clientHeight);
+  const crumbs = document.getElementById("treemap-path");
+  crumbs.replaceChildren();
+  for (const [i, ancestor] of path(node).entries()) {
+    const link = document.createElement("a");
+    link.textContent = ancestor.n || "(all)";
+    link.onclick = () => show(ancestor);
+    if (i > 0) crumbs.append(" / ");
+    crumbs.append(link);
+  }
+}
+function link(node) {
+  for (const child of node.c || []) { child.p = node; link(child); }
 }
+function legend(node, langs) {
+  if (node.c) node.c.forEach(child => legend(child, langs));
+  else langs.set(node.l, (langs.get(node.l) || 0) + node.v);
+  return langs;
+}
+link(tree);
+for (const [lang, loc] of [...legend(tree, new Map())].sort((a, b) => b[1] - a[1])) {
+  const item = document.createElement("span");
+  item.className = "legend-item";
+  const swatch = document.createElement("span");
+  swatch.className = "legend-swatch";
+  swatch.style.backgroundColor = color(lang);
+  item.append(swatch, `${lang} (${loc})`);
+  document.getElementById("treemap-legend").appendChild(item);
+}
+show(tree);
+window.addEventListener("resize", () => show(tree));
+"#;
 
 fn print_text_report(
-    sum: &Stats,
-    per_lang_sum: &std::collections::HashMap<String, Stats>,
-    show_default: bool,
-    show_actual_klocs: bool,
-    show_actual_loc: bool,
-    show_raw_klocs: bool,
-    show_raw_loc: bool,
-    show_words: bool,
-    show_chars: bool,
-    show_bytes: bool,
+    report: &RunReport,
+    columns: &[Column],
+    numbers: NumberFormat,
+    sort: SortOrder,
+    percent: Option<SortColumn>,
 ) {
+    let (sum, per_lang_sum) = (&report.total, report.dir_langs());
     println!("Source Code Statistics Report");
     println!("{}", "=".repeat(80));
     println!();
@@ -690,28 +3708,8 @@ fn print_text_report(
     // Summary section
     println!("Summary:");
     println!("{}", "-".repeat(80));
-    if show_actual_klocs || (show_default && show_actual_loc) {
-        if show_actual_klocs {
-            println!("  Actual Lines of Code (KLOC): {:>12.3}", sum.actual_loc as f64 / 1000.0);
-        } else {
-            println!("  Actual Lines of Code:        {:>12}", sum.actual_loc);
-        }
-    }
-    if show_raw_klocs || (show_default && show_raw_loc) {
-        if show_raw_klocs {
-            println!("  Raw Lines of Code (KLOC):    {:>12.3}", sum.raw_loc as f64 / 1000.0);
-        } else {
-            println!("  Raw Lines of Code:           {:>12}", sum.raw_loc);
-        }
-    }
-    if show_words || show_default {
-        println!("  Words:                       {:>12}", sum.words);
-    }
-    if show_chars || show_default {
-        println!("  Characters:                  {:>12}", sum.chars);
-    }
-    if show_bytes || show_default {
-        println!("  Bytes:                       {:>12}", sum.bytes);
+    for column in columns {
+        println!("  {:<29}{:>12}", format!("{}:", column.label()), column.format(sum, numbers));
     }
     println!();
     
@@ -720,44 +3718,23 @@ fn print_text_report(
         println!("Per-Language Breakdown:");
         println!("{}", "-".repeat(80));
         
-        // Sort by actual_loc descending
+        // Sort by --sort, actual_loc descending by default
         let mut lang_items: Vec<(&String, &Stats)> = per_lang_sum.iter().collect();
-        lang_items.sort_by(|(_, sa), (_, sb)| sb.actual_loc.cmp(&sa.actual_loc));
+        lang_items.sort_by(|(la, sa), (lb, sb)| sort.compare((la, sa), (lb, sb)));
         
         // Calculate table width
         let mut table_width = 20; // Language column
-        let num_cols = (if show_actual_klocs || (show_default && show_actual_loc) { 1 } else { 0 })
-            + (if show_raw_klocs || (show_default && show_raw_loc) { 1 } else { 0 })
-            + (if show_words || show_default { 1 } else { 0 })
-            + (if show_chars || show_default { 1 } else { 0 })
-            + (if show_bytes || show_default { 1 } else { 0 });
+        let num_cols = columns.len() + usize::from(percent.is_some());
         table_width += num_cols * 13; // 12 chars + 1 space for each column
         table_width += 2; // Leading spaces
         
         // Print header
         print!("  {:<20}", "Language");
-        if show_actual_klocs || (show_default && show_actual_loc) {
-            if show_actual_klocs {
-                print!(" {:>12}", "Actual KLOC");
-            } else {
-                print!(" {:>12}", "Actual LOC");
-            }
+        for column in columns {
+            print!(" {:>12}", column.title());
         }
-        if show_raw_klocs || (show_default && show_raw_loc) {
-            if show_raw_klocs {
-                print!(" {:>12}", "Raw KLOC");
-            } else {
-                print!(" {:>12}", "Raw LOC");
-            }
-        }
-        if show_words || show_default {
-            print!(" {:>12}", "Words");
-        }
-        if show_chars || show_default {
-            print!(" {:>12}", "Chars");
-        }
-        if show_bytes || show_default {
-            print!(" {:>12}", "Bytes");
+        if percent.is_some() {
+            print!(" {:>12}", "Share");
         }
         println!();
         println!("  {}", "-".repeat(table_width - 2));
@@ -770,28 +3747,11 @@ fn print_text_report(
             }
             
             print!("  {:<20}", lang);
-            if show_actual_klocs || (show_default && show_actual_loc) {
-                if show_actual_klocs {
-                    print!(" {:>12.3}", stats.actual_loc as f64 / 1000.0);
-                } else {
-                    print!(" {:>12}", stats.actual_loc);
-                }
-            }
-            if show_raw_klocs || (show_default && show_raw_loc) {
-                if show_raw_klocs {
-                    print!(" {:>12.3}", stats.raw_loc as f64 / 1000.0);
-                } else {
-                    print!(" {:>12}", stats.raw_loc);
-                }
-            }
-            if show_words || show_default {
-                print!(" {:>12}", stats.words);
-            }
-            if show_chars || show_default {
-                print!(" {:>12}", stats.chars);
+            for column in columns {
+                print!(" {:>12}", column.format(stats, numbers));
             }
-            if show_bytes || show_default {
-                print!(" {:>12}", stats.bytes);
+            if let Some(column) = percent {
+                print!(" {:>11}%", numbers.fixed(column.percent(stats, sum), 1));
             }
             println!();
         }
@@ -802,17 +3762,14 @@ fn print_text_report(
 }
 
 fn print_html_report(
-    sum: &Stats,
-    per_lang_sum: &std::collections::HashMap<String, Stats>,
-    show_default: bool,
-    show_actual_klocs: bool,
-    show_actual_loc: bool,
-    show_raw_klocs: bool,
-    show_raw_loc: bool,
-    show_words: bool,
-    show_chars: bool,
-    show_bytes: bool,
+    report: &RunReport,
+    columns: &[Column],
+    numbers: NumberFormat,
+    sort: SortOrder,
+    percent: Option<SortColumn>,
 ) {
+    let (sum, per_lang_sum) = (&report.total, report.dir_langs());
+    let treemap = &TreemapDir::from_report(report);
     println!("<!DOCTYPE html>");
     println!("<html lang=\"en\">");
     println!("<head>");
@@ -831,6 +3788,13 @@ fn print_html_report(
     println!("    .summary {{ background-color: #e8f5e9; padding: 15px; border-radius: 5px; margin: 20px 0; }}");
     println!("    .summary-item {{ margin: 8px 0; font-size: 16px; }}");
     println!("    .summary-label {{ font-weight: bold; color: #2e7d32; }}");
+    println!("    #treemap {{ position: relative; height: 600px; background-color: #fafafa; }}");
+    println!("    #treemap div {{ position: absolute; box-sizing: border-box; overflow: hidden; font-size: 11px; white-space: nowrap; }}");
+    println!("    #treemap .dir {{ border: 1px solid #999; background-color: #eee; cursor: zoom-in; padding-left: 2px; }}");
+    println!("    #treemap .file {{ border: 1px solid white; padding: 1px 2px; }}");
+    println!("    #treemap-path a {{ cursor: pointer; color: #2e7d32; text-decoration: underline; }}");
+    println!("    .legend-item {{ display: inline-block; margin: 4px 12px 4px 0; }}");
+    println!("    .legend-swatch {{ display: inline-block; width: 12px; height: 12px; margin-right: 4px; vertical-align: middle; }}");
     println!("  </style>");
     println!("</head>");
     println!("<body>");
@@ -840,28 +3804,12 @@ fn print_html_report(
     // Summary section
     println!("    <h2>Summary</h2>");
     println!("    <div class=\"summary\">");
-    if show_actual_klocs || (show_default && show_actual_loc) {
-        if show_actual_klocs {
-            println!("      <div class=\"summary-item\"><span class=\"summary-label\">Actual Lines of Code (KLOC):</span> {:.3}</div>", sum.actual_loc as f64 / 1000.0);
-        } else {
-            println!("      <div class=\"summary-item\"><span class=\"summary-label\">Actual Lines of Code:</span> {}</div>", sum.actual_loc);
-        }
-    }
-    if show_raw_klocs || (show_default && show_raw_loc) {
-        if show_raw_klocs {
-            println!("      <div class=\"summary-item\"><span class=\"summary-label\">Raw Lines of Code (KLOC):</span> {:.3}</div>", sum.raw_loc as f64 / 1000.0);
-        } else {
-            println!("      <div class=\"summary-item\"><span class=\"summary-label\">Raw Lines of Code:</span> {}</div>", sum.raw_loc);
-        }
-    }
-    if show_words || show_default {
-        println!("      <div class=\"summary-item\"><span class=\"summary-label\">Words:</span> {}</div>", sum.words);
-    }
-    if show_chars || show_default {
-        println!("      <div class=\"summary-item\"><span class=\"summary-label\">Characters:</span> {}</div>", sum.chars);
-    }
-    if show_bytes || show_default {
-        println!("      <div class=\"summary-item\"><span class=\"summary-label\">Bytes:</span> {}</div>", sum.bytes);
+    for column in columns {
+        println!(
+            "      <div class=\"summary-item\"><span class=\"summary-label\">{}:</span> {}</div>",
+            column.label(),
+            column.format(sum, numbers)
+        );
     }
     println!("    </div>");
     
@@ -871,36 +3819,19 @@ fn print_html_report(
         println!("    <table>");
         println!("      <thead>");
         print!("        <tr><th>Language</th>");
-        if show_actual_klocs || (show_default && show_actual_loc) {
-            if show_actual_klocs {
-                print!("<th>Actual KLOC</th>");
-            } else {
-                print!("<th>Actual LOC</th>");
-            }
-        }
-        if show_raw_klocs || (show_default && show_raw_loc) {
-            if show_raw_klocs {
-                print!("<th>Raw KLOC</th>");
-            } else {
-                print!("<th>Raw LOC</th>");
-            }
-        }
-        if show_words || show_default {
-            print!("<th>Words</th>");
-        }
-        if show_chars || show_default {
-            print!("<th>Chars</th>");
+        for column in columns {
+            print!("<th>{}</th>", column.title());
         }
-        if show_bytes || show_default {
-            print!("<th>Bytes</th>");
+        if percent.is_some() {
+            print!("<th>Share</th>");
         }
         println!("</tr>");
         println!("      </thead>");
         println!("      <tbody>");
         
-        // Sort by actual_loc descending
+        // Sort by --sort, actual_loc descending by default
         let mut lang_items: Vec<(&String, &Stats)> = per_lang_sum.iter().collect();
-        lang_items.sort_by(|(_, sa), (_, sb)| sb.actual_loc.cmp(&sa.actual_loc));
+        lang_items.sort_by(|(la, sa), (lb, sb)| sort.compare((la, sa), (lb, sb)));
         
         for (lang, stats) in lang_items {
             // Filter out zero-count languages
@@ -909,28 +3840,11 @@ fn print_html_report(
             }
             
             print!("        <tr><td>{}</td>", lang);
-            if show_actual_klocs || (show_default && show_actual_loc) {
-                if show_actual_klocs {
-                    print!("<td>{:.3}</td>", stats.actual_loc as f64 / 1000.0);
-                } else {
-                    print!("<td>{}</td>", stats.actual_loc);
-                }
-            }
-            if show_raw_klocs || (show_default && show_raw_loc) {
-                if show_raw_klocs {
-                    print!("<td>{:.3}</td>", stats.raw_loc as f64 / 1000.0);
-                } else {
-                    print!("<td>{}</td>", stats.raw_loc);
-                }
-            }
-            if show_words || show_default {
-                print!("<td>{}</td>", stats.words);
+            for column in columns {
+                print!("<td>{}</td>", column.format(stats, numbers));
             }
-            if show_chars || show_default {
-                print!("<td>{}</td>", stats.chars);
-            }
-            if show_bytes || show_default {
-                print!("<td>{}</td>", stats.bytes);
+            if let Some(column) = percent {
+                print!("<td>{}%</td>", numbers.fixed(column.percent(stats, sum), 1));
             }
             println!("</tr>");
         }
@@ -938,155 +3852,92 @@ fn print_html_report(
         println!("      </tbody>");
         println!("    </table>");
     }
+
+    // Treemap of the files, under the argument when there is only one
+    let only = match (treemap.dirs.iter().next(), treemap.dirs.len(), treemap.files.len()) {
+        (Some((name, dir)), 1, 0) => Some((name.as_str(), dir)),
+        _ => None,
+    };
+    let (name, root) = only.unwrap_or(("", treemap));
+    if root.actual_loc() > 0 {
+        println!("    <h2>Treemap</h2>");
+        println!("    <p>Area is actual lines of code, color is language. Click a directory to zoom in.</p>");
+        println!("    <div id=\"treemap-path\"></div>");
+        println!("    <div id=\"treemap\"></div>");
+        println!("    <div id=\"treemap-legend\"></div>");
+        println!("    <script>");
+        println!("const tree = {};", root.to_json(name));
+        print!("{}", TREEMAP_SCRIPT);
+        println!("    </script>");
+    }
     
     println!("  </div>");
     println!("</body>");
     println!("</html>");
 }
 
-fn print_latex_report(
-    sum: &Stats,
-    per_lang_sum: &std::collections::HashMap<String, Stats>,
-    show_default: bool,
-    show_actual_klocs: bool,
-    show_actual_loc: bool,
-    show_raw_klocs: bool,
-    show_raw_loc: bool,
-    show_words: bool,
-    show_chars: bool,
-    show_bytes: bool,
-) {
-    println!("\\documentclass{{article}}");
-    println!("\\usepackage[utf8]{{inputenc}}");
-    println!("\\usepackage{{booktabs}}");
-    println!("\\usepackage{{longtable}}");
-    println!("\\usepackage{{geometry}}");
-    println!("\\geometry{{a4paper, margin=1in}}");
-    println!("\\title{{Source Code Statistics Report}}");
-    println!("\\author{{sourcelines}}");
-    println!("\\date{{\\today}}");
-    println!("\\begin{{document}}");
-    println!("\\maketitle");
-    println!();
-    println!("\\section{{Summary}}");
-    println!("\\begin{{itemize}}");
-    
-    if show_actual_klocs || (show_default && show_actual_loc) {
-        if show_actual_klocs {
-            println!("  \\item \\textbf{{Actual Lines of Code (KLOC):}} {:.3}", sum.actual_loc as f64 / 1000.0);
-        } else {
-            println!("  \\item \\textbf{{Actual Lines of Code:}} {}", sum.actual_loc);
-        }
-    }
-    if show_raw_klocs || (show_default && show_raw_loc) {
-        if show_raw_klocs {
-            println!("  \\item \\textbf{{Raw Lines of Code (KLOC):}} {:.3}", sum.raw_loc as f64 / 1000.0);
-        } else {
-            println!("  \\item \\textbf{{Raw Lines of Code:}} {}", sum.raw_loc);
-        }
-    }
-    if show_words || show_default {
-        println!("  \\item \\textbf{{Words:}} {}", sum.words);
-    }
-    if show_chars || show_default {
-        println!("  \\item \\textbf{{Characters:}} {}", sum.chars);
-    }
-    if show_bytes || show_default {
-        println!("  \\item \\textbf{{Bytes:}} {}", sum.bytes);
+/// The LaTeX document printed by --latex and compiled by --pdf
+fn latex_report(
+    report: &RunReport,
+    columns: &[Column],
+    numbers: NumberFormat,
+    sort: SortOrder,
+    percent: Option<SortColumn>,
+) -> String {
+    let (sum, per_lang_sum) = (&report.total, report.dir_langs());
+    use std::fmt::Write;
+
+    let mut out = String::new();
+    writeln!(out, "\\documentclass{{article}}").unwrap();
+    writeln!(out, "\\usepackage[utf8]{{inputenc}}").unwrap();
+    writeln!(out, "\\usepackage{{booktabs}}").unwrap();
+    writeln!(out, "\\usepackage{{longtable}}").unwrap();
+    writeln!(out, "\\usepackage{{geometry}}").unwrap();
+    writeln!(out, "\\geometry{{a4paper, margin=1in}}").unwrap();
+    writeln!(out, "\\title{{Source Code Statistics Report}}").unwrap();
+    writeln!(out, "\\author{{sourcelines}}").unwrap();
+    writeln!(out, "\\date{{\\today}}").unwrap();
+    writeln!(out, "\\begin{{document}}").unwrap();
+    writeln!(out, "\\maketitle").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "\\section{{Summary}}").unwrap();
+    writeln!(out, "\\begin{{itemize}}").unwrap();
+    for column in columns {
+        writeln!(out, "  \\item \\textbf{{{}:}} {}", column.label(), column.format(sum, numbers)).unwrap();
     }
-    println!("\\end{{itemize}}");
-    println!();
+    writeln!(out, "\\end{{itemize}}").unwrap();
+    writeln!(out).unwrap();
     
     // Per-language breakdown
     if !per_lang_sum.is_empty() {
-        println!("\\section{{Per-Language Breakdown}}");
-        println!("\\begin{{longtable}}{{l");
-        if show_actual_klocs || (show_default && show_actual_loc) {
-            print!("r");
-        }
-        if show_raw_klocs || (show_default && show_raw_loc) {
-            print!("r");
-        }
-        if show_words || show_default {
-            print!("r");
-        }
-        if show_chars || show_default {
-            print!("r");
-        }
-        if show_bytes || show_default {
-            print!("r");
-        }
-        println!("}}");
-        println!("\\toprule");
-        print!("  \\textbf{{Language}}");
-        if show_actual_klocs || (show_default && show_actual_loc) {
-            if show_actual_klocs {
-                print!(" & \\textbf{{Actual KLOC}}");
-            } else {
-                print!(" & \\textbf{{Actual LOC}}");
-            }
-        }
-        if show_raw_klocs || (show_default && show_raw_loc) {
-            if show_raw_klocs {
-                print!(" & \\textbf{{Raw KLOC}}");
-            } else {
-                print!(" & \\textbf{{Raw LOC}}");
-            }
-        }
-        if show_words || show_default {
-            print!(" & \\textbf{{Words}}");
-        }
-        if show_chars || show_default {
-            print!(" & \\textbf{{Chars}}");
-        }
-        if show_bytes || show_default {
-            print!(" & \\textbf{{Bytes}}");
-        }
-        println!(" \\\\");
-        println!("\\midrule");
-        println!("\\endfirsthead");
-        println!("\\multicolumn{{{}}}{{c}}{{\\textit{{Continued from previous page}}}} \\\\", 
-                 1 + (if show_actual_klocs || (show_default && show_actual_loc) { 1 } else { 0 })
-                 + (if show_raw_klocs || (show_default && show_raw_loc) { 1 } else { 0 })
-                 + (if show_words || show_default { 1 } else { 0 })
-                 + (if show_chars || show_default { 1 } else { 0 })
-                 + (if show_bytes || show_default { 1 } else { 0 }));
-        println!("\\toprule");
-        print!("  \\textbf{{Language}}");
-        if show_actual_klocs || (show_default && show_actual_loc) {
-            if show_actual_klocs {
-                print!(" & \\textbf{{Actual KLOC}}");
-            } else {
-                print!(" & \\textbf{{Actual LOC}}");
-            }
-        }
-        if show_raw_klocs || (show_default && show_raw_loc) {
-            if show_raw_klocs {
-                print!(" & \\textbf{{Raw KLOC}}");
-            } else {
-                print!(" & \\textbf{{Raw LOC}}");
-            }
-        }
-        if show_words || show_default {
-            print!(" & \\textbf{{Words}}");
+        let num_cols = 1 + columns.len() + usize::from(percent.is_some());
+        let mut header = String::from("  \\textbf{Language}");
+        for column in columns {
+            write!(header, " & \\textbf{{{}}}", column.title()).unwrap();
         }
-        if show_chars || show_default {
-            print!(" & \\textbf{{Chars}}");
+        if percent.is_some() {
+            header += " & \\textbf{Share}";
         }
-        if show_bytes || show_default {
-            print!(" & \\textbf{{Bytes}}");
-        }
-        println!(" \\\\");
-        println!("\\midrule");
-        println!("\\endhead");
-        println!("\\bottomrule");
-        println!("\\endfoot");
-        println!("\\bottomrule");
-        println!("\\endlastfoot");
+
+        writeln!(out, "\\section{{Per-Language Breakdown}}").unwrap();
+        writeln!(out, "\\begin{{longtable}}{{l{}}}", "r".repeat(num_cols - 1)).unwrap();
+        writeln!(out, "\\toprule").unwrap();
+        writeln!(out, "{} \\\\", header).unwrap();
+        writeln!(out, "\\midrule").unwrap();
+        writeln!(out, "\\endfirsthead").unwrap();
+        writeln!(out, "\\multicolumn{{{}}}{{c}}{{\\textit{{Continued from previous page}}}} \\\\", num_cols).unwrap();
+        writeln!(out, "\\toprule").unwrap();
+        writeln!(out, "{} \\\\", header).unwrap();
+        writeln!(out, "\\midrule").unwrap();
+        writeln!(out, "\\endhead").unwrap();
+        writeln!(out, "\\bottomrule").unwrap();
+        writeln!(out, "\\endfoot").unwrap();
+        writeln!(out, "\\bottomrule").unwrap();
+        writeln!(out, "\\endlastfoot").unwrap();
         
-        // Sort by actual_loc descending
+        // Sort by --sort, actual_loc descending by default
         let mut lang_items: Vec<(&String, &Stats)> = per_lang_sum.iter().collect();
-        lang_items.sort_by(|(_, sa), (_, sb)| sb.actual_loc.cmp(&sa.actual_loc));
+        lang_items.sort_by(|(la, sa), (lb, sb)| sort.compare((la, sa), (lb, sb)));
         
         for (lang, stats) in lang_items {
             // Filter out zero-count languages
@@ -1097,79 +3948,49 @@ fn print_latex_report(
             // Escape LaTeX special characters in language name
             let lang_escaped = lang.replace('&', "\\&").replace('%', "\\%").replace('$', "\\$").replace('#', "\\#").replace('^', "\\textasciicircum{}").replace('_', "\\_").replace('{', "\\{").replace('}', "\\}");
             
-            print!("  {}", lang_escaped);
-            if show_actual_klocs || (show_default && show_actual_loc) {
-                if show_actual_klocs {
-                    print!(" & {:.3}", stats.actual_loc as f64 / 1000.0);
-                } else {
-                    print!(" & {}", stats.actual_loc);
-                }
-            }
-            if show_raw_klocs || (show_default && show_raw_loc) {
-                if show_raw_klocs {
-                    print!(" & {:.3}", stats.raw_loc as f64 / 1000.0);
-                } else {
-                    print!(" & {}", stats.raw_loc);
-                }
-            }
-            if show_words || show_default {
-                print!(" & {}", stats.words);
+            write!(out, "  {}", lang_escaped).unwrap();
+            for column in columns {
+                write!(out, " & {}", column.format(stats, numbers)).unwrap();
             }
-            if show_chars || show_default {
-                print!(" & {}", stats.chars);
+            if let Some(column) = percent {
+                write!(out, " & {}\\%", numbers.fixed(column.percent(stats, sum), 1)).unwrap();
             }
-            if show_bytes || show_default {
-                print!(" & {}", stats.bytes);
-            }
-            println!(" \\\\");
+            writeln!(out, " \\\\").unwrap();
         }
         
-        println!("\\end{{longtable}}");
+        writeln!(out, "\\end{{longtable}}").unwrap();
     }
     
-    println!("\\end{{document}}");
+    writeln!(out, "\\end{{document}}").unwrap();
+    out
+}
+
+fn print_latex_report(
+    report: &RunReport,
+    columns: &[Column],
+    numbers: NumberFormat,
+    sort: SortOrder,
+    percent: Option<SortColumn>,
+) {
+    print!("{}", latex_report(report, columns, numbers, sort, percent));
 }
 
 fn print_markdown_report(
-    sum: &Stats,
-    per_lang_sum: &std::collections::HashMap<String, Stats>,
-    show_default: bool,
-    show_actual_klocs: bool,
-    show_actual_loc: bool,
-    show_raw_klocs: bool,
-    show_raw_loc: bool,
-    show_words: bool,
-    show_chars: bool,
-    show_bytes: bool,
+    report: &RunReport,
+    columns: &[Column],
+    numbers: NumberFormat,
+    sort: SortOrder,
+    percent: Option<SortColumn>,
 ) {
+    let (sum, per_lang_sum) = (&report.total, report.dir_langs());
     println!("# Source Code Statistics Report");
     println!();
     
     // Summary section
     println!("## Summary");
     println!();
-    if show_actual_klocs || (show_default && show_actual_loc) {
-        if show_actual_klocs {
-            println!("- **Actual Lines of Code (KLOC):** {:.3}", sum.actual_loc as f64 / 1000.0);
-        } else {
-            println!("- **Actual Lines of Code:** {}", sum.actual_loc);
-        }
-    }
-    if show_raw_klocs || (show_default && show_raw_loc) {
-        if show_raw_klocs {
-            println!("- **Raw Lines of Code (KLOC):** {:.3}", sum.raw_loc as f64 / 1000.0);
-        } else {
-            println!("- **Raw Lines of Code:** {}", sum.raw_loc);
-        }
-    }
-    if show_words || show_default {
-        println!("- **Words:** {}", sum.words);
-    }
-    if show_chars || show_default {
-        println!("- **Characters:** {}", sum.chars);
-    }
-    if show_bytes || show_default {
-        println!("- **Bytes:** {}", sum.bytes);
+    for column in columns {
+        println!("- **{}:** {}", column.label(), column.format(sum, numbers));
     }
     println!();
     
@@ -1178,44 +3999,23 @@ fn print_markdown_report(
         println!("## Per-Language Breakdown");
         println!();
         
-        // Sort by actual_loc descending
+        // Sort by --sort, actual_loc descending by default
         let mut lang_items: Vec<(&String, &Stats)> = per_lang_sum.iter().collect();
-        lang_items.sort_by(|(_, sa), (_, sb)| sb.actual_loc.cmp(&sa.actual_loc));
+        lang_items.sort_by(|(la, sa), (lb, sb)| sort.compare((la, sa), (lb, sb)));
         
         // Print table header
         print!("| Language");
-        if show_actual_klocs || (show_default && show_actual_loc) {
-            if show_actual_klocs {
-                print!(" | Actual KLOC");
-            } else {
-                print!(" | Actual LOC");
-            }
-        }
-        if show_raw_klocs || (show_default && show_raw_loc) {
-            if show_raw_klocs {
-                print!(" | Raw KLOC");
-            } else {
-                print!(" | Raw LOC");
-            }
+        for column in columns {
+            print!(" | {}", column.title());
         }
-        if show_words || show_default {
-            print!(" | Words");
-        }
-        if show_chars || show_default {
-            print!(" | Chars");
-        }
-        if show_bytes || show_default {
-            print!(" | Bytes");
+        if percent.is_some() {
+            print!(" | Share");
         }
         println!(" |");
         
         // Print separator
         print!("|");
-        let num_cols = 1 + (if show_actual_klocs || (show_default && show_actual_loc) { 1 } else { 0 })
-            + (if show_raw_klocs || (show_default && show_raw_loc) { 1 } else { 0 })
-            + (if show_words || show_default { 1 } else { 0 })
-            + (if show_chars || show_default { 1 } else { 0 })
-            + (if show_bytes || show_default { 1 } else { 0 });
+        let num_cols = 1 + columns.len() + usize::from(percent.is_some());
         for _ in 0..num_cols {
             print!(" --- |");
         }
@@ -1229,28 +4029,11 @@ fn print_markdown_report(
             }
             
             print!("| {}", lang);
-            if show_actual_klocs || (show_default && show_actual_loc) {
-                if show_actual_klocs {
-                    print!(" | {:.3}", stats.actual_loc as f64 / 1000.0);
-                } else {
-                    print!(" | {}", stats.actual_loc);
-                }
-            }
-            if show_raw_klocs || (show_default && show_raw_loc) {
-                if show_raw_klocs {
-                    print!(" | {:.3}", stats.raw_loc as f64 / 1000.0);
-                } else {
-                    print!(" | {}", stats.raw_loc);
-                }
-            }
-            if show_words || show_default {
-                print!(" | {}", stats.words);
+            for column in columns {
+                print!(" | {}", column.format(stats, numbers));
             }
-            if show_chars || show_default {
-                print!(" | {}", stats.chars);
-            }
-            if show_bytes || show_default {
-                print!(" | {}", stats.bytes);
+            if let Some(column) = percent {
+                print!(" | {}%", numbers.fixed(column.percent(stats, sum), 1));
             }
             println!(" |");
         }
@@ -1259,193 +4042,23 @@ fn print_markdown_report(
 }
 
 fn print_pdf_report(
-    sum: &Stats,
-    per_lang_sum: &std::collections::HashMap<String, Stats>,
-    show_default: bool,
-    show_actual_klocs: bool,
-    show_actual_loc: bool,
-    show_raw_klocs: bool,
-    show_raw_loc: bool,
-    show_words: bool,
-    show_chars: bool,
-    show_bytes: bool,
+    report: &RunReport,
+    columns: &[Column],
+    numbers: NumberFormat,
+    sort: SortOrder,
+    percent: Option<SortColumn>,
 ) {
-    use std::io::Write;
     use std::process::Command;
     
     // Generate LaTeX content
-    let mut latex_content = Vec::new();
-    let mut latex_writer = std::io::Cursor::new(&mut latex_content);
-    
-    writeln!(latex_writer, "\\documentclass{{article}}").unwrap();
-    writeln!(latex_writer, "\\usepackage[utf8]{{inputenc}}").unwrap();
-    writeln!(latex_writer, "\\usepackage{{booktabs}}").unwrap();
-    writeln!(latex_writer, "\\usepackage{{longtable}}").unwrap();
-    writeln!(latex_writer, "\\usepackage{{geometry}}").unwrap();
-    writeln!(latex_writer, "\\geometry{{a4paper, margin=1in}}").unwrap();
-    writeln!(latex_writer, "\\title{{Source Code Statistics Report}}").unwrap();
-    writeln!(latex_writer, "\\author{{sourcelines}}").unwrap();
-    writeln!(latex_writer, "\\date{{\\today}}").unwrap();
-    writeln!(latex_writer, "\\begin{{document}}").unwrap();
-    writeln!(latex_writer, "\\maketitle").unwrap();
-    writeln!(latex_writer).unwrap();
-    writeln!(latex_writer, "\\section{{Summary}}").unwrap();
-    writeln!(latex_writer, "\\begin{{itemize}}").unwrap();
-    
-    if show_actual_klocs || (show_default && show_actual_loc) {
-        if show_actual_klocs {
-            writeln!(latex_writer, "  \\item \\textbf{{Actual Lines of Code (KLOC):}} {:.3}", sum.actual_loc as f64 / 1000.0).unwrap();
-        } else {
-            writeln!(latex_writer, "  \\item \\textbf{{Actual Lines of Code:}} {}", sum.actual_loc).unwrap();
-        }
-    }
-    if show_raw_klocs || (show_default && show_raw_loc) {
-        if show_raw_klocs {
-            writeln!(latex_writer, "  \\item \\textbf{{Raw Lines of Code (KLOC):}} {:.3}", sum.raw_loc as f64 / 1000.0).unwrap();
-        } else {
-            writeln!(latex_writer, "  \\item \\textbf{{Raw Lines of Code:}} {}", sum.raw_loc).unwrap();
-        }
-    }
-    if show_words || show_default {
-        writeln!(latex_writer, "  \\item \\textbf{{Words:}} {}", sum.words).unwrap();
-    }
-    if show_chars || show_default {
-        writeln!(latex_writer, "  \\item \\textbf{{Characters:}} {}", sum.chars).unwrap();
-    }
-    if show_bytes || show_default {
-        writeln!(latex_writer, "  \\item \\textbf{{Bytes:}} {}", sum.bytes).unwrap();
-    }
-    writeln!(latex_writer, "\\end{{itemize}}").unwrap();
-    writeln!(latex_writer).unwrap();
-    
-    // Per-language breakdown
-    if !per_lang_sum.is_empty() {
-        writeln!(latex_writer, "\\section{{Per-Language Breakdown}}").unwrap();
-        let col_spec = format!("l{}{}{}{}{}",
-            if show_actual_klocs || (show_default && show_actual_loc) { "r" } else { "" },
-            if show_raw_klocs || (show_default && show_raw_loc) { "r" } else { "" },
-            if show_words || show_default { "r" } else { "" },
-            if show_chars || show_default { "r" } else { "" },
-            if show_bytes || show_default { "r" } else { "" });
-        writeln!(latex_writer, "\\begin{{longtable}}{{{}}}", col_spec).unwrap();
-        writeln!(latex_writer, "\\toprule").unwrap();
-        write!(latex_writer, "  \\textbf{{Language}}").unwrap();
-        if show_actual_klocs || (show_default && show_actual_loc) {
-            if show_actual_klocs {
-                write!(latex_writer, " & \\textbf{{Actual KLOC}}").unwrap();
-            } else {
-                write!(latex_writer, " & \\textbf{{Actual LOC}}").unwrap();
-            }
-        }
-        if show_raw_klocs || (show_default && show_raw_loc) {
-            if show_raw_klocs {
-                write!(latex_writer, " & \\textbf{{Raw KLOC}}").unwrap();
-            } else {
-                write!(latex_writer, " & \\textbf{{Raw LOC}}").unwrap();
-            }
-        }
-        if show_words || show_default {
-            write!(latex_writer, " & \\textbf{{Words}}").unwrap();
-        }
-        if show_chars || show_default {
-            write!(latex_writer, " & \\textbf{{Chars}}").unwrap();
-        }
-        if show_bytes || show_default {
-            write!(latex_writer, " & \\textbf{{Bytes}}").unwrap();
-        }
-        writeln!(latex_writer, " \\\\").unwrap();
-        writeln!(latex_writer, "\\midrule").unwrap();
-        writeln!(latex_writer, "\\endfirsthead").unwrap();
-        writeln!(latex_writer, "\\multicolumn{{{}}}{{c}}{{\\textit{{Continued from previous page}}}} \\\\", 
-                 1 + (if show_actual_klocs || (show_default && show_actual_loc) { 1 } else { 0 })
-                 + (if show_raw_klocs || (show_default && show_raw_loc) { 1 } else { 0 })
-                 + (if show_words || show_default { 1 } else { 0 })
-                 + (if show_chars || show_default { 1 } else { 0 })
-                 + (if show_bytes || show_default { 1 } else { 0 })).unwrap();
-        writeln!(latex_writer, "\\toprule").unwrap();
-        write!(latex_writer, "  \\textbf{{Language}}").unwrap();
-        if show_actual_klocs || (show_default && show_actual_loc) {
-            if show_actual_klocs {
-                write!(latex_writer, " & \\textbf{{Actual KLOC}}").unwrap();
-            } else {
-                write!(latex_writer, " & \\textbf{{Actual LOC}}").unwrap();
-            }
-        }
-        if show_raw_klocs || (show_default && show_raw_loc) {
-            if show_raw_klocs {
-                write!(latex_writer, " & \\textbf{{Raw KLOC}}").unwrap();
-            } else {
-                write!(latex_writer, " & \\textbf{{Raw LOC}}").unwrap();
-            }
-        }
-        if show_words || show_default {
-            write!(latex_writer, " & \\textbf{{Words}}").unwrap();
-        }
-        if show_chars || show_default {
-            write!(latex_writer, " & \\textbf{{Chars}}").unwrap();
-        }
-        if show_bytes || show_default {
-            write!(latex_writer, " & \\textbf{{Bytes}}").unwrap();
-        }
-        writeln!(latex_writer, " \\\\").unwrap();
-        writeln!(latex_writer, "\\midrule").unwrap();
-        writeln!(latex_writer, "\\endhead").unwrap();
-        writeln!(latex_writer, "\\bottomrule").unwrap();
-        writeln!(latex_writer, "\\endfoot").unwrap();
-        writeln!(latex_writer, "\\bottomrule").unwrap();
-        writeln!(latex_writer, "\\endlastfoot").unwrap();
-        
-        // Sort by actual_loc descending
-        let mut lang_items: Vec<(&String, &Stats)> = per_lang_sum.iter().collect();
-        lang_items.sort_by(|(_, sa), (_, sb)| sb.actual_loc.cmp(&sa.actual_loc));
-        
-        for (lang, stats) in lang_items {
-            // Filter out zero-count languages
-            if stats.actual_loc == 0 && stats.raw_loc == 0 && stats.words == 0 && stats.chars == 0 && stats.bytes == 0 {
-                continue;
-            }
-            
-            // Escape LaTeX special characters
-            let lang_escaped = lang.replace('&', "\\&").replace('%', "\\%").replace('$', "\\$").replace('#', "\\#").replace('^', "\\textasciicircum{}").replace('_', "\\_").replace('{', "\\{").replace('}', "\\}");
-            
-            write!(latex_writer, "  {}", lang_escaped).unwrap();
-            if show_actual_klocs || (show_default && show_actual_loc) {
-                if show_actual_klocs {
-                    write!(latex_writer, " & {:.3}", stats.actual_loc as f64 / 1000.0).unwrap();
-                } else {
-                    write!(latex_writer, " & {}", stats.actual_loc).unwrap();
-                }
-            }
-            if show_raw_klocs || (show_default && show_raw_loc) {
-                if show_raw_klocs {
-                    write!(latex_writer, " & {:.3}", stats.raw_loc as f64 / 1000.0).unwrap();
-                } else {
-                    write!(latex_writer, " & {}", stats.raw_loc).unwrap();
-                }
-            }
-            if show_words || show_default {
-                write!(latex_writer, " & {}", stats.words).unwrap();
-            }
-            if show_chars || show_default {
-                write!(latex_writer, " & {}", stats.chars).unwrap();
-            }
-            if show_bytes || show_default {
-                write!(latex_writer, " & {}", stats.bytes).unwrap();
-            }
-            writeln!(latex_writer, " \\\\").unwrap();
-        }
-        
-        writeln!(latex_writer, "\\end{{longtable}}").unwrap();
-    }
-    
-    writeln!(latex_writer, "\\end{{document}}").unwrap();
+    let latex_content = latex_report(report, columns, numbers, sort, percent);
     
     // Write LaTeX to temporary file
     let temp_dir = std::env::temp_dir();
     let temp_tex = temp_dir.join("sourcelines_report.tex");
     fs::write(&temp_tex, &latex_content).unwrap_or_else(|_| {
         eprintln!("Error: Could not write temporary LaTeX file");
-        std::process::exit(1);
+        exit(2);
     });
     
     // Compile LaTeX to PDF using pdflatex
@@ -1467,111 +4080,174 @@ fn print_pdf_report(
                     let mut handle = stdout.lock();
                     handle.write_all(&pdf_data).unwrap_or_else(|_| {
                         eprintln!("Error: Could not write PDF to stdout");
-                        std::process::exit(1);
+                        exit(2);
                     });
                 } else {
                     eprintln!("Error: PDF file was not generated");
-                    std::process::exit(1);
+                    exit(2);
                 }
             } else {
                 eprintln!("Error: pdflatex failed");
                 if !result.stderr.is_empty() {
                     eprintln!("{}", String::from_utf8_lossy(&result.stderr));
                 }
-                std::process::exit(1);
+                exit(2);
             }
         }
         Err(_) => {
             eprintln!("Error: pdflatex not found. Please install a LaTeX distribution (e.g., texlive)");
-            std::process::exit(1);
+            exit(2);
         }
     }
 }
 
-// Help is now handled by clap
-
-fn add_stats(a: Stats, b: Stats) -> Stats {
-    Stats {
-        actual_loc: a.actual_loc + b.actual_loc,
-        raw_loc: a.raw_loc + b.raw_loc,
-        words: a.words + b.words,
-        chars: a.chars + b.chars,
-        bytes: a.bytes + b.bytes,
+/// Warn when more than PERCENT of the lines of directory ARG are of languages without built-in
+/// comment syntax, whose counts rest on guesses
+fn warn_unknown_languages(arg: &str, summary: &DirSummary, percent: u8) {
+    let mut unknown: Vec<(&String, &Stats)> = summary
+        .langs
+        .iter()
+        .filter(|(lang, _)| {
+            builtin_comment_syntax(lang).is_none() && *lang != VENDORED_BUCKET && *lang != SUBMODULE_BUCKET
+        })
+        .collect();
+    let lines: usize = unknown.iter().map(|(_, stats)| stats.raw_loc).sum();
+    if lines == 0 || lines * 100 <= summary.total.raw_loc * percent as usize {
+        return;
     }
+    unknown.sort_by(|(la, a), (lb, b)| b.raw_loc.cmp(&a.raw_loc).then_with(|| la.cmp(lb)));
+    let names: Vec<&str> = unknown.iter().take(3).map(|(lang, _)| lang.as_str()).collect();
+    eprintln!(
+        "sourcelines: warning: {:.0}% of the lines in {} are of languages without built-in comment syntax ({}{}); \
+         see --explain, or leave them out with --exclude-lang",
+        lines as f64 * 100.0 / summary.total.raw_loc as f64,
+        arg,
+        names.join(", "),
+        if unknown.len() > names.len() { ", ..." } else { "" }
+    );
 }
 
-fn is_binary_file(path: &Path) -> bool {
-    // Read first 8KB to check for binary content
-    const SAMPLE_SIZE: usize = 8192;
-    let mut file = match File::open(path) {
-        Ok(f) => f,
-        Err(_) => return false, // If we can't open it, assume it's not binary
-    };
-    let mut buffer = vec![0u8; SAMPLE_SIZE];
-    match file.read(&mut buffer) {
-        Ok(n) => {
-            // Check for null bytes in the sample
-            buffer[..n].contains(&0)
+/// Lines of the file shown by --explain with a single file argument
+const EXPLAIN_PREVIEW_LINES: usize = 40;
+
+// Describe how a single file is detected, filtered and counted, line by line, for --explain
+fn explain_file(path: &Path, opts: &Options, exclude_patterns: &[String], ignore: bool) -> io::Result<()> {
+    let file = SourceFile::open(path)?;
+    let prefix = file.prefix();
+    println!("{}", path.display());
+
+    let first_line = String::from_utf8_lossy(prefix.split(|&b| b == b'\n').next().unwrap_or_default()).into_owned();
+    let shebang = first_line.starts_with("#!").then(|| first_line.trim_end());
+    let by_shebang = shebang.and_then(|line| SHEBANG_LANGUAGES.iter().find(|(word, _)| line.contains(word)));
+    match (shebang, by_shebang) {
+        (Some(line), Some((word, lang))) => println!("  shebang:    {} -> {} (contains '{}')", line, lang, word),
+        (Some(line), None) => println!("  shebang:    {} (no known interpreter)", line),
+        (None, _) => println!("  shebang:    none"),
+    }
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => {
+            let known = EXTENSION_LANGUAGES.iter().find(|(_, exts)| exts.contains(&ext));
+            let mut line = match known {
+                Some((lang, _)) => format!(".{} -> {}", ext, lang),
+                None => format!(".{} (not known, the language is named after it)", ext),
+            };
+            if by_shebang.is_some() {
+                line += ", not used since the shebang decides";
+            }
+            println!("  extension:  {}", line);
         }
-        Err(_) => false, // If we can't read it, assume it's not binary
+        None => println!("  extension:  none"),
+    }
+    let lang = remap_language(file.lang.clone(), &opts.remap);
+    if lang == file.lang {
+        println!("  language:   {}", lang);
+    } else {
+        println!("  language:   {}, remapped to {} by --remap", file.lang, lang);
+    }
+    if !opts.lang_selected(&lang) {
+        println!("  selected:   no, left out by --include-lang or --exclude-lang");
     }
-}
 
-fn process_file(path: &Path) -> Stats {
-    let mut stats = Stats::default();
-    
-    // Skip binary files
-    if is_binary_file(path) {
-        return stats;
+    // Exclude patterns and ignore files only apply to files found in directories
+    let fname = path.file_name().and_then(|s| s.to_str()).unwrap_or("");
+    let path_str = path.to_string_lossy().replace('\\', "/");
+    let mut matched: Vec<String> = opts
+        .exclude_set
+        .matches(fname)
+        .into_iter()
+        .filter_map(|i| exclude_patterns.get(i))
+        .map(|pat| format!("'{}'", pat))
+        .collect();
+    matched.extend(opts.exclude_regex.iter().filter(|re| re.is_match(&path_str)).map(|re| format!("/{}/", re)));
+    if matched.is_empty() {
+        println!("  excludes:   none match");
+    } else if !opts.is_excluded(path) {
+        println!("  excludes:   {} match, but an include pattern overrides them", matched.join(", "));
+    } else {
+        println!("  excludes:   {} match, skipped when found in a directory", matched.join(", "));
     }
-    
-    let lang = detect_language(path);
-    let comment_syntax = detect_comment_syntax(&lang, path);
-    let file = match File::open(path) {
-        Ok(f) => f,
-        Err(_) => return stats,
-    };
-    let mut reader = io::BufReader::new(file);
-    let mut buf = String::new();
-    let mut in_block_comment = false;
-    while let Ok(n) = reader.read_line(&mut buf) {
-        if n == 0 {
-            break;
-        }
-        stats.raw_loc += 1;
-        stats.bytes += buf.as_bytes().len();
-        stats.chars += buf.chars().count();
-        stats.words += buf.split_whitespace().count();
-        let trimmed = buf.trim();
-        let is_empty = trimmed.is_empty();
-        let is_comment = is_pure_comment(trimmed, &comment_syntax, &mut in_block_comment);
-        if !is_empty && !is_comment {
-            stats.actual_loc += 1;
-        }
-        buf.clear();
+    if ignore && !ignore_chain(path).include_test(&normalize_path(path), false) {
+        println!("  ignored:    by an ignore file, skipped when found in a directory");
     }
-    stats
-}
+    println!("  test code:  {}", if opts.is_test_file(path, Path::new(".")) { "yes" } else { "no" });
 
-fn is_pure_comment(line: &str, syntax: &CommentSyntax, in_block_comment: &mut bool) -> bool {
-    if *in_block_comment {
-        if let Some(ref end) = syntax.block_end {
-            if line.contains(end) {
-                *in_block_comment = false;
+    let forced = opts.binary_extension(path);
+    if forced || file.is_binary() {
+        let reason = if forced { "extension listed in --binary-extensions" } else { "NUL byte near the start" };
+        println!("  binary:     yes ({}), {}", reason, if opts.count_binary { "only bytes are counted" } else { "skipped" });
+        return Ok(());
+    }
+
+    let syntax = match builtin_comment_syntax(&file.lang) {
+        Some(syntax) => {
+            println!("  comments:   {} (built in)", syntax.describe());
+            syntax
+        }
+        None => {
+            let inference = infer_comment_syntax(prefix);
+            println!("  comments:   {} (inferred from {} non-blank lines)", inference.syntax.describe(), inference.lines);
+            for line in inference_table(&inference) {
+                println!("  {}", line);
             }
+            inference.syntax
         }
-        return true;
+    };
+    if literate_style(&file.lang).is_some() {
+        println!("  literate:   only code chunks count, prose is treated like comments");
     }
-    if let Some(ref start) = syntax.block_start {
-        if line.starts_with(start) {
-            *in_block_comment = true;
-            return true;
+
+    let mut classifier = LineClassifier::new(&file.lang, syntax);
+    let mut stats = Stats::default();
+    let mut content = Vec::new();
+    File::open(path)?.read_to_end(&mut content)?;
+    println!();
+    for (n, line) in split_lines(&content).enumerate() {
+        let before = stats.actual_loc;
+        count_line(&mut stats, &mut classifier, line);
+        if n >= EXPLAIN_PREVIEW_LINES {
+            continue;
         }
+        let text = String::from_utf8_lossy(line);
+        let text = text.trim_end_matches(['\n', '\r']);
+        let kind = if stats.actual_loc > before {
+            "code"
+        } else if text.trim().is_empty() {
+            "blank"
+        } else if literate_style(&file.lang).is_some() {
+            "prose"
+        } else {
+            "comment"
+        };
+        println!("  {:>5} {:<8}| {}", n + 1, kind, text);
     }
-    if let Some(ref line_comment) = syntax.line {
-        if line.starts_with(line_comment) {
-            return true;
-        }
+    if stats.raw_loc > EXPLAIN_PREVIEW_LINES {
+        println!("  ... {} more lines", stats.raw_loc - EXPLAIN_PREVIEW_LINES);
     }
-    false
+    println!();
+    println!(
+        "  {} actual LOC, {} raw LOC, {} words, {} chars, {} bytes",
+        stats.actual_loc, stats.raw_loc, stats.words, stats.chars, stats.bytes
+    );
+    Ok(())
 }
+
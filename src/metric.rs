@@ -0,0 +1,36 @@
+//! Counters of their own that callers run over every line, besides the built-in counts
+
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::LineKind;
+
+/// A line as shown to metrics
+#[derive(Debug, Clone, Copy)]
+pub struct LineInfo<'a> {
+    /// Line number in the file, from 1
+    pub number: usize,
+    /// The line with its newline; lines longer than `Options::line_cap` are cut there, and
+    /// invalid UTF-8 sequences are replaced
+    pub text: &'a str,
+    pub kind: LineKind,
+}
+
+/// A counter that looks at each line of a file, e.g. the lines matching a regex. A new one
+/// is made for every file, and the values of the files are summed.
+pub trait Metric: Send {
+    /// Told which file is counted, with its language, before its first line is observed
+    fn begin_file(&mut self, _path: &Path, _lang: &str) {}
+    fn observe_line(&mut self, line: &LineInfo);
+    /// The value for the file, once every line has been observed
+    fn finish(&self) -> f64;
+    /// The values for the file by name, once every line has been observed: by default
+    /// `finish()` under `name`, the name the metric was added with. A metric that gives
+    /// several values, such as the columns of a plugin, returns them all.
+    fn values(&self, name: &str) -> Vec<(String, f64)> {
+        vec![(name.to_string(), self.finish())]
+    }
+}
+
+/// Makes a `Metric` for each file counted, see `Options::metrics`
+pub type MetricFactory = Arc<dyn Fn() -> Box<dyn Metric> + Send + Sync>;
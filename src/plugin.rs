@@ -0,0 +1,157 @@
+//! --metric-plugin: metrics computed by other programs. A plugin is started once per run and
+//! talks JSON, one object per line: it is sent
+//!
+//!   {"type":"hello","version":1}
+//!
+//! and answers with the names of its columns, and whether it wants the lines of the files:
+//!
+//!   {"metrics":["todo","long_lines"],"lines":true}
+//!
+//! Then, for each file counted, it is sent
+//!
+//!   {"type":"file","path":"src/main.rs","language":"rust"}
+//!   {"type":"line","number":1,"text":"// TODO: split","kind":"comment"}   (with "lines")
+//!   {"type":"end"}
+//!
+//! and answers with the values of its columns for the file, e.g. {"todo":1,"long_lines":0};
+//! columns it leaves out are 0. The values are summed like the counts. The plugin's stdin is
+//! closed at the end of the run.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+use serde_json::{Value, json};
+use sourcelines::{LineInfo, LineKind, Metric, MetricFactory};
+
+/// Version of the protocol, sent in the hello message
+const PROTOCOL_VERSION: u32 = 1;
+
+/// A running plugin
+pub struct Plugin {
+    command: String,
+    /// Names of the values the plugin gives for each file
+    pub columns: Vec<String>,
+    /// Whether the plugin is sent the lines of each file, or only its name
+    lines: bool,
+    process: Mutex<Process>,
+}
+
+struct Process {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Process {
+    fn send(&mut self, messages: &[u8]) -> io::Result<()> {
+        let stdin = self.stdin.as_mut().ok_or(io::ErrorKind::BrokenPipe)?;
+        stdin.write_all(messages)?;
+        stdin.flush()
+    }
+
+    fn receive(&mut self) -> io::Result<serde_json::Map<String, Value>> {
+        let mut line = String::new();
+        if self.stdout.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the plugin exited"));
+        }
+        match serde_json::from_str(&line) {
+            Ok(Value::Object(object)) => Ok(object),
+            _ => Err(io::Error::other(format!("expected a JSON object, got {}", line.trim_end()))),
+        }
+    }
+}
+
+impl Plugin {
+    /// Start COMMAND, a program and its arguments separated by whitespace, and ask it for its
+    /// columns
+    pub fn start(command: &str) -> io::Result<Plugin> {
+        let mut words = command.split_whitespace();
+        let program = words.next().ok_or_else(|| io::Error::other("empty command"))?;
+        let mut child = Command::new(program).args(words).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+        let stdin = child.stdin.take();
+        let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        let mut process = Process { child, stdin, stdout };
+        process.send(format!("{}\n", json!({"type": "hello", "version": PROTOCOL_VERSION})).as_bytes())?;
+        let hello = process.receive()?;
+        let columns = match hello.get("metrics") {
+            Some(Value::Array(names)) => names.iter().filter_map(|name| name.as_str().map(str::to_string)).collect(),
+            _ => return Err(io::Error::other("the hello answer has no \"metrics\" list")),
+        };
+        Ok(Plugin {
+            command: command.to_string(),
+            columns,
+            lines: hello.get("lines").and_then(Value::as_bool).unwrap_or(false),
+            process: Mutex::new(process),
+        })
+    }
+
+    /// Makes the metric that asks the plugin for the values of each file
+    pub fn factory(self: &Arc<Self>) -> MetricFactory {
+        let plugin = self.clone();
+        Arc::new(move || Box::new(PluginMetric { plugin: plugin.clone(), messages: Vec::new() }))
+    }
+
+    // Send the MESSAGES about a file and read the values of the columns
+    fn file_values(&self, messages: &[u8]) -> io::Result<Vec<(String, f64)>> {
+        let mut process = self.process.lock().unwrap();
+        process.send(messages)?;
+        let values = process.receive()?;
+        let value = |name: &String| values.get(name).and_then(Value::as_f64).unwrap_or(0.0);
+        Ok(self.columns.iter().map(|name| (name.clone(), value(name))).collect())
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        let process = self.process.get_mut().unwrap();
+        process.stdin = None;
+        let _ = process.child.wait();
+    }
+}
+
+/// The messages about one file, sent once it is counted, as files are counted in parallel
+struct PluginMetric {
+    plugin: Arc<Plugin>,
+    messages: Vec<u8>,
+}
+
+impl Metric for PluginMetric {
+    fn begin_file(&mut self, path: &Path, lang: &str) {
+        let file = json!({"type": "file", "path": path.to_string_lossy(), "language": lang});
+        let _ = writeln!(self.messages, "{}", file);
+    }
+
+    fn observe_line(&mut self, line: &LineInfo) {
+        if !self.plugin.lines {
+            return;
+        }
+        let kind = match line.kind {
+            LineKind::Code => "code",
+            LineKind::Comment => "comment",
+            LineKind::Blank => "blank",
+            LineKind::Mixed => "mixed",
+        };
+        let text = line.text.trim_end_matches(['\n', '\r']);
+        let line = json!({"type": "line", "number": line.number, "text": text, "kind": kind});
+        let _ = writeln!(self.messages, "{}", line);
+    }
+
+    // The plugin's values are given by `values`
+    fn finish(&self) -> f64 {
+        0.0
+    }
+
+    fn values(&self, _name: &str) -> Vec<(String, f64)> {
+        let mut messages = self.messages.clone();
+        messages.extend_from_slice(b"{\"type\":\"end\"}\n");
+        match self.plugin.file_values(&messages) {
+            Ok(values) => values,
+            Err(e) => {
+                eprintln!("Error: metric plugin {}: {}", self.plugin.command, e);
+                crate::exit(2);
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! The results of a run, which the rows, the sums and every report format are printed from
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use sourcelines::{DirSummary, SkipCounts, Stats, add_stats};
+
+/// An argument counted, with its totals
+pub struct Entry {
+    /// The argument as given
+    pub arg: String,
+    /// Where it was counted: the argument itself, or the clone of a remote repository
+    pub path: PathBuf,
+    /// Language of a file argument, "*" for a directory
+    pub lang: String,
+    pub stats: Stats,
+    /// Sums of the --metric-plugin values
+    pub metrics: BTreeMap<String, f64>,
+    /// For a directory, the summary of its walk: its languages, the subdirectories of
+    /// --by-dir and, with --html, its files
+    pub dir: Option<DirSummary>,
+}
+
+/// Entries left out of a run, by reason
+#[derive(Default, Clone, Copy)]
+pub struct Skipped {
+    pub excluded: usize,
+    pub size: usize,
+    pub symlinks: usize,
+    /// Files and directories that could not be read
+    pub unreadable: usize,
+    /// Binary files that were not counted
+    pub binary: usize,
+}
+
+impl Skipped {
+    /// What `counts` counted since it was last taken from
+    pub fn take(counts: &SkipCounts, binary: usize) -> Skipped {
+        let take = |count: &AtomicUsize| count.swap(0, Ordering::Relaxed);
+        Skipped {
+            excluded: take(&counts.excluded),
+            size: take(&counts.size),
+            symlinks: take(&counts.symlinks),
+            unreadable: take(&counts.unreadable),
+            binary,
+        }
+    }
+}
+
+/// Everything one run counted; entries are in the order the arguments were given and
+/// languages in the order of their names, so the same tree always gives the same report
+#[derive(Default)]
+pub struct RunReport {
+    pub entries: Vec<Entry>,
+    /// Sums over every argument
+    pub total: Stats,
+    /// Part of `total` that is test code
+    pub tests: Stats,
+    /// Sums of the --metric-plugin values over every argument
+    pub metrics: BTreeMap<String, f64>,
+    pub skipped: Skipped,
+    /// Time spent counting
+    pub elapsed: Duration,
+}
+
+impl RunReport {
+    pub fn add_dir(&mut self, arg: &str, path: &Path, summary: DirSummary) {
+        self.total = add_stats(self.total.clone(), summary.total.clone());
+        self.tests = add_stats(self.tests.clone(), summary.tests.clone());
+        self.skipped.binary += summary.binary_skipped;
+        add_metrics(&mut self.metrics, &summary.metrics);
+        self.entries.push(Entry {
+            arg: arg.to_string(),
+            path: path.to_path_buf(),
+            lang: "*".to_string(),
+            stats: summary.total.clone(),
+            metrics: summary.metrics.clone(),
+            dir: Some(summary),
+        });
+    }
+
+    pub fn add_file(
+        &mut self,
+        arg: &str,
+        path: &Path,
+        lang: String,
+        stats: Stats,
+        metrics: BTreeMap<String, f64>,
+        is_test: bool,
+    ) {
+        self.total = add_stats(self.total.clone(), stats.clone());
+        add_metrics(&mut self.metrics, &metrics);
+        if is_test {
+            self.tests = add_stats(self.tests.clone(), stats.clone());
+        }
+        self.entries.push(Entry {
+            arg: arg.to_string(),
+            path: path.to_path_buf(),
+            lang,
+            stats,
+            metrics,
+            dir: None,
+        });
+    }
+
+    /// Per-language sums of the directory arguments, as the report formats show them
+    pub fn dir_langs(&self) -> BTreeMap<String, Stats> {
+        let mut langs = BTreeMap::new();
+        for dir in self.entries.iter().filter_map(|entry| entry.dir.as_ref()) {
+            for (lang, stats) in &dir.langs {
+                add_lang(&mut langs, lang, stats);
+            }
+        }
+        langs
+    }
+
+    /// Per-language sums of every argument, files and directories alike
+    pub fn langs(&self) -> BTreeMap<String, Stats> {
+        let mut langs = self.dir_langs();
+        for entry in self.entries.iter().filter(|entry| entry.dir.is_none()) {
+            add_lang(&mut langs, &entry.lang, &entry.stats);
+        }
+        langs
+    }
+}
+
+fn add_metrics(sums: &mut BTreeMap<String, f64>, metrics: &BTreeMap<String, f64>) {
+    for (name, value) in metrics {
+        *sums.entry(name.clone()).or_default() += value;
+    }
+}
+
+fn add_lang(langs: &mut BTreeMap<String, Stats>, lang: &str, stats: &Stats) {
+    let entry = langs.entry(lang.to_string()).or_default();
+    *entry = add_stats(entry.clone(), stats.clone());
+}
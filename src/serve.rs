@@ -0,0 +1,147 @@
+//! sourcelines serve: counting on request, over HTTP. `POST /count` counts a path on the
+//! server, given as {"path": "src"}, or a tar archive, plain or compressed with gzip, xz or
+//! zstd, sent as the body, and answers with a JSON report:
+//!
+//!   {"path":"src","total":{"actual_loc":1200,...,"files":14},"languages":{"rust":{...}},
+//!    "skipped":{"excluded":3,...},"elapsed_ms":12}
+//!
+//! Only paths below the roots given at startup are counted: a path outside of them is refused
+//! just like a missing one, so clients cannot tell which paths exist.
+//!
+//! Failed requests are answered with {"error":"..."}. Files below the roots are not read again
+//! while they are unchanged, so asking again is cheap. Archives are counted as they are
+//! received, without being unpacked or cached, and refused once their decompressed size
+//! exceeds MAX_UNPACKED. Each of their files is held in memory while it is counted, so those
+//! larger than MAX_ENTRY are skipped, as with --max-file-size. Requests are answered one at a
+//! time, paths counted in parallel.
+
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use serde_json::{Value, json};
+use sourcelines::{CountCache, DirSummary, Options, Stats, count_path};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::archive;
+use crate::report::Skipped;
+
+/// Largest body accepted, in bytes
+const MAX_BODY: u64 = 1 << 30;
+
+/// Largest decompressed size of an archive accepted, in bytes
+const MAX_UNPACKED: u64 = 1 << 32;
+
+/// Largest file of an archive counted, in bytes
+const MAX_ENTRY: u64 = 64 << 20;
+
+/// Answer requests on LISTEN until the process is stopped, counting only paths below ROOTS,
+/// which are canonical. With CACHE, the counts of the files are written to its file after each
+/// request
+pub fn serve(
+    listen: &str,
+    roots: &[PathBuf],
+    opts: &Options,
+    cache: Option<(&str, &Mutex<CountCache>)>,
+) -> io::Result<()> {
+    let server = Server::http(listen).map_err(io::Error::other)?;
+    eprintln!("sourcelines: listening on http://{}", server.server_addr());
+    for mut request in server.incoming_requests() {
+        let (status, body) = match (request.method(), request.url().split('?').next().unwrap_or_default()) {
+            (Method::Post, "/count") => match count_request(&mut request, roots, opts) {
+                Ok(report) => (200, report),
+                Err((status, e)) => (status, json!({ "error": e }).to_string()),
+            },
+            (_, "/count") => (405, json!({ "error": "use POST" }).to_string()),
+            (_, url) => (404, json!({ "error": format!("no such endpoint {}", url) }).to_string()),
+        };
+        let content_type = Header::from_bytes("Content-Type", "application/json").expect("valid header");
+        let response = Response::from_string(body).with_status_code(status).with_header(content_type);
+        if let Err(e) = request.respond(response) {
+            eprintln!("sourcelines: could not answer a request: {}", e);
+        }
+        if let Some((file, cache)) = cache
+            && let Err(e) = cache.lock().unwrap().save(file)
+        {
+            eprintln!("sourcelines: could not write cache {}: {}", file, e);
+        }
+    }
+    Ok(())
+}
+
+// The report for one POST /count, or the HTTP status and message of its failure
+fn count_request(
+    request: &mut Request,
+    roots: &[PathBuf],
+    opts: &Options,
+) -> Result<String, (u16, String)> {
+    if request.body_length().is_some_and(|length| length as u64 > MAX_BODY) {
+        return Err((413, format!("the request is larger than {} bytes", MAX_BODY)));
+    }
+    let start = Instant::now();
+    let mut reader = BufReader::new(request.as_reader().take(MAX_BODY));
+    let peek = reader.fill_buf().map_err(|e| (400, format!("could not read the request: {}", e)))?;
+    if peek.trim_ascii_start().starts_with(b"{") {
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body).map_err(|e| (400, format!("could not read the request: {}", e)))?;
+        let query: Value = serde_json::from_slice(&body).map_err(|e| (400, format!("invalid JSON: {}", e)))?;
+        let path = query.get("path").and_then(Value::as_str).ok_or((400, "no \"path\" to count".to_string()))?;
+        // Missing paths and paths outside the roots get the same answer
+        let full = Path::new(path).canonicalize().ok();
+        let Some(full) = full.filter(|full| roots.iter().any(|root| full.starts_with(root))) else {
+            return Err((403, format!("{} is not a path below the served roots", path)));
+        };
+        let mut report = count(&full, opts, start)?;
+        report["path"] = json!(path);
+        return Ok(report.to_string());
+    }
+    let max_file_size = Some(opts.max_file_size.map_or(MAX_ENTRY, |max| max.min(MAX_ENTRY)));
+    let opts = &Options { max_file_size, ..opts.clone() };
+    let counted = archive::count(reader, Path::new(""), opts, Some(MAX_UNPACKED), |_, _, _| ());
+    let summary = counted.map_err(|e| {
+        // The files skipped so far are not to be reported with the next request
+        Skipped::take(&opts.skipped, 0);
+        (400, format!("could not read the archive: {}", e))
+    })?;
+    Ok(report(&summary, opts, start).to_string())
+}
+
+fn count(path: &Path, opts: &Options, start: Instant) -> Result<Value, (u16, String)> {
+    let summary = count_path(path, opts).map_err(|e| (500, e.to_string()))?;
+    Ok(report(&summary, opts, start))
+}
+
+// The JSON report of SUMMARY, counted since START
+fn report(summary: &DirSummary, opts: &Options, start: Instant) -> Value {
+    let skipped = Skipped::take(&opts.skipped, summary.binary_skipped);
+    let mut langs: Vec<(&String, &Stats)> = summary.langs.iter().collect();
+    langs.sort_by_key(|(lang, _)| *lang);
+    let languages: serde_json::Map<String, Value> =
+        langs.into_iter().map(|(lang, stats)| (lang.clone(), stats_json(stats))).collect();
+    json!({
+        "total": stats_json(&summary.total),
+        "languages": languages,
+        "skipped": {
+            "excluded": skipped.excluded,
+            "size": skipped.size,
+            "symlinks": skipped.symlinks,
+            "unreadable": skipped.unreadable,
+            "binary": skipped.binary,
+        },
+        "elapsed_ms": start.elapsed().as_millis() as u64,
+    })
+}
+
+/// STATS as a JSON object, with the names of the fields
+pub fn stats_json(stats: &Stats) -> Value {
+    json!({
+        "actual_loc": stats.actual_loc,
+        "raw_loc": stats.raw_loc,
+        "comments": stats.comments,
+        "words": stats.words,
+        "chars": stats.chars,
+        "bytes": stats.bytes,
+        "files": stats.files,
+    })
+}
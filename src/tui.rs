@@ -0,0 +1,305 @@
+//! `sourcelines tui`: browse a directory tree with the counts of every directory, like
+//! ncdu does with disk usage
+
+use std::io;
+use std::path::Path;
+
+use ratatui::DefaultTerminal;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Cell, Row, Table, TableState};
+
+use sourcelines::{DirSummary, Stats, add_stats, sub_stats};
+
+use super::{SortColumn, SortOrder, chart_bar};
+
+/// Columns in the order the `s` key cycles through them
+const SORT_COLUMNS: [SortColumn; 6] = [
+    SortColumn::ActualLoc,
+    SortColumn::RawLoc,
+    SortColumn::Words,
+    SortColumn::Chars,
+    SortColumn::Bytes,
+    SortColumn::Name,
+];
+
+/// Label of the row holding the files directly in a directory
+const FILES_ROW: &str = "(files)";
+
+/// How often --watch checks for changes while no key is pressed
+const WATCH_POLL: std::time::Duration = std::time::Duration::from_millis(200);
+
+const HELP: &str =
+    " ↑↓ move  →/enter open  ←/backspace up  s sort  r reverse  f language  R rescan  q quit ";
+
+struct Browser {
+    root: DirSummary,
+    /// Names of the directories opened below the root
+    path: Vec<String>,
+    order: SortOrder,
+    /// Only count this language, if set
+    lang: Option<String>,
+    table: TableState,
+}
+
+/// One row of the table: a subdirectory, or the files of the directory itself
+struct Entry {
+    name: String,
+    stats: Stats,
+    is_dir: bool,
+}
+
+impl Browser {
+    fn new(root: DirSummary) -> Self {
+        Browser {
+            root,
+            path: Vec::new(),
+            order: SortOrder {
+                column: SortColumn::ActualLoc,
+                descending: true,
+            },
+            lang: None,
+            table: TableState::default().with_selected(0),
+        }
+    }
+
+    /// The directory being shown; after a rescan, the part of the path that still exists
+    fn current(&mut self) -> &DirSummary {
+        let mut node = &self.root;
+        let mut depth = 0;
+        for name in &self.path {
+            match node.children.iter().find(|(child, _)| child_name(child) == name) {
+                Some((_, child)) => node = child,
+                None => break,
+            }
+            depth += 1;
+        }
+        self.path.truncate(depth);
+        node
+    }
+
+    /// Subdirectories and the files row of the current directory, in the chosen order
+    fn entries(&mut self) -> (Stats, Vec<Entry>) {
+        let lang = self.lang.clone();
+        let order = self.order;
+        let node = self.current();
+        let stats = |summary: &DirSummary| match &lang {
+            Some(lang) => summary.langs.get(lang).cloned().unwrap_or_default(),
+            None => summary.total.clone(),
+        };
+        let total = stats(node);
+        let mut entries: Vec<Entry> = node
+            .children
+            .iter()
+            .map(|(name, child)| Entry {
+                name: child_name(name).to_string(),
+                stats: stats(child),
+                is_dir: true,
+            })
+            .collect();
+        let in_dirs = entries.iter().fold(Stats::default(), |sum, e| add_stats(sum, e.stats.clone()));
+        entries.push(Entry {
+            name: FILES_ROW.to_string(),
+            stats: sub_stats(total.clone(), in_dirs),
+            is_dir: false,
+        });
+        entries.retain(|e| e.stats.raw_loc > 0 || e.stats.bytes > 0);
+        entries.sort_by(|a, b| order.compare((&a.name, &a.stats), (&b.name, &b.stats)));
+        (total, entries)
+    }
+
+    /// Languages counted anywhere in the tree, in name order
+    fn languages(&self) -> Vec<String> {
+        let mut langs: Vec<String> = self.root.langs.keys().cloned().collect();
+        langs.sort();
+        langs
+    }
+
+    fn select(&mut self, delta: isize, len: usize) {
+        let selected = self.table.selected().unwrap_or(0) as isize + delta;
+        self.table.select(Some(selected.clamp(0, len.saturating_sub(1) as isize) as usize));
+    }
+
+    fn open(&mut self) {
+        let (_, entries) = self.entries();
+        if let Some(entry) = self.table.selected().and_then(|i| entries.get(i))
+            && entry.is_dir
+        {
+            self.path.push(entry.name.clone());
+            self.table.select(Some(0));
+        }
+    }
+
+    /// Go to the parent directory, selecting the one just left
+    fn up(&mut self) {
+        let Some(left) = self.path.pop() else {
+            return;
+        };
+        let (_, entries) = self.entries();
+        let index = entries.iter().position(|e| e.is_dir && e.name == left);
+        self.table.select(Some(index.unwrap_or(0)));
+    }
+
+    fn cycle_sort(&mut self) {
+        let index = SORT_COLUMNS.iter().position(|c| *c == self.order.column).unwrap_or(0);
+        let column = SORT_COLUMNS[(index + 1) % SORT_COLUMNS.len()];
+        self.order = SortOrder {
+            column,
+            descending: column != SortColumn::Name,
+        };
+    }
+
+    fn cycle_language(&mut self) {
+        let langs = self.languages();
+        let next = match &self.lang {
+            None => langs.first(),
+            Some(lang) => langs.iter().skip_while(|l| *l != lang).nth(1),
+        };
+        self.lang = next.cloned();
+        self.table.select(Some(0));
+    }
+
+    fn draw(&mut self, frame: &mut ratatui::Frame, dir: &Path) {
+        let (total, entries) = self.entries();
+        let [header, body] = Layout::vertical([Constraint::Length(1), Constraint::Min(0)]).areas(frame.area());
+
+        let mut location = dir.display().to_string();
+        for name in &self.path {
+            location = format!("{}/{}", location.trim_end_matches('/'), name);
+        }
+        let filter = self.lang.as_deref().map_or(String::new(), |lang| format!(" <{}>", lang));
+        frame.render_widget(
+            Line::from(format!(" {}{}: {} actual LOC", location, filter, total.actual_loc)).bold(),
+            header,
+        );
+
+        // Shares and bars are of the sort column, or of actual LOC when sorting by name
+        let column = match self.order.column {
+            SortColumn::Name => SortColumn::ActualLoc,
+            column => column,
+        };
+        let largest = entries.iter().map(|e| column.value(&e.stats)).max().unwrap_or(0);
+        let number = |n: usize| Cell::from(Line::from(n.to_string()).right_aligned());
+        let rows = entries.iter().map(|e| {
+            let fraction = if largest > 0 { column.value(&e.stats) as f64 / largest as f64 } else { 0.0 };
+            let name = if e.is_dir { format!("{}/", e.name) } else { e.name.clone() };
+            Row::new(vec![
+                number(e.stats.actual_loc),
+                number(e.stats.raw_loc),
+                number(e.stats.words),
+                number(e.stats.chars),
+                number(e.stats.bytes),
+                Cell::from(Line::from(format!("{:.1}%", column.percent(&e.stats, &total))).right_aligned()),
+                Cell::from(chart_bar(fraction, 10)),
+                Cell::from(name),
+            ])
+        });
+        let arrow = if self.order.descending { "▼" } else { "▲" };
+        let titles = [
+            ("LOC", Some(SortColumn::ActualLoc)),
+            ("Raw LOC", Some(SortColumn::RawLoc)),
+            ("Words", Some(SortColumn::Words)),
+            ("Chars", Some(SortColumn::Chars)),
+            ("Bytes", Some(SortColumn::Bytes)),
+            ("Share", None),
+            ("", None),
+            ("Name", Some(SortColumn::Name)),
+        ];
+        let header_row = Row::new(titles.into_iter().map(|(title, sorts)| {
+            let title = match sorts {
+                Some(c) if c == self.order.column => format!("{}{}", title, arrow),
+                _ => title.to_string(),
+            };
+            match sorts {
+                Some(SortColumn::Name) => Cell::from(title),
+                _ => Cell::from(Line::from(title).right_aligned()),
+            }
+        }))
+        .style(Style::new().add_modifier(Modifier::UNDERLINED));
+        let widths = [
+            Constraint::Length(9),
+            Constraint::Length(9),
+            Constraint::Length(9),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(6),
+            Constraint::Length(10),
+            Constraint::Min(10),
+        ];
+        let table = Table::new(rows, widths)
+            .header(header_row)
+            .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+            .block(Block::bordered().title_bottom(HELP));
+        frame.render_stateful_widget(table, body, &mut self.table);
+    }
+}
+
+/// Last component of a --by-dir child path
+fn child_name(path: &str) -> &str {
+    Path::new(path).file_name().and_then(|n| n.to_str()).unwrap_or(path)
+}
+
+/// Show the tree that `scan` counts under `dir` until the user quits; `R` scans it again,
+/// and so does a change reported by `changed` (--watch)
+pub(crate) fn run(
+    dir: &Path,
+    root: DirSummary,
+    scan: &dyn Fn() -> DirSummary,
+    changed: Option<&dyn Fn() -> io::Result<bool>>,
+) -> io::Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    let result = browse(&mut terminal, dir, Browser::new(root), scan, changed);
+    ratatui::restore();
+    result
+}
+
+fn browse(
+    terminal: &mut DefaultTerminal,
+    dir: &Path,
+    mut browser: Browser,
+    scan: &dyn Fn() -> DirSummary,
+    changed: Option<&dyn Fn() -> io::Result<bool>>,
+) -> io::Result<()> {
+    loop {
+        terminal.draw(|frame| browser.draw(frame, dir))?;
+        if let Some(changed) = changed
+            && !event::poll(WATCH_POLL)?
+        {
+            if changed()? {
+                browser.root = scan();
+                let len = browser.entries().1.len();
+                browser.select(0, len);
+            }
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let len = browser.entries().1.len();
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Up | KeyCode::Char('k') => browser.select(-1, len),
+            KeyCode::Down | KeyCode::Char('j') => browser.select(1, len),
+            KeyCode::PageUp => browser.select(-10, len),
+            KeyCode::PageDown => browser.select(10, len),
+            KeyCode::Home => browser.table.select(Some(0)),
+            KeyCode::End => browser.select(len as isize, len),
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => browser.open(),
+            KeyCode::Left | KeyCode::Backspace | KeyCode::Char('h') => browser.up(),
+            KeyCode::Char('s') => browser.cycle_sort(),
+            KeyCode::Char('r') => browser.order.descending = !browser.order.descending,
+            KeyCode::Char('f') => browser.cycle_language(),
+            KeyCode::Char('R') => {
+                browser.root = scan();
+                let len = browser.entries().1.len();
+                browser.select(0, len);
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! Hooks called by a walk as it goes, for progress displays, logging and the like
+
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::SyncSender;
+
+use crate::{Error, FileReport, SkipReason};
+
+/// Callbacks for the events of a walk, set with `Options::visitor`. Files are counted in
+/// parallel, so the methods can be called from several threads at once, in no particular
+/// order. Every method does nothing by default.
+pub trait Visitor: Send + Sync {
+    /// Called with each directory before it is read; returning false leaves it out
+    fn enter_dir(&self, _path: &Path) -> bool {
+        true
+    }
+
+    /// Called with the counts of each file, or with the files found with `Options::list_only`
+    fn file_counted(&self, _file: &FileReport) {}
+
+    /// Called with each file or directory left out by a filter. Entries that cannot be read
+    /// are passed to `error` instead.
+    fn file_skipped(&self, _path: &Path, _reason: SkipReason) {}
+
+    /// Called with each file or directory that cannot be read, and each binary file not
+    /// counted. The error for the path given to `count_path` itself is returned instead.
+    fn error(&self, _error: Error) {}
+}
+
+// Sends the results of a walk to count_iter, besides passing them on to the visitor of the
+// options it was given. STOP is the cancel flag of the walk: it is set once the iterator is
+// dropped, or once CANCEL, the flag of the options it was given, is set
+pub(crate) struct ChannelVisitor {
+    pub sender: SyncSender<Result<FileReport, Error>>,
+    pub inner: Option<Arc<dyn Visitor>>,
+    pub stop: Arc<AtomicBool>,
+    pub cancel: Option<Arc<AtomicBool>>,
+}
+
+impl ChannelVisitor {
+    fn send(&self, result: Result<FileReport, Error>) {
+        if self.sender.send(result).is_err() || self.cancel.as_ref().is_some_and(|c| c.load(Ordering::Relaxed)) {
+            self.stop.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Visitor for ChannelVisitor {
+    fn enter_dir(&self, path: &Path) -> bool {
+        self.inner.as_ref().is_none_or(|v| v.enter_dir(path))
+    }
+
+    fn file_counted(&self, file: &FileReport) {
+        if let Some(v) = &self.inner {
+            v.file_counted(file);
+        }
+        self.send(Ok(file.clone()));
+    }
+
+    fn file_skipped(&self, path: &Path, reason: SkipReason) {
+        if let Some(v) = &self.inner {
+            v.file_skipped(path, reason);
+        }
+    }
+
+    fn error(&self, error: Error) {
+        // Errors cannot be cloned, the iterator gets them
+        self.send(Err(error));
+    }
+}
@@ -0,0 +1,1046 @@
+//! Walking directories: which files are counted, under which language, and their sums
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use regex::Regex;
+
+use crate::visit::ChannelVisitor;
+use crate::{
+    ContentHash, Error, CountCache, DirObject, Language, SourceFile, Stats, SyntaxInference, add_stats,
+    Metric, MetricFactory, builtin_comment_syntax, Visitor, ignore_chain, infer_comment_syntax,
+};
+
+/// Called with each file before it is counted, e.g. to show progress
+pub type FileCallback = Arc<dyn Fn(&Path) + Send + Sync>;
+
+/// Settings that stay the same for every directory visited by a walk. The defaults count a
+/// directory tree like `sourcelines -r` does, without printing anything on stderr.
+#[derive(Clone)]
+pub struct Options {
+    pub recursive: bool,
+    pub follow_symlinks: bool,
+    /// Honor .gitignore, .sourcelinesignore and .git/info/exclude
+    pub ignore_files: bool,
+    pub exclude_set: GlobSet,
+    pub include_set: Option<GlobSet>,
+    pub exclude_regex: Vec<Regex>,
+    pub include_regex: Vec<Regex>,
+    pub remap: HashMap<String, String>,
+    pub include_langs: Vec<String>,
+    pub exclude_langs: Vec<String>,
+    pub max_depth: Option<usize>,
+    pub one_file_system: bool,
+    pub include_vendored: bool,
+    pub submodules: SubmoduleMode,
+    pub test_set: GlobSet,
+    pub count_binary: bool,
+    pub binary_exts: Vec<String>,
+    /// Memory-map every file, not only large ones
+    pub mmap: bool,
+    /// Lines longer than this many bytes, at least 1, are counted piece by piece
+    pub line_cap: usize,
+    /// Counts of unchanged files from earlier runs
+    pub cache: Option<Arc<Mutex<CountCache>>>,
+    pub progress: Option<FileCallback>,
+    /// Called as the walk goes, see `Visitor`
+    pub visitor: Option<Arc<dyn Visitor>>,
+    /// Set to true from another thread to stop a walk before its next file or directory
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Metrics run over every line of the files counted, by name
+    pub metrics: Vec<(String, MetricFactory)>,
+    pub skip_hidden: bool,
+    pub min_file_size: Option<u64>,
+    pub max_file_size: Option<u64>,
+    /// Paths a walk is restricted to (and their parent directories), such as the files tracked by git
+    pub only: Option<Arc<HashSet<PathBuf>>>,
+    /// Keep the summaries of subdirectories down to this depth
+    pub by_dir: Option<usize>,
+    /// Keep the counts of every file in the summaries
+    pub keep_files: bool,
+    /// Keep the files that would be counted without counting them
+    pub list_only: bool,
+    /// Numbers of files and directories left out, by reason
+    pub skipped: Arc<SkipCounts>,
+    /// Files already counted, when deduplicating
+    pub seen: Option<Arc<Mutex<SeenFiles>>>,
+    /// Report the comment syntax inferred for files of languages without a built-in one, as
+    /// tracing events with the target `EXPLAIN_TARGET`
+    pub explain: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        let excludes: Vec<String> = DEFAULT_EXCLUDES.iter().map(|s| s.to_string()).collect();
+        let tests: Vec<String> = DEFAULT_TEST_PATTERNS.iter().map(|s| s.to_string()).collect();
+        Options {
+            recursive: true,
+            follow_symlinks: false,
+            ignore_files: true,
+            exclude_set: build_globset(&excludes, false),
+            include_set: None,
+            exclude_regex: Vec::new(),
+            include_regex: Vec::new(),
+            remap: HashMap::new(),
+            include_langs: Vec::new(),
+            exclude_langs: Vec::new(),
+            max_depth: None,
+            one_file_system: false,
+            include_vendored: false,
+            submodules: SubmoduleMode::Separate,
+            test_set: build_globset(&tests, false),
+            count_binary: false,
+            binary_exts: Vec::new(),
+            mmap: false,
+            line_cap: crate::DEFAULT_LINE_CAP,
+            cache: None,
+            progress: None,
+            visitor: None,
+            cancel: None,
+            metrics: Vec::new(),
+            skip_hidden: false,
+            min_file_size: None,
+            max_file_size: None,
+            only: None,
+            by_dir: None,
+            keep_files: false,
+            list_only: false,
+            skipped: Arc::default(),
+            seen: None,
+            explain: false,
+        }
+    }
+}
+
+/// The counts of a single file
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileStats {
+    /// Language of the file, after `Options::remap`
+    pub lang: Language,
+    pub stats: Stats,
+}
+
+/// The counts of a file found by `count_iter`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileReport {
+    pub path: PathBuf,
+    /// Language the file is summed under: after `Options::remap`, or "vendored" or "submodule"
+    pub lang: Language,
+    pub stats: Stats,
+    /// Whether the file matches one of the test patterns
+    pub is_test: bool,
+    /// The values of `Options::metrics` for the file
+    pub metrics: BTreeMap<String, f64>,
+}
+
+/// Count the file or directory at `path` with `options`: a directory is walked, recursively
+/// if `options.recursive`, and a file is counted unless a filter leaves it out. It is an
+/// error if `path` itself cannot be read; entries below it that cannot be read, and binary
+/// files, are only counted in `options.skipped` and `DirSummary::binary_skipped`. A walk
+/// stopped with `options.cancel` fails with `Error::Cancelled`.
+#[tracing::instrument(level = "info", skip_all, fields(path = %path.display()))]
+pub fn count_path(path: &Path, options: &Options) -> Result<DirSummary, Error> {
+    let summary = if !path.is_dir() {
+        count_dir_file(path, Path::new("."), options, DirContext::default())?
+    } else {
+        let ignore = options.ignore_files.then(|| ignore_chain(path));
+        process_dir_lang_filtered(path, path, options, ignore.as_ref(), DirContext::default(), &[])?
+    };
+    if options.cancelled() {
+        return Err(Error::Cancelled { path: path.to_path_buf() });
+    }
+    Ok(summary)
+}
+
+/// Number of results the walk of `count_iter` gets ahead of its consumer by
+const COUNT_ITER_AHEAD: usize = 256;
+
+/// Like `count_path`, but yields the result of each file as soon as it is counted, in no
+/// particular order, while the walk goes on in the background. Files that cannot be read and
+/// binary files yield an error, like a directory that cannot be read; entries left out by
+/// the filters yield nothing. The visitor of `options` is still called, except with errors.
+/// The walk waits while its consumer is `COUNT_ITER_AHEAD` results behind, and dropping the
+/// iterator stops it.
+pub fn count_iter(path: &Path, options: &Options) -> impl Iterator<Item = Result<FileReport, Error>> + use<> {
+    let (sender, receiver) = mpsc::sync_channel(COUNT_ITER_AHEAD);
+    let path = path.to_path_buf();
+    let stop = Arc::new(AtomicBool::new(options.cancelled()));
+    let channel = ChannelVisitor {
+        sender: sender.clone(),
+        inner: options.visitor.clone(),
+        stop: stop.clone(),
+        cancel: options.cancel.clone(),
+    };
+    let options = Options { visitor: Some(Arc::new(channel)), cancel: Some(stop), ..options.clone() };
+    std::thread::spawn(move || {
+        if let Err(e) = count_path(&path, &options) {
+            let _ = sender.send(Err(e));
+        }
+    });
+    receiver.into_iter()
+}
+
+/// A file a walk would count
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Candidate {
+    pub path: PathBuf,
+    /// Language the file would be summed under, as in `FileReport`
+    pub lang: Language,
+    pub is_test: bool,
+}
+
+// A condition of the caller's own on the files a Walker finds
+type CandidateFilter = Arc<dyn Fn(&Candidate) -> bool + Send + Sync>;
+
+/// Finds the files that `count_path` would count, with their language, without counting
+/// them: the same walk, globs, ignore files and language filters, for tools that only need
+/// to know which files are source code of which language
+#[derive(Clone)]
+pub struct Walker {
+    options: Options,
+    filters: Vec<CandidateFilter>,
+}
+
+impl Walker {
+    pub fn new(options: Options) -> Walker {
+        Walker {
+            options: Options { list_only: true, ..options },
+            filters: Vec::new(),
+        }
+    }
+
+    /// Only keep the files `keep` returns true for, besides those of the other filters
+    pub fn filter(mut self, keep: impl Fn(&Candidate) -> bool + Send + Sync + 'static) -> Walker {
+        self.filters.push(Arc::new(keep));
+        self
+    }
+
+    /// The files under `path`, or `path` itself if it is a file, as they are found; binary
+    /// files and entries that cannot be read yield an error, as with `count_iter`
+    pub fn files(&self, path: &Path) -> impl Iterator<Item = Result<Candidate, Error>> + use<> {
+        let filters = self.filters.clone();
+        count_iter(path, &self.options)
+            .map(|found| found.map(|file| Candidate { path: file.path, lang: file.lang, is_test: file.is_test }))
+            .filter(move |found| match found {
+                Ok(file) => filters.iter().all(|keep| keep(file)),
+                Err(_) => true,
+            })
+    }
+}
+
+/// Count one file whatever the filters of `options` say. Unlike walks, which count
+/// invalid UTF-8 sequences as replacement characters, this fails for files that are not
+/// valid UTF-8; binary files fail unless `options.count_binary`.
+pub fn count_file(path: &Path, options: &Options) -> Result<FileStats, Error> {
+    // Read even if cached, the cache does not tell whether the file is valid UTF-8
+    let file = options.open_uncached(path)?;
+    let lang = remap_language(file.lang.clone(), &options.remap);
+    let mut invalid = None;
+    let stats = options.count_source(path, file, &mut [], &mut invalid)?;
+    if let Some(line) = invalid {
+        return Err(Error::Decode { path: path.to_path_buf(), line });
+    }
+    Ok(FileStats { lang, stats })
+}
+
+/// Entries left out of walks unless an include pattern names them
+pub const DEFAULT_EXCLUDES: [&str; 15] = [
+    "*~",
+    "~*",
+    "*$",
+    "$*",
+    ".git",
+    ".svn",
+    "*.bak",
+    "*.lock",
+    "*.log",
+    "*.tmp",
+    "_build",
+    "build",
+    "builddir",
+    "node_modules",
+    "target",
+];
+
+pub fn remap_language(lang: Language, remap: &HashMap<String, String>) -> Language {
+    match remap.get(lang.name()) {
+        Some(to) => Language::named(to),
+        None => lang,
+    }
+}
+
+/// Why a file or directory found while walking was left out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Matched by an exclude pattern or an ignore file, hidden with --no-hidden, or on
+    /// another file system or in a submodule
+    Excluded,
+    /// Outside --min-file-size/--max-file-size
+    Size,
+    /// A symlink not followed, or one back to a directory being walked
+    Symlink,
+    Unreadable,
+}
+
+/// Numbers of files and directories left out of a run, by reason; binary files are
+/// counted in DirSummary
+#[derive(Default)]
+pub struct SkipCounts {
+    pub excluded: AtomicUsize,
+    pub size: AtomicUsize,
+    pub symlinks: AtomicUsize,
+    pub unreadable: AtomicUsize,
+}
+
+impl SkipCounts {
+    fn count(&self, reason: SkipReason) -> &AtomicUsize {
+        match reason {
+            SkipReason::Excluded => &self.excluded,
+            SkipReason::Size => &self.size,
+            SkipReason::Symlink => &self.symlinks,
+            SkipReason::Unreadable => &self.unreadable,
+        }
+    }
+}
+
+/// Directory names whose contents are third-party code
+const VENDOR_DIRS: [&str; 8] = [
+    "vendor",
+    "vendored",
+    "third_party",
+    "third-party",
+    "thirdparty",
+    "deps",
+    "external",
+    "extern",
+];
+
+/// Language bucket that vendored files are reported under
+pub const VENDORED_BUCKET: &str = "vendored";
+
+/// Language bucket that files in submodules are reported under
+pub const SUBMODULE_BUCKET: &str = "submodule";
+
+/// Files counted as test code rather than production code
+pub const DEFAULT_TEST_PATTERNS: [&str; 20] = [
+    "**/tests/**",
+    "**/test/**",
+    "**/__tests__/**",
+    "**/spec/**",
+    "**/tests.rs",
+    "*_test.go",
+    "*_test.py",
+    "test_*.py",
+    "*_spec.rb",
+    "*Test.java",
+    "*Tests.java",
+    "*.test.js",
+    "*.test.jsx",
+    "*.test.ts",
+    "*.test.tsx",
+    "*.spec.js",
+    "*.spec.jsx",
+    "*.spec.ts",
+    "*.spec.tsx",
+    "*_test.c",
+];
+
+/// What a directory walk found
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DirSummary {
+    pub total: Stats,
+    pub langs: HashMap<String, Stats>,
+    /// Part of `total` that is test code
+    pub tests: Stats,
+    /// Binary files that were not counted
+    pub binary_skipped: usize,
+    /// Subdirectories kept with Options::by_dir, with their own summaries
+    pub children: Vec<(String, DirSummary)>,
+    /// Counted files with their language, with Options::keep_files
+    pub files: Vec<(PathBuf, String, Stats)>,
+    /// Sums of the values of Options::metrics
+    pub metrics: BTreeMap<String, f64>,
+}
+
+impl DirSummary {
+    pub fn add(&mut self, other: DirSummary) {
+        self.total = add_stats(self.total.clone(), other.total);
+        self.tests = add_stats(self.tests.clone(), other.tests);
+        self.binary_skipped += other.binary_skipped;
+        self.files.extend(other.files);
+        for (lang, stats) in other.langs {
+            let entry = self.langs.entry(lang).or_default();
+            *entry = add_stats(entry.clone(), stats);
+        }
+        self.add_metrics(&other.metrics);
+    }
+
+    pub fn add_metrics(&mut self, metrics: &BTreeMap<String, f64>) {
+        for (name, value) in metrics {
+            *self.metrics.entry(name.clone()).or_default() += value;
+        }
+    }
+
+    pub fn add_file(&mut self, lang: &str, stats: Stats, is_test: bool) {
+        if is_test {
+            self.tests = add_stats(self.tests.clone(), stats.clone());
+        }
+        // Only the first file of a language allocates its name
+        if !self.langs.contains_key(lang) {
+            self.langs.insert(lang.to_string(), Stats::default());
+        }
+        let entry = self.langs.get_mut(lang).unwrap();
+        *entry = add_stats(entry.clone(), stats.clone());
+        self.total = add_stats(self.total.clone(), stats);
+    }
+}
+
+/// Per-directory state inherited while walking down from an argument
+#[derive(Debug, Clone, Copy, Default)]
+struct DirContext {
+    depth: usize,
+    vendored: bool,
+    submodule: bool,
+}
+
+impl DirContext {
+    fn enter(self, dir_name: &str) -> Self {
+        DirContext {
+            depth: self.depth + 1,
+            vendored: self.vendored || VENDOR_DIRS.contains(&dir_name),
+            ..self
+        }
+    }
+}
+
+/// What to do with git submodules (and other nested repositories) found by a walk
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+pub enum SubmoduleMode {
+    /// Do not count them
+    Skip,
+    /// Count them like ordinary directories
+    Include,
+    /// Count them, reported under "submodule" instead of their language
+    Separate,
+}
+
+/// Identity of a counted file for --dedupe
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum FileKey {
+    /// Device and inode number
+    Inode(u64, u64),
+    /// Size and SHA-256 digest of the content
+    Content(u64, [u8; 32]),
+}
+
+#[cfg(unix)]
+fn same_device(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(ma), Ok(mb)) => ma.dev() == mb.dev(),
+        _ => true,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_device(_a: &Path, _b: &Path) -> bool {
+    true
+}
+
+/// The files counted so far, for --dedupe
+#[derive(Debug, Default)]
+pub struct SeenFiles {
+    keys: HashSet<FileKey>,
+    // By size, the file counted with it whose content is not hashed yet, or `None` once every
+    // file of the size is hashed: contents are only read when their sizes match
+    sizes: HashMap<u64, Option<PathBuf>>,
+}
+
+impl SeenFiles {
+    // Whether PATH is neither a hardlink nor a copy of a file seen before; if not, it is seen
+    fn first_occurrence(&mut self, path: &Path) -> bool {
+        let Ok(meta) = fs::metadata(path) else {
+            return true;
+        };
+        #[cfg(unix)]
+        let inode = {
+            use std::os::unix::fs::MetadataExt;
+            let inode = FileKey::Inode(meta.dev(), meta.ino());
+            if self.keys.contains(&inode) {
+                return false;
+            }
+            inode
+        };
+        let size = meta.len();
+        let content = match self.sizes.get_mut(&size) {
+            None => {
+                self.sizes.insert(size, Some(path.to_path_buf()));
+                None
+            }
+            Some(unhashed) => {
+                if let Some(key) = unhashed.take().and_then(|first| content_key(&first, size)) {
+                    self.keys.insert(key);
+                }
+                let key = content_key(path, size);
+                if key.as_ref().is_some_and(|key| self.keys.contains(key)) {
+                    return false;
+                }
+                key
+            }
+        };
+        #[cfg(unix)]
+        self.keys.insert(inode);
+        self.keys.extend(content);
+        true
+    }
+}
+
+fn content_key(path: &Path, size: u64) -> Option<FileKey> {
+    use sha2::Digest;
+    let mut hasher = sha2::Sha256::new();
+    std::io::copy(&mut fs::File::open(path).ok()?, &mut hasher).ok()?;
+    Some(FileKey::Content(size, hasher.finalize().into()))
+}
+
+impl Options {
+    /// Whether files of the (remapped) language `lang` should be counted
+    pub fn lang_selected(&self, lang: &str) -> bool {
+        (self.include_langs.is_empty() || self.include_langs.iter().any(|l| l == lang))
+            && !self.exclude_langs.iter().any(|l| l == lang)
+    }
+
+    /// Whether `path` is within the size limits; skipped files are reported in verbose mode
+    fn size_selected(&self, path: &Path) -> bool {
+        if self.min_file_size.is_none() && self.max_file_size.is_none() {
+            return true;
+        }
+        let size = match fs::metadata(path) {
+            Ok(m) => m.len(),
+            Err(_) => return true,
+        };
+        let reason = if self.max_file_size.is_some_and(|max| size > max) {
+            "over --max-file-size"
+        } else if self.min_file_size.is_some_and(|min| size < min) {
+            "under --min-file-size"
+        } else {
+            return true;
+        };
+        self.skip(path, SkipReason::Size, &format_args!("{} bytes, {}", size, reason));
+        false
+    }
+
+    /// Whether `path` matches one of the test-code patterns, by file name or by
+    /// its path relative to `root`, the walked argument
+    pub fn is_test_file(&self, path: &Path, root: &Path) -> bool {
+        let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        let rel = path.strip_prefix(root).unwrap_or(path);
+        let rel = rel.to_string_lossy().replace('\\', "/");
+        self.test_set.is_match(name.as_ref()) || self.test_set.is_match(&rel)
+    }
+
+    /// Count a file, or tell why it was not counted, setting `invalid` to the number of its
+    /// first line that is not valid UTF-8. With --count-binary, binary files contribute
+    /// their size in bytes only.
+    fn count_source(
+        &self,
+        path: &Path,
+        file: SourceFile,
+        metrics: &mut [Box<dyn Metric>],
+        invalid: &mut Option<usize>,
+    ) -> Result<Stats, Error> {
+        if let Some(progress) = &self.progress {
+            progress(path);
+        }
+        let forced = self.binary_extension(path);
+        let fresh = file.cached.is_none();
+        if !forced && !file.is_binary() {
+            let lang = file.lang.clone();
+            let mut hash = self.cache.as_ref().filter(|_| fresh).map(|_| ContentHash::default());
+            let stats = match file.count(self.mmap, self.line_cap, hash.as_mut(), metrics, invalid) {
+                Ok(stats) => stats,
+                Err(e) => {
+                    self.unreadable(path, &e);
+                    return Err(Error::Io { path: path.to_path_buf(), source: e });
+                }
+            };
+            if let Some(cache) = &self.cache
+                && fresh
+            {
+                cache.lock().unwrap().store(path, lang.into(), false, &stats, hash.map(|h| h.0));
+            }
+            // Often a sign of comment syntax that does not fit the file
+            if stats.actual_loc == 0 && stats.bytes > 0 {
+                tracing::warn!(path = %path.display(), bytes = stats.bytes, "no actual lines of code");
+            }
+            return Ok(Stats { files: 1, ..stats });
+        }
+        if let Some(cache) = &self.cache
+            && fresh
+            && file.is_binary()
+        {
+            cache.lock().unwrap().store(path, file.lang.into(), true, &Stats::default(), None);
+        }
+        if !self.count_binary || forced {
+            return Err(Error::Binary { path: path.to_path_buf() });
+        }
+        let bytes = fs::metadata(path).map(|m| m.len() as usize).unwrap_or(0);
+        Ok(Stats {
+            bytes,
+            files: 1,
+            ..Stats::default()
+        })
+    }
+
+    /// Whether `path` has one of the --binary-extensions, which are never counted
+    pub fn binary_extension(&self, path: &Path) -> bool {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|ext| self.binary_exts.iter().any(|b| b.eq_ignore_ascii_case(ext)))
+    }
+
+    /// Whether counting `file` would skip it as binary
+    fn skips_binary(&self, path: &Path, file: &SourceFile) -> bool {
+        self.binary_extension(path) || (file.is_binary() && !self.count_binary)
+    }
+
+    /// Open `path` for counting, or take its counts from the cache
+    fn open_file(&self, path: &Path) -> Result<SourceFile, Error> {
+        // --explain and metrics look at the content, which cached files do not provide
+        if let Some(cache) = &self.cache
+            && !self.explain
+            && self.metrics.is_empty()
+            && let Some(file) = cache.lock().unwrap().lookup(path).map(SourceFile::from_cache)
+        {
+            return Ok(file);
+        }
+        self.open_uncached(path)
+    }
+
+    /// Open `path` for counting, whatever the cache holds
+    fn open_uncached(&self, path: &Path) -> Result<SourceFile, Error> {
+        SourceFile::open(path).map_err(|e| {
+            self.unreadable(path, &e);
+            Error::Io { path: path.to_path_buf(), source: e }
+        })
+    }
+
+    /// Record a file or directory left out of the run; verbose mode names it
+    fn skip(&self, path: &Path, reason: SkipReason, detail: &dyn std::fmt::Display) {
+        self.skipped.count(reason).fetch_add(1, Ordering::Relaxed);
+        match reason {
+            SkipReason::Unreadable => tracing::warn!(path = %path.display(), %detail, "unreadable"),
+            _ => tracing::debug!(path = %path.display(), ?reason, %detail, "skipped"),
+        }
+        if let Some(visitor) = &self.visitor
+            && reason != SkipReason::Unreadable
+        {
+            visitor.file_skipped(path, reason);
+        }
+    }
+
+    /// Whether the walk was asked to stop
+    fn cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|c| c.load(Ordering::Relaxed))
+    }
+
+    /// Pass an error the walk goes on after to the visitor
+    fn visit_error(&self, error: Error) {
+        if let Error::Binary { path } = &error {
+            tracing::debug!(path = %path.display(), detail = "binary", "skipped");
+        }
+        if let Some(visitor) = &self.visitor {
+            visitor.error(error);
+        }
+    }
+
+    /// Pass the counts of a file to the visitor
+    fn visit_file(&self, path: &Path, lang: &Language, stats: &Stats, is_test: bool, metrics: &BTreeMap<String, f64>) {
+        if let Some(visitor) = &self.visitor {
+            let file = FileReport {
+                path: path.to_path_buf(),
+                lang: lang.clone(),
+                stats: stats.clone(),
+                is_test,
+                metrics: metrics.clone(),
+            };
+            visitor.file_counted(&file);
+        }
+    }
+
+    /// Record a file or directory that could not be read
+    fn unreadable(&self, path: &Path, reason: &dyn std::fmt::Display) {
+        self.skip(path, SkipReason::Unreadable, reason);
+    }
+
+    /// Whether the exclude globs or regexes drop the entry at `path`, unless an include matches it
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        let fname = path.file_name().and_then(|s| s.to_str()).unwrap_or("");
+        let path_str = path.to_string_lossy().replace('\\', "/");
+        (self.exclude_set.is_match(fname) || self.exclude_regex.iter().any(|re| re.is_match(&path_str)))
+            && self.include_set.as_ref().is_none_or(|inc| !inc.is_match(fname))
+            && !self.include_regex.iter().any(|re| re.is_match(&path_str))
+    }
+
+    /// Whether neither `path` nor any directory above it (below `root`) is excluded
+    pub fn path_selected(&self, path: &Path, root: &Path) -> bool {
+        path.ancestors()
+            .take_while(|p| *p != root && !p.as_os_str().is_empty())
+            .all(|p| !self.is_excluded(p))
+    }
+
+    /// With --dedupe, forget the files counted so far, so that the next walk counts them again
+    pub fn forget_seen(&self) {
+        if let Some(seen) = &self.seen {
+            *seen.lock().unwrap() = SeenFiles::default();
+        }
+    }
+
+    /// With --dedupe, whether `path` is neither a hardlink nor a copy of a file counted before
+    fn first_occurrence(&self, path: &Path) -> bool {
+        let Some(seen) = &self.seen else {
+            return true;
+        };
+        if !seen.lock().unwrap().first_occurrence(path) {
+            tracing::debug!(path = %path.display(), detail = "duplicate", "skipped");
+            return false;
+        }
+        true
+    }
+}
+
+// Walk a directory of the argument ROOT with filtering, returning totals, per-language sums
+// and test-code totals; fails only if the directory itself cannot be read
+#[tracing::instrument(level = "debug", name = "dir", skip_all, fields(path = %path.display()))]
+fn process_dir_lang_filtered(
+    path: &Path,
+    root: &Path,
+    opts: &Options,
+    parent_dir_obj: Option<&Arc<DirObject>>,
+    ctx: DirContext,
+    visiting: &[PathBuf],
+) -> Result<DirSummary, Error> {
+    let summary = DirSummary::default();
+    if opts.cancelled() || opts.visitor.as_ref().is_some_and(|v| !v.enter_dir(path)) {
+        return Ok(summary);
+    }
+
+    // Create DirObject for this directory if ignorelist is enabled
+    let dir_obj = if let Some(parent) = parent_dir_obj {
+        // Check if ignorelist is enabled (parent exists means it's enabled)
+        let mut dir_obj = DirObject::new(path.to_path_buf(), Some(parent.clone()));
+        dir_obj.load_ignore_files();
+        Some(Arc::new(dir_obj))
+    } else {
+        None
+    };
+    
+    let entries = match fs::read_dir(path) {
+        Ok(e) => e,
+        Err(e) => {
+            opts.unreadable(path, &e);
+            return Err(Error::Io { path: path.to_path_buf(), source: e });
+        }
+    };
+    // Canonical paths of the directories being walked, used to detect
+    // symlinks that point back to one of them
+    let mut visiting = visiting.to_vec();
+    if opts.follow_symlinks {
+        visiting.push(fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+    }
+    // Entries are filtered here; subdirectories and files are then walked and counted in parallel,
+    // or in order with --dedupe
+    let mut subdirs = Vec::new();
+    let mut files = Vec::new();
+    for entry in entries.flatten() {
+        let p = entry.path();
+        let fname = p.file_name().and_then(|s| s.to_str()).unwrap_or("");
+        if opts.skip_hidden && fname.starts_with('.') {
+            opts.skip(&p, SkipReason::Excluded, &"hidden");
+            continue;
+        }
+        if opts.is_excluded(&p) {
+            opts.skip(&p, SkipReason::Excluded, &"excluded");
+            continue;
+        }
+        if opts.only.as_ref().is_some_and(|only| !only.contains(&p)) {
+            continue;
+        }
+        
+        // Check ignore list if enabled
+        if let Some(ref dir_obj) = dir_obj {
+            let is_dir_entry = p.is_dir();
+            if !dir_obj.include_test(&p, is_dir_entry) {
+                opts.skip(&p, SkipReason::Excluded, &"ignore file");
+                continue;
+            }
+        }
+        
+        // Check if it's a symlink
+        let is_symlink = fs::symlink_metadata(&p)
+            .map(|m| m.file_type().is_symlink())
+            .unwrap_or(false);
+        
+        // Skip symlinks if follow_symlinks is false
+        if is_symlink && !opts.follow_symlinks {
+            opts.skip(&p, SkipReason::Symlink, &"symlink, use -L to follow");
+            continue;
+        }
+        
+        if opts.recursive && p.is_dir() {
+            if opts.max_depth.is_some_and(|max| ctx.depth >= max) {
+                continue;
+            }
+            if opts.one_file_system && !same_device(path, &p) {
+                opts.skip(&p, SkipReason::Excluded, &"different file system");
+                continue;
+            }
+            if is_symlink
+                && let Ok(target) = fs::canonicalize(&p)
+                && visiting.contains(&target)
+            {
+                opts.skip(&p, SkipReason::Symlink, &format_args!("symlink loop to {}", target.display()));
+                continue;
+            }
+            let mut sub_ctx = ctx.enter(fname);
+            // A submodule checkout has a .git file (a nested clone a .git directory)
+            if p.join(".git").exists() {
+                match opts.submodules {
+                    SubmoduleMode::Skip => {
+                        opts.skip(&p, SkipReason::Excluded, &"submodule");
+                        continue;
+                    }
+                    SubmoduleMode::Include => {}
+                    SubmoduleMode::Separate => sub_ctx.submodule = true,
+                }
+            }
+            subdirs.push((p, sub_ctx));
+        } else if p.is_file() {
+            files.push(p);
+        } else if is_symlink && let Err(e) = fs::metadata(&p) {
+            opts.unreadable(&p, &"broken symlink");
+            opts.visit_error(Error::Io { path: p, source: e });
+        }
+    }
+    let merge = |mut a: DirSummary, b: DirSummary| {
+        a.add(b);
+        a
+    };
+    let count_subdir = |(p, sub_ctx): &(PathBuf, DirContext)| {
+        process_dir_lang_filtered(p, root, opts, dir_obj.as_ref(), *sub_ctx, &visiting).unwrap_or_else(|e| {
+            opts.visit_error(e);
+            DirSummary::default()
+        })
+    };
+    let count_file = |p: &PathBuf| {
+        count_dir_file(p, root, opts, ctx).unwrap_or_else(|e| {
+            opts.visit_error(e);
+            DirSummary::default()
+        })
+    };
+    #[cfg(feature = "parallel")]
+    let (dir_summaries, files_summary) = match opts.seen {
+        None => rayon::join(
+            || subdirs.par_iter().map(count_subdir).collect::<Vec<_>>(),
+            || files.par_iter().map(count_file).reduce(DirSummary::default, merge),
+        ),
+        Some(_) => count_in_order(&mut subdirs, &mut files, count_subdir, count_file),
+    };
+    #[cfg(not(feature = "parallel"))]
+    let (dir_summaries, files_summary) = count_in_order(&mut subdirs, &mut files, count_subdir, count_file);
+    let mut summary = merge(summary, files_summary);
+    let keep = opts.by_dir.is_some_and(|max| ctx.depth < max);
+    for ((p, _), mut child) in subdirs.iter().zip(dir_summaries) {
+        if keep {
+            let totals = DirSummary {
+                total: child.total.clone(),
+                langs: child.langs.clone(),
+                tests: child.tests.clone(),
+                binary_skipped: child.binary_skipped,
+                children: Vec::new(),
+                files: std::mem::take(&mut child.files),
+                metrics: child.metrics.clone(),
+            };
+            summary.add(totals);
+            summary.children.push((p.display().to_string(), child));
+        } else {
+            summary.add(child);
+        }
+    }
+    Ok(summary)
+}
+
+// Count SUBDIRS and FILES one after the other in the order of their names, so that of several
+// copies --dedupe keeps the one with the smallest path
+fn count_in_order(
+    subdirs: &mut [(PathBuf, DirContext)],
+    files: &mut [PathBuf],
+    count_subdir: impl Fn(&(PathBuf, DirContext)) -> DirSummary,
+    count_file: impl Fn(&PathBuf) -> DirSummary,
+) -> (Vec<DirSummary>, DirSummary) {
+    subdirs.sort_by(|a, b| a.0.cmp(&b.0));
+    files.sort();
+    let mut files = files.iter().peekable();
+    let mut files_summary = DirSummary::default();
+    let mut dir_summaries = Vec::new();
+    for subdir in subdirs.iter() {
+        while let Some(p) = files.next_if(|p| **p < subdir.0) {
+            files_summary.add(count_file(p));
+        }
+        dir_summaries.push(count_subdir(subdir));
+    }
+    for p in files {
+        files_summary.add(count_file(p));
+    }
+    (dir_summaries, files_summary)
+}
+
+// Count one file found while walking a directory, or given as an argument; fails only if
+// the file cannot be read
+#[tracing::instrument(level = "debug", name = "file", skip_all, fields(path = %p.display()))]
+fn count_dir_file(p: &Path, root: &Path, opts: &Options, ctx: DirContext) -> Result<DirSummary, Error> {
+    let mut summary = DirSummary::default();
+    if opts.cancelled() || !opts.size_selected(p) {
+        return Ok(summary);
+    }
+    let file = opts.open_file(p)?;
+    let mut lang = remap_language(file.lang.clone(), &opts.remap);
+    if !opts.lang_selected(&lang) || !opts.first_occurrence(p) {
+        return Ok(summary);
+    }
+    if opts.list_only {
+        if opts.skips_binary(p, &file) {
+            summary.binary_skipped += 1;
+            opts.visit_error(Error::Binary { path: p.to_path_buf() });
+        } else {
+            if opts.visitor.is_some() {
+                opts.visit_file(p, &lang, &Stats::default(), opts.is_test_file(p, root), &BTreeMap::new());
+            }
+            summary.files.push((p.to_path_buf(), lang.into(), Stats::default()));
+        }
+        return Ok(summary);
+    }
+    if opts.explain {
+        explain_comment_syntax(p, &file);
+    }
+    if ctx.submodule {
+        lang = Language::from_static(SUBMODULE_BUCKET);
+    } else if ctx.vendored && !opts.include_vendored {
+        lang = Language::from_static(VENDORED_BUCKET);
+    }
+    let mut metrics: Vec<Box<dyn Metric>> = opts.metrics.iter().map(|(_, new)| new()).collect();
+    for metric in &mut metrics {
+        metric.begin_file(p, &lang);
+    }
+    match opts.count_source(p, file, &mut metrics, &mut None) {
+        Ok(stats) => {
+            let is_test = opts.is_test_file(p, root);
+            let metrics = opts.metrics.iter().zip(&metrics).flat_map(|((name, _), m)| m.values(name)).collect();
+            tracing::debug!(%lang, actual_loc = stats.actual_loc, raw_loc = stats.raw_loc, is_test, "counted");
+            if opts.keep_files {
+                summary.files.push((p.to_path_buf(), lang.to_string(), stats.clone()));
+            }
+            opts.visit_file(p, &lang, &stats, is_test, &metrics);
+            summary.add_metrics(&metrics);
+            summary.add_file(&lang, stats, is_test);
+        }
+        Err(e @ Error::Binary { .. }) => {
+            summary.binary_skipped += 1;
+            opts.visit_error(e);
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(summary)
+}
+
+
+pub fn build_globset(patterns: &[String], case_insensitive: bool) -> GlobSet {
+    let glob = |pat: &str| GlobBuilder::new(pat).case_insensitive(case_insensitive).build();
+    let mut builder = GlobSetBuilder::new();
+    for pat in patterns {
+        // Accept both literal and glob patterns
+        let g = glob(pat).unwrap_or_else(|_| glob(&glob_escape(pat)).unwrap());
+        builder.add(g);
+    }
+    builder.build().unwrap()
+}
+
+pub fn glob_escape(s: &str) -> String {
+    // Escape all special glob characters
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '*' | '?' | '[' | ']' | '{' | '}' | '!' | '(' | ')' | '|' | '^' | '$' | '+'
+            | '.' | '#' => {
+                out.push('[');
+                out.push(c);
+                out.push(']');
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// With --explain, tell how the comment syntax of a file without a built-in one was inferred
+fn explain_comment_syntax(path: &Path, file: &SourceFile) {
+    if builtin_comment_syntax(&file.lang).is_some() {
+        return;
+    }
+    let inference = infer_comment_syntax(file.prefix());
+    let table = inference_table(&inference).join("\n");
+    let lines = inference.lines;
+    tracing::info!(target: EXPLAIN_TARGET, path = %path.display(), lang = %file.lang, lines, table, "syntax inferred");
+}
+
+/// Target of the events `Options::explain` asks for, to be shown whatever the log level
+pub const EXPLAIN_TARGET: &str = "sourcelines::explain";
+
+// The candidates of a comment syntax inference with their scores, and the choice made
+pub fn inference_table(inference: &SyntaxInference) -> Vec<String> {
+    let mut lines = vec![format!("  {:<3}{:<12} {:>9} {:>10} {:>7}", "", "candidate", "comments", "code-like", "score")];
+    for (i, cand) in inference.candidates.iter().enumerate() {
+        let mark = if inference.winner == Some(i) {
+            "*"
+        } else if inference.runner_up == Some(i) {
+            "+"
+        } else {
+            ""
+        };
+        lines.push(format!(
+            "  {:<3}{:<12} {:>9} {:>10} {:>7}",
+            mark,
+            cand.syntax.describe(),
+            cand.comment_lines,
+            cand.code_like_lines,
+            cand.score
+        ));
+    }
+    lines.push(match (inference.winner, inference.runner_up) {
+        (Some(_), Some(r)) => format!(
+            "  chose {} with confidence {:.2} over runner-up {}",
+            inference.syntax.describe(),
+            inference.confidence,
+            inference.candidates[r].syntax.describe()
+        ),
+        (Some(_), None) => format!(
+            "  chose {} with confidence {:.2}, no competing candidate",
+            inference.syntax.describe(),
+            inference.confidence
+        ),
+        _ => "  no candidate matched, counting every non-blank line as code".to_string(),
+    });
+    lines
+}
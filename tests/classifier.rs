@@ -0,0 +1,154 @@
+//! The line classifier against a corpus of tricky sources, and invariants that hold for any text
+
+use std::io::BufReader;
+
+use proptest::prelude::*;
+use sourcelines::{LineKind, Stats, classify_lines, count_reader, count_str};
+
+// What each line of a corpus file is counted as: C code, M code with a comment, # comment, _ blank.
+// Some rows record known limits of the classifier rather than what a parser would say; they
+// are marked, so that a rewrite that fixes them updates the row on purpose.
+const CORPUS: &[(&str, &str, &str)] = &[
+    // Block comments do not nest: the first */ ends the comment. A line that starts with a
+    // closed block comment is a comment, whatever follows it.
+    ("nested_comments.rs", "rust", "##CCC#M#C"),
+    // Comment markers within string literals
+    ("string_markers.c", "c", "C_CMMCCC"),
+    // Lines of a heredoc that look like comments are counted as comments
+    ("heredoc.sh", "shell", "##C#CC_C"),
+    ("crlf.py", "python", "C_#CM_C"),
+    // Docstrings are code, but a line within one that starts with # is a comment
+    ("docstring.py", "python", "CC_#CM_#"),
+    ("jsx_comments.jsx", "javascript", "CCC#M###CCC"),
+    ("no_final_newline.c", "c", "#C"),
+    ("blank_only.c", "c", "___"),
+];
+
+fn kind_of(letter: char) -> LineKind {
+    match letter {
+        'C' => LineKind::Code,
+        'M' => LineKind::Mixed,
+        '#' => LineKind::Comment,
+        '_' => LineKind::Blank,
+        _ => panic!("unknown line kind {:?}", letter),
+    }
+}
+
+// The counts two ways of counting the same text must agree on
+fn counts(stats: &Stats) -> (usize, usize, usize, usize, usize, usize) {
+    (stats.actual_loc, stats.raw_loc, stats.comments, stats.words, stats.chars, stats.bytes)
+}
+
+#[test]
+fn test_corpus() {
+    for (file, lang, expected) in CORPUS {
+        let text = std::fs::read_to_string(format!("tests/testdata/corpus/{}", file)).unwrap();
+        let kinds: Vec<LineKind> = classify_lines(lang, text.as_bytes()).map(|(_, kind)| kind).collect();
+        let expected: Vec<LineKind> = expected.chars().map(kind_of).collect();
+        assert_eq!(kinds, expected, "{}", file);
+
+        let stats = count_str(lang, &text);
+        let code = expected.iter().filter(|kind| matches!(kind, LineKind::Code | LineKind::Mixed)).count();
+        let comments = expected.iter().filter(|kind| **kind == LineKind::Comment).count();
+        assert_eq!(stats.raw_loc, expected.len(), "{}", file);
+        assert_eq!(stats.actual_loc, code, "{}", file);
+        assert_eq!(stats.comments, comments, "{}", file);
+    }
+}
+
+#[test]
+fn test_corpus_crlf_file() {
+    let text = std::fs::read_to_string("tests/testdata/corpus/crlf.py").unwrap();
+    assert!(text.contains("\r\n"), "crlf.py must keep its CRLF line endings");
+    let lf = text.replace("\r\n", "\n");
+    let (crlf, lf) = (count_str("python", &text), count_str("python", &lf));
+    assert_eq!((crlf.actual_loc, crlf.comments), (lf.actual_loc, lf.comments));
+}
+
+// Pieces of lines that trip up classifiers: comment markers of several languages, markers
+// within strings, unclosed and closing block comments, JSX comments and non-ASCII text
+const FRAGMENTS: &[&str] = &[
+    "",
+    "   ",
+    "\t",
+    "code();",
+    "x = 1",
+    "// line",
+    "# line",
+    "-- line",
+    "/* open",
+    "*/",
+    "/* closed */",
+    "/* closed */ code();",
+    "code(); // trailing",
+    "s = \"/* not a comment\";",
+    "s = '# not a comment'",
+    "<!-- open",
+    "-->",
+    "{/* jsx */}",
+    "\"\"\"",
+    "cat <<END",
+    "END",
+    "héllo wörld ✓",
+];
+
+const LANGS: &[&str] = &["rust", "c", "python", "shell", "sql", "html", "javascript"];
+
+fn source() -> impl Strategy<Value = (&'static str, Vec<String>)> {
+    let line = prop::collection::vec(prop::sample::select(FRAGMENTS), 1..3).prop_map(|parts| parts.join(" "));
+    (prop::sample::select(LANGS), prop::collection::vec(line, 0..40))
+}
+
+fn join(lines: &[String], newline: &str, final_newline: bool) -> String {
+    let mut text = lines.join(newline);
+    if final_newline && !lines.is_empty() {
+        text.push_str(newline);
+    }
+    text
+}
+
+proptest! {
+    #[test]
+    fn prop_every_line_is_code_comment_or_blank((lang, lines) in source(), final_newline: bool) {
+        let text = join(&lines, "\n", final_newline);
+        let stats = count_str(lang, &text);
+        let blank = text.lines().filter(|line| line.trim().is_empty()).count();
+        prop_assert_eq!(stats.raw_loc, text.lines().count());
+        prop_assert_eq!(stats.actual_loc + stats.comments + blank, stats.raw_loc);
+        prop_assert_eq!(stats.bytes, text.len());
+        prop_assert_eq!(stats.chars, text.chars().count());
+        prop_assert_eq!(stats.words, text.split_whitespace().count());
+    }
+
+    #[test]
+    fn prop_classify_lines_agrees_with_counts((lang, lines) in source()) {
+        let text = join(&lines, "\n", true);
+        let stats = count_str(lang, &text);
+        let kinds: Vec<LineKind> = classify_lines(lang, text.as_bytes()).map(|(_, kind)| kind).collect();
+        let code = kinds.iter().filter(|kind| matches!(kind, LineKind::Code | LineKind::Mixed)).count();
+        let comments = kinds.iter().filter(|kind| **kind == LineKind::Comment).count();
+        let blank = kinds.iter().filter(|kind| **kind == LineKind::Blank).count();
+        prop_assert_eq!(kinds.len(), stats.raw_loc);
+        prop_assert_eq!(code, stats.actual_loc);
+        prop_assert_eq!(comments, stats.comments);
+        prop_assert_eq!(blank, lines.iter().filter(|line| line.trim().is_empty()).count());
+    }
+
+    #[test]
+    fn prop_crlf_counts_as_lf((lang, lines) in source(), final_newline: bool) {
+        let lf = count_str(lang, &join(&lines, "\n", final_newline));
+        let crlf = count_str(lang, &join(&lines, "\r\n", final_newline));
+        prop_assert_eq!(crlf.actual_loc, lf.actual_loc);
+        prop_assert_eq!(crlf.comments, lf.comments);
+        prop_assert_eq!(crlf.raw_loc, lf.raw_loc);
+        prop_assert_eq!(crlf.words, lf.words);
+    }
+
+    #[test]
+    fn prop_buffer_size_does_not_matter((lang, lines) in source(), capacity in 1usize..64) {
+        let text = join(&lines, "\n", true);
+        let whole = count_str(lang, &text);
+        let pieces = count_reader(lang, BufReader::with_capacity(capacity, text.as_bytes())).unwrap();
+        prop_assert_eq!(counts(&pieces), counts(&whole));
+    }
+}
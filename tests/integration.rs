@@ -1,5 +1,26 @@
+use std::path::Path;
 use std::process::Command;
 
+// git run in ROOT, with an identity to commit as
+fn git_command(root: &Path) -> Command {
+    let mut git = Command::new("git");
+    git.arg("-C").arg(root).args(["-c", "user.name=test", "-c", "user.email=test@example.com"]);
+    git
+}
+
+// Run git in ROOT, which must succeed
+fn git(root: &Path, args: &[&str]) {
+    let status = git_command(root).args(args).status().expect("failed to run git");
+    assert!(status.success());
+}
+
+// A new, empty git repository in a temporary directory
+fn init_repo() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    git(dir.path(), &["init", "-q"]);
+    dir
+}
+
 #[test]
 fn test_python_file() {
     let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
@@ -9,9 +30,9 @@ fn test_python_file() {
     let stdout = String::from_utf8_lossy(&output.stdout);
     assert!(stdout.contains("python"));
     assert!(stdout.contains("simple.py"));
-    // 2 code lines, 3 comment lines, 2 empty lines
+    // 2 code lines, 4 comment lines (incl. shebang), 2 empty lines
     assert!(stdout.contains("2 ")); // actual_loc
-    assert!(stdout.contains("7 ")); // raw_loc
+    assert!(stdout.contains("8 ")); // raw_loc
 }
 
 #[test]
@@ -52,3 +73,2079 @@ fn test_txt_file() {
     assert!(stdout.contains("3 ")); // actual_loc
     assert!(stdout.contains("5 ")); // raw_loc
 }
+
+#[test]
+fn test_remap_language() {
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .arg("--remap")
+        .arg("shell=posix")
+        .arg("tests/testdata/simple.sh")
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("<posix>"));
+    assert!(!stdout.contains("<shell>"));
+}
+
+#[test]
+fn test_literate_haskell_file() {
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .arg("-l")
+        .arg("-R")
+        .arg("tests/testdata/simple.lhs")
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("literate-haskell"));
+    // 2 bird-tracked lines + 2 lines in a code block
+    assert!(stdout.contains("       4       12 "));
+}
+
+#[test]
+fn test_org_file() {
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .arg("-l")
+        .arg("-R")
+        .arg("tests/testdata/simple.org")
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("<org>"));
+    // 2 non-blank lines inside the source block
+    assert!(stdout.contains("       2        8 "));
+}
+
+#[test]
+fn test_jsx_file() {
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .arg("-l")
+        .arg("-R")
+        .arg("tests/testdata/simple.jsx")
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("<javascript>"));
+    // The line comment and the {/* */} comment are not code
+    assert!(stdout.contains("       7        9 "));
+}
+
+#[test]
+fn test_gitignore_above_scan_root() {
+    let repo = tempfile::tempdir().unwrap();
+    let root = repo.path();
+    std::fs::create_dir_all(root.join(".git/info")).unwrap();
+    std::fs::create_dir_all(root.join("src/gen")).unwrap();
+    std::fs::write(root.join(".gitignore"), "gen/\n").unwrap();
+    std::fs::write(root.join(".git/info/exclude"), "local.c\n").unwrap();
+    std::fs::write(root.join("src/main.c"), "int main;\n").unwrap();
+    std::fs::write(root.join("src/local.c"), "int local;\n").unwrap();
+    std::fs::write(root.join("src/gen/out.c"), "int out;\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .arg("-r")
+        .arg("-l")
+        .arg(root.join("src"))
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // Only main.c is counted: gen/ is ignored by the repository root
+    // .gitignore and local.c by .git/info/exclude
+    assert!(stdout.starts_with("       1 <*>"));
+}
+
+#[test]
+fn test_language_filters() {
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .arg("-rv")
+        .arg("--include-lang")
+        .arg("c,shell")
+        .arg("--exclude-lang=shell")
+        .arg("tests/testdata")
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("<c>"));
+    assert!(!stdout.contains("<shell>"));
+    assert!(!stdout.contains("<python>"));
+}
+
+#[test]
+fn test_sourcelinesignore() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("fixtures")).unwrap();
+    std::fs::write(root.join(".sourcelinesignore"), "fixtures/\n").unwrap();
+    std::fs::write(root.join("main.c"), "int main;\n").unwrap();
+    std::fs::write(root.join("fixtures/big.c"), "int a;\nint b;\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .arg("-r")
+        .arg("-l")
+        .arg("--include-lang=c")
+        .arg(root)
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("       1 <*>"));
+}
+
+#[test]
+fn test_list_excludes() {
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["--list-excludes", "--exclude", "*.gen", "--include", "build"])
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(lines.contains(&"target"));
+    assert!(lines.contains(&"*.gen"));
+    assert!(lines.contains(&"!build"));
+    assert!(!lines.contains(&"build"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["--list-excludes", "--no-default-excludes"])
+        .output()
+        .expect("failed to run sourcelines");
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_max_depth() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("a/b")).unwrap();
+    std::fs::write(root.join("top.c"), "int top;\n").unwrap();
+    std::fs::write(root.join("a/mid.c"), "int mid;\n").unwrap();
+    std::fs::write(root.join("a/b/deep.c"), "int deep;\n").unwrap();
+    let count = |depth: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-r", "-l", "--max-depth", depth])
+            .arg(root)
+            .output()
+            .expect("failed to run sourcelines");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    assert!(count("0").starts_with("       1 <*>"));
+    assert!(count("1").starts_with("       2 <*>"));
+    assert!(count("5").starts_with("       3 <*>"));
+}
+
+#[test]
+fn test_file_size_limits() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::write(root.join("small.c"), "int a;\n").unwrap();
+    std::fs::write(root.join("large.c"), "int b;\n".repeat(200)).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["-rv", "-l", "--max-file-size", "1K"])
+        .arg(root)
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stdout.starts_with("       1 <*>"));
+    assert!(stderr.contains("large.c"));
+    assert!(stderr.contains("over --max-file-size"));
+}
+
+#[test]
+fn test_hidden_toggle() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join(".config")).unwrap();
+    std::fs::write(root.join("main.c"), "int a;\n").unwrap();
+    std::fs::write(root.join(".config/hidden.c"), "int b;\n").unwrap();
+    let count = |flag: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-r", "-l", flag])
+            .arg(root)
+            .output()
+            .expect("failed to run sourcelines");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    assert!(count("--hidden").starts_with("       2 <*>"));
+    assert!(count("--no-hidden").starts_with("       1 <*>"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlink_loop_is_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("a")).unwrap();
+    std::fs::write(root.join("a/main.c"), "int a;\n").unwrap();
+    std::os::unix::fs::symlink("..", root.join("a/up")).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["-r", "-L", "-l"])
+        .arg(root)
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    assert!(stdout.starts_with("       1 <*>"));
+}
+
+#[test]
+fn test_dedupe() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("vendor")).unwrap();
+    std::fs::write(root.join("lib.c"), "int a;\nint b;\n").unwrap();
+    std::fs::write(root.join("vendor/lib.c"), "int a;\nint b;\n").unwrap();
+    let count = |dedupe: bool| {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_sourcelines"));
+        cmd.args(["-r", "-l"]);
+        if dedupe {
+            cmd.arg("--dedupe");
+        }
+        let output = cmd.arg(root).output().expect("failed to run sourcelines");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    assert!(count(false).starts_with("       4 <*>"));
+    assert!(count(true).starts_with("       2 <*>"));
+    // Of several copies, the one with the smallest path is kept, however the walk is scheduled
+    std::fs::create_dir_all(root.join("a/vendor")).unwrap();
+    std::fs::write(root.join("a/vendor/x.c"), "int b;\n").unwrap();
+    std::fs::write(root.join("b.c"), "int b;\n").unwrap();
+    std::fs::write(root.join("c.c"), "int c;\n").unwrap();
+    for _ in 0..10 {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-r", "-l", "--by-lang", "--dedupe"])
+            .arg(root)
+            .output()
+            .expect("failed to run sourcelines");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "       3 <c>\n       1 <vendored>\n       4 <*> (sum)\n");
+    }
+}
+
+#[test]
+fn test_regex_filters() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("v1.2")).unwrap();
+    std::fs::create_dir_all(root.join("v2.0")).unwrap();
+    std::fs::write(root.join("main.c"), "int a;\n").unwrap();
+    std::fs::write(root.join("v1.2/old.c"), "int b;\n").unwrap();
+    std::fs::write(root.join("v2.0/new.c"), "int c;\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["-r", "-l"])
+        .args(["--exclude-regex", r"/v[0-9]+\.[0-9]+$"])
+        .args(["--include-regex", r"/v2\.0$"])
+        .arg(root)
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("       2 <*>"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["--exclude-regex", "("])
+        .arg(root)
+        .output()
+        .expect("failed to run sourcelines");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_case_insensitive_glob() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::write(root.join("main.c"), "int a;\n").unwrap();
+    std::fs::write(root.join("OLD.C"), "int b;\n").unwrap();
+    let run = |extra: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-r", "-l", "--exclude=*.c"])
+            .args(extra)
+            .arg(root)
+            .output()
+            .expect("failed to run sourcelines");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    assert!(run(&[]).starts_with("       1 <*>"));
+    assert!(run(&["--iglob"]).starts_with("       0 <*>"));
+}
+
+#[test]
+fn test_git_tracked_files() {
+    let dir = init_repo();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("src")).unwrap();
+    std::fs::write(root.join(".gitignore"), "*.c\n").unwrap();
+    std::fs::write(root.join("src/main.c"), "int a;\n").unwrap();
+    std::fs::write(root.join("src/scratch.py"), "a = 1\nb = 2\n").unwrap();
+    git(root, &["add", "-f", "src/main.c"]);
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["-r", "-l", "--git"])
+        .arg(root)
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("       1 <*>"));
+
+    let not_a_repo = tempfile::tempdir().unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["--git"])
+        .arg(not_a_repo.path())
+        .output()
+        .expect("failed to run sourcelines");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_diff_revisions() {
+    let dir = init_repo();
+    let root = dir.path();
+    std::fs::write(root.join("main.c"), "int a;\n// old\nint b;\n").unwrap();
+    git(root, &["add", "."]);
+    git(root, &["commit", "-q", "-m", "first"]);
+    std::fs::write(root.join("main.c"), "int a;\n// new\nint c;\nint d;\n").unwrap();
+    std::fs::write(root.join("util.py"), "x = 1\n").unwrap();
+    git(root, &["add", "."]);
+    git(root, &["commit", "-q", "-m", "second"]);
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["diff", "HEAD~1", "HEAD"])
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("      +1       -0       ~1 <c>"));
+    assert!(stdout.contains("      +1       -0       ~0 <python>"));
+    assert!(stdout.contains("      +2       -0       ~1 <*> HEAD~1..HEAD"));
+
+    // Languages are detected from the content too, and names need not be UTF-8
+    git(root, &["mv", "main.c", "core.c"]);
+    git(root, &["rm", "-q", "util.py"]);
+    std::fs::write(root.join("tool"), "#!/usr/bin/env python3\ny = 2\n").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        std::fs::write(root.join(std::ffi::OsStr::from_bytes(b"caf\xe9.c")), "int z;\n").unwrap();
+    }
+    git(root, &["add", "."]);
+    git(root, &["commit", "-q", "-m", "third"]);
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["diff", "HEAD~1", "HEAD"])
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("      +1       -1       ~0 <python>"), "{}", stdout);
+    #[cfg(unix)]
+    assert!(stdout.contains("      +1       -0       ~0 <c>"), "{}", stdout);
+}
+
+#[test]
+fn test_history_series() {
+    let dir = init_repo();
+    let root = dir.path();
+    let commit = |args: &[&str], date: &str| {
+        let status = git_command(root)
+            .arg("commit")
+            .args(args)
+            .env("GIT_AUTHOR_DATE", date)
+            .env("GIT_COMMITTER_DATE", date)
+            .status()
+            .expect("failed to run git");
+        assert!(status.success());
+    };
+    std::fs::write(root.join("main.c"), "int a;\n").unwrap();
+    git(root, &["add", "."]);
+    commit(&["-q", "-m", "first"], "2024-01-01T12:00:00");
+    std::fs::write(root.join("main.c"), "int a;\nint b;\n").unwrap();
+    commit(&["-q", "-a", "-m", "second"], "2024-01-02T12:00:00");
+    std::fs::write(root.join("main.c"), "int a;\nint b;\nint c;\n").unwrap();
+    commit(&["-q", "-a", "-m", "third"], "2024-02-01T12:00:00");
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["history", "--interval", "month"])
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let rows: Vec<&str> = stdout.lines().collect();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[0], "date,commit,language,actual_loc");
+    assert!(rows[1].starts_with("2024-01-02,") && rows[1].ends_with(",c,2"));
+    assert!(rows[2].starts_with("2024-02-01,") && rows[2].ends_with(",c,3"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["history", "--since", "2024-01-15", "--format", "json"])
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("\"languages\": {\"c\": 3}, \"total\": 3}"));
+    assert!(!stdout.contains("2024-01-02"));
+}
+
+#[test]
+fn test_changed_in_range() {
+    let dir = init_repo();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("src")).unwrap();
+    std::fs::write(root.join("src/old.c"), "int a;\nint b;\n").unwrap();
+    std::fs::write(root.join("src/main.c"), "int c;\n").unwrap();
+    git(root, &["add", "."]);
+    git(root, &["commit", "-q", "-m", "first"]);
+    std::fs::write(root.join("src/main.c"), "int c;\nint d;\nint e;\n").unwrap();
+    git(root, &["commit", "-q", "-a", "-m", "second"]);
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["-r", "-l", "--changed-in", "HEAD~1..HEAD"])
+        .arg(root)
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("       3 <*>"));
+}
+
+#[test]
+fn test_remote_repository() {
+    let dir = init_repo();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("src")).unwrap();
+    std::fs::write(root.join("src/main.c"), "int a;\n").unwrap();
+    git(root, &["add", "."]);
+    git(root, &["commit", "-q", "-m", "first"]);
+    git(root, &["tag", "v1"]);
+    std::fs::write(root.join("src/main.c"), "int a;\nint b;\n").unwrap();
+    git(root, &["commit", "-q", "-a", "-m", "second"]);
+    let url = format!("file://{}", root.display());
+    let run = |extra: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-l"])
+            .args(extra)
+            .arg(&url)
+            .output()
+            .expect("failed to run sourcelines");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    assert_eq!(run(&[]), format!("       2 <*> {}\n", url));
+    assert_eq!(run(&["--ref", "v1"]), format!("       1 <*> {}\n", url));
+    // Clones are removed however the run ends, here when a second clone fails
+    let tmp = tempfile::tempdir().unwrap();
+    let missing = format!("file://{}", root.join("missing").display());
+    for args in [vec![url.as_str()], vec![url.as_str(), missing.as_str()]] {
+        Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(&args)
+            .env("TMPDIR", tmp.path())
+            .output()
+            .expect("failed to run sourcelines");
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 0);
+    }
+    // Arguments starting with "-" are never handed to git, where they would be options
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["--", "--bare@h:x"])
+        .output()
+        .expect("failed to run sourcelines");
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("clone"));
+}
+
+#[test]
+fn test_vendored_bucket() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("third_party/zlib")).unwrap();
+    std::fs::write(root.join("main.c"), "int a;\n").unwrap();
+    std::fs::write(root.join("third_party/zlib/zlib.c"), "int b;\nint c;\n").unwrap();
+    let run = |extra: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-rv", "-l"])
+            .args(extra)
+            .arg(root)
+            .output()
+            .expect("failed to run sourcelines");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    let stdout = run(&[]);
+    assert!(stdout.contains("       1 <c>"));
+    assert!(stdout.contains("       2 <vendored>"));
+    let stdout = run(&["--include-vendored"]);
+    assert!(stdout.contains("       3 <c>"));
+    assert!(!stdout.contains("<vendored>"));
+}
+
+#[test]
+fn test_submodules() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("lib/dep")).unwrap();
+    std::fs::write(root.join("main.c"), "int a;\n").unwrap();
+    std::fs::write(root.join("lib/dep/.git"), "gitdir: ../../.git/modules/dep\n").unwrap();
+    std::fs::write(root.join("lib/dep/dep.c"), "int b;\nint c;\n").unwrap();
+    let run = |extra: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-rv", "-l"])
+            .args(extra)
+            .arg(root)
+            .output()
+            .expect("failed to run sourcelines");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    let stdout = run(&[]);
+    assert!(stdout.starts_with("       3 <*>"));
+    assert!(stdout.contains("       2 <submodule>"));
+    assert!(stdout.contains("       1 <c>"));
+    let stdout = run(&["--submodules", "include"]);
+    assert!(stdout.contains("       3 <c>"));
+    assert!(run(&["--submodules", "skip"]).starts_with("       1 <*>"));
+}
+
+#[test]
+fn test_split_tests() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("src")).unwrap();
+    std::fs::create_dir_all(root.join("tests")).unwrap();
+    std::fs::write(root.join("src/lib.go"), "package lib\n\nvar x = 1\n").unwrap();
+    std::fs::write(root.join("src/lib_test.go"), "package lib\n").unwrap();
+    std::fs::write(root.join("tests/it.rs"), "fn a() {}\nfn b() {}\n").unwrap();
+    std::fs::write(root.join("src/gen.c"), "int a;\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["-r", "-l", "--split-tests", "--test-pattern", "gen.*"])
+        .arg(root)
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("       4 <test> (sum)"));
+    assert!(stdout.contains("       2 <production> (sum)"));
+}
+
+#[test]
+fn test_files_from_stdin() {
+    use std::io::Write;
+    use std::process::Stdio;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["-l", "-0", "--files-from", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to run sourcelines");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"tests/testdata/simple.c\0tests/testdata/simple.sh\0")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("simple.c"));
+    assert!(stdout.contains("simple.sh"));
+    assert_eq!(stdout.lines().count(), 2);
+}
+
+// macOS does not allow names that are not UTF-8
+#[cfg(target_os = "linux")]
+#[test]
+fn test_files_from_non_utf8_names() {
+    use std::os::unix::ffi::OsStrExt;
+    let dir = tempfile::tempdir().unwrap();
+    let name = std::ffi::OsStr::from_bytes(b"caf\xe9.c");
+    std::fs::write(dir.path().join(name), "int a;\nint b;\n").unwrap();
+    let list = dir.path().join("list");
+    std::fs::write(&list, [name.as_bytes(), b"\0"].concat()).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["-l", "-0", "--files-from"])
+        .arg(&list)
+        .current_dir(dir.path())
+        .output()
+        .expect("failed to run sourcelines");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "       2 <c> caf\u{fffd}.c\n");
+}
+
+#[test]
+fn test_binary_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::write(root.join("main.c"), "int a;\n").unwrap();
+    std::fs::write(root.join("blob.bin"), [0u8, 1, 2, 3, 0, 5]).unwrap();
+    std::fs::write(root.join("data.csv"), "a,b\n1,2\n").unwrap();
+    let run = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-r", "-b"])
+            .args(extra)
+            .arg(root)
+            .output()
+            .expect("failed to run sourcelines")
+    };
+    let output = run(&[]);
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("      15 <*>"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("skipped 1 binary file"));
+
+    let output = run(&["--count-binary", "--binary-extensions", "csv"]);
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("      13 <*>"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("skipped 1 binary file"));
+}
+
+#[test]
+fn test_baseline_compare() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    let src = root.join("src");
+    std::fs::create_dir_all(&src).unwrap();
+    std::fs::write(src.join("main.c"), "int a;\nint b;\n").unwrap();
+    std::fs::write(src.join("util.py"), "x = 1\n").unwrap();
+    let baseline = root.join("baseline.tsv");
+    let run = |flag: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-r", "-s", "-l", flag])
+            .arg(&baseline)
+            .arg(&src)
+            .output()
+            .expect("failed to run sourcelines");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    run("--save-baseline");
+    std::fs::write(src.join("main.c"), "int a;\nint b;\nint c;\n").unwrap();
+    std::fs::remove_file(src.join("util.py")).unwrap();
+    std::fs::write(src.join("run.sh"), "echo hi\n").unwrap();
+    let stdout = run("--compare-baseline");
+    assert!(stdout.contains("       2        3       +1 <c>"));
+    assert!(stdout.contains("       1        0       -1 <python>"));
+    assert!(stdout.contains("       0        1       +1 <shell>"));
+    assert!(stdout.contains("       3        4       +1 <*> (since "));
+}
+
+#[test]
+fn test_fail_if_gates() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::write(root.join("main.c"), "int a;\nint b;\n").unwrap();
+    let baseline = root.join("baseline.tsv");
+    let run = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-s"])
+            .args(extra)
+            .arg(root.join("main.c"))
+            .output()
+            .expect("failed to run sourcelines")
+    };
+    assert_eq!(run(&["--fail-if", "total.actual_loc > 2"]).status.code(), Some(0));
+    let output = run(&["--fail-if", "total.actual_loc > 1"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("gate failed: total.actual_loc > 1 (value 2)"));
+
+    run(&["--save-baseline", baseline.to_str().unwrap()]);
+    std::fs::write(root.join("main.c"), "int a;\nint b;\nint c;\n").unwrap();
+    let delta = ["--compare-baseline", baseline.to_str().unwrap(), "--fail-if"];
+    let output = run(&[&delta[..], &["lang.c.actual_loc delta >= 1"]].concat());
+    assert_eq!(output.status.code(), Some(3));
+    let output = run(&[&delta[..], &["lang.c.actual_loc delta > 1"]].concat());
+    assert_eq!(output.status.code(), Some(0));
+
+    assert_eq!(run(&["--fail-if", "total.lines > 1"]).status.code(), Some(2));
+    assert_eq!(run(&["--fail-if", "total.bytes delta > 1"]).status.code(), Some(2));
+}
+
+#[test]
+fn test_churn() {
+    let dir = init_repo();
+    let root = dir.path();
+    std::fs::write(root.join("main.c"), "int a;\n").unwrap();
+    std::fs::write(root.join("util.py"), "x = 1\ny = 2\n").unwrap();
+    git(root, &["add", "."]);
+    git(root, &["commit", "-q", "-m", "first"]);
+    std::fs::write(root.join("main.c"), "int b;\nint c;\n").unwrap();
+    git(root, &["commit", "-q", "-a", "-m", "second"]);
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["-s", "--churn", "--since", "1y"])
+        .arg(root)
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let rows: Vec<&str> = stdout.lines().skip(1).collect();
+    assert_eq!(rows.len(), 4);
+    assert!(rows[0].starts_with("       2       +3       -1        2 <c> "));
+    assert!(rows[0].ends_with("main.c"));
+    assert!(rows[1].starts_with("       1       +2       -0        2 <python> "));
+    assert_eq!(rows[2], "       2       +3       -1        2 <c> (churn)");
+    assert_eq!(rows[3], "       1       +2       -0        2 <python> (churn)");
+}
+
+#[test]
+fn test_pre_commit_hook() {
+    let dir = init_repo();
+    let root = dir.path();
+    let hook = || {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["hook", "pre-commit"])
+            .current_dir(root)
+            .output()
+            .expect("failed to run sourcelines");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    std::fs::write(root.join("main.c"), "int a;\nint b;\n").unwrap();
+    git(root, &["add", "."]);
+    assert_eq!(hook(), "sourcelines: +2 actual LOC (c +2)\n");
+    git(root, &["commit", "-q", "-m", "first"]);
+    std::fs::write(root.join("main.c"), "int a;\nint c;\nint d;\n").unwrap();
+    std::fs::write(root.join("util.py"), "x = 1\n").unwrap();
+    git(root, &["add", "main.c"]);
+    assert_eq!(hook(), "sourcelines: +1 ~1 actual LOC (c +1 ~1)\n");
+}
+
+#[test]
+fn test_staged() {
+    let dir = init_repo();
+    let root = dir.path();
+    let staged = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .arg("--staged")
+            .args(args)
+            .current_dir(root)
+            .output()
+            .expect("failed to run sourcelines")
+    };
+    std::fs::write(root.join("main.c"), "int a;\n").unwrap();
+    git(root, &["add", "."]);
+    git(root, &["commit", "-q", "-m", "first"]);
+    std::fs::write(root.join("main.c"), "int a;\nint b;\n// c\n").unwrap();
+    std::fs::write(root.join("util.py"), "x = 1\n").unwrap();
+    git(root, &["add", "main.c", "util.py"]);
+    // The index is counted, not the files on disk
+    std::fs::write(root.join("main.c"), "").unwrap();
+    let output = staged(&[]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "sourcelines: staged 2 files, 3 actual LOC (c 2, python 1)\n");
+    let output = staged(&["--fail-if", "file.actual_loc > 1"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("gate failed: file.actual_loc > 1 in main.c (value 2)"));
+    let output = staged(&["util.py"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "sourcelines: staged 1 file, 1 actual LOC (python 1)\n");
+}
+
+#[test]
+fn test_serve() {
+    use std::io::{BufRead, Read, Write};
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir(root.join("src")).unwrap();
+    std::fs::write(root.join("src/main.c"), "int a;\n// b\nint c;\n").unwrap();
+    std::fs::write(root.join("src/util.py"), "x = 1\n").unwrap();
+    let status = Command::new("tar").args(["czf", "src.tgz", "src"]).current_dir(root).status().unwrap();
+    assert!(status.success());
+    let mut server = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["serve", "--listen", "127.0.0.1:0"])
+        .current_dir(root)
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .expect("failed to run sourcelines");
+    let mut line = String::new();
+    std::io::BufReader::new(server.stderr.take().unwrap()).read_line(&mut line).unwrap();
+    let addr = line.trim().strip_prefix("sourcelines: listening on http://").unwrap().to_string();
+    let request = |method: &str, url: &str, body: &[u8]| {
+        let mut stream = std::net::TcpStream::connect(&addr).unwrap();
+        let head = format!("{} {} HTTP/1.1\r\nHost: x\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            method, url, body.len());
+        stream.write_all(head.as_bytes()).unwrap();
+        stream.write_all(body).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status: u16 = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str::<serde_json::Value>(body).unwrap())
+    };
+    let (status, report) = request("POST", "/count", br#"{"path": "src"}"#);
+    assert_eq!(status, 200);
+    assert_eq!(report["path"], "src");
+    assert_eq!(report["total"]["actual_loc"], 3);
+    assert_eq!(report["languages"]["c"]["comments"], 1);
+    assert_eq!(report["languages"]["python"]["files"], 1);
+    let archive = std::fs::read(root.join("src.tgz")).unwrap();
+    for _ in 0..2 {
+        let (status, report) = request("POST", "/count", &archive);
+        assert_eq!(status, 200);
+        assert_eq!(report["total"]["actual_loc"], 3);
+        assert_eq!(report["total"]["files"], 2);
+    }
+    // Files of archives larger than the server holds in memory are skipped, unread
+    let mut builder = tar::Builder::new(Vec::new());
+    for (name, size) in [("big.c", 65 << 20), ("small.c", 7)] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(size as u64);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, name, &vec![b'x'; size][..]).unwrap();
+    }
+    let (status, report) = request("POST", "/count", &builder.into_inner().unwrap());
+    assert_eq!(status, 200);
+    assert_eq!(report["total"]["files"], 1);
+    assert_eq!(report["skipped"]["size"], 1);
+    let (status, report) = request("POST", "/count", br#"{"path": "missing"}"#);
+    assert_eq!(status, 403);
+    assert_eq!(report["error"], "missing is not a path below the served roots");
+    let (status, report) = request("POST", "/count", br#"{"path": "/etc"}"#);
+    assert_eq!(status, 403);
+    assert_eq!(report["error"], "/etc is not a path below the served roots");
+    let (status, report) = request("POST", "/count", br#"{"path": "src/../.."}"#);
+    assert_eq!(status, 403);
+    assert_eq!(report["error"], "src/../.. is not a path below the served roots");
+    assert_eq!(request("GET", "/count", b"").0, 405);
+    assert_eq!(request("POST", "/other", b"").0, 404);
+    server.kill().unwrap();
+    server.wait().unwrap();
+}
+
+#[test]
+fn test_daemon() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir(root.join("a")).unwrap();
+    std::fs::create_dir(root.join("b")).unwrap();
+    std::fs::write(root.join("a/main.c"), "int a;\n// b\nint c;\n").unwrap();
+    std::fs::write(root.join("b/util.py"), "x = 1\n").unwrap();
+    let daemon = |sink: &str| {
+        Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["daemon", "--every", "10ms", "--paths", "a,b", "--snapshots", "2", "--sink", sink])
+            .current_dir(root)
+            .output()
+            .expect("failed to run sourcelines")
+    };
+    let output = daemon("jsonl:snapshots.jsonl");
+    assert!(output.status.success());
+    let lines = std::fs::read_to_string(root.join("snapshots.jsonl")).unwrap();
+    let snapshots: Vec<serde_json::Value> = lines.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(snapshots.len(), 4);
+    assert_eq!(snapshots[0]["path"], "a");
+    assert_eq!(snapshots[0]["total"]["actual_loc"], 2);
+    assert_eq!(snapshots[1]["languages"]["python"]["files"], 1);
+    assert_eq!(snapshots[2]["path"], "a");
+    assert!(!daemon("csv:snapshots.csv").status.success());
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["daemon", "--every", "999999999999999999d", "--paths", "a", "--sink", "jsonl:x.jsonl"])
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid interval '999999999999999999d'"));
+    // The sqlite sink needs the sqlite3 program
+    if Command::new("sqlite3").arg("-version").output().is_ok_and(|output| output.status.success()) {
+        assert!(daemon("sqlite:snapshots.db").status.success());
+        let output = Command::new("sqlite3")
+            .args(["snapshots.db", "SELECT path, language, actual_loc FROM snapshots"])
+            .current_dir(root)
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "a|c|2\nb|python|1\na|c|2\nb|python|1\n");
+        // A file name starting with "-" is not an option of sqlite3
+        assert!(daemon("sqlite:-snapshots.db").status.success());
+        assert!(root.join("-snapshots.db").is_file());
+        // sqlite3's own message is reported, not the pipe it stopped reading
+        std::fs::write(root.join("broken.db"), b"not a database, but long enough to look like one....").unwrap();
+        let output = daemon("sqlite:broken.db");
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("file is not a database"));
+    }
+}
+
+#[test]
+fn test_stdin() {
+    use std::io::Write;
+    let run = |args: &[&str], input: &str| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(args)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .expect("failed to run sourcelines");
+        child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+        child.wait_with_output().unwrap()
+    };
+    let rust = "fn main() {\n    // hello\n    let a = 1;\n}\n";
+    let output = run(&["-l", "-", "--stdin-name", "main.rs"], rust);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "       3 <rust> main.rs\n");
+    let output = run(&["-l", "-"], "#!/usr/bin/env python\nx = 1\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "       1 <python> -\n");
+    let output = run(&["-l", "-", "--stdin-lang", "C"], "int a; // b\n\nint c;\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "       2 <c> -\n");
+    let output = run(&["-l", "-", "--stdin-name", "main.rs", "--include-lang", "python"], rust);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    let output = run(&["-", "--stdin-name", "main.rs", "--fail-if", "line.chars > 12"], rust);
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("gate failed: line.chars > 12 at main.rs:3 (value 14)"));
+    assert!(!stderr.contains("main.rs:2"));
+}
+
+#[test]
+fn test_tarballs() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("pkg/src")).unwrap();
+    std::fs::write(root.join("pkg/src/main.c"), "int a;\n// b\nint c;\n").unwrap();
+    std::fs::write(root.join("pkg/setup.py"), "x = 1\n").unwrap();
+    let tar = |args: &[&str]| Command::new("tar").args(args).current_dir(root).status().is_ok_and(|s| s.success());
+    assert!(tar(&["cf", "pkg.tar", "pkg"]));
+    assert!(tar(&["czf", "pkg.tgz", "pkg"]));
+    let mut archives = vec!["pkg.tar", "pkg.tgz"];
+    // Not every tar can compress with xz and zstd
+    if tar(&["cJf", "pkg.tar.xz", "pkg"]) {
+        archives.push("pkg.tar.xz");
+    }
+    if tar(&["--zstd", "-cf", "pkg.tar.zst", "pkg"]) {
+        archives.push("pkg.tar.zst");
+    }
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["-l", "-v"])
+        .args(&archives)
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for archive in &archives {
+        assert!(stdout.contains(&format!("       3 <*> {}\n       2 <c>\n       1 <python>\n", archive)));
+    }
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["--exclude=*.py", "--fail-if", "file.actual_loc > 1", "pkg.tgz"])
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("       2        3 "));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("gate failed: file.actual_loc > 1 in pkg.tgz/pkg/src/main.c (value 2)"));
+    // Files over --max-file-size are skipped without being read
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["-l", "-v", "--max-file-size", "10", "pkg.tgz"])
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "       1 <*> pkg.tgz\n       1 <python>\n");
+    std::fs::write(root.join("broken.tar.gz"), "not an archive").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .arg("broken.tar.gz")
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_crate_from_cargo_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("foo-1.0.0/src")).unwrap();
+    std::fs::create_dir_all(root.join("foo-1.0.0/vendor")).unwrap();
+    std::fs::write(root.join("foo-1.0.0/src/lib.rs"), "// foo\npub fn foo() {}\n").unwrap();
+    std::fs::write(root.join("foo-1.0.0/vendor/bar.c"), "int bar;\n").unwrap();
+    let cache = root.join("cargo/registry/cache/index.crates.io-0000000000000000");
+    std::fs::create_dir_all(&cache).unwrap();
+    let status = Command::new("tar")
+        .args(["czf"])
+        .arg(cache.join("foo-1.0.0.crate"))
+        .arg("foo-1.0.0")
+        .current_dir(root)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["-l", "crate", "foo@1.0.0"])
+        .env("CARGO_HOME", root.join("cargo"))
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    assert!(output.status.success());
+    // Vendored code is counted apart, as in directories
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "       2 <*> foo@1.0.0\n       1 <rust>\n       1 <vendored>\n"
+    );
+    // The caches of other registries are not taken for crates.io
+    let other = root.join("cargo/registry/cache/example.com-0000000000000000");
+    std::fs::create_dir_all(&other).unwrap();
+    std::fs::rename(cache.join("foo-1.0.0.crate"), other.join("sourcelines-no-such-crate-1.0.0.crate")).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["crate", "sourcelines-no-such-crate@1.0.0"])
+        .env("CARGO_HOME", root.join("cargo"))
+        .output()
+        .expect("failed to run sourcelines");
+    assert_eq!(output.status.code(), Some(2));
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["crate", "foo@1.0.0/x"])
+        .output()
+        .expect("failed to run sourcelines");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_non_ascii_and_invalid_utf8() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("notes.txt");
+    std::fs::write(&file, b"h\xc3\xa9llo w\xc3\xb6rld\n\xff\xfe abc\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["-R", "-w", "-c", "-b"])
+        .arg(&file)
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("       2        4       19       21 <text>"));
+}
+
+#[test]
+fn test_mmap_matches_buffered_reading() {
+    let run = |extra: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-rv"])
+            .args(extra)
+            .arg("tests/testdata")
+            .output()
+            .expect("failed to run sourcelines");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    assert_eq!(run(&["--mmap"]), run(&[]));
+}
+
+#[test]
+fn test_cache_reuses_unchanged_counts() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    std::fs::create_dir(&src).unwrap();
+    std::fs::write(src.join("a.c"), "int a;\n// note\nint b;\n").unwrap();
+    std::fs::write(src.join("b.py"), "x = 1\n").unwrap();
+    let cache = dir.path().join("counts.cache");
+
+    let run = || {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .arg("-rv")
+            .arg(format!("--cache={}", cache.display()))
+            .arg(&src)
+            .output()
+            .expect("failed to run sourcelines");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    let first = run();
+    assert!(cache.exists());
+    assert_eq!(run(), first);
+
+    // A changed file is counted again
+    std::fs::write(src.join("a.c"), "int a;\nint b;\nint c;\nint d;\n").unwrap();
+    let third = run();
+    assert_ne!(third, first);
+    assert!(third.contains("       4        4        8       28       28 <c>"), "{}", third);
+}
+
+#[test]
+fn test_no_progress_when_piped() {
+    let run = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .arg("-r")
+            .args(extra)
+            .arg("tests/testdata")
+            .output()
+            .expect("failed to run sourcelines")
+    };
+    let output = run(&[]);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains('\r'));
+    assert_eq!(output.stdout, run(&["--no-progress"]).stdout);
+}
+
+#[test]
+fn test_long_lines_counted_in_pieces() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("bundle.c");
+    std::fs::write(
+        &file,
+        "/* start\nstill comment */\nint   ünï  cödé = 1; // ééé words here\n  \n// c\nchar *s = \"αβγ δεζ\";",
+    )
+    .unwrap();
+    let run = |extra: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(extra)
+            .arg(&file)
+            .output()
+            .expect("failed to run sourcelines");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    let whole = run(&[]);
+    assert!(whole.starts_with("       2        6       21       93      106"), "{}", whole);
+    assert_eq!(run(&["--line-cap=5"]), whole);
+    assert_eq!(run(&["--line-cap=5", "--mmap"]), whole);
+
+    // Comment markers cut by a piece, or far from either end of the line
+    let long = format!("int a;\n   /* c */\n{}// note\n/* {} */\nint b;\n", " ".repeat(300), "x".repeat(300));
+    std::fs::write(&file, long).unwrap();
+    let whole = run(&[]);
+    assert!(whole.starts_with("       2        5"), "{}", whole);
+    for cap in 1..=6 {
+        assert_eq!(run(&[&format!("--line-cap={}", cap)]), whole);
+        assert_eq!(run(&[&format!("--line-cap={}", cap), "--mmap"]), whole);
+    }
+    assert!(run(&["--line-cap=0"]).is_empty());
+}
+
+#[test]
+fn test_parallel_walk_matches_sequential() {
+    let run = |threads: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-rv", "-j", threads])
+            .args(["tests/testdata", "src"])
+            .output()
+            .expect("failed to run sourcelines");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    assert_eq!(run("4"), run("1"));
+}
+
+#[test]
+fn test_generate_test_tree_is_reproducible() {
+    let dir = tempfile::tempdir().unwrap();
+    let generate = |name: &str| {
+        let status = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["generate-test-tree", "--files", "30", "--size", "1K", "--seed", "7"])
+            .arg(dir.path().join(name))
+            .status()
+            .expect("failed to run sourcelines");
+        assert!(status.success());
+        std::fs::read_to_string(dir.path().join(name).join("d0/d0/f12.c")).unwrap()
+    };
+    let first = generate("a");
+    assert!(!first.is_empty());
+    assert_eq!(generate("b"), first);
+}
+
+#[test]
+fn test_stream_matches_buffered_output() {
+    let run = |extra: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-rvs"])
+            .args(extra)
+            .args(["tests/testdata", "src/main.rs", "src/lib.rs"])
+            .output()
+            .expect("failed to run sourcelines");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    let buffered = run(&[]);
+    assert!(buffered.contains("src/lib.rs"));
+    assert_eq!(run(&["--stream"]), buffered);
+}
+
+#[test]
+fn test_unreadable_files_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.c"), "int a;\n").unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(dir.path().join("gone.c"), dir.path().join("broken.c")).unwrap();
+    let run = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(extra)
+            .arg(dir.path())
+            .arg(dir.path().join("missing.c"))
+            .output()
+            .expect("failed to run sourcelines")
+    };
+
+    let output = run(&["-rL"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let expected = if cfg!(unix) { "could not read 2 files or directories" } else { "could not read 1 file" };
+    assert!(stderr.contains(expected), "{}", stderr);
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("       1 "));
+
+    let output = run(&["-rLv"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("missing.c ("), "{}", stderr);
+
+    assert_eq!(run(&["-rL", "--strict"]).status.code(), Some(1));
+    // A failed gate takes precedence, usage errors are 2
+    assert_eq!(run(&["-rL", "--fail-if", "total.actual_loc > 0"]).status.code(), Some(3));
+    assert_eq!(run(&["--no-such-option"]).status.code(), Some(2));
+}
+
+#[test]
+fn test_by_dir_rollup() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("core/deep")).unwrap();
+    std::fs::create_dir_all(root.join("ui")).unwrap();
+    std::fs::write(root.join("core/a.c"), "int a;\nint b;\n").unwrap();
+    std::fs::write(root.join("core/deep/b.c"), "int c;\n").unwrap();
+    std::fs::write(root.join("ui/c.py"), "x = 1\n").unwrap();
+    let run = |by_dir: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-r", by_dir, "-l"])
+            .arg(root)
+            .output()
+            .expect("failed to run sourcelines");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    let core = root.join("core").display().to_string();
+    let ui = root.join("ui").display().to_string();
+    let deep = root.join("core/deep").display().to_string();
+
+    let lines: Vec<String> = run("--by-dir").lines().map(|l| l.to_string()).collect();
+    assert_eq!(lines.len(), 3, "{:?}", lines);
+    assert!(lines[0].starts_with("       4 <*> "));
+    assert_eq!(lines[1], format!("       3 <*>   {}", core));
+    assert_eq!(lines[2], format!("       1 <*>   {}", ui));
+
+    let lines: Vec<String> = run("--by-dir=2").lines().map(|l| l.to_string()).collect();
+    assert_eq!(lines[2], format!("       1 <*>     {}", deep));
+}
+
+#[test]
+fn test_sort_rows() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::write(root.join("a.c"), "int a;\n").unwrap();
+    std::fs::write(root.join("b.py"), "x = 1\ny = 2\nz = 3\n").unwrap();
+    std::fs::write(root.join("c.sh"), "echo 1\necho 2\n").unwrap();
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(args)
+            .current_dir(root)
+            .output()
+            .expect("failed to run sourcelines");
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        (output.status.success(), stdout)
+    };
+    let column = |stdout: &str| -> Vec<String> {
+        stdout
+            .lines()
+            .map(|l| l.split_whitespace().last().unwrap().to_string())
+            .collect()
+    };
+
+    let (_, stdout) = run(&["-l", "--sort", "loc", "a.c", "b.py", "c.sh"]);
+    assert_eq!(column(&stdout), ["b.py", "c.sh", "a.c"]);
+    let (_, stdout) = run(&["-l", "--sort=name:desc", "a.c", "b.py", "c.sh"]);
+    assert_eq!(column(&stdout), ["c.sh", "b.py", "a.c"]);
+    let (_, stdout) = run(&["-l", "a.c", "b.py", "c.sh"]);
+    assert_eq!(column(&stdout), ["a.c", "b.py", "c.sh"]);
+
+    // Language rows of a directory
+    let (_, stdout) = run(&["-rv", "-l", "--sort=loc:asc", "."]);
+    assert_eq!(column(&stdout), [".", "<c>", "<shell>", "<python>"]);
+
+    let (ok, _) = run(&["--sort=size", "a.c"]);
+    assert!(!ok);
+}
+
+#[test]
+fn test_percent_column() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::write(root.join("a.c"), "int a;\nint b;\nint c;\n").unwrap();
+    std::fs::write(root.join("b.py"), "x = 1\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["-lvs", "--percent", "a.c", "b.py"])
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0], "       3    75.0% <c> a.c");
+    assert_eq!(lines[1], "       1    25.0% <python> b.py");
+    assert!(lines[2].ends_with("   100.0% <*> (sum)"), "{}", lines[2]);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["--markdown", "--percent", "."])
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(" | Share |"), "{}", stdout);
+    assert!(stdout.contains(" | 75.0% |"), "{}", stdout);
+}
+
+#[test]
+fn test_by_lang_totals() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir(root.join("lib")).unwrap();
+    std::fs::write(root.join("lib/a.c"), "int a;\nint b;\n").unwrap();
+    std::fs::write(root.join("lib/b.py"), "x = 1\n").unwrap();
+    std::fs::write(root.join("c.c"), "int c;\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["-rl", "--by-lang", "lib", "c.c"])
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines, ["       3 <c>", "       1 <python>", "       4 <*> (sum)"]);
+}
+
+#[test]
+fn test_chart_bars() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::write(root.join("a.c"), "int a;\nint b;\nint c;\nint d;\n").unwrap();
+    std::fs::write(root.join("b.py"), "x = 1\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["-l", "--by-lang", "--chart", "a.c", "b.py"])
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0], format!("       4 {} <c>", "█".repeat(20)));
+    assert_eq!(lines[1], format!("       1 {}{} <python>", "█".repeat(5), " ".repeat(15)));
+    assert_eq!(lines[2], "       5 <*> (sum)");
+}
+
+#[test]
+fn test_color_modes() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::write(root.join("a.c"), "int a;\n").unwrap();
+    let run = |args: &[&str], env: &[(&str, &str)]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(args)
+            .env_remove("NO_COLOR")
+            .env_remove("CLICOLOR_FORCE")
+            .envs(env.iter().copied())
+            .current_dir(root)
+            .output()
+            .expect("failed to run sourcelines");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    let colored = "\u{1b}[36m       1\u{1b}[0m \u{1b}[32m<c>\u{1b}[0m a.c\n";
+
+    // Output to a pipe is plain unless color is forced
+    assert_eq!(run(&["-l", "a.c"], &[]), "       1 <c> a.c\n");
+    assert_eq!(run(&["-l", "-C", "a.c"], &[]), colored);
+    assert_eq!(run(&["-l", "--color=always", "a.c"], &[("NO_COLOR", "1")]), colored);
+    assert_eq!(run(&["-l", "a.c"], &[("CLICOLOR_FORCE", "1")]), colored);
+    assert_eq!(run(&["-l", "--color=never", "a.c"], &[("CLICOLOR_FORCE", "1")]), "       1 <c> a.c\n");
+
+    // Language rows are faint as a whole, and only when colored
+    assert_eq!(run(&["-lv", "."], &[]).lines().nth(1), Some("       1 <c>"));
+    assert_eq!(run(&["-lvC", "."], &[]).lines().nth(1), Some("\u{1b}[2m       1 <c>\u{1b}[0m"));
+}
+
+#[test]
+fn test_column_width() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::write(root.join("a.c"), "int a;\n".repeat(12)).unwrap();
+    std::fs::write(root.join("b.py"), "x = 1\n").unwrap();
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(args)
+            .current_dir(root)
+            .output()
+            .expect("failed to run sourcelines");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    // Columns grow from --width to fit the total
+    let stdout = run(&["-lvs", "--width", "1", "a.c", "b.py"]);
+    assert_eq!(stdout, "12 <c> a.c\n 1 <python> b.py\n13 <*> (sum)\n");
+    let stdout = run(&["-lvs", "--width", "4", "a.c", "b.py"]);
+    assert_eq!(stdout, "  12 <c> a.c\n   1 <python> b.py\n  13 <*> (sum)\n");
+}
+
+#[test]
+fn test_tui_needs_terminal() {
+    let dir = tempfile::tempdir().unwrap();
+    // Without the tui feature, or with stdout not a terminal, the browser refuses to start
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["tui", "."])
+        .current_dir(dir.path())
+        .output()
+        .expect("failed to run sourcelines");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Error: "));
+}
+
+#[test]
+fn test_watch_recounts() {
+    use std::io::BufRead;
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir(root.join("src")).unwrap();
+    std::fs::create_dir(root.join("target")).unwrap();
+    std::fs::write(root.join("src/a.c"), "int a;\n").unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["-rl", "--watch", "."])
+        .current_dir(root)
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .expect("failed to run sourcelines");
+    let (tx, rx) = std::sync::mpsc::channel();
+    let stdout = child.stdout.take().unwrap();
+    std::thread::spawn(move || {
+        for line in std::io::BufReader::new(stdout).lines().map_while(Result::ok) {
+            let _ = tx.send(line);
+        }
+    });
+    let timeout = std::time::Duration::from_secs(10);
+    let next_row = || loop {
+        let line = rx.recv_timeout(timeout).expect("no output from --watch");
+        if !line.is_empty() {
+            break line;
+        }
+    };
+    assert_eq!(next_row(), "       1 <*> .");
+
+    // Excluded directories are not watched for changes
+    std::fs::write(root.join("target/b.c"), "int b;\n").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    std::fs::write(root.join("src/a.c"), "int a;\nint b;\n").unwrap();
+    assert_eq!(next_row(), "       2 <*> .");
+    std::fs::write(root.join("src/c.c"), "int c;\n").unwrap();
+    assert_eq!(next_row(), "       3 <*> .");
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn test_compare_trees() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    for tree in ["a/src", "b/src"] {
+        std::fs::create_dir_all(root.join(tree)).unwrap();
+    }
+    std::fs::write(root.join("a/src/x.c"), "int a;\n").unwrap();
+    std::fs::write(root.join("b/src/x.c"), "int a;\nint b;\n").unwrap();
+    std::fs::write(root.join("a/old.py"), "x = 1\n").unwrap();
+    std::fs::write(root.join("b/new.sh"), "echo\n").unwrap();
+    std::fs::write(root.join("a/same.c"), "int c;\n").unwrap();
+    std::fs::write(root.join("b/same.c"), "int c;\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["--dedupe", "compare", "a", "b"])
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "       0        1       +1 <shell> new.sh",
+            "       1        0       -1 <python> old.py",
+            "       1        2       +1 <c> src/x.c",
+            "       2        3       +1 <c>",
+            "       1        0       -1 <python>",
+            "       0        1       +1 <shell>",
+            "       3        4       +1 <*> a..b",
+        ]
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["compare", "a", "missing"])
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_languages_list() {
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["--remap", "c=cpp", "languages"])
+        .output()
+        .expect("failed to run sourcelines");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let row = |lang: &str| {
+        stdout
+            .lines()
+            .find(|l| l.split_whitespace().next() == Some(lang))
+            .map(|l| l.split_whitespace().collect::<Vec<_>>().join(" "))
+            .unwrap_or_default()
+    };
+    assert_eq!(row("rust"), "rust .rs - // /* */");
+    assert_eq!(row("shell"), "shell .sh .bash .zsh .env bash sh zsh #");
+    assert_eq!(row("ruby"), "ruby - ruby (inferred from content)");
+    assert_eq!(row("c"), "c .c .h - // /* */, counted as cpp");
+}
+
+#[test]
+fn test_explain_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("main.c");
+    std::fs::write(&file, "// entry point\n\nint main() { return 0; }\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .arg("--explain")
+        .arg(&file)
+        .args(["--exclude", "*.c"])
+        .output()
+        .expect("failed to run sourcelines");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(lines.contains(&"  extension:  .c -> c"));
+    assert!(lines.contains(&"  language:   c"));
+    assert!(lines.contains(&"  excludes:   '*.c' match, skipped when found in a directory"));
+    assert!(lines.contains(&"  comments:   // /* */ (built in)"));
+    assert!(lines.contains(&"      1 comment | // entry point"));
+    assert!(lines.contains(&"      2 blank   | "));
+    assert!(lines.contains(&"      3 code    | int main() { return 0; }"));
+    assert!(lines.contains(&"  1 actual LOC, 3 raw LOC, 9 words, 41 chars, 41 bytes"));
+}
+
+#[test]
+fn test_list_only() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("src")).unwrap();
+    std::fs::write(dir.path().join("src/main.rs"), "fn main() {}\n").unwrap();
+    std::fs::write(dir.path().join("setup.py"), "print(1)\n").unwrap();
+    std::fs::write(dir.path().join("notes.txt"), "skip me\n").unwrap();
+    std::fs::write(dir.path().join("image.dat"), b"\x00\x01\x02").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["--list-only", "-r"])
+        .arg(dir.path())
+        .args(["--exclude", "*.txt"])
+        .output()
+        .expect("failed to run sourcelines");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let listed: Vec<String> = stdout.lines().map(|l| l.split_whitespace().collect::<Vec<_>>().join(" ")).collect();
+    let root = dir.path().display();
+    assert_eq!(listed, [format!("python {}/setup.py", root), format!("rust {}/src/main.rs", root)]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("sourcelines: 2 files would be counted"));
+    assert!(stderr.contains("sourcelines: skipped 1 binary file"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_skipped_summary() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::write(root.join("main.c"), "int a;\n").unwrap();
+    std::fs::write(root.join("notes.tmp"), "scratch\n").unwrap();
+    std::fs::write(root.join("dump.sql"), "x".repeat(2000)).unwrap();
+    std::fs::write(root.join("logo.dat"), b"\x00\x01").unwrap();
+    std::os::unix::fs::symlink("main.c", root.join("link.c")).unwrap();
+    let run = |verbose: bool| {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_sourcelines"));
+        cmd.args(["-r", "-l", "--max-file-size", "1000"]).arg(root);
+        if verbose {
+            cmd.arg("-v");
+        }
+        let output = cmd.output().expect("failed to run sourcelines");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+    let stderr = run(false);
+    assert!(stderr.contains("sourcelines: skipped 1 binary file"), "{}", stderr);
+    assert!(
+        stderr.contains("sourcelines: skipped 1 excluded entry, 1 file outside the size limits, 1 symlink"),
+        "{}",
+        stderr
+    );
+    let stderr = run(true);
+    assert!(stderr.contains("notes.tmp (excluded)"), "{}", stderr);
+    assert!(stderr.contains("dump.sql (2000 bytes, over --max-file-size)"), "{}", stderr);
+    assert!(stderr.contains("link.c (symlink, use -L to follow)"), "{}", stderr);
+}
+
+#[test]
+fn test_quiet_and_log_level() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::write(root.join("main.c"), "int a;\n").unwrap();
+    std::fs::write(root.join("notes.tmp"), "scratch\n").unwrap();
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-r", "-l"])
+            .args(args)
+            .arg(root)
+            .output()
+            .expect("failed to run sourcelines");
+        assert!(output.status.success());
+        (
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+    };
+    let (stdout, stderr) = run(&[]);
+    assert!(stderr.contains("sourcelines: skipped 1 excluded entry"), "{}", stderr);
+    assert!(!stderr.contains("notes.tmp"), "{}", stderr);
+    assert!(!stdout.contains("skipped"));
+    let (quiet_stdout, stderr) = run(&["-q"]);
+    assert_eq!(quiet_stdout, stdout);
+    assert!(stderr.is_empty(), "{}", stderr);
+    let (_, stderr) = run(&["--log-level", "debug"]);
+    assert!(stderr.contains("notes.tmp (excluded)"), "{}", stderr);
+    let (_, stderr) = run(&["-v", "--log-level", "warn"]);
+    assert!(!stderr.contains("notes.tmp"), "{}", stderr);
+    assert!(stderr.contains("sourcelines: skipped 1 excluded entry"), "{}", stderr);
+}
+
+#[test]
+fn test_completions() {
+    let completions = |shell: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["completions", shell])
+            .output()
+            .expect("failed to run sourcelines");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    let bash = completions("bash");
+    assert!(bash.contains("complete -F _sourcelines"));
+    assert!(bash.contains("--list-only"));
+    assert!(completions("zsh").starts_with("#compdef sourcelines"));
+    assert!(completions("fish").contains("complete -c sourcelines"));
+    assert!(completions("powershell").contains("Register-ArgumentCompleter"));
+}
+
+#[test]
+fn test_man_page() {
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .arg("man")
+        .output()
+        .expect("failed to run sourcelines");
+    assert!(output.status.success());
+    let man = String::from_utf8_lossy(&output.stdout);
+    assert!(man.contains(".TH sourcelines 1"));
+    assert!(man.contains(".SH OPTIONS"));
+    assert!(man.contains("\\-\\-list\\-only"));
+}
+
+#[test]
+fn test_per_arg_sum() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("src")).unwrap();
+    std::fs::create_dir_all(root.join("tests")).unwrap();
+    std::fs::write(root.join("src/a.c"), "int a;\nint b;\nint c;\n").unwrap();
+    std::fs::write(root.join("tests/t.c"), "int t;\n").unwrap();
+    let run = |extra: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-rsl"])
+            .args(extra)
+            .args(["src", "tests"])
+            .current_dir(root)
+            .output()
+            .expect("failed to run sourcelines");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    assert_eq!(run(&[]), "       4 <*> (sum)\n");
+    assert_eq!(
+        run(&["--per-arg-sum"]),
+        "       3 <*> src (sum)\n       1 <*> tests (sum)\n       4 <*> (sum)\n"
+    );
+}
+
+#[test]
+fn test_columns() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::write(root.join("a.c"), "// one\nint a;\n\nint b;\n").unwrap();
+    std::fs::write(root.join("b.c"), "/* two */\nint c;\n").unwrap();
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(args)
+            .arg(".")
+            .current_dir(root)
+            .output()
+            .expect("failed to run sourcelines");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    assert_eq!(
+        run(&["-rs", "--columns", "actual_loc,comments,files,bytes"]),
+        "       3        2        2       39 <*> (sum)\n"
+    );
+    let markdown = run(&["--markdown", "--columns", "files,raw-loc"]);
+    assert!(markdown.contains("- **Files:** 2\n- **Raw Lines of Code:** 6\n"));
+    assert!(markdown.contains("| Language | Files | Raw LOC |\n"));
+    assert!(markdown.contains("| c | 2 | 6 |\n"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["--columns", "loc", "-w", "."])
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_locale() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::write(root.join("a.c"), "int a;\n".repeat(1234)).unwrap();
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-rs", "--percent", "--columns", "klocs,loc"])
+            .args(args)
+            .arg(".")
+            .current_dir(root)
+            .env_remove("LC_ALL")
+            .env_remove("LC_NUMERIC")
+            .env("LANG", "de_DE.UTF-8")
+            .output()
+            .expect("failed to run sourcelines");
+        (output.status.code(), String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    assert_eq!(run(&[]).1, "   1.234     1234   100.0% <*> (sum)\n");
+    assert_eq!(run(&["--locale", "en_US"]).1, "   1.234    1,234   100.0% <*> (sum)\n");
+    assert_eq!(run(&["--locale", "auto"]).1, "   1,234    1.234   100,0% <*> (sum)\n");
+    assert_eq!(run(&["--locale", "xx_YY"]).0, Some(2));
+}
+
+#[test]
+fn test_summary_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::write(root.join("a.c"), "int a;\nint b;\n").unwrap();
+    std::fs::write(root.join("b.py"), "x = 1\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["-rvl", "--summary-only", "a.c", "."])
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "       4 <c>\n       1 <python>\n       5 <*> (sum)\n"
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["--summary-only", "--by-dir", "."])
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_suspicious_results() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::write(root.join("main.c"), "int a;\n").unwrap();
+    std::fs::write(root.join("empty.c"), "/* nothing here */\n").unwrap();
+    std::fs::write(root.join("data.xyz"), "1\n2\n3\n").unwrap();
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-rs"])
+            .args(args)
+            .arg(".")
+            .current_dir(root)
+            .output()
+            .expect("failed to run sourcelines");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+    let stderr = run(&[]);
+    assert!(
+        stderr.contains("warning: ./empty.c has 19 bytes but no actual lines of code"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("warning: 60% of the lines in . are of languages without built-in comment syntax (xyz)"),
+        "{}",
+        stderr
+    );
+    assert!(!run(&["--warn-unknown", "60"]).contains("xyz"));
+    assert!(run(&["-q"]).is_empty());
+}
+
+#[test]
+fn test_icons() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::write(root.join("main.rs"), "fn main() {}\n").unwrap();
+    std::fs::write(root.join("notes.zzz"), "hello\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["-svl", "--icons", "--icon", "zzz=Z", "main.rs", "notes.zzz"])
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    assert!(output.status.success());
+    // Every glyph cell is as wide as an emoji, two columns
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "🦀        1 <rust> main.rs\nZ         1 <zzz> notes.zzz\n          2 <*> (sum)\n"
+    );
+}
+
+#[test]
+fn test_html_treemap() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("src/net")).unwrap();
+    std::fs::write(root.join("src/main.c"), "int a;\nint b;\n").unwrap();
+    std::fs::write(root.join("src/net/<tcp>.py"), "x = 1\n").unwrap();
+    std::fs::write(root.join("src/empty.c"), "\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+        .args(["--html", "src"])
+        .current_dir(root)
+        .output()
+        .expect("failed to run sourcelines");
+    assert!(output.status.success());
+    let html = String::from_utf8_lossy(&output.stdout);
+    assert!(html.contains("<h2>Treemap</h2>"), "{}", html);
+    // Files without actual LOC take no area; names cannot close the script element
+    assert!(
+        html.contains(
+            r#"const tree = {"n":"src","c":[{"n":"net","c":[{"n":"\u003ctcp\u003e.py","l":"python","v":1}]},{"n":"main.c","l":"c","v":2}]};"#
+        ),
+        "{}",
+        html
+    );
+}
+
+#[test]
+fn test_run_report_timing() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.c"), "int a;\n").unwrap();
+    std::fs::write(dir.path().join("b.py"), "b = 1\n").unwrap();
+    let run = |flag: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-r", flag])
+            .arg(dir.path())
+            .arg(dir.path().join("a.c"))
+            .output()
+            .expect("failed to run sourcelines");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+    // The file argument is counted again, on top of the directory
+    assert!(run("-v").contains("sourcelines: counted 3 files in "), "{}", run("-v"));
+    assert!(!run("-s").contains("counted"));
+}
+
+#[test]
+fn test_debug_tracing() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("main.c"), "int main;\n").unwrap();
+    std::fs::write(dir.path().join("notes.tmp"), "x\n").unwrap();
+    let run = |args: &[&str], rust_log: Option<&str>| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_sourcelines"));
+        command.args(args).arg(dir.path()).env_remove("RUST_LOG");
+        if let Some(filter) = rust_log {
+            command.env("RUST_LOG", filter);
+        }
+        let output = command.output().expect("failed to run sourcelines");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+    let stderr = run(&["-r", "--debug"], None);
+    assert!(stderr.contains("counted lang=c actual_loc=1"), "{}", stderr);
+    assert!(stderr.contains("skipped") && stderr.contains("notes.tmp"), "{}", stderr);
+    assert!(stderr.contains("time.busy="), "{}", stderr);
+    assert!(!stderr.contains('\x1b'), "{}", stderr);
+    assert!(!run(&["-r"], None).contains("counted lang"));
+    assert!(!run(&["-r"], Some("sourcelines=warn")).contains("counted lang"));
+    assert!(run(&["-r"], Some("sourcelines=debug")).contains("counted lang=c"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_metric_plugin() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    std::fs::create_dir(&src).unwrap();
+    std::fs::write(src.join("a.c"), "// TODO: free\nint a;\n").unwrap();
+    std::fs::write(src.join("b.py"), "x = 1  # TODO\n# TODO: more\n").unwrap();
+    // Counts the lines with a TODO, and the files it is told about
+    let plugin = dir.path().join("todo.sh");
+    std::fs::write(
+        &plugin,
+        r#"todo=0
+while IFS= read -r message; do
+    case "$message" in
+        *'"type":"hello"'*) echo '{"metrics":["todo","seen"],"lines":true}' ;;
+        *'"type":"file"'*) todo=0 ;;
+        *'"type":"line"'*) case "$message" in *TODO*) todo=$((todo + 1)) ;; esac ;;
+        *'"type":"end"'*) echo "{\"todo\":$todo,\"seen\":1}" ;;
+    esac
+done
+"#,
+    )
+    .unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-l", "--metric-plugin"])
+            .args(args)
+            .current_dir(dir.path())
+            .output()
+            .expect("failed to run sourcelines")
+    };
+    let output = run(&["sh todo.sh", "-r", "src", "src/a.c"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let rows: Vec<&str> = stdout.lines().collect();
+    assert_eq!(rows, ["       2        3        2 <*> src", "       1        1        1 <c> src/a.c"]);
+
+    let output = run(&["no-such-plugin", "src"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("could not start metric plugin no-such-plugin"));
+}
+
+#[test]
+fn test_config_profiles() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    std::fs::create_dir(&src).unwrap();
+    std::fs::write(src.join("a.c"), "// a\nint a;\n").unwrap();
+    std::fs::write(src.join("b.h"), "int b;\n").unwrap();
+    std::fs::write(
+        dir.path().join(".sourcelines.toml"),
+        "recursive = true\nexclude = [\"*.h\"]\n\n[profile.ci]\nsum = true\ncolumns = [\"actual_loc\", \"files\"]\n",
+    )
+    .unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(args)
+            .current_dir(dir.path())
+            .output()
+            .expect("failed to run sourcelines")
+    };
+    let stdout = |args: &[&str]| String::from_utf8_lossy(&run(args).stdout).into_owned();
+    assert_eq!(stdout(&["-l", "src"]), "       1 <*> src\n");
+    assert_eq!(stdout(&["--profile", "ci", "src"]), "       1        1 <*> (sum)\n");
+    // The command line comes after the file, and --no-config leaves it out
+    assert_eq!(stdout(&["--profile", "ci", "--exclude=*.c", "src"]), "       0        0 <*> (sum)\n");
+    assert_eq!(stdout(&["--no-config", "-l", "-r", "src"]), "       2 <*> src\n");
+
+    let output = run(&["--profile", "release", "src"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no profile 'release'"));
+    std::fs::write(dir.path().join("other.toml"), "colour = \"never\"\n").unwrap();
+    let output = run(&["--config", "other.toml", "src"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown option 'colour'"));
+}
+
+#[test]
+fn test_env_overrides() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    std::fs::create_dir(&src).unwrap();
+    std::fs::write(src.join("a.c"), "int a;\n").unwrap();
+    std::fs::write(src.join("b.py"), "b = 1\n").unwrap();
+    std::fs::write(dir.path().join(".sourcelines.toml"), "exclude = [\"*.c\"]\nsum = true\n").unwrap();
+    let run = |vars: &[(&str, &str)], args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .envs(vars.iter().copied())
+            .args(["-l", "-r"])
+            .args(args)
+            .current_dir(dir.path())
+            .output()
+            .expect("failed to run sourcelines")
+    };
+    let stdout = |vars: &[(&str, &str)], args: &[&str]| String::from_utf8_lossy(&run(vars, args).stdout).into_owned();
+    // The variables add to the file, and the command line comes last
+    assert_eq!(stdout(&[("SOURCELINES_EXCLUDE", "*.py,*.h")], &["src"]), "       0 <*> (sum)\n");
+    assert_eq!(stdout(&[("SOURCELINES_SUM", "0")], &["src"]), "       1 <*> (sum)\n");
+    assert_eq!(stdout(&[("SOURCELINES_NO_CONFIG", "1"), ("SOURCELINES_JOBS", "2")], &["src"]), "       2 <*> src\n");
+
+    let output = run(&[("SOURCELINES_EXLUDE", "*.py")], &["src"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown option 'exlude' in SOURCELINES_EXLUDE"));
+}
+
+#[test]
+fn test_config_check() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join(".sourcelines.toml");
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(args)
+            .arg("config")
+            .arg("check")
+            .current_dir(dir.path())
+            .output()
+            .expect("failed to run sourcelines")
+    };
+    std::fs::write(
+        &config,
+        "exclude = [\"a[b\"]\nremap = [\"jsp=java\", \"java=kotlin\"]\n\n[profile.ci]\nremap = [\"jsp=jsx\"]\n\n\
+         [profile.typo]\ncolour = \"never\"\n",
+    )
+    .unwrap();
+    let output = run(&[]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let problems: Vec<&str> = stderr.lines().map(|line| line.split_once(": ").unwrap().1).collect();
+    assert_eq!(
+        problems,
+        [
+            "'a[b' is not a valid wildcard, it only matches itself",
+            "jsp is remapped to java, which is remapped to kotlin",
+            "jsp is remapped to both java and jsx",
+            "unknown option 'colour'",
+        ]
+    );
+    assert!(stderr.contains("[profile.typo]: unknown option"));
+
+    std::fs::write(&config, "recursive = true\nexclude = [\"*.min.js\"]\n\n[profile.ci]\nsum = true\n").unwrap();
+    let output = run(&["--profile", "ci", "-j", "2"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "exclude = [\"*.min.js\"]\nrecursive = true\nsum = true\nthreads = 2\n"
+    );
+}
+
+#[test]
+fn test_file_and_line_gates() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    std::fs::create_dir(&src).unwrap();
+    std::fs::write(src.join("a.c"), "int a;\n").unwrap();
+    std::fs::write(src.join("b.c"), "int b;\nint long_enough_to_fail, with, a, comma;\nint c;\n").unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-r", "--fail-if", "file.actual_loc > 2", "--fail-if", "line.chars > 30"])
+            .args(args)
+            .arg("src")
+            .current_dir(dir.path())
+            .output()
+            .expect("failed to run sourcelines")
+    };
+    let output = run(&[]);
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let failed: Vec<&str> = stderr.lines().filter(|line| line.contains("gate failed")).collect();
+    assert_eq!(
+        failed,
+        [
+            "sourcelines: gate failed: file.actual_loc > 2 in src/b.c (value 3)",
+            "sourcelines: gate failed: line.chars > 30 at src/b.c:2 (value 40)",
+        ]
+    );
+
+    let output = run(&["--output", "github"]);
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let annotations: Vec<&str> = stdout.lines().filter(|line| line.starts_with("::")).collect();
+    assert_eq!(
+        annotations,
+        [
+            "::warning file=src/b.c,title=sourcelines::gate failed: file.actual_loc > 2 (value 3)",
+            "::warning file=src/b.c,line=2,title=sourcelines::gate failed: line.chars > 30 (value 40)",
+        ]
+    );
+}
+
+#[test]
+fn test_budgets() {
+    let dir = tempfile::tempdir().unwrap();
+    let legacy = dir.path().join("src/legacy");
+    std::fs::create_dir_all(&legacy).unwrap();
+    std::fs::write(dir.path().join("src/a.c"), "int a;\n").unwrap();
+    std::fs::write(legacy.join("old.c"), "int a;\nint b;\nint c;\n").unwrap();
+    std::fs::write(legacy.join("old.py"), "a = 1\n").unwrap();
+    let run = |budgets: &str| {
+        std::fs::write(dir.path().join("budgets.toml"), budgets).unwrap();
+        Command::new(env!("CARGO_BIN_EXE_sourcelines"))
+            .args(["-r", "-s", "--budgets", "budgets.toml", "src"])
+            .current_dir(dir.path())
+            .output()
+            .expect("failed to run sourcelines")
+    };
+    let output = run("\"src/legacy/**\" = 4\n\"src/*.c\" = 1\n\n[languages]\nc = 4\n");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = run("\"src/legacy/**\" = 3\n\"src/*.c\" = 1\n\n[languages]\nc = 3\npython = 1\n");
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let failed: Vec<&str> = stderr.lines().filter(|line| line.contains("over budget")).collect();
+    assert_eq!(
+        failed,
+        [
+            "sourcelines: over budget: src/legacy/** = 3 in budgets.toml (value 4)",
+            "sourcelines: over budget: <c> = 3 in budgets.toml (value 4)",
+        ]
+    );
+
+    let output = run("\"src/legacy/**\" = \"many\"\n");
+    assert_eq!(output.status.code(), Some(2));
+}
@@ -0,0 +1,7 @@
+import sys
+
+# entry point
+def main():
+    return 0  # ok
+
+sys.exit(main())
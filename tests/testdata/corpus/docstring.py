@@ -0,0 +1,8 @@
+def f():
+    """Docstrings are strings, so they count as code.
+
+    # not a comment either
+    """
+    return "# hash in a string"
+
+# a real comment
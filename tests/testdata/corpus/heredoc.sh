@@ -0,0 +1,8 @@
+#!/bin/sh
+# Write a config file
+cat > out.conf <<END
+# a comment in the config, not in the script
+key = value
+END
+
+echo done
@@ -0,0 +1,11 @@
+export function App() {
+  return (
+    <div>
+      {/* a JSX comment */}
+      <p>// text, not a comment</p>
+      {/*
+        spanning lines
+      */}
+    </div>
+  );
+}
@@ -0,0 +1,9 @@
+/* outer
+   /* inner */
+   still in the outer comment
+*/
+fn main() {
+    /* one line */
+    let x = 1; /* trailing */
+    /* closed */ let y = 2;
+}
@@ -0,0 +1,2 @@
+// no newline at the end
+int x;
\ No newline at end of file
@@ -0,0 +1,8 @@
+#include <stdio.h>
+
+int main(void) {
+    const char *open = "/* not a comment";
+    printf("// not a comment either\n");
+    const char *close = "*/";
+    return 0;
+}
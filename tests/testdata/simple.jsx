@@ -0,0 +1,9 @@
+// A small component
+export function App() {
+  return (
+    <div>
+      {/* JSX comment */}
+      <p>Hello</p>
+    </div>
+  );
+}